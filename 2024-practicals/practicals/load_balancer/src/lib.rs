@@ -1,5 +1,6 @@
 pub mod load_balancer;
 pub mod request;
+pub mod trace;
 
 pub mod rate_limiter_proto {
     include!("proto/rate_limiter.rs");
@@ -73,6 +73,7 @@ pub mod consistent_hashing {
 
         // distribute requests based on consistent hash
         pub async fn distribute(&mut self, request: Request) -> Result<Vec<u8>, hyper::Error> {
+            let request_id: String = request.request_id.clone();
             let rate_limit_request = RateLimitRequest {
                 ip_address: request.client_ip.clone(),
                 endpoint: request.uri.clone(),
@@ -84,7 +85,10 @@ pub mod consistent_hashing {
                 match RateLimiterClient::connect(RATELIMITERADDRESS.to_string().clone()).await {
                     Ok(c) => c,
                     Err(_) => {
-                        eprintln!("Connection to rate limiter could not be esablished");
+                        eprintln!(
+                            "[{}] Connection to rate limiter could not be esablished",
+                            request_id
+                        );
                         return Ok(
                             "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
                                 .to_string()
@@ -160,7 +164,7 @@ pub mod consistent_hashing {
             };
 
             if (stream.write_all(&request).await).is_err() {
-                eprintln!("Failed to write to server");
+                eprintln!("[{}] Failed to write to server", request_id);
                 return Ok(
                     "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
                         .to_string()
@@ -170,7 +174,7 @@ pub mod consistent_hashing {
 
             let mut server_response = Vec::new();
             if (stream.read_to_end(&mut server_response).await).is_err() {
-                eprintln!("Failed to read from server");
+                eprintln!("[{}] Failed to read from server", request_id);
                 return Ok(
                     "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
                         .to_string()
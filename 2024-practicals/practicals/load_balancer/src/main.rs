@@ -1,12 +1,18 @@
 use dotenv::dotenv;
 use load_balancer::load_balancer::consistent_hashing::LoadBalancer;
-use load_balancer::request::buffer_to_request;
+use load_balancer::request::{buffer_to_request, request_path};
 use std::env;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+/// How long a shutdown waits for the requests being proxied to finish.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
@@ -37,29 +43,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
             std::process::exit(1);
         } else {
             shutdown_signal.notify_one();
-            println!("Tasks complete, server shutdown started");
-            std::process::exit(0);
         }
     });
 
+    let mut tasks: JoinSet<()> = JoinSet::new();
+
     tokio::select! {
-        _ = reverse_proxy(listener,state.clone()) => {
+        _ = reverse_proxy(listener,state.clone(),&mut tasks) => {
             println!("loop ended");
         },
         _ = shutdown.notified() => {
                 eprintln!("Graceful shutdown initiated");
-                std::process::exit(0);
             }
     }
 
+    // Let the requests being proxied finish, but do not wait for them forever
+    let drain = async { while tasks.join_next().await.is_some() {} };
+    if timeout(SHUTDOWN_GRACE_PERIOD, drain).await.is_err() {
+        eprintln!("Dropping {} requests that did not finish", tasks.len());
+    }
+    println!("Tasks complete, server shut down");
+
     Ok(())
 }
 
-async fn reverse_proxy(listener: TcpListener, state: Arc<Mutex<LoadBalancer>>) {
+async fn reverse_proxy(
+    listener: TcpListener,
+    state: Arc<Mutex<LoadBalancer>>,
+    tasks: &mut JoinSet<()>,
+) {
     loop {
         let state = state.clone();
+        // Forget the requests that are done, the set only tracks those still running
+        while tasks.try_join_next().is_some() {}
         if let Ok((mut stream, client_address)) = listener.accept().await {
-            tokio::spawn(async move {
+            tasks.spawn(async move {
                 let mut buffer: [u8; 4096] = [0; 4096];
 
                 if let Ok(bytes_read) = stream.read(&mut buffer).await {
@@ -67,10 +85,7 @@ async fn reverse_proxy(listener: TcpListener, state: Arc<Mutex<LoadBalancer>>) {
                         return;
                     }
 
-                    println!(
-                        "{}",
-                        String::from_utf8(buffer[..bytes_read].to_vec()).unwrap()
-                    );
+                    let path: Option<String> = request_path(&buffer[..bytes_read]);
 
                     let mut request: http::Request<Vec<u8>> = match buffer_to_request(
                         buffer[..bytes_read].to_vec(),
@@ -86,11 +101,17 @@ async fn reverse_proxy(listener: TcpListener, state: Arc<Mutex<LoadBalancer>>) {
                     };
 
                     // Ignore favicon.ico requests
-                    if request.uri().path() == "/favicon.ico" {
+                    if path.as_deref() == Some("/favicon.ico") {
                         send_error_response(404, &mut stream).await;
                         return;
                     }
 
+                    // Liveness checks are answered by the load balancer, not passed to a node
+                    if path.as_deref() == Some("/healthz") {
+                        send_health_response(&mut stream).await;
+                        return;
+                    }
+
                     // add the client IP address custom header
                     request
                         .headers_mut()
@@ -104,6 +125,8 @@ async fn reverse_proxy(listener: TcpListener, state: Arc<Mutex<LoadBalancer>>) {
                             client_address.to_string(),
                             request,
                         );
+                    println!("{request}");
+                    let request_id: String = request.request_id.clone();
 
                     let mut state = state.lock().await;
 
@@ -115,7 +138,7 @@ async fn reverse_proxy(listener: TcpListener, state: Arc<Mutex<LoadBalancer>>) {
                     };
 
                     if (stream.write_all(&response).await).is_err() {
-                        eprintln!("Failed to responed to client");
+                        eprintln!("[{}] Failed to responed to client", request_id);
                     };
                 }
             });
@@ -148,6 +171,20 @@ fn get_nodes() -> Vec<String> {
     nodes
 }
 
+/// Answers a liveness check. The load balancer is alive as long as it accepts connections, whether
+/// or not its nodes are.
+async fn send_health_response(stream: &mut TcpStream) {
+    let body: &str = r#"{"status":"ok"}"#;
+    let response_bytes = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .into_bytes();
+
+    let _ = stream.write_all(&response_bytes).await;
+}
+
 async fn send_error_response(code: u64, stream: &mut TcpStream) {
     match code {
         429 => {
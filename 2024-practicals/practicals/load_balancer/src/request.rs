@@ -1,7 +1,12 @@
-use uuid::Uuid;
+use crate::trace::TraceParent;
+
+/// The client headers forwarded to the backends. The forwarded request is a new `GET /` with no
+/// body, so framing headers such as `Content-Length` and hop-by-hop headers such as `Connection`
+/// would make the backend wait for a body that never comes.
+const FORWARDED_HEADERS: [&str; 2] = ["X-Request-ID", "traceparent"];
 
 pub struct Request {
-    pub request_id: Uuid,
+    pub request_id: String,
     pub client_ip: String,
     pub uri: String,
     pub request: http::Request<Vec<u8>>,
@@ -14,12 +19,31 @@ impl Request {
             uri = "/".to_string();
         }
 
-        let request_id = Uuid::new_v4();
+        // accept the request ID and trace context sent by the client or generate new ones
+        let headers = request.headers();
+        let incoming_trace: Option<TraceParent> = headers
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .and_then(TraceParent::parse);
+        let request_id: String = crate::trace::request_id(
+            headers.get("X-Request-ID").and_then(|v| v.to_str().ok()),
+            incoming_trace.as_ref(),
+        );
+
+        // the load balancer is a hop in the trace so backends see it as the parent
+        let traceparent: TraceParent = match incoming_trace {
+            Some(t) => t.child(),
+            None if crate::trace::is_trace_id(&request_id) => TraceParent::new(request_id.clone()),
+            None => TraceParent::new(uuid::Uuid::new_v4().simple().to_string()),
+        };
 
-        // add the request ID to the headers
-        request
-            .headers_mut()
-            .insert("X-Request-ID", request_id.to_string().parse().unwrap());
+        // add the request ID and trace context to the forwarded headers
+        if let Ok(value) = request_id.parse() {
+            request.headers_mut().insert("X-Request-ID", value);
+        }
+        if let Ok(value) = traceparent.to_string().parse() {
+            request.headers_mut().insert("traceparent", value);
+        }
 
         Request {
             request_id,
@@ -30,6 +54,29 @@ impl Request {
     }
 }
 
+impl Display for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Request: {{method: {}, path: {}, request_id: {}, client_ip: {}}}",
+            self.request.method(),
+            self.uri,
+            self.request_id,
+            self.client_ip
+        )
+    }
+}
+
+/// Returns the path the client requested, without the query string.
+///
+/// The forwarded request is always `GET /`, so the load balancer reads the path from the request
+/// line to answer the routes it handles itself.
+pub fn request_path(buffer: &[u8]) -> Option<String> {
+    let line: &[u8] = buffer.split(|b| *b == b'\n').next()?;
+    let target: &str = std::str::from_utf8(line).ok()?.split_whitespace().nth(1)?;
+    target.split('?').next().map(str::to_string)
+}
+
 pub fn buffer_to_request(
     buffer: Vec<u8>,
     client_ip: String,
@@ -40,14 +87,25 @@ pub fn buffer_to_request(
         Err(e) => return Err(e),
     };
 
-    println!("{http_request}");
+    // carry the request ID and trace context over so they can be propagated
+    let mut builder = http::Request::builder().method("GET").uri("/");
+    for header in &http_request.headers {
+        if let Some((name, value)) = header.split_once(':') {
+            let name: &str = name.trim();
+            if FORWARDED_HEADERS
+                .iter()
+                .any(|forwarded| forwarded.eq_ignore_ascii_case(name))
+            {
+                builder = builder.header(name, value.trim());
+            }
+        }
+    }
 
     let body: Vec<u8> = Vec::new();
-    return Ok(http::Request::builder()
-        .method("GET")
-        .uri("/")
-        .body(body)
-        .unwrap());
+    return match builder.body(body) {
+        Ok(r) => Ok(r),
+        Err(_) => Err(String::from("Malformed Header")),
+    };
 }
 
 /*pub fn buffer_to_request(buffer: Vec<u8>) -> Result<http::Request<Vec<u8>>, String> {
@@ -439,3 +497,35 @@ impl PartialEq for HttpMethod {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_headers() {
+        let buffer: Vec<u8> = b"POST /submit HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\nConnection: keep-alive\r\nx-request-id: abc-123\r\n\r\nhello".to_vec();
+        let request = buffer_to_request(buffer, String::from("127.0.0.1:5000"), 0).unwrap();
+        assert_eq!(request.headers().len(), 1);
+        assert_eq!(request.headers()["X-Request-ID"], "abc-123");
+        assert!(request.body().is_empty());
+
+        let request = Request::new(String::from("/"), String::from("127.0.0.1:5000"), request);
+        assert_eq!(request.request_id, "abc-123");
+        assert!(request.to_string().contains("request_id: abc-123"));
+        assert!(request.request.headers().contains_key("traceparent"));
+    }
+
+    #[test]
+    fn test_request_path() {
+        assert_eq!(
+            request_path(b"GET /healthz?verbose=1 HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+            Some(String::from("/healthz"))
+        );
+        assert_eq!(
+            request_path(b"GET / HTTP/1.1\r\n\r\n"),
+            Some(String::from("/"))
+        );
+        assert_eq!(request_path(b"GARBAGE\r\n\r\n"), None);
+    }
+}
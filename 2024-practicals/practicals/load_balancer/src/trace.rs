@@ -0,0 +1,170 @@
+//! Request ID propagation.
+//!
+//! The load balancer forwards an `X-Request-ID` and a W3C `traceparent` header with every request
+//! it sends to a backend. The ID is taken from an incoming `X-Request-ID` header, otherwise from
+//! the trace ID of an incoming `traceparent` header, otherwise a new one is generated.
+use rand::Rng;
+use std::fmt::Display;
+
+/// The longest `X-Request-ID` value accepted from a client.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// A parsed W3C `traceparent` header (`version-trace_id-parent_id-flags`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: String,
+    pub parent_id: String,
+    pub flags: u8,
+}
+
+impl TraceParent {
+    /// Parses a `traceparent` header value, returning `None` if it is malformed.
+    pub fn parse(value: &str) -> Option<TraceParent> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        if parts.len() < 4 {
+            return None;
+        }
+
+        let (version, trace_id, parent_id, flags) = (parts[0], parts[1], parts[2], parts[3]);
+
+        // Version ff is invalid and version 00 must have exactly four fields
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.len() != 4) {
+            return None;
+        }
+
+        if !is_hex(trace_id, 32) || trace_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+
+        if !is_hex(parent_id, 16) || parent_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+
+        if !is_hex(flags, 2) {
+            return None;
+        }
+
+        Some(TraceParent {
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        })
+    }
+
+    /// Starts a new sampled trace with the given trace ID.
+    pub fn new(trace_id: String) -> TraceParent {
+        TraceParent {
+            trace_id,
+            parent_id: new_span_id(),
+            flags: 1,
+        }
+    }
+
+    /// Returns a `traceparent` for the next hop, keeping the trace ID and using a new parent ID.
+    pub fn child(&self) -> TraceParent {
+        TraceParent {
+            trace_id: self.trace_id.clone(),
+            parent_id: new_span_id(),
+            flags: self.flags,
+        }
+    }
+}
+
+impl Display for TraceParent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
+
+/// Chooses the ID of a request from its headers or generates a new one.
+///
+/// # Arguments
+/// - `request_id`: The value of the incoming `X-Request-ID` header, if any.
+/// - `traceparent`: The parsed incoming `traceparent` header, if any.
+pub fn request_id(request_id: Option<&str>, traceparent: Option<&TraceParent>) -> String {
+    if let Some(id) = request_id
+        .map(str::trim)
+        .filter(|id| is_valid_request_id(id))
+    {
+        return id.to_string();
+    }
+
+    match traceparent {
+        Some(t) => t.trace_id.clone(),
+        None => uuid::Uuid::new_v4().simple().to_string(),
+    }
+}
+
+/// Returns true if `id` can be used as a W3C trace ID.
+pub fn is_trace_id(id: &str) -> bool {
+    is_hex(id, 32) && !id.bytes().all(|b| b == b'0')
+}
+
+/// Only visible ASCII without spaces, quotes or backslashes is accepted so that client supplied
+/// IDs cannot inject content into log lines or response headers.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && b != b'"' && b != b'\\')
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn new_span_id() -> String {
+    let mut rng = rand::thread_rng();
+    loop {
+        let id: u64 = rng.gen();
+        if id != 0 {
+            return format!("{:016x}", id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_traceparent() {
+        let parent = TraceParent::parse(TRACEPARENT).unwrap();
+        assert_eq!(parent.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(parent.flags, 1);
+        assert_eq!(parent.to_string(), TRACEPARENT);
+        assert!(
+            TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(TraceParent::parse("00-4bf92f3577b34da6-00f067aa0ba902b7-01").is_none());
+    }
+
+    #[test]
+    fn test_request_id_precedence() {
+        let parent = TraceParent::parse(TRACEPARENT).unwrap();
+        assert_eq!(request_id(Some("abc-123"), Some(&parent)), "abc-123");
+        assert_eq!(
+            request_id(Some("bad id\""), Some(&parent)),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(request_id(None, None).len(), 32);
+    }
+
+    #[test]
+    fn test_child_keeps_trace_id() {
+        let parent = TraceParent::parse(TRACEPARENT).unwrap();
+        let child = parent.child();
+        assert_eq!(child.trace_id, parent.trace_id);
+        assert_ne!(child.parent_id, parent.parent_id);
+    }
+}
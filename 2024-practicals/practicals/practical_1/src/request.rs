@@ -96,8 +96,13 @@ impl Request {
     }
 
     pub fn new(buffer: &[u8], client_ip: String, request_id: i64) -> Result<Request, ErrorType> {
-        // unwrap is safe as request has been parsed for any issues before this is called
-        let request = String::from_utf8(buffer.to_vec()).unwrap();
+        let request = match String::from_utf8(buffer.to_vec()) {
+            Ok(r) => r,
+            Err(_) => {
+                error!(target: "error_logger","Recieved a request that is not UTF-8");
+                return Err(ErrorType::ConnectionError(String::from("Invalid request")));
+            }
+        };
 
         // split the request by line
         let request: Vec<&str> = request.lines().collect();
@@ -107,12 +112,15 @@ impl Request {
             return Err(ErrorType::ConnectionError(String::from("Invalid request")));
         }
 
-        // get the http method from the first line
-        let method: HttpMethod =
-            HttpMethod::new(request[0].split_whitespace().collect::<Vec<&str>>()[0]);
-
-        // get the uri from the first line
-        let mut uri: String = request[0].split_whitespace().collect::<Vec<&str>>()[1].to_string();
+        // get the http method and the uri from the first line
+        let (method, mut uri): (HttpMethod, String) =
+            match request[0].split_whitespace().collect::<Vec<&str>>()[..] {
+                [method, uri, ..] => (HttpMethod::new(method), uri.to_string()),
+                _ => {
+                    error!(target: "error_logger","Recieved invalid request line");
+                    return Err(ErrorType::ConnectionError(String::from("Invalid request")));
+                }
+            };
         if uri == "/favicon.ico" {
             uri = "/".to_string();
         }
@@ -188,6 +196,7 @@ pub enum HttpCode {
     RequestTimeout,
     Teapot,
     InternalServerError,
+    ServiceUnavailable,
 }
 
 impl Display for HttpCode {
@@ -202,6 +211,7 @@ impl Display for HttpCode {
             HttpCode::RequestTimeout => write!(f, "408 Request Timeout"),
             HttpCode::Teapot => write!(f, "418 I'm a teapot"),
             HttpCode::InternalServerError => write!(f, "500 Internal Server Error"),
+            HttpCode::ServiceUnavailable => write!(f, "503 Service Unavailable"),
         }
     }
 }
@@ -1,19 +1,21 @@
 //! A Secure, multi-threaded HTTP/1.1 server implementation using TLS for secure communication.
 //! This server demonstrates concurrency management, secure password storage and itergration with
 //! external services like PostgreSQL and Redis.
-use crate::redis_connection::{get_cached_content, read_and_cache_page, set_up_redis};
+use crate::health::handle_health;
+use crate::redis_connection::{get_cached_content, read_and_cache_page, set_up_redis, PageCache};
 use crate::response::Response;
-use crate::socket::connection::{get_listener, load_tls_config};
-use crate::{handle_response, Clock, ErrorType, Request};
+use crate::socket::connection::{get_listener, load_tls_config, TlsIdentity, CERT_PATH, KEY_PATH};
+use crate::{handle_response, Clock, ContentType, ErrorType, HttpCode, Protocol, Request};
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
 use colored::Colorize;
 use dotenv::dotenv;
 use log::{error, info};
 use rand::rngs::OsRng;
+use rustls::pki_types::CertificateDer;
+use std::collections::HashMap;
 use std::env;
 use std::path::Path;
-use std::str::from_utf8;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
@@ -29,23 +31,56 @@ use uuid::Uuid;
 /// The default port used by the server if none is specified.
 const DEFAULT_PORT: u16 = 7878;
 
-/// Shared state structure holding connecions to Redis, PostgreSQL and a logical clock.
+/// How long a shutdown waits for the open connections to finish.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Where user accounts are stored.
+pub enum UserStore {
+    Postgres(Client),
+    /// Keeps each user's password hash and session ID by username in the process, for tests and
+    /// running without PostgreSQL.
+    Memory(HashMap<String, (String, Uuid)>),
+}
+
+impl UserStore {
+    /// Checks that PostgreSQL answers a query. The in-memory store is always available.
+    pub async fn ping(&self) -> Result<String, String> {
+        match self {
+            UserStore::Postgres(client) => match client.simple_query("SELECT 1").await {
+                Ok(_) => Ok(String::from("OK")),
+                Err(e) => Err(e.to_string()),
+            },
+            UserStore::Memory(_) => Ok(String::from("In memory")),
+        }
+    }
+}
+
+/// Shared state structure holding the page cache, the user accounts and a logical clock.
 pub struct SharedState {
-    pub redis_connection: redis::Connection,
+    pub cache: PageCache,
     pub clock: Clock,
-    pub client: Client,
+    pub users: UserStore,
 }
 
 impl SharedState {
     /// Creates a new `SharedState` instance.
-    pub fn new(redis_connection: redis::Connection, clock: Clock, client: Client) -> Self {
+    pub fn new(cache: PageCache, clock: Clock, users: UserStore) -> Self {
         SharedState {
-            redis_connection,
+            cache,
             clock,
-            client,
+            users,
         }
     }
 
+    /// Creates a state that keeps its pages and users in memory.
+    pub fn in_memory() -> Self {
+        SharedState::new(
+            PageCache::memory(),
+            Clock::new(),
+            UserStore::Memory(HashMap::new()),
+        )
+    }
+
     /// Increments the logical clock value.
     pub async fn increment_clock(&mut self) -> i64 {
         self.clock.increment_time()
@@ -53,12 +88,12 @@ impl SharedState {
 
     /// Retrieves the cached content for a given route name from Redis.
     pub async fn get_cached_content(&mut self, route_name: &str) -> Option<Vec<u8>> {
-        get_cached_content(&mut self.redis_connection, route_name).await
+        get_cached_content(&mut self.cache, route_name).await
     }
 
     /// Reads and caches a page in Redis based on the path and route name.
     pub async fn read_and_cache_page(&mut self, path: &Path, route_name: &str) -> Vec<u8> {
-        read_and_cache_page(&mut self.redis_connection, path, route_name).await
+        read_and_cache_page(&mut self.cache, path, route_name).await
     }
 
     /// Adds a new user to the PostgreSQL database, hashing their password.
@@ -77,13 +112,24 @@ impl SharedState {
         let hash = Self::hash_password(&password).unwrap();
         let session_id = Uuid::new_v4();
 
-        let query = self
-            .client
+        let client: &Client = match &mut self.users {
+            UserStore::Postgres(client) => client,
+            UserStore::Memory(users) => {
+                if users.contains_key(&username) {
+                    return Err(Box::new(ErrorType::BadRequest(
+                        "User already exists".to_string(),
+                    )));
+                }
+                users.insert(username, (hash, session_id));
+                return Ok(session_id);
+            }
+        };
+
+        let query = client
             .prepare("INSERT INTO users (username, password, session_id) VALUES ($1,$2,$3);")
             .await?;
 
-        let _ = self
-            .client
+        let _ = client
             .execute(&query, &[&username, &hash, &session_id])
             .await?;
 
@@ -95,12 +141,23 @@ impl SharedState {
         &mut self,
         username: String,
     ) -> Result<Uuid, Box<dyn std::error::Error>> {
-        let query = self
-            .client
+        let client: &Client = match &self.users {
+            UserStore::Postgres(client) => client,
+            UserStore::Memory(users) => {
+                return match users.get(&username) {
+                    Some((_, session_id)) => Ok(*session_id),
+                    None => Err(Box::new(ErrorType::ReadError(
+                        "Failed to find user".to_string(),
+                    ))),
+                };
+            }
+        };
+
+        let query = client
             .prepare("SELECT * FROM users WHERE username = $1")
             .await?;
 
-        let row = self.client.query_one(&query, &[&username]).await?;
+        let row = client.query_one(&query, &[&username]).await?;
 
         if row.is_empty() {
             return Err(Box::new(ErrorType::ReadError(
@@ -175,14 +232,14 @@ pub async fn set_up_server() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    let state: Arc<Mutex<SharedState>> = Arc::new(Mutex::new(SharedState::new(
+    let state: SharedState = SharedState::new(
         match set_up_redis() {
-            Ok(c) => c,
+            Ok(c) => PageCache::Redis(c),
             _ => std::process::exit(1),
         },
         Clock::new(),
-        client,
-    )));
+        UserStore::Postgres(client),
+    );
 
     rustls::crypto::ring::default_provider()
         .install_default()
@@ -190,62 +247,163 @@ pub async fn set_up_server() -> Result<(), Box<dyn std::error::Error>> {
 
     log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
 
+    let tls: TlsIdentity = match load_tls_config(CERT_PATH, KEY_PATH).await {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to load the TLS certificate: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let server: Server = Server::bind(ServerConfig::new(tls).port(port), state).await?;
+
     print_server_info(port);
 
+    let shutdown: Arc<Shutdown> = server.shutdown_handle();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            eprintln!("Failed to listen for shutdown signal");
+            std::process::exit(1);
+        } else {
+            println!("Recieved shutdown request");
+            println!("Waiting for tasks to finish");
+            shutdown.shutdown();
+        }
+    });
+
     info!(target: "request_logger","Server Started");
-    let _ = start_server(port, state).await;
+    server.run().await;
     Ok(())
 }
 
-/// Starts the server, accepting incoming connections and managing their lifecycles.
-///
-/// # Arguments
-/// - `port`: The port the server is running on.
-/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// The port and TLS identity of a server.
+pub struct ServerConfig {
+    /// The port of the TLS listener. Port 0 picks a free port.
+    pub port: u16,
+    pub tls: TlsIdentity,
+}
+
+impl ServerConfig {
+    /// Creates a configuration using the default port.
+    pub fn new(tls: TlsIdentity) -> Self {
+        ServerConfig {
+            port: DEFAULT_PORT,
+            tls,
+        }
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+}
+
+/// Tells a running server to shut down.
+pub struct Shutdown {
+    requested: AtomicBool,
+    notify: Notify,
+}
+
+impl Shutdown {
+    fn new() -> Self {
+        Shutdown {
+            requested: AtomicBool::new(false),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Starts a graceful shutdown, like Ctrl+C.
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// A server with its listener bound, ready to accept connections.
 ///
-/// # Returns
-/// A `Result` object with either and Ok(()) or an Err(Box<dyn std::error::Error>)
-async fn start_server(
-    port: u16,
+/// `set_up_server` builds one from the command line, the certificate files, PostgreSQL and Redis.
+/// Tests can build one with their own certificate, port 0 and `SharedState::in_memory`.
+pub struct Server {
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
     state: Arc<Mutex<SharedState>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let listener: TcpListener = get_listener(port)?;
-
-    let tls_config = load_tls_config().await?;
-    let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+    shutdown: Arc<Shutdown>,
+    /// The certificate the TLS listener presents, checked by `/readyz`.
+    certificate: Arc<CertificateDer<'static>>,
+}
 
-    let shutdown: Arc<Notify> = Arc::new(Notify::new());
-    let is_shutting_down = Arc::new(AtomicBool::new(false));
+impl Server {
+    /// Binds the TLS listener.
+    ///
+    /// # Arguments
+    /// - `config`: The port and TLS identity of the server.
+    /// - `state`: The state holding the page cache and users the handlers use.
+    ///
+    /// # Returns
+    /// A `Result` object with either the `Server` or an Err(Box<dyn std::error::Error>)
+    pub async fn bind(
+        config: ServerConfig,
+        state: SharedState,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let listener: TcpListener = get_listener(config.port)?;
+
+        Ok(Server {
+            listener,
+            acceptor: TlsAcceptor::from(Arc::new(config.tls.config)),
+            state: Arc::new(Mutex::new(state)),
+            shutdown: Arc::new(Shutdown::new()),
+            certificate: Arc::new(config.tls.certificate),
+        })
+    }
 
-    let connections: Arc<Semaphore> = Arc::new(Semaphore::new(15));
+    /// Returns the address of the TLS listener.
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
 
-    let shutdown_signal = shutdown.clone();
-    let shutdown_flag = is_shutting_down.clone();
+    pub fn state(&self) -> Arc<Mutex<SharedState>> {
+        self.state.clone()
+    }
 
-    tokio::spawn(async move {
-        if tokio::signal::ctrl_c().await.is_err() {
-            eprintln!("Failed to listen for shutdown signal");
-            std::process::exit(1);
-        } else {
-            println!("Recieved shutdown request");
-            println!("Waiting for tasks to finish");
-            shutdown_flag.store(true, Ordering::SeqCst);
-            shutdown_signal.notify_one();
-            println!("Tasks complete, server shutdown started");
-            std::process::exit(0);
-        }
-    });
+    pub fn shutdown_handle(&self) -> Arc<Shutdown> {
+        self.shutdown.clone()
+    }
 
-    tokio::select! {
-        _ = run_server(listener,acceptor,connections.clone(),is_shutting_down.clone(),state.clone())=> {
+    /// Accepts connections until a shutdown is started with `Shutdown::shutdown`.
+    ///
+    /// The server then stops accepting connections and waits for the open ones to finish, for at
+    /// most `SHUTDOWN_GRACE_PERIOD`.
+    pub async fn run(self) {
+        let Server {
+            listener,
+            acceptor,
+            state,
+            shutdown,
+            certificate,
+        } = self;
+
+        let connections: Arc<Semaphore> = Arc::new(Semaphore::new(15));
+
+        tokio::select! {
+            _ = run_server(listener,acceptor,connections.clone(),shutdown.clone(),certificate,state)=> {
+            }
+            _ = shutdown.notify.notified() => {
+                    info!(target: "request_logger","Server shutdown signal recieved.");
+                    println!("Server shutdown signal recieved.");
+            }
         }
-        _ = shutdown.notified() => {
-                info!(target: "request_logger","Server shutdown signal recieved.");
-                println!("Server shutdown signal recieved.");
+        // Let the open connections finish, but do not wait for them forever
+        if timeout(SHUTDOWN_GRACE_PERIOD, connections.acquire_many(15))
+            .await
+            .is_err()
+        {
+            println!("Closing the connections that did not finish");
         }
+        println!("Server shut down");
     }
-    while connections.clone().available_permits() != 15 {}
-    Ok(())
 }
 
 /// Accepts connections from incoming clients and completes the TLS hanshake.
@@ -254,19 +412,21 @@ async fn start_server(
 /// - `listener`: A TcpListener
 /// - `acceptor`: A TlsAcceptor to handle the Tls hanshake.
 /// - `connections`: A Semaphore for limiting the amout of concurrent connections.
-/// - `is_shutdown`: A thread-safe `AtomicBool` to indicate if the server is in shutdown mode.
+/// - `shutdown`: Whether the server is shutting down.
+/// - `certificate`: The certificate the TLS listener presents.
 /// - `state`: A shared, thread-safe state used for managing server data and caching.
 async fn run_server(
     listener: TcpListener,
     acceptor: TlsAcceptor,
     connections: Arc<Semaphore>,
-    is_shutdown: Arc<AtomicBool>,
+    shutdown: Arc<Shutdown>,
+    certificate: Arc<CertificateDer<'static>>,
     state: Arc<Mutex<SharedState>>,
 ) {
     loop {
         let connections = connections.clone();
 
-        if Arc::clone(&is_shutdown).load(Ordering::SeqCst) {
+        if shutdown.is_shutting_down() {
             return;
         }
 
@@ -289,11 +449,20 @@ async fn run_server(
         let acceptor = acceptor.clone();
 
         let state = state.clone();
+        let shutdown = shutdown.clone();
+        let certificate = certificate.clone();
         let handle = tokio::spawn(async move {
             if let Ok(tls_stream) = acceptor.accept(stream).await {
                 info!(target: "request_logger","TLS handshake successful with {}", address);
 
-                let _ = handle_connection(tls_stream, address.to_string(), state.clone()).await;
+                let _ = handle_connection(
+                    tls_stream,
+                    address.to_string(),
+                    state.clone(),
+                    &shutdown,
+                    &certificate,
+                )
+                .await;
             } else {
                 error!(target: "error_logger","TLS handshake failed with {}", address);
             }
@@ -311,6 +480,8 @@ async fn run_server(
 /// - `stream`: A mutable TlsStream.
 /// - `address`: The address of the client connected to the server.
 /// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `shutdown`: Whether the server is shutting down.
+/// - `certificate`: The certificate the TLS listener presents.
 ///
 /// # Returns
 /// A `Result` object with either and Ok(()) or an Err(Box<dyn std::error::Error>)
@@ -318,6 +489,8 @@ async fn handle_connection(
     mut stream: TlsStream<TcpStream>,
     address: String,
     state: Arc<Mutex<SharedState>>,
+    shutdown: &Shutdown,
+    certificate: &CertificateDer<'static>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     loop {
         let mut buffer = [0; 4096];
@@ -335,7 +508,7 @@ async fn handle_connection(
             return Ok(());
         }
 
-        println!("{}", from_utf8(&buffer[..bytes_read]).unwrap());
+        println!("{}", String::from_utf8_lossy(&buffer[..bytes_read]));
 
         let request: Request = match Request::new(
             &buffer[..bytes_read],
@@ -345,7 +518,17 @@ async fn handle_connection(
             Ok(r) => r,
             Err(_) => {
                 println!("Unable to parse in request");
-                std::process::exit(1);
+                let mut response: Response = Response::new(
+                    Protocol::Http,
+                    HttpCode::BadRequest,
+                    ContentType::Text,
+                    false,
+                )
+                .body(String::from("Invalid request").into());
+                stream.write_all(&response.to_bytes()).await?;
+                stream.flush().await?;
+
+                return Ok(());
             }
         };
 
@@ -360,7 +543,11 @@ async fn handle_connection(
             return Ok(());
         }
 
-        let mut response: Response = handle_response(request, state.clone()).await;
+        let mut response: Response =
+            match handle_health(&request, &state, certificate, shutdown.is_shutting_down()).await {
+                Some(r) => r,
+                None => handle_response(request, state.clone()).await,
+            };
 
         stream.write_all(&response.to_bytes()).await?;
         stream.flush().await?;
@@ -1,10 +1,17 @@
-use core::str;
 use libc::*;
 use rand::Rng;
 use std::error::Error;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::question::Question;
+use crate::quiz::{Quiz, QUESTION_TIME};
+
+/// How often a player waiting at the menu or for a round to end checks on the round.
+const ROUND_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+const MENU_PROMPT: &str =
+    "Enter y for a question, r for a round with everyone, l for the leaderboard or n to quit: ";
+const ANSWER_PROMPT: &str = "Enter the correct answer(s) (e.g., 1 or 1,2 or leave blank): ";
 
 pub fn create_raw_socket(port: u16) -> Result<i32, Box<dyn Error>> {
     unsafe {
@@ -62,93 +69,283 @@ pub fn create_raw_socket(port: u16) -> Result<i32, Box<dyn Error>> {
     }
 }
 
+/// What a client sent, or why it sent nothing.
+enum Input {
+    Line(String),
+    TimedOut,
+    Closed,
+}
+
+/// Runs a player's session: they choose a nickname, then answer questions on their own or in
+/// rounds with everyone, until they quit or disconnect.
+///
+/// # Arguments
+/// - `client_fd`: The client's socket, closed when the session ends.
+/// - `questions`: The questions from `file.txt`.
+/// - `quiz`: The players and rounds shared by all connections.
 pub fn handle_telnet_connection(
     client_fd: i32,
     questions: Arc<Vec<Question>>,
+    quiz: Arc<Mutex<Quiz>>,
 ) -> Result<(), Box<dyn Error>> {
+    let mut buffer: [u8; 1024] = [0; 1024];
+    send(client_fd, "Welcome to the Telnet server!\n");
+
+    if let Some(id) = choose_nickname(client_fd, &mut buffer, &quiz) {
+        play(client_fd, &mut buffer, &questions, &quiz, id);
+        quiz.lock().unwrap().leave(id);
+    }
     unsafe {
-        let mut buffer: [u8; 1024] = [0; 1024];
-        let welcome_msg: &str = "Welcome to the Telnet server!\n";
-
-        // write the welcome message to the client
-        write(
-            client_fd,
-            welcome_msg.as_ptr() as *const c_void,
-            welcome_msg.len(),
-        );
-
-        loop {
-            let welcome_msg: &str = "Do you want a question? (y/n): ";
-            write(
-                client_fd,
-                welcome_msg.as_ptr() as *const c_void,
-                welcome_msg.len(),
-            );
-            // Read the input from the client
-            let bytes_read = read(client_fd, buffer.as_mut_ptr() as *mut c_void, buffer.len());
-            if bytes_read <= 0 {
-                break;
+        close(client_fd);
+    }
+    Ok(())
+}
+
+/// Asks for a nickname until the player gives one that is free.
+///
+/// # Returns
+/// The player's id, or `None` if they disconnected.
+fn choose_nickname(client_fd: i32, buffer: &mut [u8], quiz: &Mutex<Quiz>) -> Option<u64> {
+    loop {
+        send(client_fd, "Choose a nickname: ");
+        let Input::Line(nickname) = read_input(client_fd, buffer, None) else {
+            return None;
+        };
+        match quiz.lock().unwrap().join(&nickname) {
+            Ok(id) => {
+                let joined_msg: String = format!("Hello {}! Your score starts at 0.\n", nickname);
+                send(client_fd, &joined_msg);
+                return Some(id);
             }
+            Err(e) => send(client_fd, &format!("{}\n", e)),
+        }
+    }
+}
+
+/// Runs the menu until the player quits or disconnects. While the player is at the menu, rounds
+/// started by anyone are asked as soon as they start.
+fn play(client_fd: i32, buffer: &mut [u8], questions: &[Question], quiz: &Mutex<Quiz>, id: u64) {
+    // The last round the player was asked, so each round is asked once
+    let mut last_round: u64 = quiz.lock().unwrap().round().map_or(0, |r| r.number);
 
-            let input: &str = str::from_utf8(&buffer[0..bytes_read as usize])
-                .unwrap_or_default()
-                .trim();
-
-            match input {
-                "y" => {
-                    let random: usize = rand::thread_rng().gen_range(0..questions.len());
-                    let question: &Question = &questions[random];
-                    let question_txt: String = format!(
-                        "{}\nEnter the correct answer(s) (e.g., 1 or 1,2 or leave blank): ",
-                        question.print()
-                    );
-
-                    write(
-                        client_fd,
-                        question_txt.as_ptr() as *const c_void,
-                        question_txt.len(),
-                    );
-
-                    let bytes_read: usize =
-                        read(client_fd, buffer.as_mut_ptr() as *mut c_void, buffer.len()) as usize;
-
-                    if bytes_read <= 0 {
-                        break;
+    loop {
+        send(client_fd, MENU_PROMPT);
+        let input: String = loop {
+            match read_input(client_fd, buffer, Some(ROUND_CHECK_INTERVAL)) {
+                Input::Line(input) => break input,
+                Input::Closed => return,
+                Input::TimedOut => {
+                    let round: Option<u64> = quiz
+                        .lock()
+                        .unwrap()
+                        .round()
+                        .filter(|r| {
+                            r.number > last_round && !r.is_over() && r.players.contains(&id)
+                        })
+                        .map(|r| r.number);
+                    if let Some(number) = round {
+                        last_round = number;
+                        if !play_round(client_fd, buffer, questions, quiz, id, number) {
+                            return;
+                        }
+                        send(client_fd, MENU_PROMPT);
                     }
+                }
+            }
+        };
 
-                    let answer_input: &str = str::from_utf8(&buffer[0..bytes_read])
-                        .unwrap_or_default()
-                        .trim();
+        match input.as_str() {
+            "y" => {
+                if !ask_question(client_fd, buffer, questions, quiz, id) {
+                    return;
+                }
+            }
+            "r" => {
+                let question: usize = rand::thread_rng().gen_range(0..questions.len());
+                let started: Result<u64, String> =
+                    quiz.lock().unwrap().start_round(question, Instant::now());
+                match started {
+                    Ok(number) => {
+                        println!("Round {} started", number);
+                        last_round = number;
+                        if !play_round(client_fd, buffer, questions, quiz, id, number) {
+                            return;
+                        }
+                    }
+                    Err(e) => send(client_fd, &format!("{}\n", e)),
+                }
+            }
+            "l" => send(client_fd, &quiz.lock().unwrap().leaderboard(id)),
+            "n" => {
+                let goodbye_msg: String = {
+                    let quiz = quiz.lock().unwrap();
+                    let score: u32 = quiz.player(id).map_or(0, |p| p.score);
+                    format!("Your final score is {}. Goodbye!\n", score)
+                };
+                send(client_fd, &goodbye_msg);
+                return;
+            }
+            _ => {
+                let error_msg: &str = "Invalid input. Please enter 'y', 'r', 'l' or 'n'.\n";
+                send(client_fd, error_msg);
+            }
+        }
+    }
+}
 
-                    let answers: Vec<usize> = answer_input
-                        .split(',')
-                        .filter_map(|s| s.parse::<usize>().ok().map(|n| n - 1))
-                        .collect();
+/// Asks the player a random question on their own and scores the answer.
+///
+/// # Returns
+/// False if the player disconnected.
+fn ask_question(
+    client_fd: i32,
+    buffer: &mut [u8],
+    questions: &[Question],
+    quiz: &Mutex<Quiz>,
+    id: u64,
+) -> bool {
+    // Rounds started while the player answers leave them out
+    quiz.lock().unwrap().set_busy(id, true);
 
-                    let answers = question.check_answer(answers);
+    let random: usize = rand::thread_rng().gen_range(0..questions.len());
+    let question: &Question = &questions[random];
+    let question_txt: String = format!(
+        "{}You have {} seconds.\n{}",
+        question.print(),
+        QUESTION_TIME.as_secs(),
+        ANSWER_PROMPT
+    );
+    send(client_fd, &question_txt);
 
-                    write(client_fd, answers.as_ptr() as *const c_void, answers.len());
-                }
-                "n" => {
-                    let goodbye_msg: &str = "Goodbye!\n";
-                    write(
-                        client_fd,
-                        goodbye_msg.as_ptr() as *const c_void,
-                        goodbye_msg.len(),
-                    );
-                    break;
-                }
-                _ => {
-                    let error_msg: &str = "Invalid input. Please enter 'y' or 'n'.\n";
-                    write(
-                        client_fd,
-                        error_msg.as_ptr() as *const c_void,
-                        error_msg.len(),
-                    );
-                }
+    let started: Instant = Instant::now();
+    let (answers, correct): (Vec<usize>, Option<bool>) =
+        match read_input(client_fd, buffer, Some(QUESTION_TIME)) {
+            Input::Line(input) => {
+                let answers: Vec<usize> = question.parse_answers(&input);
+                let correct: bool = question.is_correct(&answers);
+                (answers, Some(correct))
             }
+            Input::TimedOut => (Vec::new(), None),
+            Input::Closed => return false,
+        };
+
+    let mut quiz = quiz.lock().unwrap();
+    quiz.set_busy(id, false);
+    let points: u32 = quiz.record(id, correct, started.elapsed());
+    let score: u32 = quiz.player(id).map_or(0, |p| p.score);
+    drop(quiz);
+
+    let feedback: String = match correct {
+        Some(_) => question.check_answer(answers),
+        None => String::from("\n\x1b[1;31mTime is up!\x1b[0m\n"),
+    };
+    send(
+        client_fd,
+        &format!("{}+{} points. Your score is {}.\n", feedback, points, score),
+    );
+    true
+}
+
+/// Asks the player the question of a round, waits for everyone to answer or the time to run
+/// out, and shows the results.
+///
+/// # Returns
+/// False if the player disconnected.
+fn play_round(
+    client_fd: i32,
+    buffer: &mut [u8],
+    questions: &[Question],
+    quiz: &Mutex<Quiz>,
+    id: u64,
+    number: u64,
+) -> bool {
+    let (index, deadline): (usize, Instant) = match quiz.lock().unwrap().round() {
+        Some(round) if round.number == number => (round.question, round.deadline),
+        _ => return true,
+    };
+    let question: &Question = &questions[index];
+    let remaining: Duration = deadline.saturating_duration_since(Instant::now());
+    let round_txt: String = format!(
+        "\n\x1b[1;35mRound {}: everyone gets the same question. You have {} seconds.\x1b[0m\n{}{}",
+        number,
+        remaining.as_secs_f64().round(),
+        question.print(),
+        ANSWER_PROMPT
+    );
+    send(client_fd, &round_txt);
+
+    let answers: Option<Vec<usize>> = match read_input(client_fd, buffer, Some(remaining)) {
+        Input::Line(input) => Some(question.parse_answers(&input)),
+        Input::TimedOut => None,
+        Input::Closed => return false,
+    };
+    match &answers {
+        Some(answers) => {
+            let correct: bool = question.is_correct(answers);
+            quiz.lock()
+                .unwrap()
+                .answer_round(id, number, correct, Instant::now());
+            send(client_fd, "Waiting for the other players...\n");
         }
-        close(client_fd);
+        None => send(client_fd, "\n\x1b[1;31mTime is up!\x1b[0m\n"),
+    }
+
+    // Whoever notices first that the round is over scores it
+    loop {
+        let mut quiz = quiz.lock().unwrap();
+        if quiz.finish_round(Instant::now()) {
+            let Some(round) = quiz.round().filter(|r| r.number == number) else {
+                return true;
+            };
+            let feedback: String = answers.map_or(String::new(), |a| question.check_answer(a));
+            let results_txt: String = format!(
+                "{}{}{}",
+                feedback,
+                round.print_results(),
+                quiz.leaderboard(id)
+            );
+            drop(quiz);
+            send(client_fd, &results_txt);
+            return true;
+        }
+        drop(quiz);
+        std::thread::sleep(ROUND_CHECK_INTERVAL);
+    }
+}
+
+/// Waits for the client to send a line.
+///
+/// # Arguments
+/// - `timeout`: How long to wait, or `None` to wait for ever.
+fn read_input(client_fd: i32, buffer: &mut [u8], timeout: Option<Duration>) -> Input {
+    let timeout_ms: i32 = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+    unsafe {
+        let mut poll_fd: pollfd = pollfd {
+            fd: client_fd,
+            events: POLLIN,
+            revents: 0,
+        };
+        let ready: i32 = poll(&mut poll_fd, 1, timeout_ms);
+        if ready == 0 {
+            return Input::TimedOut;
+        }
+        if ready < 0 {
+            return Input::Closed;
+        }
+
+        let bytes_read: isize = read(client_fd, buffer.as_mut_ptr() as *mut c_void, buffer.len());
+        if bytes_read <= 0 {
+            return Input::Closed;
+        }
+        let input: String = String::from_utf8_lossy(&buffer[0..bytes_read as usize])
+            .trim()
+            .to_string();
+        Input::Line(input)
+    }
+}
+
+fn send(client_fd: i32, text: &str) {
+    unsafe {
+        write(client_fd, text.as_ptr() as *const c_void, text.len());
     }
-    return Ok(());
 }
@@ -1,2 +1,3 @@
 pub mod connection;
 pub mod question;
+pub mod quiz;
@@ -1,7 +1,8 @@
 use libc::*;
 use practical_2::connection::{create_raw_socket, handle_telnet_connection};
 use practical_2::question::Question;
-use std::sync::Arc;
+use practical_2::quiz::Quiz;
+use std::sync::{Arc, Mutex};
 use tokio::sync::Semaphore;
 
 use std::error::Error;
@@ -23,6 +24,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
     };
 
     let questions: Arc<Vec<Question>> = Arc::new(Question::parse_file().await);
+    if questions.is_empty() {
+        eprintln!("Error: file.txt has no questions");
+        std::process::exit(1);
+    }
+    let quiz: Arc<Mutex<Quiz>> = Arc::new(Mutex::new(Quiz::new()));
     let server_fd = create_raw_socket(port).unwrap();
 
     // Limit the amout of async connections to 5
@@ -30,10 +36,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     loop {
         let client_fd;
-        let permit = semaphore.acquire().await.unwrap();
+        let permit = Arc::clone(&semaphore).acquire_owned().await.unwrap();
         println!(
             "Current concurrent connections: {}",
-            semaphore.available_permits() - 5
+            5 - semaphore.available_permits()
         );
 
         unsafe {
@@ -52,10 +58,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         }
         let question_clone: Arc<Vec<Question>> = Arc::clone(&questions);
-        tokio::spawn(async move {
-            let _ = handle_telnet_connection(client_fd, question_clone);
+        let quiz_clone: Arc<Mutex<Quiz>> = Arc::clone(&quiz);
+        // Sessions block on their socket, so each gets a thread of its own and holds its permit
+        // until it ends
+        tokio::task::spawn_blocking(move || {
+            let _ = handle_telnet_connection(client_fd, question_clone, quiz_clone);
+            drop(permit);
+            println!("Connection dropped");
         });
-        drop(permit);
-        println!("Connection dropped");
     }
 }
@@ -31,9 +31,32 @@ impl Question {
         return output;
     }
 
+    /// Reads the options a player chose, such as `1` or `1,2`, ignoring anything that is not
+    /// one of the question's options.
+    ///
+    /// # Returns
+    /// The indices of the options, in order.
+    pub fn parse_answers(&self, input: &str) -> Vec<usize> {
+        let mut answers: Vec<usize> = input
+            .split(',')
+            .filter_map(|s| s.trim().parse::<usize>().ok())
+            .filter(|&n| n >= 1 && n <= self.options.len())
+            .map(|n| n - 1)
+            .collect();
+        answers.sort_unstable();
+        answers.dedup();
+        answers
+    }
+
+    /// Returns true if the options chosen, as returned by `parse_answers`, are all and only the
+    /// correct ones.
+    pub fn is_correct(&self, answers: &[usize]) -> bool {
+        answers == self.answers
+    }
+
     pub fn check_answer(&self, answers: Vec<usize>) -> String {
         let mut output: String = String::new();
-        if answers != self.answers {
+        if !self.is_correct(&answers) {
             output.push_str(
                 format!("\x1b[1;31mIncorrect\x1b[0m the question answers are:\n").as_str(),
             );
@@ -66,6 +89,12 @@ impl Question {
             }
         };
 
+        Question::parse(&file)
+    }
+
+    /// Reads questions in the format of `file.txt`: a line starting with `?` is a question, and
+    /// the lines after it starting with `+` or `-` are its correct and wrong options.
+    pub fn parse(file: &str) -> Vec<Question> {
         let mut questions: Vec<Question> = Vec::new();
 
         let lines: Vec<String> = file.lines().map(|s| s.to_string()).collect();
@@ -83,7 +112,6 @@ impl Question {
                         let marker: &Option<char> = &lines[j].chars().nth(0);
                         match marker {
                             Some('?') => {
-                                questions.push(question);
                                 break;
                             }
                             Some('-') => {
@@ -104,6 +132,7 @@ impl Question {
                             }
                         }
                     }
+                    questions.push(question);
                     i = j;
                     continue;
                 }
@@ -117,3 +146,20 @@ impl Question {
         return questions;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let questions: Vec<Question> = Question::parse("?First\n-a\n+b\n+c\n\n?Last\n+yes\n-no\n");
+        assert_eq!(questions.len(), 2);
+        assert_eq!(questions[0].answers, vec![1, 2]);
+        assert_eq!(questions[1].options, vec!["yes", "no"]);
+
+        assert_eq!(questions[0].parse_answers("3, 2,2,0,4,x"), vec![1, 2]);
+        assert!(questions[0].is_correct(&questions[0].parse_answers("3,2")));
+        assert!(!questions[1].is_correct(&questions[1].parse_answers("")));
+    }
+}
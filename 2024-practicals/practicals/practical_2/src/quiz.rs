@@ -0,0 +1,373 @@
+//! The state shared by all connections: the players with their scores, and the rounds in which
+//! everyone answers the same question.
+//!
+//! Questions are referred to by their index in the list read from `file.txt`, and answers are
+//! checked by the connections, so the quiz only keeps who answered what and when.
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long players have to answer a question.
+pub const QUESTION_TIME: Duration = Duration::from_secs(20);
+/// The points for a correct answer.
+pub const CORRECT_POINTS: u32 = 10;
+/// The most extra points for answering quickly, given in full for an instant answer and
+/// nothing for an answer at the deadline.
+pub const SPEED_POINTS: u32 = 10;
+pub const MAX_NICKNAME_LENGTH: usize = 16;
+
+pub struct Player {
+    pub nickname: String,
+    pub score: u32,
+    /// How many questions the player was asked.
+    pub questions: u32,
+    pub correct: u32,
+    /// True while the player answers a question on their own, when rounds leave them out.
+    pub busy: bool,
+}
+
+/// A question all the players answer at the same time.
+pub struct Round {
+    pub number: u64,
+    /// The index of the question.
+    pub question: usize,
+    pub deadline: Instant,
+    /// The players at the menu when the round started, who are the ones asked.
+    pub players: Vec<u64>,
+    /// Whether each player's answer was correct, and how long they took.
+    answers: HashMap<u64, (bool, Duration)>,
+    started: Instant,
+    /// The nickname, correctness and points of each player once the round is over. `None` for
+    /// the correctness of players who did not answer.
+    pub results: Option<Vec<(String, Option<bool>, u32)>>,
+}
+
+impl Round {
+    pub fn is_over(&self) -> bool {
+        self.results.is_some()
+    }
+
+    pub fn has_answered(&self, id: u64) -> bool {
+        self.answers.contains_key(&id)
+    }
+
+    /// Returns the results of the round, best first.
+    pub fn print_results(&self) -> String {
+        let mut output: String = format!("\x1b[1;35mResults of round {}:\x1b[0m\n", self.number);
+        for (nickname, correct, points) in self.results.iter().flatten() {
+            let result: &str = match correct {
+                Some(true) => "\x1b[1;32mcorrect\x1b[0m",
+                Some(false) => "\x1b[1;31mwrong\x1b[0m",
+                None => "no answer",
+            };
+            output.push_str(&format!(
+                "  {:<width$} {} +{}\n",
+                nickname,
+                result,
+                points,
+                width = MAX_NICKNAME_LENGTH
+            ));
+        }
+        output
+    }
+}
+
+#[derive(Default)]
+pub struct Quiz {
+    players: HashMap<u64, Player>,
+    next_id: u64,
+    /// The current round, or the last one once it is over.
+    round: Option<Round>,
+}
+
+impl Quiz {
+    pub fn new() -> Self {
+        Quiz::default()
+    }
+
+    /// Adds a player with a nickname no connected player has.
+    ///
+    /// # Returns
+    /// The player's id, or why the nickname cannot be used.
+    pub fn join(&mut self, nickname: &str) -> Result<u64, String> {
+        if nickname.is_empty()
+            || nickname.chars().count() > MAX_NICKNAME_LENGTH
+            || !nickname
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!(
+                "Nicknames have 1 to {} letters, digits, '_' or '-'.",
+                MAX_NICKNAME_LENGTH
+            ));
+        }
+        if self
+            .players
+            .values()
+            .any(|p| p.nickname.eq_ignore_ascii_case(nickname))
+        {
+            return Err(format!("The nickname {} is taken.", nickname));
+        }
+
+        self.next_id += 1;
+        self.players.insert(
+            self.next_id,
+            Player {
+                nickname: nickname.to_string(),
+                score: 0,
+                questions: 0,
+                correct: 0,
+                busy: false,
+            },
+        );
+        Ok(self.next_id)
+    }
+
+    /// Removes a player who disconnected, with their score.
+    pub fn leave(&mut self, id: u64) {
+        self.players.remove(&id);
+    }
+
+    pub fn player(&self, id: u64) -> Option<&Player> {
+        self.players.get(&id)
+    }
+
+    /// Marks the player as answering a question on their own, or as back at the menu.
+    pub fn set_busy(&mut self, id: u64, busy: bool) {
+        if let Some(player) = self.players.get_mut(&id) {
+            player.busy = busy;
+        }
+    }
+
+    /// Scores a question the player answered on their own.
+    ///
+    /// # Arguments
+    /// - `correct`: Whether the answer was correct, `None` if the time ran out.
+    /// - `elapsed`: How long the player took.
+    ///
+    /// # Returns
+    /// The points the player got.
+    pub fn record(&mut self, id: u64, correct: Option<bool>, elapsed: Duration) -> u32 {
+        let points: u32 = points(correct == Some(true), elapsed);
+        if let Some(player) = self.players.get_mut(&id) {
+            player.questions += 1;
+            player.score += points;
+            if correct == Some(true) {
+                player.correct += 1;
+            }
+        }
+        points
+    }
+
+    /// Starts a round with the question for every player at the menu. Players answering a
+    /// question on their own would not see the round, so they are left out of it.
+    ///
+    /// # Returns
+    /// The round's number, or why it cannot start.
+    pub fn start_round(&mut self, question: usize, now: Instant) -> Result<u64, String> {
+        let number: u64 = match &self.round {
+            Some(round) if !round.is_over() => {
+                return Err(format!("Round {} is still running.", round.number))
+            }
+            Some(round) => round.number + 1,
+            None => 1,
+        };
+        self.round = Some(Round {
+            number,
+            question,
+            deadline: now + QUESTION_TIME,
+            players: self
+                .players
+                .iter()
+                .filter(|(_, player)| !player.busy)
+                .map(|(id, _)| *id)
+                .collect(),
+            answers: HashMap::new(),
+            started: now,
+            results: None,
+        });
+        Ok(number)
+    }
+
+    pub fn round(&self) -> Option<&Round> {
+        self.round.as_ref()
+    }
+
+    /// Records a player's answer to the running round, unless the round is over or they were not
+    /// asked.
+    pub fn answer_round(&mut self, id: u64, number: u64, correct: bool, now: Instant) {
+        if let Some(round) = self.round.as_mut() {
+            if round.number == number && !round.is_over() && round.players.contains(&id) {
+                round
+                    .answers
+                    .entry(id)
+                    .or_insert((correct, now - round.started));
+            }
+        }
+    }
+
+    /// Ends the running round once its time is up or every player still connected has
+    /// answered, and adds the points to the scores.
+    ///
+    /// # Returns
+    /// True if the round is over.
+    pub fn finish_round(&mut self, now: Instant) -> bool {
+        let Some(round) = self.round.as_mut() else {
+            return true;
+        };
+        if round.is_over() {
+            return true;
+        }
+        let all_answered: bool = round
+            .players
+            .iter()
+            .filter(|id| self.players.contains_key(id))
+            .all(|id| round.answers.contains_key(id));
+        if now < round.deadline && !all_answered {
+            return false;
+        }
+
+        let mut results: Vec<(String, Option<bool>, u32)> = Vec::new();
+        for id in &round.players {
+            let Some(player) = self.players.get_mut(id) else {
+                continue;
+            };
+            let answer: Option<&(bool, Duration)> = round.answers.get(id);
+            let points: u32 = answer.map_or(0, |&(correct, elapsed)| points(correct, elapsed));
+            player.questions += 1;
+            player.score += points;
+            if answer.is_some_and(|&(correct, _)| correct) {
+                player.correct += 1;
+            }
+            results.push((
+                player.nickname.clone(),
+                answer.map(|&(correct, _)| correct),
+                points,
+            ));
+        }
+        results.sort_by_key(|(nickname, _, points)| (Reverse(*points), nickname.clone()));
+        round.results = Some(results);
+        true
+    }
+
+    /// Returns the connected players by score, best first, marking the player asking.
+    pub fn leaderboard(&self, id: u64) -> String {
+        let mut players: Vec<(&u64, &Player)> = self.players.iter().collect();
+        players.sort_by(|(_, a), (_, b)| b.score.cmp(&a.score).then(a.nickname.cmp(&b.nickname)));
+
+        let mut output: String = String::from("\x1b[1;34mLeaderboard:\x1b[0m\n");
+        for (rank, (player_id, player)) in players.iter().enumerate() {
+            output.push_str(&format!(
+                "{:>3}. {:<width$} {:>5} points ({}/{} correct){}\n",
+                rank + 1,
+                player.nickname,
+                player.score,
+                player.correct,
+                player.questions,
+                if **player_id == id { " <- you" } else { "" },
+                width = MAX_NICKNAME_LENGTH
+            ));
+        }
+        output
+    }
+}
+
+/// Returns the points for an answer that took `elapsed`.
+fn points(correct: bool, elapsed: Duration) -> u32 {
+    if !correct {
+        return 0;
+    }
+    let remaining: f64 = QUESTION_TIME.saturating_sub(elapsed).as_secs_f64();
+    CORRECT_POINTS + (SPEED_POINTS as f64 * remaining / QUESTION_TIME.as_secs_f64()).round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_players() {
+        let mut quiz: Quiz = Quiz::new();
+        let ann: u64 = quiz.join("ann").unwrap();
+        assert!(quiz.join("ANN").is_err());
+        assert!(quiz.join("").is_err());
+        assert!(quiz.join("bob smith").is_err());
+        let bob: u64 = quiz.join("bob").unwrap();
+
+        assert_eq!(quiz.record(ann, Some(true), Duration::ZERO), 20);
+        assert_eq!(quiz.record(ann, Some(true), QUESTION_TIME), 10);
+        assert_eq!(quiz.record(bob, Some(false), Duration::ZERO), 0);
+        assert_eq!(quiz.record(bob, None, QUESTION_TIME), 0);
+        assert_eq!(quiz.player(ann).unwrap().score, 30);
+        assert_eq!(quiz.player(bob).unwrap().questions, 2);
+
+        let leaderboard: String = quiz.leaderboard(bob);
+        assert!(leaderboard.find("ann").unwrap() < leaderboard.find("bob").unwrap());
+        assert!(leaderboard.contains("(0/2 correct) <- you"));
+
+        // Nicknames are free again once their player leaves
+        quiz.leave(ann);
+        assert!(quiz.join("ann").is_ok());
+    }
+
+    #[test]
+    fn test_rounds() {
+        let mut quiz: Quiz = Quiz::new();
+        let ann: u64 = quiz.join("ann").unwrap();
+        let bob: u64 = quiz.join("bob").unwrap();
+        let start: Instant = Instant::now();
+
+        assert_eq!(quiz.start_round(3, start), Ok(1));
+        assert!(quiz.start_round(4, start).is_err());
+        // Players who join later wait for the next round
+        let cat: u64 = quiz.join("cat").unwrap();
+        assert!(!quiz.round().unwrap().players.contains(&cat));
+
+        quiz.answer_round(ann, 1, true, start + Duration::from_secs(5));
+        assert!(!quiz.finish_round(start + Duration::from_secs(5)));
+        quiz.answer_round(bob, 1, false, start + Duration::from_secs(6));
+        quiz.answer_round(bob, 1, true, start + Duration::from_secs(7));
+        assert!(quiz.finish_round(start + Duration::from_secs(7)));
+
+        let round: &Round = quiz.round().unwrap();
+        assert_eq!(round.question, 3);
+        assert_eq!(
+            round.results,
+            Some(vec![
+                (String::from("ann"), Some(true), 18),
+                (String::from("bob"), Some(false), 0)
+            ])
+        );
+        assert_eq!(quiz.player(ann).unwrap().score, 18);
+        assert_eq!(quiz.player(cat).unwrap().questions, 0);
+
+        // A round ends at its deadline even if players have not answered
+        assert_eq!(quiz.start_round(0, start), Ok(2));
+        quiz.leave(bob);
+        assert!(!quiz.finish_round(start));
+        assert!(quiz.finish_round(start + QUESTION_TIME));
+        assert!(quiz.round().unwrap().print_results().contains("no answer"));
+    }
+
+    #[test]
+    fn test_busy_players_skip_rounds() {
+        let mut quiz: Quiz = Quiz::new();
+        let ann: u64 = quiz.join("ann").unwrap();
+        let bob: u64 = quiz.join("bob").unwrap();
+        let start: Instant = Instant::now();
+
+        // Bob is answering a question on their own, so the round does not wait for them
+        quiz.set_busy(bob, true);
+        assert_eq!(quiz.start_round(0, start), Ok(1));
+        assert_eq!(quiz.round().unwrap().players, vec![ann]);
+        quiz.answer_round(bob, 1, true, start);
+        quiz.answer_round(ann, 1, true, start);
+        assert!(quiz.finish_round(start));
+        assert_eq!(quiz.player(bob).unwrap().questions, 0);
+
+        // Back at the menu they are asked the next round
+        quiz.set_busy(bob, false);
+        assert_eq!(quiz.start_round(1, start), Ok(2));
+        assert!(quiz.round().unwrap().players.contains(&bob));
+    }
+}
@@ -10,7 +10,7 @@ use crate::server::SharedState;
 use crate::{ContentType, HttpCode, HttpMethod, Request};
 use colored::Colorize;
 use log::{error, info};
-use std::collections::HashMap;
+use serde::Deserialize;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::fs::{self, File};
@@ -51,16 +51,11 @@ async fn get_bytes(
     file_path: PathBuf,
     route_name: &str,
 ) -> Vec<u8> {
-    return match state.lock().await.get_cached_content(route_name).await {
+    let mut state = state.lock().await;
+    match state.get_cached_content(route_name).await {
         Some(b) => b,
-        None => {
-            state
-                .lock()
-                .await
-                .read_and_cache_page(&file_path, route_name)
-                .await
-        }
-    };
+        None => state.read_and_cache_page(&file_path, route_name).await,
+    }
 }
 
 /// Handles incoming HTTP requests and routes them to the appropriate method-specific handler.
@@ -153,6 +148,13 @@ async fn handle_get(request: Request, state: Arc<Mutex<SharedState>>) -> Respons
     response
 }
 
+/// The JSON body of `/signup` and `/login` requests.
+#[derive(Deserialize)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
 /// Handles HTTP POST requests for specific routes like `/signup` and `/login`.
 ///
 /// # Arguments
@@ -171,7 +173,7 @@ async fn handle_post(request: Request, state: Arc<Mutex<SharedState>>) -> Respon
     if request.uri == "/signup" {
         // parse the JSON into a hashmap
         info!("POST /signup from");
-        let user: HashMap<String, String> = match serde_json::from_str(&request.body) {
+        let user: Credentials = match serde_json::from_str(&request.body) {
             Ok(u) => u,
             Err(_) => {
                 error!("Failed to parse JSON in request from");
@@ -192,7 +194,7 @@ async fn handle_post(request: Request, state: Arc<Mutex<SharedState>>) -> Respon
         let session_id: Uuid = match state
             .lock()
             .await
-            .add_user(user["username"].clone(), user["password"].clone())
+            .add_user(user.username, user.password)
             .await
         {
             Ok(s) => s,
@@ -223,7 +225,7 @@ async fn handle_post(request: Request, state: Arc<Mutex<SharedState>>) -> Respon
             .code(HttpCode::Ok);
     } else if request.uri == "/login" {
         info!("POST /login from ");
-        let user: HashMap<String, String> = match serde_json::from_str(&request.body) {
+        let user: Credentials = match serde_json::from_str(&request.body) {
             Ok(u) => u,
             Err(_) => {
                 error!("Failed to parse JSON");
@@ -242,7 +244,7 @@ async fn handle_post(request: Request, state: Arc<Mutex<SharedState>>) -> Respon
             }
         };
 
-        let input_username: &str = &user["username"];
+        let input_username: &str = &user.username;
 
         let session_id: Uuid = match state
             .lock()
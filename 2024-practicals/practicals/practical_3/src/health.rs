@@ -0,0 +1,104 @@
+//! Health checks answered on the TLS listener.
+//!
+//! `/healthz` answers as long as the server is accepting connections. `/readyz` reports the
+//! server ready only if PostgreSQL answers a query, Redis answers a `PING` and the TLS certificate
+//! the server presents has not expired. The certificate check and the report come from the
+//! `health` crate in `shared/health`.
+use crate::response::Response;
+use crate::server::SharedState;
+use crate::{ContentType, HttpCode, HttpMethod, Protocol, Request};
+use health::Check;
+use log::{error, info};
+use rustls::pki_types::CertificateDer;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// How long a check waits for the shared state before failing.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs every readiness check.
+///
+/// # Arguments
+/// - `state`: A shared, thread-safe state holding the user store and the page cache.
+/// - `certificate`: The certificate the TLS listener presents.
+///
+/// # Returns
+/// The result of each check, in a fixed order.
+pub async fn readiness_checks(
+    state: &Arc<Mutex<SharedState>>,
+    certificate: &CertificateDer<'_>,
+) -> Vec<Check> {
+    let mut checks: Vec<Check> = match tokio::time::timeout(LOCK_TIMEOUT, state.lock()).await {
+        Ok(mut state) => vec![
+            Check {
+                name: "postgres",
+                result: state.users.ping().await,
+            },
+            Check {
+                name: "redis",
+                result: state.cache.ping(),
+            },
+        ],
+        Err(_) => ["postgres", "redis"]
+            .into_iter()
+            .map(|name| Check {
+                name,
+                result: Err(String::from("Timed out waiting for the shared state")),
+            })
+            .collect(),
+    };
+
+    checks.push(Check::certificate(certificate));
+    checks
+}
+
+/// Answers `/healthz` and `/readyz`.
+///
+/// # Arguments
+/// - `request`: The incoming HTTP request.
+/// - `state`: A shared, thread-safe state holding the user store and the page cache.
+/// - `certificate`: The certificate the TLS listener presents.
+/// - `shutting_down`: Whether the server is shutting down, which makes it not ready.
+///
+/// # Returns
+/// The response, or `None` if the request is for another route.
+pub async fn handle_health(
+    request: &Request,
+    state: &Arc<Mutex<SharedState>>,
+    certificate: &CertificateDer<'_>,
+    shutting_down: bool,
+) -> Option<Response> {
+    if request.uri != "/healthz" && request.uri != "/readyz" {
+        return None;
+    }
+
+    let response = Response::new(Protocol::Http, HttpCode::Ok, ContentType::Json, false);
+    if !matches!(request.method, HttpMethod::GET) {
+        info!(target: "request_logger","{} {} status: 405", request.method, request.uri);
+        return Some(response.code(HttpCode::MethodNotAllowed));
+    }
+
+    if request.uri == "/healthz" {
+        return Some(response.body(health::liveness().to_string().into_bytes()));
+    }
+
+    let checks: Vec<Check> = readiness_checks(state, certificate).await;
+    for check in &checks {
+        if let Err(e) = &check.result {
+            error!(target: "error_logger","Readiness check {} failed: {}", check.name, e);
+        }
+    }
+    let (ready, body) = health::report(&checks, shutting_down);
+    info!(target: "request_logger","GET /readyz ready: {}", ready);
+
+    Some(
+        response
+            .code(if ready {
+                HttpCode::Ok
+            } else {
+                HttpCode::ServiceUnavailable
+            })
+            .body(body.to_string().into_bytes()),
+    )
+}
@@ -4,6 +4,8 @@ pub use crate::error::my_errors::{ErrorType, Logger};
 pub mod api;
 pub use api::*;
 
+pub mod health;
+
 pub mod redis_connection;
 pub mod server;
 
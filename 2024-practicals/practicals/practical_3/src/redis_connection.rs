@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::{Duration, Instant};
 
 use log::{error, info};
 use redis::Commands;
@@ -38,30 +40,74 @@ pub fn set_up_redis() -> Result<redis::Connection, Box<dyn std::error::Error>> {
     Ok(client.get_connection()?)
 }
 
+/// Where pages are cached.
+pub enum PageCache {
+    Redis(redis::Connection),
+    /// Keeps pages in the process with their expiry time, for tests and running without Redis.
+    Memory(HashMap<String, (Instant, Vec<u8>)>),
+}
+
+impl PageCache {
+    pub fn memory() -> Self {
+        PageCache::Memory(HashMap::new())
+    }
+
+    /// Returns the page cached under `key`, or `None` if there is none or Redis failed.
+    fn get(&mut self, key: &str) -> Option<Vec<u8>> {
+        match self {
+            PageCache::Redis(connection) => match connection.get::<_, String>(key) {
+                Ok(content) => Some(content.into_bytes()),
+                Err(_) => None,
+            },
+            PageCache::Memory(pages) => match pages.get(key) {
+                Some((expires, content)) if *expires > Instant::now() => Some(content.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Checks that Redis answers a `PING`. The in-memory cache is always available.
+    pub fn ping(&mut self) -> Result<String, String> {
+        match self {
+            PageCache::Redis(connection) => redis::cmd("PING")
+                .query::<String>(connection)
+                .map_err(|e| e.to_string()),
+            PageCache::Memory(_) => Ok(String::from("In memory")),
+        }
+    }
+
+    fn set_ex(&mut self, key: &str, content: &str, seconds: u64) -> redis::RedisResult<()> {
+        match self {
+            PageCache::Redis(connection) => connection.set_ex(key, content, seconds),
+            PageCache::Memory(pages) => {
+                let expires: Instant = Instant::now() + Duration::from_secs(seconds);
+                pages.insert(key.to_string(), (expires, content.as_bytes().to_vec()));
+                Ok(())
+            }
+        }
+    }
+}
+
 // caches a files content and returns the string
-pub async fn read_and_cache_page(
-    redis_connection: &mut redis::Connection,
-    path: &Path,
-    route_name: &str,
-) -> Vec<u8> {
+pub async fn read_and_cache_page(cache: &mut PageCache, path: &Path, route_name: &str) -> Vec<u8> {
     let content: String = match fs::read_to_string(path.to_path_buf()).await {
         Ok(content) => content,
-        Err(_) => fs::read_to_string("static/404.html")
-            .await
-            .expect("404 Not Found"),
+        Err(_) => match fs::read_to_string("static/404.html").await {
+            Ok(content) => content,
+            Err(e) => {
+                error!(target:"error_logger","Failed to read {:?}: {}", path, e);
+                String::new()
+            }
+        },
     };
 
     // set for 10 minuets
-    let _: () = redis_connection.set_ex(route_name, &content, 600).unwrap();
+    if let Err(e) = cache.set_ex(route_name, &content, 600) {
+        error!(target:"error_logger","Failed to cache {}: {}", route_name, e);
+    }
     content.as_bytes().to_vec()
 }
 
-pub async fn get_cached_content(
-    redis_connection: &mut redis::Connection,
-    route_name: &str,
-) -> Option<Vec<u8>> {
-    match redis_connection.get::<_, String>(route_name) {
-        Ok(content) => Some(content.as_bytes().to_vec()),
-        Err(_) => None,
-    }
+pub async fn get_cached_content(cache: &mut PageCache, route_name: &str) -> Option<Vec<u8>> {
+    cache.get(route_name)
 }
@@ -0,0 +1,125 @@
+//! End-to-end tests that send real TLS requests to a server running in-process.
+mod common;
+
+use common::{TestResponse, TestServer};
+
+#[tokio::test]
+async fn test_routing() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    let index = client.get("/").await;
+    assert_eq!(index.status, 200);
+    assert!(index
+        .header("Content-Type")
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(index.text().contains("Hi from Rust"));
+
+    let home = client.get("/home").await;
+    assert_eq!(home.status, 200);
+    assert!(home.text().contains("<h1>FERRIS!</h1>"));
+
+    let coffee = client.get("/coffee").await;
+    assert_eq!(coffee.status, 418);
+    assert!(coffee.text().contains("I'm a Teapot"));
+
+    for method in ["PUT", "PATCH", "DELETE"] {
+        assert_eq!(client.request(method, "/", b"").await.status, 405);
+    }
+}
+
+#[tokio::test]
+async fn test_sessions() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+    let credentials = r#"{"username":"ferris","password":"crab"}"#;
+
+    let signup = client.post_json("/signup", credentials).await;
+    assert_eq!(signup.status, 200);
+    assert_eq!(signup.text(), "New user successfully created!");
+    let session = client.cookie.clone().expect("signup sets a session cookie");
+    assert!(session.starts_with("session="));
+
+    // Signing up twice with the same username fails
+    let again = client.post_json("/signup", credentials).await;
+    assert_eq!(again.status, 500);
+
+    // Logging in from another client gets the session of the user
+    let mut other = server.client();
+    let login = other.post_json("/login", credentials).await;
+    assert_eq!(login.status, 200);
+    assert_eq!(other.cookie, Some(session));
+
+    let unknown = server
+        .client()
+        .post_json("/login", r#"{"username":"nobody","password":"crab"}"#)
+        .await;
+    assert_eq!(unknown.status, 400);
+    assert!(unknown.header("Set-Cookie").is_none());
+}
+
+#[tokio::test]
+async fn test_error_paths() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    // Unknown pages are answered with the error page
+    let missing = client.get("/missing").await;
+    assert_eq!(missing.status, 400);
+    assert!(missing.text().contains("400 Error"));
+
+    let invalid = client.post_json("/signup", "{not json").await;
+    assert_eq!(
+        (invalid.status, invalid.text().as_str()),
+        (400, "Invalid JSON.")
+    );
+    let incomplete = client
+        .post_json("/signup", r#"{"username":"ferris"}"#)
+        .await;
+    assert_eq!(incomplete.status, 400);
+    assert!(client.cookie.is_none());
+
+    let unknown = client.post_json("/unknown", "{}").await;
+    assert_eq!(unknown.status, 400);
+
+    // A malformed request is refused without stopping the server
+    let malformed = TestResponse::parse(&client.send_raw(b"GARBAGE\r\n\r\n").await);
+    assert_eq!(malformed.status, 400);
+    assert_eq!(client.get("/").await.status, 200);
+}
+
+#[tokio::test]
+async fn test_shutdown() {
+    let server = TestServer::start().await;
+    assert_eq!(server.client().get("/").await.status, 200);
+
+    server.shutdown.shutdown();
+    let address = server.address;
+    server.finished().await;
+
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+#[tokio::test]
+async fn test_health_checks() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    let health = client.get("/healthz").await;
+    assert_eq!(
+        (health.status, health.text().as_str()),
+        (200, r#"{"status":"ok"}"#)
+    );
+
+    let ready = client.get("/readyz").await;
+    assert_eq!(ready.status, 200);
+    assert_eq!(ready.header("Content-Type"), Some("application/json"));
+    let ready: serde_json::Value = serde_json::from_str(&ready.text()).unwrap();
+    assert_eq!(ready["status"], "ready");
+    for check in ["postgres", "redis", "certificate"] {
+        assert_eq!(ready["checks"][check]["status"], "ok");
+    }
+
+    assert_eq!(client.post_json("/healthz", "{}").await.status, 405);
+}
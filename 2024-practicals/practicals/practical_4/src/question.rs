@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use templates::{TemplateError, Templates};
 use tokio::fs;
 use uuid::Uuid;
 
@@ -125,79 +126,25 @@ impl Question {
         questions
     }
 
-    pub fn generate_html_page(&self) -> Vec<u8> {
-        let options = self
-            .options
-            .iter()
-            .enumerate()
-            .map(|(i, opt)| {
-                format!(
-                    "<div><input type='checkbox' name='answer' value='{}' /> {} </div>",
-                    i + 1,
-                    opt
-                )
-            })
-            .collect::<String>();
-
-        format!(
-            r#"
-<!DOCTYPE html>
-<html lang="en">
-<head>
-    <meta charset="UTF-8">
-    <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>Question</title>
-    <link href="https://cdn.jsdelivr.net/npm/bootstrap@5.3.0-alpha1/dist/css/bootstrap.min.css" rel="stylesheet">
-</head>
-<body>
-<div class="container mt-5">
-    <h1>{}</h1>
-    <form id="question-form" class="mt-4">
-        <input type="hidden" name="uuid" id="uuid" value="{}" />
-        {}
-        <button type="button" class="btn btn-primary mt-3" onclick="submitAnswer()">Submit Answer</button>
-    </form>
-    <div id="response" class="mt-4"></div>
-</div>
-<script>
-    function submitAnswer() {{
-        // Get the UUID
-        const uuid = document.getElementById('uuid').value;
-
-        // Get all checked checkboxes
-        const checkedAnswers = Array.from(
-            document.querySelectorAll('input[name="answer"]:checked')
-        ).map(input => Number(input.value));
-
-        // Create the JSON payload
-        const payload = {{
-            uuid: uuid,
-            answers: checkedAnswers,
-        }};
-
-        // Send the JSON payload via fetch
-        fetch('/answer', {{
-            method: 'POST',
-            headers: {{
-                'Content-Type': 'application/json',
-            }},
-            body: JSON.stringify(payload),
-        }})
-        .then(response => response.text())
-        .then(data => {{
-            document.getElementById('response').innerHTML = `
-                <div class="alert alert-info">${{data}}</div>`;
-        }})
-        .catch(err => {{
-            document.getElementById('response').innerHTML = `
-                <div class="alert alert-danger">Error: ${{err}}</div>`;
-        }});
-    }}
-</script>
-</body>
-</html>
-"#,
-            self.question, self.question_id, options
-        ).as_bytes().to_vec()
+    /// Renders the question as an HTML page whose form carries `csrf_token`.
+    ///
+    /// # Arguments
+    /// - `templates`: The templates the page is rendered from.
+    /// - `csrf_token`: The token the page must send back with its answer.
+    pub fn generate_html_page(
+        &self,
+        templates: &Templates,
+        csrf_token: &str,
+    ) -> Result<Vec<u8>, TemplateError> {
+        let page: String = templates.render(
+            "question.html",
+            &serde_json::json!({
+                "question": self.question,
+                "question_id": self.question_id.to_string(),
+                "csrf_token": csrf_token,
+                "options": self.options,
+            }),
+        )?;
+        Ok(page.into_bytes())
     }
 }
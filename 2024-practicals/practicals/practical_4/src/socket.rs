@@ -10,7 +10,6 @@ pub mod connection {
     use std::error::Error;
     use std::net::TcpListener as StdTcpListener;
     use std::os::unix::io::FromRawFd;
-    use std::path::PathBuf;
     use tokio::net::TcpListener;
 
     fn create_raw_socket(port: u16) -> Result<i32, Box<dyn Error>> {
@@ -69,38 +68,68 @@ pub mod connection {
         }
     }
 
-    pub async fn load_tls_config() -> Result<ServerConfig, Box<dyn std::error::Error>> {
-        let cert_path: PathBuf = PathBuf::from("server.crt");
-        let key_path: PathBuf = PathBuf::from("server.key");
+    /// The PEM certificate the server presents.
+    pub const CERT_PATH: &str = "server.crt";
+    /// The PEM private key of the certificate.
+    pub const KEY_PATH: &str = "server.key";
 
-        let certs = vec![match CertificateDer::from_pem_file(&cert_path) {
+    /// A TLS configuration and the certificate it presents.
+    pub struct TlsIdentity {
+        pub config: ServerConfig,
+        /// The DER encoded certificate the server presents.
+        pub certificate: CertificateDer<'static>,
+    }
+
+    impl TlsIdentity {
+        /// Builds a TLS configuration for the certificate and key.
+        pub fn new(
+            certificate: CertificateDer<'static>,
+            key: PrivateKeyDer<'static>,
+        ) -> Result<Self, rustls::Error> {
+            let config = ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(vec![certificate.clone()], key)?;
+
+            Ok(TlsIdentity {
+                config,
+                certificate,
+            })
+        }
+    }
+
+    /// Loads the PEM certificate and key at `cert_path` and `key_path`.
+    pub async fn load_tls_config(
+        cert_path: &str,
+        key_path: &str,
+    ) -> Result<TlsIdentity, Box<dyn std::error::Error>> {
+        let cert = match CertificateDer::from_pem_file(cert_path) {
             Ok(c) => c,
-            Err(_) => {
-                error!(target:"error_logger","Cannot open certificate file");
-                std::process::exit(1);
+            Err(e) => {
+                error!(target:"error_logger","Cannot open certificate file {}", cert_path);
+                return Err(Box::new(e));
             }
-        }];
+        };
 
-        let key = match PrivateKeyDer::from_pem_file(&key_path) {
+        let key = match PrivateKeyDer::from_pem_file(key_path) {
             Ok(k) => k,
-            Err(_) => {
-                error!(target: "error_logger","Cannot open pk file");
-                std::process::exit(1);
+            Err(e) => {
+                error!(target: "error_logger","Cannot open pk file {}", key_path);
+                return Err(Box::new(e));
             }
         };
 
-        let config = ServerConfig::builder()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)?;
+        let identity = TlsIdentity::new(cert, key)?;
 
         info!(target: "request_logger","TLS certificate and keys configured");
-        Ok(config)
+        Ok(identity)
     }
 
     /// Converts a raw libc socket into a tokio TcpListener
     pub fn get_listener(port: u16) -> Result<TcpListener, Box<dyn std::error::Error>> {
         let raw_fd = create_raw_socket(port)?;
         let listener: StdTcpListener = unsafe { StdTcpListener::from_raw_fd(raw_fd) };
+        // tokio only accepts sockets in non-blocking mode
+        listener.set_nonblocking(true)?;
         Ok(TcpListener::from_std(listener)?)
     }
 }
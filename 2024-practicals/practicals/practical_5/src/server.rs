@@ -24,6 +24,9 @@ use uuid::Uuid;
 /// The default port used by the server if none is specified.
 const DEFAULT_PORT: u16 = 7878;
 
+/// How long a shutdown waits for the open connections to finish.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 pub struct State {
     pub questions: HashMap<Uuid, Question>,
     pub ids: Vec<Uuid>,
@@ -95,8 +98,6 @@ async fn start_server(
             println!("Recieved shutdown request");
             println!("Waiting for tasks to finish");
             shutdown_signal.notify_one();
-            println!("Tasks complete, server shutdown started");
-            std::process::exit(0);
         }
     });
 
@@ -108,7 +109,14 @@ async fn start_server(
                 println!("Server shutdown signal recieved.");
         }
     }
-    while connections.clone().available_permits() != 15 {}
+    // Let the open connections finish, but do not wait for them forever
+    if timeout(SHUTDOWN_GRACE_PERIOD, connections.acquire_many(15))
+        .await
+        .is_err()
+    {
+        println!("Closing the connections that did not finish");
+    }
+    println!("Server shut down");
     Ok(())
 }
 
@@ -45,6 +45,23 @@ pub mod question_api {
     /// # Returns
     /// A `Response` object generated based on the request.
     pub async fn handle_response(request: Request, questions: Arc<Mutex<State>>) -> Response {
+        // Every method but GET changes state and must carry the session's token
+        if !matches!(request.method, HttpMethod::GET)
+            && !questions
+                .lock()
+                .await
+                .sessions
+                .verify(session_id(&request), supplied_token(&request).as_deref())
+        {
+            error!(target: "error_logger","Missing or invalid CSRF token for {} {}", request.method, request.uri);
+            info!(target: "request_logger","{} {} status: 403", request.method, request.uri);
+            return Response::default()
+                .await
+                .code(HttpCode::Forbidden)
+                .content_type(ContentType::Text)
+                .body(String::from("Invalid CSRF token").into());
+        }
+
         match request.method {
             HttpMethod::GET => handle_get(request, questions).await,
             HttpMethod::POST => handle_post(request, questions).await,
@@ -54,6 +71,21 @@ pub mod question_api {
         }
     }
 
+    /// Returns the session ID from the request's session cookie.
+    fn session_id(request: &Request) -> Option<&str> {
+        csrf::cookie_value(request.get_header("Cookie")?, csrf::SESSION_COOKIE)
+    }
+
+    /// Returns the CSRF token sent in the header or the JSON body of a request.
+    fn supplied_token(request: &Request) -> Option<String> {
+        if let Some(token) = request.get_header(csrf::CSRF_HEADER) {
+            return Some(token.to_string());
+        }
+
+        let body: serde_json::Value = serde_json::from_str(&request.body).ok()?;
+        body.get(csrf::CSRF_FIELD)?.as_str().map(str::to_string)
+    }
+
     /// Handles HTTP GET requests, serving static files and handling special routes.
     ///
     /// # Arguments
@@ -63,7 +95,8 @@ pub mod question_api {
     /// # Returns
     /// A `Response` object with the appropriate content and status code.
     async fn handle_get(request: Request, questions: Arc<Mutex<State>>) -> Response {
-        let questions = questions.lock().await;
+        let mut questions = questions.lock().await;
+        let session: csrf::Session = questions.sessions.start(session_id(&request));
         let client_id: Uuid = Uuid::new_v4();
 
         let random_index = rand::thread_rng().gen_range(0..questions.ids.len() - 1);
@@ -89,10 +122,32 @@ pub mod question_api {
             }
         };
 
-        let response = Response::default()
+        let page: Vec<u8> = match random_question.generate_html_page(
+            &questions.templates,
+            client_id,
+            &session.token,
+        ) {
+            Ok(p) => p,
+            Err(e) => {
+                error!(target: "error_logger","Failed to render question page: {}", e);
+                info!(target: "request_logger","GET {} status: 500", request.uri);
+                return Response::default()
+                    .await
+                    .code(HttpCode::InternalServerError)
+                    .content_type(ContentType::Text)
+                    .body(String::from("Failed to render the question").into());
+            }
+        };
+        let mut response = Response::default()
             .await
             .compression(request.is_compression_supported())
-            .body(random_question.generate_html_page(client_id));
+            .body(page);
+        if session.new {
+            response.add_header(
+                String::from("Set-Cookie"),
+                csrf::session_cookie(&session.id),
+            );
+        }
 
         if request.uri == "/" {
             info!(target: "request_logger","GET / from status 200");
@@ -228,4 +283,41 @@ pub mod question_api {
             .body(read_file_to_bytes("static/index.html").await)
             .code(HttpCode::MethodNotAllowed)
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn post(headers: &[&str], body: &str) -> Request {
+            let mut buffer: String = String::from("POST /answer HTTP/1.1\r\nHost: localhost\r\n");
+            for header in headers {
+                buffer.push_str(header);
+                buffer.push_str("\r\n");
+            }
+            buffer.push_str("\r\n");
+            buffer.push_str(body);
+            Request::new(buffer.as_bytes(), String::from("127.0.0.1")).unwrap()
+        }
+
+        #[test]
+        fn test_token_bound_to_session() {
+            let mut sessions = csrf::Sessions::new();
+            let session: csrf::Session = sessions.start(None);
+            let other: csrf::Session = sessions.start(None);
+            let cookie: String = format!("Cookie: theme=dark; session_id={}", session.id);
+            let header: String = format!("X-CSRF-Token: {}", session.token);
+            let body: String = format!("{{\"csrf_token\":\"{}\"}}", session.token);
+
+            let mut accepted = |request: Request| {
+                sessions.verify(session_id(&request), supplied_token(&request).as_deref())
+            };
+            assert!(accepted(post(&[&cookie, &header], "{}")));
+            assert!(accepted(post(&[&cookie], &body)));
+            assert!(!accepted(post(&[&cookie], "{}")));
+            assert!(!accepted(post(&[&header], "{}")));
+            // A token from another session is refused
+            let forged: String = format!("X-CSRF-Token: {}", other.token);
+            assert!(!accepted(post(&[&cookie, &forged], "{}")));
+        }
+    }
 }
@@ -98,8 +98,13 @@ pub mod http_request {
         }
 
         pub fn new(buffer: &[u8], client_ip: String) -> Result<Request, ErrorType> {
-            // unwrap is safe as request has been parsed for any issues before this is called
-            let request = String::from_utf8(buffer.to_vec()).unwrap();
+            let request = match String::from_utf8(buffer.to_vec()) {
+                Ok(r) => r,
+                Err(_) => {
+                    error!(target: "error_logger","Recieved a request that is not UTF-8");
+                    return Err(ErrorType::ConnectionError(String::from("Invalid request")));
+                }
+            };
 
             // split the request by line
             let request: Vec<&str> = request.lines().collect();
@@ -109,13 +114,15 @@ pub mod http_request {
                 return Err(ErrorType::ConnectionError(String::from("Invalid request")));
             }
 
-            // get the http method from the first line
-            let method: HttpMethod =
-                HttpMethod::new(request[0].split_whitespace().collect::<Vec<&str>>()[0]);
-
-            // get the uri from the first line
-            let mut uri: String =
-                request[0].split_whitespace().collect::<Vec<&str>>()[1].to_string();
+            // get the http method and the uri from the first line
+            let (method, mut uri): (HttpMethod, String) =
+                match request[0].split_whitespace().collect::<Vec<&str>>()[..] {
+                    [method, uri, ..] => (HttpMethod::new(method), uri.to_string()),
+                    _ => {
+                        error!(target: "error_logger","Recieved invalid request line");
+                        return Err(ErrorType::ConnectionError(String::from("Invalid request")));
+                    }
+                };
             if uri == "/favicon.ico" {
                 uri = "/".to_string();
             }
@@ -146,6 +153,18 @@ pub mod http_request {
             })
         }
 
+        /// Returns the value of the first header called `name`, ignoring case.
+        pub fn get_header(&self, name: &str) -> Option<&str> {
+            self.headers.iter().find_map(|header| {
+                let (title, value) = header.split_once(':')?;
+                if title.trim().eq_ignore_ascii_case(name) {
+                    Some(value.trim())
+                } else {
+                    None
+                }
+            })
+        }
+
         pub fn is_compression_supported(&self) -> bool {
             for header in &self.headers {
                 let header = header.to_lowercase();
@@ -186,11 +205,13 @@ pub mod http_request {
         Created,
         BadRequest,
         Unauthorized,
+        Forbidden,
         NotFound,
         MethodNotAllowed,
         RequestTimeout,
         Teapot,
         InternalServerError,
+        ServiceUnavailable,
     }
 
     impl Display for HttpCode {
@@ -200,11 +221,13 @@ pub mod http_request {
                 HttpCode::Created => write!(f, "201 Created"),
                 HttpCode::BadRequest => write!(f, "400 Bad Request"),
                 HttpCode::Unauthorized => write!(f, "401 Unauthorized"),
+                HttpCode::Forbidden => write!(f, "403 Forbidden"),
                 HttpCode::NotFound => write!(f, "404 Not Found"),
                 HttpCode::MethodNotAllowed => write!(f, "405 Method Not Allowed"),
                 HttpCode::RequestTimeout => write!(f, "408 Request Timeout"),
                 HttpCode::Teapot => write!(f, "418 I'm a teapot"),
                 HttpCode::InternalServerError => write!(f, "500 Internal Server Error"),
+                HttpCode::ServiceUnavailable => write!(f, "503 Service Unavailable"),
             }
         }
     }
@@ -2,17 +2,20 @@
 //! This server demonstrates concurrency management, secure password storage and itergration with
 //! external services like PostgreSQL and Redis.
 use crate::api::question_api::handle_response;
+use crate::health::handle_health;
 use crate::question::Question;
-use crate::request::http_request::Request;
+use crate::request::http_request::{ContentType, HttpCode, Protocol, Request};
 use crate::response::http_response::Response;
-use crate::socket::connection::{get_listener, load_tls_config};
+use crate::socket::connection::{get_listener, load_tls_config, TlsIdentity, CERT_PATH, KEY_PATH};
 use colored::Colorize;
 use log::{error, info};
+use rustls::pki_types::CertificateDer;
 use std::collections::HashMap;
 use std::env;
-use std::str::from_utf8;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
+use templates::{Templates, TEMPLATE_DIR};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{Mutex, Notify, Semaphore};
@@ -24,10 +27,32 @@ use uuid::Uuid;
 /// The default port used by the server if none is specified.
 const DEFAULT_PORT: u16 = 7878;
 
+/// How long a shutdown waits for the open connections to finish.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// The questions and sessions the handlers share.
 pub struct State {
     pub questions: HashMap<Uuid, Question>,
-    pub user_scores: HashMap<Uuid, usize>,
     pub ids: Vec<Uuid>,
+    pub user_scores: HashMap<Uuid, usize>,
+    pub templates: Templates,
+    /// The sessions and the CSRF tokens their answers must carry.
+    pub sessions: csrf::Sessions,
+}
+
+impl State {
+    /// Creates a state asking `questions`, rendered with `templates`.
+    pub fn new(questions: HashMap<Uuid, Question>, templates: Templates) -> Self {
+        let ids: Vec<Uuid> = questions.keys().copied().collect();
+
+        State {
+            questions,
+            ids,
+            user_scores: HashMap::new(),
+            templates,
+            sessions: csrf::Sessions::new(),
+        }
+    }
 }
 
 /// Sets up the server by initializing the connections and configuring logging.
@@ -50,71 +75,165 @@ pub async fn set_up_server() -> Result<(), Box<dyn std::error::Error>> {
 
     log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
 
-    print_server_info(port);
+    let state: State = State::new(Question::parse_file().await, Templates::new(TEMPLATE_DIR));
 
-    let questions: HashMap<Uuid, Question> = Question::parse_file().await;
-    let mut ids: Vec<Uuid> = Vec::new();
+    let tls: TlsIdentity = match load_tls_config(CERT_PATH, KEY_PATH).await {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to load the TLS certificate: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let server: Server = Server::bind(ServerConfig::new(tls).port(port), state).await?;
 
-    for key in questions.keys() {
-        ids.push(*key);
-    }
+    print_server_info(port);
 
-    let state: Arc<Mutex<State>> = Arc::new(Mutex::new(State {
-        questions,
-        user_scores: HashMap::new(),
-        ids,
-    }));
+    let shutdown: Arc<Shutdown> = server.shutdown_handle();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            eprintln!("Failed to listen for shutdown signal");
+            std::process::exit(1);
+        } else {
+            println!("Recieved shutdown request");
+            println!("Waiting for tasks to finish");
+            shutdown.shutdown();
+        }
+    });
 
     info!(target: "request_logger","Server Started");
-    let _ = start_server(port, state.clone()).await;
+    server.run().await;
     Ok(())
 }
 
-/// Starts the server, accepting incoming connections and managing their lifecycles.
-///
-/// # Arguments
-/// - `port`: The port the server is running on.
-///
-/// # Returns
-/// A `Result` object with either and Ok(()) or an Err(Box<dyn std::error::Error>)
-async fn start_server(
-    port: u16,
-    questions: Arc<Mutex<State>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let listener: TcpListener = get_listener(port)?;
-
-    let tls_config = load_tls_config().await?;
-    let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+/// The port and TLS identity of a server.
+pub struct ServerConfig {
+    /// The port of the TLS listener. Port 0 picks a free port.
+    pub port: u16,
+    pub tls: TlsIdentity,
+}
 
-    let shutdown: Arc<Notify> = Arc::new(Notify::new());
+impl ServerConfig {
+    /// Creates a configuration using the default port.
+    pub fn new(tls: TlsIdentity) -> Self {
+        ServerConfig {
+            port: DEFAULT_PORT,
+            tls,
+        }
+    }
 
-    let connections: Arc<Semaphore> = Arc::new(Semaphore::new(15));
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+}
 
-    let shutdown_signal = shutdown.clone();
+/// Tells a running server to shut down.
+pub struct Shutdown {
+    requested: AtomicBool,
+    notify: Notify,
+}
 
-    tokio::spawn(async move {
-        if tokio::signal::ctrl_c().await.is_err() {
-            eprintln!("Failed to listen for shutdown signal");
-            std::process::exit(1);
-        } else {
-            println!("Recieved shutdown request");
-            println!("Waiting for tasks to finish");
-            shutdown_signal.notify_one();
-            println!("Tasks complete, server shutdown started");
-            std::process::exit(0);
+impl Shutdown {
+    fn new() -> Self {
+        Shutdown {
+            requested: AtomicBool::new(false),
+            notify: Notify::new(),
         }
-    });
+    }
+
+    /// Starts a graceful shutdown, like Ctrl+C.
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// A server with its listener bound, ready to accept connections.
+///
+/// `set_up_server` builds one from the command line, the certificate files and the questions in
+/// `file.txt`. Tests can build one with their own certificate and port 0.
+pub struct Server {
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<Shutdown>,
+    /// The certificate the TLS listener presents, checked by `/readyz`.
+    certificate: Arc<CertificateDer<'static>>,
+}
+
+impl Server {
+    /// Binds the TLS listener.
+    ///
+    /// # Arguments
+    /// - `config`: The port and TLS identity of the server.
+    /// - `state`: The questions and sessions the handlers use.
+    ///
+    /// # Returns
+    /// A `Result` object with either the `Server` or an Err(Box<dyn std::error::Error>)
+    pub async fn bind(
+        config: ServerConfig,
+        state: State,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let listener: TcpListener = get_listener(config.port)?;
+
+        Ok(Server {
+            listener,
+            acceptor: TlsAcceptor::from(Arc::new(config.tls.config)),
+            state: Arc::new(Mutex::new(state)),
+            shutdown: Arc::new(Shutdown::new()),
+            certificate: Arc::new(config.tls.certificate),
+        })
+    }
+
+    /// Returns the address of the TLS listener.
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn state(&self) -> Arc<Mutex<State>> {
+        self.state.clone()
+    }
 
-    tokio::select! {
-        _ = run_server(listener,acceptor,connections.clone(),questions.clone())=> {
+    pub fn shutdown_handle(&self) -> Arc<Shutdown> {
+        self.shutdown.clone()
+    }
+
+    /// Accepts connections until a shutdown is started with `Shutdown::shutdown`.
+    ///
+    /// The server then stops accepting connections and waits for the open ones to finish, for at
+    /// most `SHUTDOWN_GRACE_PERIOD`.
+    pub async fn run(self) {
+        let Server {
+            listener,
+            acceptor,
+            state,
+            shutdown,
+            certificate,
+        } = self;
+
+        let connections: Arc<Semaphore> = Arc::new(Semaphore::new(15));
+
+        tokio::select! {
+            _ = run_server(listener,acceptor,connections.clone(),shutdown.clone(),certificate,state)=> {
+            }
+            _ = shutdown.notify.notified() => {
+                    info!(target: "request_logger","Server shutdown signal recieved.");
+                    println!("Server shutdown signal recieved.");
+            }
         }
-        _ = shutdown.notified() => {
-                info!(target: "request_logger","Server shutdown signal recieved.");
-                println!("Server shutdown signal recieved.");
+        // Let the open connections finish, but do not wait for them forever
+        if timeout(SHUTDOWN_GRACE_PERIOD, connections.acquire_many(15))
+            .await
+            .is_err()
+        {
+            println!("Closing the connections that did not finish");
         }
+        println!("Server shut down");
     }
-    while connections.clone().available_permits() != 15 {}
-    Ok(())
 }
 
 /// Accepts connections from incoming clients and completes the TLS hanshake.
@@ -123,15 +242,24 @@ async fn start_server(
 /// - `listener`: A TcpListener
 /// - `acceptor`: A TlsAcceptor to handle the Tls hanshake.
 /// - `connections`: A Semaphore for limiting the amout of concurrent connections.
+/// - `shutdown`: Whether the server is shutting down.
+/// - `certificate`: The certificate the TLS listener presents.
+/// - `questions`: The questions and sessions the handlers share.
 async fn run_server(
     listener: TcpListener,
     acceptor: TlsAcceptor,
     connections: Arc<Semaphore>,
+    shutdown: Arc<Shutdown>,
+    certificate: Arc<CertificateDer<'static>>,
     questions: Arc<Mutex<State>>,
 ) {
     loop {
         let connections = connections.clone();
 
+        if shutdown.is_shutting_down() {
+            return;
+        }
+
         let permit = connections.clone().acquire_owned().await.unwrap();
 
         let result = timeout(Duration::from_millis(100), listener.accept()).await;
@@ -151,12 +279,21 @@ async fn run_server(
         let acceptor = acceptor.clone();
 
         let questions = Arc::clone(&questions);
+        let shutdown = shutdown.clone();
+        let certificate = certificate.clone();
 
         let handle = tokio::spawn(async move {
             if let Ok(tls_stream) = acceptor.accept(stream).await {
                 info!(target: "request_logger","TLS handshake successful with {}", address);
 
-                let _ = handle_connection(tls_stream, address.to_string(), questions.clone()).await;
+                let _ = handle_connection(
+                    tls_stream,
+                    address.to_string(),
+                    questions.clone(),
+                    &shutdown,
+                    &certificate,
+                )
+                .await;
             } else {
                 error!(target: "error_logger","TLS handshake failed with {}", address);
             }
@@ -174,6 +311,8 @@ async fn run_server(
 /// - `stream`: A mutable TlsStream.
 /// - `address`: The address of the client connected to the server.
 /// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `shutdown`: Whether the server is shutting down.
+/// - `certificate`: The certificate the TLS listener presents.
 ///
 /// # Returns
 /// A `Result` object with either and Ok(()) or an Err(Box<dyn std::error::Error>)
@@ -181,6 +320,8 @@ async fn handle_connection(
     mut stream: TlsStream<TcpStream>,
     address: String,
     questions: Arc<Mutex<State>>,
+    shutdown: &Shutdown,
+    certificate: &CertificateDer<'static>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     loop {
         let mut buffer = [0; 4096];
@@ -198,13 +339,23 @@ async fn handle_connection(
             return Ok(());
         }
 
-        println!("{}", from_utf8(&buffer[..bytes_read]).unwrap());
+        println!("{}", String::from_utf8_lossy(&buffer[..bytes_read]));
 
         let request: Request = match Request::new(&buffer[..bytes_read], address.clone()) {
             Ok(r) => r,
             Err(_) => {
                 println!("Unable to parse in request");
-                std::process::exit(1);
+                let mut response: Response = Response::new(
+                    Protocol::Http,
+                    HttpCode::BadRequest,
+                    ContentType::Text,
+                    false,
+                )
+                .body(String::from("Invalid request").into());
+                stream.write_all(&response.to_bytes()).await?;
+                stream.flush().await?;
+
+                return Ok(());
             }
         };
 
@@ -219,7 +370,17 @@ async fn handle_connection(
             return Ok(());
         }
 
-        let mut response: Response = handle_response(request, questions.clone()).await;
+        let mut response: Response = match handle_health(
+            &request,
+            &questions,
+            certificate,
+            shutdown.is_shutting_down(),
+        )
+        .await
+        {
+            Some(r) => r,
+            None => handle_response(request, questions.clone()).await,
+        };
 
         stream.write_all(&response.to_bytes()).await?;
         stream.flush().await?;
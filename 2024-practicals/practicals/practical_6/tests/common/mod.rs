@@ -0,0 +1,293 @@
+//! Boots a server in-process and talks to it over real TLS connections.
+//!
+//! `TestServer::start` runs the server on a free port with a freshly generated self-signed
+//! certificate and the questions in `file.txt`, so tests do not need the certificate files the
+//! server is deployed with. `TestClient` keeps the session cookie like a browser and opens a new
+//! connection for every request.
+#![allow(dead_code)]
+
+use practical_6::question::Question;
+use practical_6::server::{Server, ServerConfig, Shutdown, State};
+use practical_6::socket::connection::TlsIdentity;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use templates::{Templates, TEMPLATE_DIR};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// A self-signed certificate for `localhost`.
+fn test_identity() -> TlsIdentity {
+    let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let key: PrivateKeyDer<'static> =
+        PrivatePkcs8KeyDer::from(certificate.key_pair.serialize_der()).into();
+    TlsIdentity::new(certificate.cert.der().clone(), key).unwrap()
+}
+
+/// A server running on a task of the test's runtime.
+pub struct TestServer {
+    pub address: SocketAddr,
+    pub shutdown: Arc<Shutdown>,
+    /// The server's questions, which tests read the right answers from.
+    pub state: Arc<Mutex<State>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> TestServer {
+        // The server's TLS configuration uses the process-wide provider
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        let config = ServerConfig::new(test_identity()).port(0);
+        let state = State::new(Question::parse_file().await, Templates::new(TEMPLATE_DIR));
+        let server = Server::bind(config, state).await.unwrap();
+
+        let address = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        let shutdown = server.shutdown_handle();
+        let state = server.state();
+        let task = Some(tokio::spawn(server.run()));
+
+        TestServer {
+            address,
+            shutdown,
+            state,
+            task,
+        }
+    }
+
+    pub fn client(&self) -> TestClient {
+        TestClient::new(self.address)
+    }
+
+    /// Waits for the server to finish after a shutdown was started.
+    pub async fn finished(mut self) {
+        let task = self.task.take().unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(15), task)
+            .await
+            .expect("server did not shut down")
+            .unwrap();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+    }
+}
+
+/// A client that connects to a test server over TLS, keeping its session cookie.
+pub struct TestClient {
+    address: SocketAddr,
+    /// The `session_id` cookie set by the server, sent with every later request.
+    pub cookie: Option<String>,
+    connector: tokio_rustls::TlsConnector,
+}
+
+impl TestClient {
+    pub fn new(address: SocketAddr) -> TestClient {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth();
+
+        TestClient {
+            address,
+            cookie: None,
+            connector: tokio_rustls::TlsConnector::from(Arc::new(config)),
+        }
+    }
+
+    pub async fn get(&mut self, path: &str) -> TestResponse {
+        self.request("GET", path, &[], b"").await
+    }
+
+    pub async fn post_json(&mut self, path: &str, csrf_token: &str, body: &str) -> TestResponse {
+        self.request(
+            "POST",
+            path,
+            &[
+                ("Content-Type", "application/json"),
+                ("X-CSRF-Token", csrf_token),
+            ],
+            body.as_bytes(),
+        )
+        .await
+    }
+
+    /// Sends a request on a new connection and reads the response until the server closes it.
+    pub async fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> TestResponse {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+        if let Some(cookie) = &self.cookie {
+            head.push_str(&format!("Cookie: {}\r\n", cookie));
+        }
+        for (title, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", title, value));
+        }
+        if !body.is_empty() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut request: Vec<u8> = head.into_bytes();
+        request.extend_from_slice(body);
+        let response = TestResponse::parse(&self.send_raw(&request).await);
+
+        if let Some(cookie) = response.header("Set-Cookie") {
+            self.cookie = cookie.split(';').next().map(str::to_string);
+        }
+        response
+    }
+
+    /// Writes `request` as it is and returns everything the server sends back.
+    pub async fn send_raw(&self, request: &[u8]) -> Vec<u8> {
+        let stream = TcpStream::connect(self.address).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = self.connector.connect(server_name, stream).await.unwrap();
+
+        stream.write_all(request).await.unwrap();
+        stream.flush().await.unwrap();
+
+        let mut data: Vec<u8> = Vec::new();
+        let mut buffer: [u8; 8192] = [0; 8192];
+        loop {
+            match stream.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(n) => data.extend_from_slice(&buffer[..n]),
+                // The server closes connections without a TLS close_notify
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => panic!("failed to read response: {}", e),
+            }
+        }
+        data
+    }
+}
+
+/// A response read by a test client.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// The body, still compressed if it was sent so.
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn parse(data: &[u8]) -> TestResponse {
+        let head_end = data
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("response has no head");
+        let head = String::from_utf8_lossy(&data[..head_end]);
+        let mut lines = head.split("\r\n");
+
+        let status: u16 = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .expect("invalid status line");
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(title, value)| (title.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        TestResponse {
+            status,
+            headers,
+            body: data[head_end + 4..].to_vec(),
+        }
+    }
+
+    /// Returns the value of the first header called `title`, ignoring case.
+    pub fn header(&self, title: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(t, _)| t.eq_ignore_ascii_case(title))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the body as text, decompressing it if it was gzip encoded.
+    pub fn text(&self) -> String {
+        if self.header("Content-Encoding") == Some("gzip") {
+            let mut text = String::new();
+            flate2::read::GzDecoder::new(&self.body[..])
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        } else {
+            String::from_utf8_lossy(&self.body).to_string()
+        }
+    }
+
+    /// Returns the value of the hidden form field with the id `id` on a question page.
+    pub fn field(&self, id: &str) -> String {
+        let text = self.text();
+        let marker = format!("id=\"{}\" value=\"", id);
+        let start = text.find(&marker).expect("page has no such field") + marker.len();
+        let end = text[start..].find('"').unwrap() + start;
+        text[start..end].to_string()
+    }
+}
+
+/// Accepts the server's self-signed test certificate, whatever its name or expiry.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,154 @@
+//! End-to-end tests that send real TLS requests to a server running in-process.
+mod common;
+
+use common::{TestResponse, TestServer};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn test_routing() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    let page = client.get("/").await;
+    assert_eq!(page.status, 200);
+    assert!(page
+        .header("Content-Type")
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(page.text().contains("id=\"question-form\""));
+
+    let question = client.get("/question").await;
+    assert_eq!(question.status, 200);
+    assert!(question.text().contains("id=\"question-form\""));
+
+    let missing = client.get("/missing").await;
+    assert_eq!(missing.status, 400);
+    assert!(missing.text().contains("400 Error"));
+
+    let token = page.field("csrf_token");
+    for method in ["PUT", "PATCH", "DELETE"] {
+        let response = client
+            .request(method, "/", &[("X-CSRF-Token", &token)], b"")
+            .await;
+        assert_eq!(response.status, 405);
+    }
+}
+
+#[tokio::test]
+async fn test_sessions_and_csrf() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    let page = client.get("/").await;
+    let cookie = client.cookie.clone().expect("no session cookie");
+    assert!(cookie.starts_with("session_id="));
+    let token = page.field("csrf_token");
+    let uuid = page.field("uuid");
+    let answer = format!(
+        r#"{{"uuid":"{}","client_id":"{}","answers":[1]}}"#,
+        uuid,
+        page.field("client_id")
+    );
+
+    // The session is kept along with its token
+    let again = client.get("/question").await;
+    assert!(again.header("Set-Cookie").is_none());
+    assert_eq!(again.field("csrf_token"), token);
+
+    // Answers must carry the session's token
+    let forged = client
+        .request(
+            "POST",
+            "/answer",
+            &[("Content-Type", "application/json")],
+            answer.as_bytes(),
+        )
+        .await;
+    assert_eq!(forged.status, 403);
+
+    let answered = client.post_json("/answer", &token, &answer).await;
+    assert_eq!(answered.status, 200);
+    let expected =
+        server.state.lock().await.questions[&Uuid::parse_str(&uuid).unwrap()].check_answer(vec![0]);
+    assert_eq!(answered.text(), expected);
+
+    // Another session has its own token
+    let mut other = server.client();
+    other.get("/").await;
+    assert_ne!(other.cookie, client.cookie);
+    let wrong_token = other.post_json("/answer", &token, &answer).await;
+    assert_eq!(wrong_token.status, 403);
+}
+
+#[tokio::test]
+async fn test_error_paths() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+    let token = client.get("/").await.field("csrf_token");
+
+    let invalid = client.post_json("/answer", &token, "{not json").await;
+    assert_eq!(
+        (invalid.status, invalid.text().as_str()),
+        (400, "Invalid post URI.")
+    );
+
+    let unknown_question = client
+        .post_json(
+            "/answer",
+            &token,
+            &format!(
+                r#"{{"uuid":"{}","client_id":"{}","answers":[1]}}"#,
+                Uuid::new_v4(),
+                Uuid::new_v4()
+            ),
+        )
+        .await;
+    assert_eq!(unknown_question.status, 400);
+
+    let unknown_route = client.post_json("/unknown", &token, "{}").await;
+    assert_eq!(unknown_route.status, 400);
+
+    // A malformed request is refused without stopping the server
+    let malformed = TestResponse::parse(&client.send_raw(b"GARBAGE\r\n\r\n").await);
+    assert_eq!(malformed.status, 400);
+    assert_eq!(client.get("/").await.status, 200);
+}
+
+#[tokio::test]
+async fn test_shutdown() {
+    let server = TestServer::start().await;
+    assert_eq!(server.client().get("/").await.status, 200);
+
+    server.shutdown.shutdown();
+    let address = server.address;
+    server.finished().await;
+
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+#[tokio::test]
+async fn test_health_checks() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    let health = client.get("/healthz").await;
+    assert_eq!(
+        (health.status, health.text().as_str()),
+        (200, r#"{"status":"ok"}"#)
+    );
+    // Health checks do not start a session
+    assert!(client.cookie.is_none());
+
+    let ready = client.get("/readyz").await;
+    assert_eq!(ready.status, 200);
+    assert_eq!(ready.header("Content-Type"), Some("application/json"));
+    let ready: serde_json::Value = serde_json::from_str(&ready.text()).unwrap();
+    assert_eq!(ready["status"], "ready");
+    assert_eq!(ready["checks"]["questions"]["status"], "ok");
+    assert_eq!(ready["checks"]["certificate"]["status"], "ok");
+
+    assert_eq!(
+        client.request("POST", "/readyz", &[], b"").await.status,
+        405
+    );
+}
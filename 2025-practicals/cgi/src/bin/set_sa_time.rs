@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
+use templates::{Templates, TEMPLATE_DIR};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Config {
@@ -22,15 +23,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     write_config(&new_config);
 
-    println!("Content-Type: text/html\n");  
-    println!("<html lang=\"en\">");
-    println!("<head>");
-    println!("<meta http-equiv='refresh' content='0;url=show_time.cgi'>");
-    println!("</head>");
-    println!("<body>");
-    println!("<h3>Switching to South Africa time</h3>");
-    println!("</body>");
-    println!("</html>");
+    let page: String = Templates::new(TEMPLATE_DIR).render("switching.html", &new_config)?;
+    println!("Content-Type: text/html\n");
+    println!("{}", page);
 
     Ok(())
 }
@@ -1,6 +1,7 @@
 use chrono::{FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use templates::{Templates, TEMPLATE_DIR};
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Config {
@@ -26,20 +27,16 @@ fn get_time(offset: i32) -> String {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config: Config = read_config();
     let current_time = get_time(config.timezone);
-     println!("Content-Type: text/html\n");  
-    println!("<html lang=\"en\">");
-    println!("<head>");
-    println!("<title>Current time</title>");
-    println!("</head>");
-    println!("<body>");
-    println!(
-        "<h1>The Current Time in {}, {} is {}</h1></body></html>",
-        config.city, config.country, current_time
-    );
-    println!("<a href='set_sa_time.cgi'>Switch South African Time</a></br>");
-    println!("<a href='set_uk_time.cgi'>Switch United Kingdom Time</a>");
-    println!("</body>");
-    println!("</html>");
+    let page: String = Templates::new(TEMPLATE_DIR).render(
+        "show_time.html",
+        &serde_json::json!({
+            "city": config.city,
+            "country": config.country,
+            "time": current_time,
+        }),
+    )?;
+    println!("Content-Type: text/html\n");
+    println!("{}", page);
 
     Ok(())
 }
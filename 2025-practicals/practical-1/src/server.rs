@@ -29,6 +29,9 @@ use uuid::Uuid;
 /// The default port used by the server if none is specified.
 const DEFAULT_PORT: u16 = 7878;
 
+/// How long a shutdown waits for the open connections to finish.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 /// Shared state structure holding connecions to Redis, PostgreSQL and a logical clock.
 pub struct SharedState {
     pub redis_connection: redis::Connection,
@@ -231,8 +234,6 @@ async fn start_server(
             println!("Waiting for tasks to finish");
             shutdown_flag.store(true, Ordering::SeqCst);
             shutdown_signal.notify_one();
-            println!("Tasks complete, server shutdown started");
-            std::process::exit(0);
         }
     });
 
@@ -244,7 +245,14 @@ async fn start_server(
                 println!("Server shutdown signal recieved.");
         }
     }
-    while connections.clone().available_permits() != 15 {}
+    // Let the open connections finish, but do not wait for them forever
+    if timeout(SHUTDOWN_GRACE_PERIOD, connections.acquire_many(15))
+        .await
+        .is_err()
+    {
+        println!("Closing the connections that did not finish");
+    }
+    println!("Server shut down");
     Ok(())
 }
 
@@ -0,0 +1,242 @@
+//! Passwords and logins.
+//!
+//! Passwords are stored as Argon2id hashes with a random salt, in the PHC string format. An account
+//! is locked for `LOCKOUT_TIME` after `MAX_FAILED_LOGINS` failed logins in a row, and a session is
+//! closed after `MAX_LOGIN_ATTEMPTS` failed logins, whichever accounts they were for. Hashing is
+//! slow on purpose, so it runs on Tokio's blocking threads without holding the database lock.
+use crate::database::{Account, Database, User};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::Mutex;
+
+/// How many failed logins in a row lock an account.
+pub const MAX_FAILED_LOGINS: u32 = 5;
+/// How long a locked account stays locked.
+pub const LOCKOUT_TIME: TimeDelta = TimeDelta::minutes(15);
+/// How many failed logins a session may make before it is closed.
+pub const MAX_LOGIN_ATTEMPTS: u32 = 3;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// The characters of generated passwords, without the ones that look alike.
+const PASSWORD_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const GENERATED_PASSWORD_LENGTH: usize = 16;
+
+/// Why a login failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginError {
+    /// The name or the password is wrong. Which one is not told.
+    Incorrect,
+    /// The account is locked after too many failed logins, until the given time. Clients are
+    /// told the same as for `Incorrect`, so they cannot find out which names exist.
+    Locked(DateTime<Utc>),
+}
+
+/// Hashes a password with Argon2id and a new random salt.
+///
+/// # Returns
+/// The hash in PHC string format, which includes the salt and the parameters.
+pub fn hash_password(password: &str) -> String {
+    let salt: SaltString = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("The default Argon2 parameters are valid")
+        .to_string()
+}
+
+/// Checks a password against a hash made by `hash_password`. Accounts without a password, whose
+/// hash is empty, match no password.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Hashes a password on a blocking thread.
+pub async fn hash_password_blocking(password: String) -> String {
+    tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .expect("Hashing a password does not panic")
+}
+
+/// Checks that a new password is good enough.
+///
+/// # Returns
+/// A message for the user if it is not.
+pub fn check_new_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "Passwords must have at least {} characters.",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+    if password.trim() != password {
+        return Err(String::from("Passwords cannot start or end with a space."));
+    }
+    Ok(())
+}
+
+/// Returns a random password for accounts created without one.
+pub fn generate_password() -> String {
+    let mut bytes: [u8; GENERATED_PASSWORD_LENGTH] = [0; GENERATED_PASSWORD_LENGTH];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+        .iter()
+        .map(|&b| PASSWORD_ALPHABET[b as usize % PASSWORD_ALPHABET.len()] as char)
+        .collect()
+}
+
+/// Gives every account without a password, such as the first admin of a new database, a
+/// generated one.
+///
+/// # Returns
+/// The names of the accounts and their new passwords, to show the operator.
+pub fn set_missing_passwords(database: &Database) -> Result<Vec<(String, String)>, String> {
+    let accounts: Vec<Account> = database.accounts().map_err(|e| e.to_string())?;
+    let mut passwords: Vec<(String, String)> = Vec::new();
+    for account in accounts {
+        if account.password_hash.is_empty() {
+            let password: String = generate_password();
+            database
+                .set_password(&account.user.name, &hash_password(&password))
+                .map_err(|e| e.to_string())?;
+            passwords.push((account.user.name, password));
+        }
+    }
+    Ok(passwords)
+}
+
+/// Logs a user in, counting failed logins and locking the account after too many.
+///
+/// # Arguments
+/// - `database`: The database holding the accounts. It is not locked while the password is
+///   checked.
+/// - `name`: The name typed at the login prompt.
+/// - `password`: The password typed.
+///
+/// # Returns
+/// The user, or why they cannot log in.
+pub async fn login(
+    database: &Arc<Mutex<Database>>,
+    name: &str,
+    password: &str,
+) -> Result<User, LoginError> {
+    let now: DateTime<Utc> = Utc::now();
+    let account: Option<Account> = database.lock().await.account(name).unwrap_or(None);
+
+    let locked: Option<DateTime<Utc>> = account
+        .as_ref()
+        .and_then(|a| a.locked_until)
+        .filter(|until| *until > now);
+
+    // Unknown names and locked accounts take as long to refuse as wrong passwords
+    let hash: String = match &account {
+        Some(account) => account.password_hash.clone(),
+        None => dummy_hash().to_string(),
+    };
+    let password: String = password.to_string();
+    let verified: bool = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+        .await
+        .unwrap_or(false);
+
+    let Some(account) = account else {
+        return Err(LoginError::Incorrect);
+    };
+    if let Some(until) = locked {
+        return Err(LoginError::Locked(until));
+    }
+    let database = database.lock().await;
+    if verified {
+        let _ = database.unlock_user(&account.user.name);
+        return Ok(account.user);
+    }
+
+    // A lockout that ran out gives a fresh count
+    let failed: u32 = if account.locked_until.is_some() {
+        let _ = database.unlock_user(&account.user.name);
+        database.record_failed_login(account.user.id).unwrap_or(1)
+    } else {
+        database
+            .record_failed_login(account.user.id)
+            .unwrap_or(MAX_FAILED_LOGINS)
+    };
+    if failed >= MAX_FAILED_LOGINS {
+        let _ = database.lock_user(account.user.id, now + LOCKOUT_TIME);
+        println!(
+            "Locked the account {} after {} failed logins",
+            account.user.name, failed
+        );
+    }
+    Err(LoginError::Incorrect)
+}
+
+/// A hash of no one's password, checked when the name is unknown.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password(&generate_password()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Role;
+
+    #[test]
+    fn test_passwords() {
+        let hash: String = hash_password("correct horse");
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("correct horse ", &hash));
+        assert!(!verify_password("", ""));
+        assert_ne!(hash, hash_password("correct horse"));
+
+        assert!(check_new_password("short").is_err());
+        assert!(check_new_password(" leading space").is_err());
+        assert_eq!(generate_password().len(), GENERATED_PASSWORD_LENGTH);
+    }
+
+    #[tokio::test]
+    async fn test_lockout() {
+        let database = Database::new(":memory:").unwrap();
+        database
+            .add_user("ann", &hash_password("ann's password"), Role::User)
+            .unwrap();
+        let database = Arc::new(Mutex::new(database));
+
+        // The first admin cannot log in before it has a password
+        assert_eq!(
+            login(&database, "admin", "").await,
+            Err(LoginError::Incorrect)
+        );
+        assert_eq!(
+            login(&database, "bob", "ann's password").await,
+            Err(LoginError::Incorrect)
+        );
+        assert_eq!(
+            login(&database, "ann", "ann's password")
+                .await
+                .unwrap()
+                .name,
+            "ann"
+        );
+
+        for _ in 0..MAX_FAILED_LOGINS {
+            assert_eq!(
+                login(&database, "ann", "wrong").await,
+                Err(LoginError::Incorrect)
+            );
+        }
+        assert!(matches!(
+            login(&database, "ann", "ann's password").await,
+            Err(LoginError::Locked(_))
+        ));
+
+        database.lock().await.unlock_user("ann").unwrap();
+        assert!(login(&database, "ann", "ann's password").await.is_ok());
+    }
+}
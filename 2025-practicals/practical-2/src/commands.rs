@@ -0,0 +1,527 @@
+//! Parsing and running the commands typed in a session.
+//!
+//! Arguments are separated by spaces. An argument in double quotes may contain spaces, and a
+//! backslash inside quotes escapes the next character, so `ADD "Ann Lee" "555 0100" mobile` adds
+//! a friend whose name and number contain spaces.
+//!
+//! Commands work on the friends of the logged in user. The account commands are for admins.
+use crate::database::{Account, Database, DatabaseError, Friend, Role, User, DEFAULT_LABEL};
+use crate::export::Format;
+
+/// How many friends `LIST` shows per page.
+pub const PAGE_SIZE: usize = 10;
+
+/// The line that ends the data of an `IMPORT`.
+pub const IMPORT_END: &str = "END";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Add {
+        name: String,
+        number: String,
+        label: String,
+    },
+    Update {
+        name: String,
+        number: String,
+        label: String,
+    },
+    Get {
+        name: String,
+    },
+    /// Deletes a friend, or only their number with the label.
+    Delete {
+        name: String,
+        label: Option<String>,
+    },
+    List {
+        page: usize,
+    },
+    Search {
+        text: String,
+    },
+    Export(Format),
+    /// Starts reading lines to import until `IMPORT_END`.
+    Import(Format),
+    /// Asks for a new password for the user, or for the named account.
+    Password {
+        name: Option<String>,
+    },
+    Users,
+    /// Asks for the password of a new account.
+    AddUser {
+        name: String,
+        role: Role,
+    },
+    DeleteUser {
+        name: String,
+    },
+    Unlock {
+        name: String,
+    },
+    SetRole {
+        name: String,
+        role: Role,
+    },
+    /// Switches to the full-screen mode.
+    Screen,
+    Help,
+    Exit,
+}
+
+impl Command {
+    /// Returns true for the commands that manage accounts, which only admins may use.
+    pub fn requires_admin(&self) -> bool {
+        match self {
+            Command::Password { name } => name.is_some(),
+            Command::Users
+            | Command::AddUser { .. }
+            | Command::DeleteUser { .. }
+            | Command::Unlock { .. }
+            | Command::SetRole { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Splits a command line into arguments.
+///
+/// # Returns
+/// The arguments, or an error message if a quote is not closed.
+pub fn split_arguments(line: &str) -> Result<Vec<String>, String> {
+    let mut arguments: Vec<String> = Vec::new();
+    let mut argument: Option<String> = None;
+    let mut quoted: bool = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                argument.get_or_insert_with(String::new);
+            }
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    argument.get_or_insert_with(String::new).push(escaped);
+                }
+            }
+            c if c.is_whitespace() && !quoted => arguments.extend(argument.take()),
+            c => argument.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return Err(String::from("A quote is not closed."));
+    }
+    arguments.extend(argument);
+    Ok(arguments)
+}
+
+/// Parses a command line.
+///
+/// # Returns
+/// The command, or a message telling the user how to use it.
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let arguments: Vec<String> = split_arguments(line)?;
+    let Some((command, arguments)) = arguments.split_first() else {
+        return Err(String::from("Please enter a command."));
+    };
+    let arguments: Vec<&str> = arguments.iter().map(String::as_str).collect();
+    let command: String = command.to_uppercase();
+
+    let usage = |usage: &str| Err(format!("Usage: {} {}", command, usage));
+    match (command.as_str(), arguments.as_slice()) {
+        ("ADD", [name, number]) | ("UPDATE", [name, number]) => {
+            number_command(&command, name, number, DEFAULT_LABEL)
+        }
+        ("ADD", [name, number, label]) | ("UPDATE", [name, number, label]) => {
+            number_command(&command, name, number, label)
+        }
+        ("ADD", _) | ("UPDATE", _) => usage("<name> <number> [<label>]"),
+        ("GET", [name]) => Ok(Command::Get {
+            name: name.to_string(),
+        }),
+        ("GET", _) => usage("<name>"),
+        ("DELETE", [name]) => Ok(Command::Delete {
+            name: name.to_string(),
+            label: None,
+        }),
+        ("DELETE", [name, label]) => Ok(Command::Delete {
+            name: name.to_string(),
+            label: Some(label.to_string()),
+        }),
+        ("DELETE", _) => usage("<name> [<label>]"),
+        ("LIST", []) => Ok(Command::List { page: 1 }),
+        ("LIST", [page]) => match page.parse::<usize>() {
+            Ok(page) if page > 0 => Ok(Command::List { page }),
+            _ => usage("[<page>]"),
+        },
+        ("LIST", _) => usage("[<page>]"),
+        ("SEARCH", [text]) => Ok(Command::Search {
+            text: text.to_string(),
+        }),
+        ("SEARCH", _) => usage("<text>"),
+        ("EXPORT", [format]) | ("IMPORT", [format]) => match Format::parse(format) {
+            Some(format) if command == "EXPORT" => Ok(Command::Export(format)),
+            Some(format) => Ok(Command::Import(format)),
+            None => usage("CSV|VCARD"),
+        },
+        ("EXPORT", _) | ("IMPORT", _) => usage("CSV|VCARD"),
+        ("PASSWORD", []) => Ok(Command::Password { name: None }),
+        ("PASSWORD", [name]) => Ok(Command::Password {
+            name: Some(name.to_string()),
+        }),
+        ("PASSWORD", _) => usage("[<user>]"),
+        ("USERS", []) => Ok(Command::Users),
+        ("USERS", _) => usage(""),
+        ("ADDUSER", [name]) if !name.trim().is_empty() => Ok(Command::AddUser {
+            name: name.trim().to_string(),
+            role: Role::User,
+        }),
+        ("ADDUSER", [name, role]) | ("ROLE", [name, role]) => match Role::parse(role) {
+            Some(role) if command == "ROLE" => Ok(Command::SetRole {
+                name: name.to_string(),
+                role,
+            }),
+            Some(role) if !name.trim().is_empty() => Ok(Command::AddUser {
+                name: name.trim().to_string(),
+                role,
+            }),
+            _ if command == "ROLE" => usage("<user> USER|ADMIN"),
+            _ => usage("<user> [USER|ADMIN]"),
+        },
+        ("ADDUSER", _) => usage("<user> [USER|ADMIN]"),
+        ("ROLE", _) => usage("<user> USER|ADMIN"),
+        ("DELUSER", [name]) => Ok(Command::DeleteUser {
+            name: name.to_string(),
+        }),
+        ("DELUSER", _) => usage("<user>"),
+        ("UNLOCK", [name]) => Ok(Command::Unlock {
+            name: name.to_string(),
+        }),
+        ("UNLOCK", _) => usage("<user>"),
+        ("SCREEN", _) => Ok(Command::Screen),
+        ("HELP", _) => Ok(Command::Help),
+        ("EXIT", _) => Ok(Command::Exit),
+        _ => Err(format!(
+            "Unknown command {}. Type HELP for the list of commands.",
+            command
+        )),
+    }
+}
+
+fn number_command(command: &str, name: &str, number: &str, label: &str) -> Result<Command, String> {
+    if name.is_empty() || number.is_empty() || label.is_empty() {
+        return Err(String::from("Names, numbers and labels cannot be empty."));
+    }
+    let (name, number, label) = (name.to_string(), number.to_string(), label.to_lowercase());
+    Ok(if command == "ADD" {
+        Command::Add {
+            name,
+            number,
+            label,
+        }
+    } else {
+        Command::Update {
+            name,
+            number,
+            label,
+        }
+    })
+}
+
+/// Checks that the user may run a command.
+///
+/// # Returns
+/// A message for the user if they may not.
+pub fn authorize(command: &Command, user: &User) -> Result<(), String> {
+    if command.requires_admin() && user.role != Role::Admin {
+        return Err(String::from("Only admins can manage accounts."));
+    }
+    Ok(())
+}
+
+/// Runs a command against the database for the user.
+///
+/// # Returns
+/// The text to show the user. `Import`, `Password`, `AddUser`, `Screen`, `Help` and `Exit` are
+/// handled by the session and give no text.
+pub fn execute(command: &Command, database: &Database, user: &User) -> String {
+    if let Err(message) = authorize(command, user) {
+        return format!("{}\n", message);
+    }
+
+    let owner: i64 = user.id;
+    let result: Result<String, DatabaseError> = match command {
+        Command::Add {
+            name,
+            number,
+            label,
+        } => database
+            .add_number(owner, name, label, number)
+            .map(|added| {
+                if added {
+                    format!("Added {} with {} number {}\n", name, label, number)
+                } else {
+                    format!("Added {} number {} to {}\n", label, number, name)
+                }
+            }),
+        Command::Update {
+            name,
+            number,
+            label,
+        } => database
+            .update_number(owner, name, label, number)
+            .map(|_| format!("{}'s {} number is now {}\n", name, label, number)),
+        Command::Get { name } => match database.get_friend(owner, name) {
+            Ok(Some(friend)) => Ok(format_friends(&[friend])),
+            Ok(None) => Ok(format!("{} is not in the database.\n", name)),
+            Err(e) => Err(e.into()),
+        },
+        Command::Delete { name, label: None } => database
+            .delete_friend(owner, name)
+            .map(|_| format!("{} has been removed from the database.\n", name)),
+        Command::Delete {
+            name,
+            label: Some(label),
+        } => database
+            .delete_number(owner, name, label)
+            .map(|_| format!("Removed {}'s {} number.\n", name, label)),
+        Command::List { page } => list(database, owner, *page),
+        Command::Search { text } => database
+            .search(owner, text)
+            .map(|friends| match friends.len() {
+                0 => format!("No friends match \"{}\".\n", text),
+                n => format!("{} found:\n{}", n, format_friends(&friends)),
+            })
+            .map_err(DatabaseError::from),
+        Command::Export(format) => database
+            .all_friends(owner)
+            .map(|friends| {
+                let mut text: String = format.export(&friends).join("\n");
+                text.push('\n');
+                text
+            })
+            .map_err(DatabaseError::from),
+        Command::Users => users(database),
+        Command::DeleteUser { name } if *name == user.name => {
+            Ok(String::from("You cannot delete your own account.\n"))
+        }
+        Command::DeleteUser { name } => database
+            .delete_user(name)
+            .map(|_| format!("Deleted the user {} and their friends.\n", name)),
+        Command::Unlock { name } => database
+            .unlock_user(name)
+            .map(|_| format!("Unlocked the user {}.\n", name)),
+        Command::SetRole { name, .. } if *name == user.name => {
+            Ok(String::from("You cannot change your own role.\n"))
+        }
+        Command::SetRole { name, role } => database
+            .set_role(name, *role)
+            .map(|_| format!("{} is now {} {}.\n", name, article(*role), role.as_str())),
+        Command::Import(_)
+        | Command::Password { .. }
+        | Command::AddUser { .. }
+        | Command::Screen
+        | Command::Help
+        | Command::Exit => Ok(String::new()),
+    };
+
+    match result {
+        Ok(text) => text,
+        Err(e) => format!("{}\n", e),
+    }
+}
+
+/// Imports lines collected after an `IMPORT` command.
+///
+/// # Returns
+/// The text to show the user.
+pub fn import(format: Format, lines: &[String], database: &mut Database, user: &User) -> String {
+    let friends: Vec<Friend> = match format.import(lines) {
+        Ok(friends) => friends,
+        Err(e) => return format!("Nothing was imported. {}\n", e),
+    };
+    match database.import(user.id, &friends) {
+        Ok(written) => format!(
+            "Imported {} numbers of {} friends.\n",
+            written,
+            friends.len()
+        ),
+        Err(e) => format!("Nothing was imported. The database failed: {}\n", e),
+    }
+}
+
+fn list(database: &Database, owner: i64, page: usize) -> Result<String, DatabaseError> {
+    let count: usize = database.friend_count(owner)?;
+    if count == 0 {
+        return Ok(String::from("The database is empty.\n"));
+    }
+    let pages: usize = count.div_ceil(PAGE_SIZE);
+    if page > pages {
+        return Ok(format!("There are only {} pages.\n", pages));
+    }
+
+    let friends: Vec<Friend> = database.list_friends(owner, (page - 1) * PAGE_SIZE, PAGE_SIZE)?;
+    let first: usize = (page - 1) * PAGE_SIZE + 1;
+    let mut text: String = format!(
+        "Friends {}-{} of {} (page {} of {}):\n",
+        first,
+        first + friends.len() - 1,
+        count,
+        page,
+        pages
+    );
+    text.push_str(&format_friends(&friends));
+    if page < pages {
+        text.push_str(&format!("Type LIST {} for more.\n", page + 1));
+    }
+    Ok(text)
+}
+
+/// Lists the accounts with their role, how many friends they have and whether they are locked.
+fn users(database: &Database) -> Result<String, DatabaseError> {
+    let accounts: Vec<Account> = database.accounts()?;
+    let mut text: String = String::from("Users:\n");
+    for account in accounts {
+        let friends: usize = database.friend_count(account.user.id)?;
+        text.push_str(&format!(
+            "    {:<16} {:<6} {:>4} friends",
+            account.user.name,
+            account.user.role.as_str(),
+            friends
+        ));
+        match account.locked_until {
+            Some(until) if until > chrono::Utc::now() => text.push_str(&format!(
+                "  locked until {}",
+                until
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+            )),
+            _ if account.password_hash.is_empty() => text.push_str("  no password"),
+            _ => {}
+        }
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+fn article(role: Role) -> &'static str {
+    match role {
+        Role::User => "a",
+        Role::Admin => "an",
+    }
+}
+
+/// Formats friends with one line per number.
+fn format_friends(friends: &[Friend]) -> String {
+    let mut text: String = String::new();
+    for friend in friends {
+        text.push_str(&format!("{}\n", friend.name));
+        if friend.numbers.is_empty() {
+            text.push_str("    (no numbers)\n");
+        }
+        for number in &friend.numbers {
+            text.push_str(&format!("    {:<10} {}\n", number.label, number.number));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::FIRST_ADMIN;
+
+    #[test]
+    fn test_split_arguments() {
+        assert_eq!(
+            split_arguments(r#"ADD  "Ann \"A\" Lee" 555"0100" """#),
+            Ok(vec![
+                String::from("ADD"),
+                String::from("Ann \"A\" Lee"),
+                String::from("5550100"),
+                String::new()
+            ])
+        );
+        assert!(split_arguments("GET \"Ann").is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let database = Database::new(":memory:").unwrap();
+        let admin: User = database.account(FIRST_ADMIN).unwrap().unwrap().user;
+        let run = |line: &str| match parse_command(line) {
+            Ok(command) => execute(&command, &database, &admin),
+            Err(e) => e,
+        };
+
+        assert_eq!(
+            run("add \"Ann Lee\" 555"),
+            "Added Ann Lee with phone number 555\n"
+        );
+        assert_eq!(
+            run("ADD \"Ann Lee\" 555"),
+            "Ann Lee already has a phone number. Use UPDATE to change it.\n"
+        );
+        assert_eq!(
+            run("ADD \"Ann Lee\" 666 Work"),
+            "Added work number 666 to Ann Lee\n"
+        );
+        assert_eq!(
+            run("GET \"Ann Lee\""),
+            "Ann Lee\n    phone      555\n    work       666\n"
+        );
+        assert_eq!(run("DELETE Bob"), "Bob is not in the database.\n");
+        assert_eq!(run("GET"), "Usage: GET <name>");
+        assert_eq!(
+            run("FETCH Ann"),
+            "Unknown command FETCH. Type HELP for the list of commands."
+        );
+
+        for i in 0..11 {
+            run(&format!("ADD Friend{:02} {}", i, i));
+        }
+        assert!(run("LIST").starts_with("Friends 1-10 of 12 (page 1 of 2):\nAnn Lee\n"));
+        assert!(run("LIST 2").starts_with("Friends 11-12 of 12 (page 2 of 2):\nFriend09\n"));
+        assert_eq!(run("LIST 3"), "There are only 2 pages.\n");
+        assert!(run("SEARCH lee").starts_with("1 found:\nAnn Lee\n"));
+    }
+
+    #[test]
+    fn test_account_commands() {
+        let database = Database::new(":memory:").unwrap();
+        let admin: User = database.account(FIRST_ADMIN).unwrap().unwrap().user;
+        database.add_user("ann", "", Role::User).unwrap();
+        let ann: User = database.account("ann").unwrap().unwrap().user;
+        let run = |line: &str, user: &User| match parse_command(line) {
+            Ok(command) => execute(&command, &database, user),
+            Err(e) => e,
+        };
+
+        assert_eq!(
+            parse_command("adduser \"Ann Lee\" ADMIN"),
+            Ok(Command::AddUser {
+                name: String::from("Ann Lee"),
+                role: Role::Admin
+            })
+        );
+        assert_eq!(
+            run("ROLE ann boss", &admin),
+            "Usage: ROLE <user> USER|ADMIN"
+        );
+        assert_eq!(run("USERS", &ann), "Only admins can manage accounts.\n");
+        assert_eq!(authorize(&Command::Password { name: None }, &ann), Ok(()));
+
+        assert_eq!(run("ROLE ann admin", &admin), "ann is now an admin.\n");
+        assert_eq!(
+            run("ROLE admin user", &admin),
+            "You cannot change your own role.\n"
+        );
+        assert!(run("USERS", &admin).starts_with("Users:\n    admin "));
+        assert_eq!(run("DELUSER bob", &admin), "There is no user bob.\n");
+        assert_eq!(
+            run("DELUSER ann", &admin),
+            "Deleted the user ann and their friends.\n"
+        );
+    }
+}
@@ -1,19 +1,101 @@
-use crate::database::Database;
-use core::str;
+use crate::auth::{self, LoginError, MAX_LOGIN_ATTEMPTS};
+use crate::commands::{self, authorize, execute, parse_command, Command, IMPORT_END};
+use crate::database::{Database, Role, User};
+use crate::export::Format;
+use crate::idle::{IdleEvent, IdleSettings, IdleTimer};
+use crate::line_editor::{Input, LineEditor};
+use crate::screen::{full_screen_size, Action, Screen};
+use crate::session::{Admission, SessionGuard, SessionManager};
+use crate::telnet::{Telnet, ECHO};
 use libc::*;
 use std::error::Error;
+use std::net::SocketAddr;
+use std::os::fd::{FromRawFd, OwnedFd};
 use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use tokio_rustls::TlsAcceptor;
 
 const CLEAR_SCREEN: &str = "\x1B[2J\x1B[H";
 const BOLD: &str = "\x1B[1m";
 const RESET: &str = "\x1B[0m";
 const PINK: &str = "\x1B[35m";
-const CYAN: &str = "\x1B[36m";
-const HEARTBEAT_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(5);
-const TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+const PROMPT: &str = "> ";
+const IMPORT_PROMPT: &str = "| ";
+/// The most lines one import reads. Further lines are ignored.
+const MAX_IMPORT_LINES: usize = 10_000;
+/// How long a client has to finish the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub fn create_raw_socket(port: u16) -> Result<i32, Box<dyn Error>> {
+/// A connection sessions run over, a plain TCP stream or a TLS stream on top of one.
+pub trait ClientStream: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> ClientStream for T {}
+
+/// What the session reads the client's next line as.
+#[derive(Debug)]
+enum Mode {
+    /// The name of the account to log in to.
+    LoginName,
+    /// The password of the named account.
+    LoginPassword(String),
+    Commands,
+    /// The lines of an import in the format.
+    Import(Format, Vec<String>),
+    /// The user's current password, checked before they choose a new one.
+    CurrentPassword,
+    /// A new password for the account, typed twice. Holds the first one once it is typed.
+    NewPassword(PasswordChange, Option<String>),
+}
+
+/// An account whose password is being set.
+#[derive(Debug)]
+struct PasswordChange {
+    name: String,
+    /// The role of a new account, `None` for an existing one.
+    new_role: Option<Role>,
+}
+
+impl Mode {
+    fn prompt(&self) -> &'static str {
+        match self {
+            Mode::LoginName => "login: ",
+            Mode::LoginPassword(_) => "Password: ",
+            Mode::Commands => PROMPT,
+            Mode::Import(_, _) => IMPORT_PROMPT,
+            Mode::CurrentPassword => "Current password: ",
+            Mode::NewPassword(_, None) => "New password: ",
+            Mode::NewPassword(_, Some(_)) => "Repeat the new password: ",
+        }
+    }
+
+    /// Returns true if the line is a password, which is neither echoed nor kept in the history.
+    fn is_secret(&self) -> bool {
+        matches!(
+            self,
+            Mode::LoginPassword(_) | Mode::CurrentPassword | Mode::NewPassword(_, _)
+        )
+    }
+}
+
+/// The state of a session beyond its line editor.
+#[derive(Debug)]
+struct Client {
+    mode: Mode,
+    /// The logged in user.
+    user: Option<User>,
+    failed_logins: u32,
+    /// The full-screen mode, which takes the input instead of the line editor while it is open.
+    screen: Option<Screen>,
+}
+
+/// Creates a TCP socket listening on `port` on all IPv4 interfaces.
+///
+/// # Returns
+/// The socket, closed when it is dropped.
+pub fn create_raw_socket(port: u16) -> Result<OwnedFd, Box<dyn Error>> {
     unsafe {
         // Create a socket
         // AF_INET specifies the IPv4 address fam
@@ -22,9 +104,10 @@ pub fn create_raw_socket(port: u16) -> Result<i32, Box<dyn Error>> {
         let socket_fd = socket(AF_INET, SOCK_STREAM, 0);
 
         if socket_fd < 0 {
-            eprintln!("Failed to create socket");
-            std::process::exit(1);
+            return Err(format!("Failed to create socket: {}", last_error()).into());
         }
+        // The socket is closed on every return from here on
+        let socket: OwnedFd = OwnedFd::from_raw_fd(socket_fd);
 
         // Set socket options
         let option_val: i32 = 1;
@@ -36,8 +119,7 @@ pub fn create_raw_socket(port: u16) -> Result<i32, Box<dyn Error>> {
             std::mem::size_of_val(&option_val) as u32,
         ) < 0
         {
-            eprintln!("Failed to set socket options");
-            std::process::exit(1);
+            return Err(format!("Failed to set socket options: {}", last_error()).into());
         }
 
         // Bind socket to address
@@ -54,209 +136,633 @@ pub fn create_raw_socket(port: u16) -> Result<i32, Box<dyn Error>> {
             std::mem::size_of::<sockaddr_in>() as u32,
         ) < 0
         {
-            eprintln!("Failed to bind socket to address");
-            std::process::exit(1);
+            return Err(format!("Failed to bind socket to address: {}", last_error()).into());
         }
 
         // Start listening at address
         if listen(socket_fd, 128) < 0 {
-            eprintln!("Failed to listen on socket");
-            std::process::exit(1);
+            return Err(format!("Failed to listen on socket: {}", last_error()).into());
         }
 
         println!("Server is listening on port {}", port);
-        Ok(socket_fd)
+        Ok(socket)
     }
 }
 
-pub async fn heartbeat_check(heartbeat_fd: i32, mut heartbeat_rx: tokio::sync::mpsc::Receiver<()>) {
-    unsafe {
-        loop {
-            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
-            let ping_msg = format!(
-                "PING\nPlease respond with {}{}PONG{} if you are still active: \n",
-                BOLD, CYAN, RESET
-            );
-            if write(
-                heartbeat_fd,
-                ping_msg.as_ptr() as *const c_void,
-                ping_msg.len(),
-            ) < 0
-            {
-                break;
-            }
+/// Creates the listening socket with `create_raw_socket` and hands it to Tokio, so that accepting
+/// connections and reading from them waits without blocking the runtime's threads.
+///
+/// Must be called from within the Tokio runtime.
+pub fn create_listener(port: u16) -> Result<TcpListener, Box<dyn Error>> {
+    let listener: std::net::TcpListener = create_raw_socket(port)?.into();
+    listener.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(listener)?)
+}
 
-            if (tokio::time::timeout(TIMEOUT, heartbeat_rx.recv()).await).is_err() {
-                close(heartbeat_fd);
-                break;
+fn last_error() -> std::io::Error {
+    std::io::Error::last_os_error()
+}
+
+/// Accepts clients on the listener for ever, serving each on its own task.
+///
+/// # Arguments
+/// - `listener`: The listening socket.
+/// - `tls`: The TLS configuration of a telnets listener, `None` for plain Telnet.
+/// - `sessions`: The session manager, shared by all listeners.
+/// - `database`: A shared, thread-safe friend database.
+/// - `idle`: How long sessions may stay idle.
+pub async fn accept_clients(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    sessions: Arc<SessionManager>,
+    database: Arc<Mutex<Database>>,
+    idle: IdleSettings,
+) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
             }
-        }
+        };
+
+        let tls_clone: Option<TlsAcceptor> = tls.clone();
+        let sessions_clone: Arc<SessionManager> = Arc::clone(&sessions);
+        let db_clone: Arc<Mutex<Database>> = Arc::clone(&database);
+
+        tokio::spawn(async move {
+            let _ = serve_client(stream, address, tls_clone, sessions_clone, db_clone, idle).await;
+        });
     }
 }
 
-pub async fn connection_loop(
-    heartbeat_tx: tokio::sync::mpsc::Sender<()>,
-    client_fd: i32,
+/// Sets up a new client's connection, completing the TLS handshake on a telnets listener, and
+/// serves it.
+///
+/// # Arguments
+/// - `stream`: The client's connection.
+/// - `address`: The client's address.
+/// - `tls`: The TLS configuration if the client connected to the telnets listener.
+/// - `sessions`: The session manager enforcing the session limits.
+/// - `database`: A shared, thread-safe friend database.
+/// - `idle`: How long the session may stay idle.
+pub async fn serve_client(
+    stream: TcpStream,
+    address: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    sessions: Arc<SessionManager>,
     database: Arc<Mutex<Database>>,
-) {
-    unsafe {
-        let mut buffer: [u8; 1024] = [0; 1024];
+    idle: IdleSettings,
+) -> Result<(), Box<dyn Error>> {
+    // TCP keepalive finds peers that vanish without closing the connection, including clients
+    // that never answer TIMING-MARK requests
+    let keepalive = socket2::TcpKeepalive::new()
+        .with_time(idle.probe_interval)
+        .with_interval(idle.probe_timeout);
+    socket2::SockRef::from(&stream).set_tcp_keepalive(&keepalive)?;
 
-        loop {
-            let welcome_msg: String =
-                format!("Available Commands:\n1) ADD <name> <phone> {}- Add a new friend{}\n2) GET <name> {}- Retrieve a friend's phone number{}\n3) DELETE <name> {}- Remove a friend from the database{}\nEXIT {}- Disconnect from the server{}\n",PINK,RESET,PINK,RESET,PINK,RESET,PINK,RESET);
+    match tls {
+        Some(acceptor) => {
+            let stream = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                .await
+                .map_err(|_| format!("The TLS handshake with {} timed out", address))??;
+            admit_client(stream, address, sessions, database, idle).await
+        }
+        None => admit_client(stream, address, sessions, database, idle).await,
+    }
+}
 
-            write(
-                client_fd,
-                welcome_msg.as_ptr() as *const c_void,
-                welcome_msg.len(),
+/// Admits a new client to a session, queueing it if the server is full and queueing is enabled,
+/// and runs the session. Clients that cannot be admitted are told why and disconnected.
+///
+/// # Arguments
+/// - `stream`: The client's connection.
+/// - `address`: The client's address.
+/// - `sessions`: The session manager enforcing the session limits.
+/// - `database`: A shared, thread-safe friend database.
+/// - `idle`: How long the session may stay idle.
+async fn admit_client(
+    mut stream: impl ClientStream,
+    address: SocketAddr,
+    sessions: Arc<SessionManager>,
+    database: Arc<Mutex<Database>>,
+    idle: IdleSettings,
+) -> Result<(), Box<dyn Error>> {
+    let mut admission: Admission = sessions.try_admit(address);
+    if matches!(admission, Admission::Busy) && sessions.limits().queue_timeout.is_some() {
+        let queued_msg: String = format!(
+            "The server is busy. You are number {} in the queue, please wait...\n",
+            sessions.queue_length() + 1
+        );
+        send(&mut stream, &queued_msg).await?;
+        admission = sessions.admit_queued(address).await;
+    }
+
+    match admission {
+        Admission::Admitted(session) => {
+            handle_telnet_connection(stream, session, database, idle).await
+        }
+        Admission::Busy => {
+            let busy_msg: &str = "The server is busy. Please try again later.\n";
+            send(&mut stream, busy_msg).await?;
+            Ok(())
+        }
+        Admission::TooManyFromAddress => {
+            let limit_msg: String = format!(
+                "Too many sessions from your address. At most {} are allowed.\n",
+                sessions.limits().max_per_address
             );
-            // Read the input from the client
-            let bytes_read = read(client_fd, buffer.as_mut_ptr() as *mut c_void, buffer.len());
-            if bytes_read <= 0 {
-                break;
-            }
+            send(&mut stream, &limit_msg).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Runs a session until the client disconnects, exits, stays idle, stops answering or is kicked.
+///
+/// # Arguments
+/// - `stream`: The client's connection, closed when the session ends.
+/// - `session`: The client's session, released when the session ends.
+/// - `database`: A shared, thread-safe friend database.
+/// - `idle`: How long the session may stay idle.
+pub async fn handle_telnet_connection(
+    mut stream: impl ClientStream,
+    session: SessionGuard,
+    database: Arc<Mutex<Database>>,
+    idle: IdleSettings,
+) -> Result<(), Box<dyn Error>> {
+    let welcome_msg: String = format!(
+        "{}{}Welcome to the Telnet Friend Database!{}\n",
+        CLEAR_SCREEN, BOLD, RESET
+    );
+    send(&mut stream, &welcome_msg).await?;
+
+    connection_loop(&mut stream, &session, &database, idle).await?;
+    // Ends a TLS session with a close_notify alert
+    stream.shutdown().await?;
+    Ok(())
+}
 
-            let mut input = str::from_utf8(&buffer[0..bytes_read as usize])
-                .unwrap_or_default()
-                .split_whitespace();
+async fn connection_loop(
+    stream: &mut impl ClientStream,
+    session: &SessionGuard,
+    database: &Arc<Mutex<Database>>,
+    idle: IdleSettings,
+) -> std::io::Result<()> {
+    let mut buffer: [u8; 1024] = [0; 1024];
+    let mut telnet: Telnet = Telnet::new();
+    let mut client = Client {
+        mode: Mode::LoginName,
+        user: None,
+        failed_logins: 0,
+        screen: None,
+    };
+    let mut editor: LineEditor = LineEditor::new(client.mode.prompt());
+    let mut idle_timer: IdleTimer = IdleTimer::new(idle, Instant::now());
 
-            let command = input.next();
+    let negotiation: Vec<u8> = telnet.start();
+    stream.write_all(&negotiation).await?;
 
-            match command {
-                Some("PONG") | Some("pong") => {
-                    let _ = heartbeat_tx.send(()).await;
-                }
-                Some("ADD") | Some("add") | Some("Add") => {
-                    if let (Some(name), Some(phone)) = (input.next(), input.next()) {
-                        match database.lock().await.add_friend(name, phone) {
-                            Ok(_) => {
-                                let response: String =
-                                    format!("Added {} with number {}\n", name, phone);
-                                write(
-                                    client_fd,
-                                    response.as_ptr() as *const c_void,
-                                    response.len(),
-                                );
+    send(stream, "Please log in.\n").await?;
+    send(stream, client.mode.prompt()).await?;
+
+    loop {
+        // Read the input from the client, or check on it while it is quiet
+        let probing: bool = telnet.is_telnet_client();
+        let bytes_read: usize = tokio::select! {
+            result = stream.read(&mut buffer) => result?,
+            _ = tokio::time::sleep_until(idle_timer.deadline(probing)) => {
+                match idle_timer.poll(Instant::now(), probing) {
+                    Some(IdleEvent::Warn(remaining)) => {
+                        let warning: String = format!(
+                            "You have been idle for a while and will be disconnected in {} seconds.",
+                            remaining.as_secs_f64().round()
+                        );
+                        match client.screen.as_mut() {
+                            Some(screen) => {
+                                screen.set_status(&warning);
+                                stream.write_all(&crate::telnet::escape(&screen.render())).await?;
                             }
-                            Err(e) => {
-                                let response: String =
-                                    format!("Error adding friend to the database:{:?}\n", e);
-                                write(
-                                    client_fd,
-                                    response.as_ptr() as *const c_void,
-                                    response.len(),
-                                );
+                            None => {
+                                let warning_msg: String = format!("\n{}{}{}\n", BOLD, warning, RESET);
+                                send(stream, &warning_msg).await?;
+                                stream.write_all(&crate::telnet::escape(&editor.redraw())).await?;
                             }
                         }
                     }
-                }
-                Some("GET") | Some("get") | Some("Get") => {
-                    if let Some(name) = input.next() {
-                        match database.lock().await.get_friend(name) {
-                            Ok(Some(phone)) => {
-                                let response: String = format!("{} : {}\n", name, phone);
-                                write(
-                                    client_fd,
-                                    response.as_ptr() as *const c_void,
-                                    response.len(),
-                                );
-                            }
-                            Ok(None) => {
-                                let response: String = String::from("Error friend not found\n");
-                                write(
-                                    client_fd,
-                                    response.as_ptr() as *const c_void,
-                                    response.len(),
-                                );
-                            }
-                            Err(e) => {
-                                let response: String = format!("Error retrieving friend:{:?}\n", e);
-                                write(
-                                    client_fd,
-                                    response.as_ptr() as *const c_void,
-                                    response.len(),
-                                );
-                            }
-                        }
+                    Some(IdleEvent::Disconnect) => {
+                        let idle_msg: String = format!(
+                            "\nDisconnected after {} seconds of inactivity.\n",
+                            idle.timeout.as_secs()
+                        );
+                        close_screen(stream, &mut client).await?;
+                        send(stream, &idle_msg).await?;
+                        return Ok(());
                     }
+                    Some(IdleEvent::Probe) => stream.write_all(&telnet.timing_mark()).await?,
+                    Some(IdleEvent::PeerDead) => return Ok(()),
+                    None => {}
                 }
-                Some("DELETE") | Some("delete") | Some("Delete") => {
-                    if let Some(name) = input.next() {
-                        match database.lock().await.delete_friend(name) {
-                            Ok(_) => {
-                                let response: String =
-                                    format!("{} has been removed from the database.\n", name);
-                                write(
-                                    client_fd,
-                                    response.as_ptr() as *const c_void,
-                                    response.len(),
-                                );
-                            }
-                            Err(e) => {
-                                let response: String = format!("Error removing friend:{:?}\n", e);
-                                write(
-                                    client_fd,
-                                    response.as_ptr() as *const c_void,
-                                    response.len(),
-                                );
-                            }
-                        }
-                    }
+                continue;
+            }
+            _ = session.kicked() => {
+                let kicked_msg: &str = "\nYou have been disconnected by the operator.\n";
+                close_screen(stream, &mut client).await?;
+                send(stream, kicked_msg).await?;
+                return Ok(());
+            }
+        };
+        if bytes_read == 0 {
+            return Ok(());
+        }
+
+        // Answer option negotiation and strip Telnet commands from the input
+        let (text, reply) = telnet.receive(&buffer[0..bytes_read]);
+        stream.write_all(&reply).await?;
+        idle_timer.received(Instant::now(), !text.is_empty());
+
+        // Character mode and a window size the screen fits in are needed for the full-screen mode
+        let screen_size: Option<(u16, u16)> = if telnet.local_enabled(ECHO) {
+            full_screen_size(telnet.terminal_type.as_deref(), telnet.window_size)
+        } else {
+            None
+        };
+        if client.screen.is_some() {
+            if !handle_screen(&text, screen_size, stream, database, &mut client).await? {
+                return Ok(());
+            }
+            if client.screen.is_none() {
+                send(stream, client.mode.prompt()).await?;
+            }
+            continue;
+        }
+
+        // Friend names are only looked up when the user asks for completion
+        let names: Vec<String> = match &client.user {
+            Some(user) if text.contains(&b'\t') => database
+                .lock()
+                .await
+                .friend_names(user.id)
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        let echo: Vec<u8> = editor.feed(&text, telnet.local_enabled(ECHO), &names);
+        stream.write_all(&crate::telnet::escape(&echo)).await?;
+
+        while let Some(input) = editor.next_input() {
+            if !handle_input(input, screen_size, stream, session, database, &mut client).await? {
+                return Ok(());
+            }
+            // Input typed ahead of the full-screen mode is dropped
+            if client.screen.is_some() {
+                while editor.next_input().is_some() {}
+                break;
+            }
+            editor.set_prompt(client.mode.prompt());
+            editor.set_secret(client.mode.is_secret());
+            send(stream, client.mode.prompt()).await?;
+        }
+    }
+}
+
+/// Acts on a line or end of input from the client according to the session's mode.
+///
+/// # Returns
+/// False if the session must end.
+async fn handle_input(
+    input: Input,
+    screen_size: Option<(u16, u16)>,
+    stream: &mut impl ClientStream,
+    session: &SessionGuard,
+    database: &Arc<Mutex<Database>>,
+    client: &mut Client,
+) -> std::io::Result<bool> {
+    // Ctrl-D ends the session, or an import
+    let line: String = match input {
+        Input::Line(line) => line,
+        Input::End if matches!(client.mode, Mode::Import(_, _)) => String::from(IMPORT_END),
+        Input::End if client.user.is_some() => String::from("EXIT"),
+        Input::End => return Ok(false),
+    };
+
+    match std::mem::replace(&mut client.mode, Mode::Commands) {
+        Mode::LoginName if line.trim().is_empty() => client.mode = Mode::LoginName,
+        Mode::LoginName => client.mode = Mode::LoginPassword(line.trim().to_string()),
+        Mode::LoginPassword(name) => match auth::login(database, &name, &line).await {
+            Ok(user) => {
+                session.set_user(&user.name);
+                let welcome_msg: String = format!("Logged in as {}{}{}.\n", BOLD, user.name, RESET);
+                send(stream, &welcome_msg).await?;
+                let role: Role = user.role;
+                client.user = Some(user);
+                match screen_size {
+                    Some(size) => open_screen(size, stream, database, client).await?,
+                    None => send(stream, &menu(role)).await?,
                 }
-                Some("EXIT") | Some("exit") => {
-                    let goodbye_msg: &str = "Goodbye!\n";
-                    write(
-                        client_fd,
-                        goodbye_msg.as_ptr() as *const c_void,
-                        goodbye_msg.len(),
+            }
+            Err(e) => {
+                client.failed_logins += 1;
+                // A locked account is refused like a wrong password, so only the log says why
+                if let LoginError::Locked(until) = e {
+                    println!(
+                        "Refused a login to the locked account {} until {}",
+                        name,
+                        until.with_timezone(&chrono::Local).format("%H:%M:%S")
                     );
-                    close(client_fd);
-                    break;
                 }
-                _ => {
-                    let error_msg: &str = "Invalid input. Please enter a valid command.\n";
-                    write(
-                        client_fd,
-                        error_msg.as_ptr() as *const c_void,
-                        error_msg.len(),
-                    );
+                send(stream, "Login incorrect.\n").await?;
+                if client.failed_logins >= MAX_LOGIN_ATTEMPTS {
+                    send(stream, "Too many failed logins.\n").await?;
+                    return Ok(false);
                 }
+                client.mode = Mode::LoginName;
             }
+        },
+        Mode::Commands if line.trim().is_empty() => {}
+        Mode::Commands => {
+            return handle_command(&line, screen_size, stream, database, client).await
+        }
+        Mode::Import(format, mut lines) => {
+            if !line.trim().eq_ignore_ascii_case(IMPORT_END) {
+                if lines.len() < MAX_IMPORT_LINES {
+                    lines.push(line);
+                }
+                client.mode = Mode::Import(format, lines);
+            } else if let Some(user) = &client.user {
+                let response: String =
+                    commands::import(format, &lines, &mut *database.lock().await, user);
+                send(stream, &response).await?;
+            }
+        }
+        Mode::CurrentPassword => {
+            let Some(user) = &client.user else {
+                return Ok(false);
+            };
+            match auth::login(database, &user.name, &line).await {
+                Ok(_) => {
+                    let change = PasswordChange {
+                        name: user.name.clone(),
+                        new_role: None,
+                    };
+                    client.mode = Mode::NewPassword(change, None);
+                }
+                Err(_) => send(stream, "The password is wrong.\n").await?,
+            }
+        }
+        Mode::NewPassword(change, None) => match auth::check_new_password(&line) {
+            Ok(()) => client.mode = Mode::NewPassword(change, Some(line)),
+            Err(message) => send(stream, &format!("{}\n", message)).await?,
+        },
+        Mode::NewPassword(_, Some(first)) if first != line => {
+            send(stream, "The passwords do not match.\n").await?;
+        }
+        Mode::NewPassword(change, Some(password)) => {
+            let hash: String = auth::hash_password_blocking(password).await;
+            let database = database.lock().await;
+            let response: String = match change.new_role {
+                Some(role) => database
+                    .add_user(&change.name, &hash, role)
+                    .map(|_| format!("Added the {} {}.\n", role.as_str(), change.name)),
+                None => database
+                    .set_password(&change.name, &hash)
+                    .map(|_| format!("Changed the password of {}.\n", change.name)),
+            }
+            .unwrap_or_else(|e| format!("{}\n", e));
+            send(stream, &response).await?;
         }
     }
+    Ok(true)
 }
 
-pub async fn handle_telnet_connection(
-    client_fd: i32,
-    database: Arc<Mutex<Database>>,
-) -> Result<(), Box<dyn Error>> {
-    unsafe {
-        let welcome_msg: String = format!(
-            "{}{}Welcome to the Telnet Friend Database!{}\n",
-            CLEAR_SCREEN, BOLD, RESET
-        );
-
-        write(
-            client_fd,
-            welcome_msg.as_ptr() as *const c_void,
-            welcome_msg.len(),
-        );
+/// Runs one command line from the logged in user.
+///
+/// # Returns
+/// False if the client asked to disconnect.
+async fn handle_command(
+    line: &str,
+    screen_size: Option<(u16, u16)>,
+    stream: &mut impl ClientStream,
+    database: &Arc<Mutex<Database>>,
+    client: &mut Client,
+) -> std::io::Result<bool> {
+    let Some(user) = &client.user else {
+        return Ok(false);
+    };
+    let command: Result<Command, String> =
+        parse_command(line).and_then(|command| authorize(&command, user).map(|_| command));
 
-        let (heartbeat_tx, heartbeat_rx) = tokio::sync::mpsc::channel::<()>(1);
-        let connection_task = tokio::spawn(connection_loop(
-            heartbeat_tx,
-            client_fd,
-            Arc::clone(&database),
-        ));
-        let heartbeat_task = tokio::spawn(heartbeat_check(client_fd, heartbeat_rx));
-
-        tokio::select! {
-            _ = connection_task => {
-            },
-            _ = heartbeat_task => {
-            },
+    match command {
+        Ok(Command::Exit) => {
+            let goodbye_msg: &str = "Goodbye!\n";
+            send(stream, goodbye_msg).await?;
+            return Ok(false);
         }
+        Ok(Command::Help) => send(stream, &menu(user.role)).await?,
+        Ok(Command::Import(format)) => {
+            let import_msg: String = format!(
+                "Paste the data to import, then type {} on a line of its own.\n",
+                IMPORT_END
+            );
+            send(stream, &import_msg).await?;
+            client.mode = Mode::Import(format, Vec::new());
+        }
+        Ok(Command::Screen) => match screen_size {
+            Some(size) => open_screen(size, stream, database, client).await?,
+            None => {
+                let dumb_msg: &str =
+                    "Your terminal did not report a window size large enough for the full-screen mode.\n";
+                send(stream, dumb_msg).await?;
+            }
+        },
+        Ok(Command::Password { name: None }) => client.mode = Mode::CurrentPassword,
+        Ok(Command::Password { name: Some(name) }) => {
+            // Admins set other users' passwords without knowing the old ones
+            if database
+                .lock()
+                .await
+                .account(&name)
+                .ok()
+                .flatten()
+                .is_none()
+            {
+                send(stream, &format!("There is no user {}.\n", name)).await?;
+            } else {
+                let change = PasswordChange {
+                    name,
+                    new_role: None,
+                };
+                client.mode = Mode::NewPassword(change, None);
+            }
+        }
+        Ok(Command::AddUser { name, role }) => {
+            if database
+                .lock()
+                .await
+                .account(&name)
+                .ok()
+                .flatten()
+                .is_some()
+            {
+                send(stream, &format!("The user {} already exists.\n", name)).await?;
+            } else {
+                let change = PasswordChange {
+                    name,
+                    new_role: Some(role),
+                };
+                client.mode = Mode::NewPassword(change, None);
+            }
+        }
+        Ok(command) => {
+            let response: String = execute(&command, &*database.lock().await, user);
+            send(stream, &response).await?;
+        }
+        Err(usage) => {
+            send(stream, &format!("{}\n", usage)).await?;
+        }
+    }
+    Ok(true)
+}
+
+/// Opens the full-screen mode with the user's friends.
+async fn open_screen(
+    size: (u16, u16),
+    stream: &mut impl ClientStream,
+    database: &Arc<Mutex<Database>>,
+    client: &mut Client,
+) -> std::io::Result<()> {
+    let Some(user) = &client.user else {
+        return Ok(());
+    };
+    let mut screen: Screen = Screen::new(&user.name, size);
+    screen.set_friends(
+        database
+            .lock()
+            .await
+            .all_friends(user.id)
+            .unwrap_or_default(),
+    );
+    stream
+        .write_all(&crate::telnet::escape(&screen.enter()))
+        .await?;
+    client.screen = Some(screen);
+    Ok(())
+}
+
+/// Closes the full-screen mode, if it is open, and returns the terminal to the command line.
+async fn close_screen(stream: &mut impl ClientStream, client: &mut Client) -> std::io::Result<()> {
+    if client.screen.take().is_some() {
+        stream.write_all(&Screen::leave()).await?;
     }
     Ok(())
 }
+
+/// Passes input to the full-screen mode and runs what it asks for.
+///
+/// # Arguments
+/// - `screen_size`: The window size, which may have changed since the screen was drawn.
+///
+/// # Returns
+/// False if the user quit.
+async fn handle_screen(
+    text: &[u8],
+    screen_size: Option<(u16, u16)>,
+    stream: &mut impl ClientStream,
+    database: &Arc<Mutex<Database>>,
+    client: &mut Client,
+) -> std::io::Result<bool> {
+    let Some(user) = client.user.clone() else {
+        return Ok(true);
+    };
+    let Some(screen) = client.screen.as_mut() else {
+        return Ok(true);
+    };
+    if !screen_size.is_some_and(|size| screen.resize(size)) {
+        close_screen(stream, client).await?;
+        let small_msg: &str = "The window is too small for the full-screen mode.\n";
+        send(stream, small_msg).await?;
+        return Ok(true);
+    }
+
+    screen.feed(text);
+    while let Some(action) = screen.next_action() {
+        match action {
+            Action::Run(commands) => {
+                let database = database.lock().await;
+                let status: Vec<String> = commands
+                    .iter()
+                    .map(|command| execute(command, &database, &user).trim().to_string())
+                    .collect();
+                screen.set_friends(database.all_friends(user.id).unwrap_or_default());
+                screen.set_status(&status.join(" "));
+            }
+            Action::LineMode => {
+                close_screen(stream, client).await?;
+                send(stream, &menu(user.role)).await?;
+                return Ok(true);
+            }
+            Action::Quit => {
+                close_screen(stream, client).await?;
+                send(stream, "Goodbye!\n").await?;
+                return Ok(false);
+            }
+        }
+    }
+    let output: Vec<u8> = screen.render();
+    stream.write_all(&crate::telnet::escape(&output)).await?;
+    Ok(true)
+}
+
+/// Returns the list of commands shown after logging in and on `HELP`, with the account commands
+/// for admins.
+fn menu(role: Role) -> String {
+    let commands: [(&str, &str); 12] = [
+        (
+            "ADD <name> <number> [<label>]",
+            "Add a friend or another number",
+        ),
+        (
+            "UPDATE <name> <number> [<label>]",
+            "Change a friend's number",
+        ),
+        ("GET <name>", "Show a friend's numbers"),
+        (
+            "DELETE <name> [<label>]",
+            "Remove a friend or one of their numbers",
+        ),
+        ("LIST [<page>]", "List friends, 10 per page"),
+        ("SEARCH <text>", "Find friends by name or number"),
+        ("EXPORT CSV|VCARD", "Print the database as CSV or vCards"),
+        ("IMPORT CSV|VCARD", "Add friends from pasted CSV or vCards"),
+        ("PASSWORD", "Change your password"),
+        ("SCREEN", "Browse your friends in a full-screen table"),
+        ("HELP", "Show this list"),
+        ("EXIT", "Disconnect from the server"),
+    ];
+    let admin_commands: [(&str, &str); 6] = [
+        ("USERS", "List the accounts"),
+        ("ADDUSER <user> [USER|ADMIN]", "Create an account"),
+        ("DELUSER <user>", "Delete an account and its friends"),
+        ("PASSWORD <user>", "Set the password of an account"),
+        ("ROLE <user> USER|ADMIN", "Change the role of an account"),
+        ("UNLOCK <user>", "Unlock an account after failed logins"),
+    ];
+
+    let mut menu: String = String::from("Available Commands:\n");
+    for (usage, description) in commands {
+        menu.push_str(&format!("{} {}- {}{}\n", usage, PINK, description, RESET));
+    }
+    if role == Role::Admin {
+        menu.push_str("Account Commands:\n");
+        for (usage, description) in admin_commands {
+            menu.push_str(&format!("{} {}- {}{}\n", usage, PINK, description, RESET));
+        }
+    }
+    menu.push_str("Put names or numbers with spaces in double quotes.\n");
+    menu
+}
+
+/// Writes text to the client in the network virtual terminal's format.
+async fn send(stream: &mut impl ClientStream, text: &str) -> std::io::Result<()> {
+    stream.write_all(&crate::telnet::encode(text)).await
+}
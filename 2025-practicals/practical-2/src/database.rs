@@ -1,47 +1,690 @@
-use rusqlite::{params, Connection};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fmt;
+
+/// The label of numbers added without one.
+pub const DEFAULT_LABEL: &str = "phone";
+
+/// The account created with a new database, which owns any friends added before there were
+/// accounts.
+pub const FIRST_ADMIN: &str = "admin";
+
+/// A friend and their numbers, ordered by label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Friend {
+    pub name: String,
+    pub numbers: Vec<Number>,
+}
+
+/// A phone number and what it is, such as `mobile` or `work`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Number {
+    pub label: String,
+    pub number: String,
+}
+
+/// What an account may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Manages their own friends.
+    User,
+    /// Also manages the accounts.
+    Admin,
+}
+
+impl Role {
+    pub fn parse(name: &str) -> Option<Role> {
+        match name.to_lowercase().as_str() {
+            "user" => Some(Role::User),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// A logged in user. Every friend belongs to one user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub role: Role,
+}
+
+/// A user's account with what is needed to log them in.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub user: User,
+    /// The Argon2 hash in PHC string format, empty while the account has no password.
+    pub password_hash: String,
+    /// Failed logins since the last successful one.
+    pub failed_logins: u32,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// Why a database command failed, worded for the user.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// No friend has the name.
+    FriendNotFound(String),
+    /// The friend has no number with the label.
+    NumberNotFound(String, String),
+    /// The friend already has a number with the label.
+    DuplicateNumber(String, String),
+    /// No account has the name.
+    UserNotFound(String),
+    /// An account with the name already exists.
+    DuplicateUser(String),
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::FriendNotFound(name) => write!(f, "{} is not in the database.", name),
+            DatabaseError::NumberNotFound(name, label) => {
+                write!(f, "{} has no {} number.", name, label)
+            }
+            DatabaseError::DuplicateNumber(name, label) => write!(
+                f,
+                "{} already has a {} number. Use UPDATE to change it.",
+                name, label
+            ),
+            DatabaseError::UserNotFound(name) => write!(f, "There is no user {}.", name),
+            DatabaseError::DuplicateUser(name) => write!(f, "The user {} already exists.", name),
+            DatabaseError::Sqlite(e) => write!(f, "The database failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<rusqlite::Error> for DatabaseError {
+    fn from(e: rusqlite::Error) -> Self {
+        DatabaseError::Sqlite(e)
+    }
+}
 
 pub struct Database {
     conn: Connection,
 }
 
 impl Database {
+    /// Opens the database, creating the tables and the first admin account if they are missing.
+    /// The first admin has no password until one is set, so nobody can log in with it yet.
     pub fn new(db_path: &str) -> rusqlite::Result<Self> {
         let conn = Connection::open(db_path)?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS friends (
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE IF NOT EXISTS users (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 name TEXT NOT NULL UNIQUE,
-                phone TEXT NOT NULL
+                password_hash TEXT NOT NULL,
+                role TEXT NOT NULL,
+                failed_logins INTEGER NOT NULL DEFAULT 0,
+                locked_until INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS friends (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                owner_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                UNIQUE (owner_id, name)
+            );
+            CREATE TABLE IF NOT EXISTS numbers (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                friend_id INTEGER NOT NULL REFERENCES friends (id) ON DELETE CASCADE,
+                label TEXT NOT NULL,
+                number TEXT NOT NULL,
+                UNIQUE (friend_id, label)
             );",
-            [],
         )?;
+        migrate_phone_column(&conn)?;
+
+        let has_users: bool = conn.prepare("SELECT 1 FROM users;")?.exists([])?;
+        if !has_users {
+            conn.execute(
+                "INSERT INTO users (name, password_hash, role) VALUES (?1, '', ?2);",
+                params![FIRST_ADMIN, Role::Admin.as_str()],
+            )?;
+        }
+        migrate_owner_column(&conn)?;
         Ok(Self { conn })
     }
 
-    pub fn add_friend(&self, name: &str, phone: &str) -> rusqlite::Result<()> {
+    /// Adds a number to a friend, adding the friend if they are new.
+    ///
+    /// # Returns
+    /// True if the friend was added.
+    pub fn add_number(
+        &self,
+        owner: i64,
+        name: &str,
+        label: &str,
+        number: &str,
+    ) -> Result<bool, DatabaseError> {
+        let (friend_id, added) = match self.friend_id(owner, name)? {
+            Some(id) => (id, false),
+            None => {
+                self.conn.execute(
+                    "INSERT INTO friends (owner_id, name) VALUES (?1, ?2);",
+                    params![owner, name],
+                )?;
+                (self.conn.last_insert_rowid(), true)
+            }
+        };
+
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO numbers (friend_id, label, number) VALUES (?1, ?2, ?3);",
+            params![friend_id, label, number],
+        )?;
+        if inserted == 0 {
+            return Err(DatabaseError::DuplicateNumber(
+                name.to_string(),
+                label.to_string(),
+            ));
+        }
+        Ok(added)
+    }
+
+    /// Sets a friend's number with the given label, adding it if they have none.
+    pub fn update_number(
+        &self,
+        owner: i64,
+        name: &str,
+        label: &str,
+        number: &str,
+    ) -> Result<(), DatabaseError> {
+        let friend_id: i64 = self
+            .friend_id(owner, name)?
+            .ok_or_else(|| DatabaseError::FriendNotFound(name.to_string()))?;
         self.conn.execute(
-            "INSERT INTO friends (name, phone) VALUES (?1, ?2);",
-            params![name, phone],
+            "INSERT INTO numbers (friend_id, label, number) VALUES (?1, ?2, ?3)
+            ON CONFLICT (friend_id, label) DO UPDATE SET number = excluded.number;",
+            params![friend_id, label, number],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_friend(&self, owner: i64, name: &str) -> rusqlite::Result<Option<Friend>> {
+        let friends: Vec<Friend> = self.query_friends(
+            "WHERE f.owner_id = ?1 AND f.name = ?2",
+            params![owner, name],
+        )?;
+        Ok(friends.into_iter().next())
+    }
+
+    pub fn delete_friend(&self, owner: i64, name: &str) -> Result<(), DatabaseError> {
+        let deleted = self.conn.execute(
+            "DELETE FROM friends WHERE owner_id = ?1 AND name = ?2;",
+            params![owner, name],
+        )?;
+        if deleted == 0 {
+            return Err(DatabaseError::FriendNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Removes one of a friend's numbers. Friends keep their entry when their last number is
+    /// removed.
+    pub fn delete_number(&self, owner: i64, name: &str, label: &str) -> Result<(), DatabaseError> {
+        let friend_id: i64 = self
+            .friend_id(owner, name)?
+            .ok_or_else(|| DatabaseError::FriendNotFound(name.to_string()))?;
+        let deleted = self.conn.execute(
+            "DELETE FROM numbers WHERE friend_id = ?1 AND label = ?2;",
+            params![friend_id, label],
         )?;
+        if deleted == 0 {
+            return Err(DatabaseError::NumberNotFound(
+                name.to_string(),
+                label.to_string(),
+            ));
+        }
         Ok(())
     }
 
-    pub fn get_friend(&self, name: &str) -> rusqlite::Result<Option<String>> {
+    pub fn friend_count(&self, owner: i64) -> rusqlite::Result<usize> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM friends WHERE owner_id = ?1;",
+            params![owner],
+            |row| row.get(0),
+        )
+    }
+
+    /// Returns up to `limit` friends in alphabetical order, skipping the first `offset`.
+    pub fn list_friends(
+        &self,
+        owner: i64,
+        offset: usize,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<Friend>> {
+        self.query_friends(
+            "WHERE f.id IN (
+                SELECT id FROM friends WHERE owner_id = ?1 ORDER BY name LIMIT ?2 OFFSET ?3
+            )",
+            params![owner, limit as i64, offset as i64],
+        )
+    }
+
+    /// Returns the friends whose name or one of whose numbers contains `text`, ignoring case.
+    pub fn search(&self, owner: i64, text: &str) -> rusqlite::Result<Vec<Friend>> {
+        let pattern: String = format!(
+            "%{}%",
+            text.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        self.query_friends(
+            "WHERE f.owner_id = ?1 AND (f.name LIKE ?2 ESCAPE '\\' OR f.id IN (
+                SELECT friend_id FROM numbers WHERE number LIKE ?2 ESCAPE '\\'
+            ))",
+            params![owner, pattern],
+        )
+    }
+
+    /// Returns every friend in alphabetical order.
+    pub fn all_friends(&self, owner: i64) -> rusqlite::Result<Vec<Friend>> {
+        self.query_friends("WHERE f.owner_id = ?1", params![owner])
+    }
+
+    /// Adds or updates friends in one transaction, so nothing is imported if anything fails.
+    ///
+    /// # Returns
+    /// The number of numbers written.
+    pub fn import(&mut self, owner: i64, friends: &[Friend]) -> rusqlite::Result<usize> {
+        let transaction = self.conn.transaction()?;
+        let mut written: usize = 0;
+        for friend in friends {
+            transaction.execute(
+                "INSERT OR IGNORE INTO friends (owner_id, name) VALUES (?1, ?2);",
+                params![owner, friend.name],
+            )?;
+            for number in &friend.numbers {
+                written += transaction.execute(
+                    "INSERT INTO numbers (friend_id, label, number)
+                    VALUES ((SELECT id FROM friends WHERE owner_id = ?1 AND name = ?2), ?3, ?4)
+                    ON CONFLICT (friend_id, label) DO UPDATE SET number = excluded.number;",
+                    params![owner, friend.name, number.label, number.number],
+                )?;
+            }
+        }
+        transaction.commit()?;
+        Ok(written)
+    }
+
+    /// Returns the names of all friends in alphabetical order.
+    pub fn friend_names(&self, owner: i64) -> rusqlite::Result<Vec<String>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT phone FROM friends WHERE name = ?1;")?;
-        let mut rows = stmt.query(params![name])?;
-        if let Some(row) = rows.next()? {
-            let phone: String = row.get(0)?;
-            Ok(Some(phone))
-        } else {
-            Ok(None)
+            .prepare("SELECT name FROM friends WHERE owner_id = ?1 ORDER BY name;")?;
+        let names = stmt.query_map(params![owner], |row| row.get(0))?;
+        names.collect()
+    }
+
+    /// Creates an account.
+    ///
+    /// # Arguments
+    /// - `password_hash`: The Argon2 hash of the account's password.
+    pub fn add_user(
+        &self,
+        name: &str,
+        password_hash: &str,
+        role: Role,
+    ) -> Result<(), DatabaseError> {
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO users (name, password_hash, role) VALUES (?1, ?2, ?3);",
+            params![name, password_hash, role.as_str()],
+        )?;
+        if inserted == 0 {
+            return Err(DatabaseError::DuplicateUser(name.to_string()));
         }
+        Ok(())
     }
 
-    pub fn delete_friend(&self, name: &str) -> rusqlite::Result<()> {
-        self.conn
-            .execute("DELETE FROM friends WHERE name = ?1;", params![name])?;
+    /// Removes an account and all the friends it owns.
+    pub fn delete_user(&self, name: &str) -> Result<(), DatabaseError> {
+        self.update_user(name, "DELETE FROM users WHERE name = ?1;", params![name])
+    }
+
+    pub fn set_password(&self, name: &str, password_hash: &str) -> Result<(), DatabaseError> {
+        self.update_user(
+            name,
+            "UPDATE users SET password_hash = ?2 WHERE name = ?1;",
+            params![name, password_hash],
+        )
+    }
+
+    pub fn set_role(&self, name: &str, role: Role) -> Result<(), DatabaseError> {
+        self.update_user(
+            name,
+            "UPDATE users SET role = ?2 WHERE name = ?1;",
+            params![name, role.as_str()],
+        )
+    }
+
+    /// Clears an account's failed logins and lifts its lockout.
+    pub fn unlock_user(&self, name: &str) -> Result<(), DatabaseError> {
+        self.update_user(
+            name,
+            "UPDATE users SET failed_logins = 0, locked_until = NULL WHERE name = ?1;",
+            params![name],
+        )
+    }
+
+    /// Counts a failed login for the account.
+    ///
+    /// # Returns
+    /// The failed logins since the last successful one.
+    pub fn record_failed_login(&self, id: i64) -> rusqlite::Result<u32> {
+        self.conn.query_row(
+            "UPDATE users SET failed_logins = failed_logins + 1 WHERE id = ?1
+            RETURNING failed_logins;",
+            params![id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Stops the account from logging in until `until`.
+    pub fn lock_user(&self, id: i64, until: DateTime<Utc>) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE users SET locked_until = ?2 WHERE id = ?1;",
+            params![id, until.timestamp()],
+        )?;
         Ok(())
     }
+
+    pub fn account(&self, name: &str) -> rusqlite::Result<Option<Account>> {
+        let accounts: Vec<Account> = self.query_accounts("WHERE name = ?1", params![name])?;
+        Ok(accounts.into_iter().next())
+    }
+
+    /// Returns every account ordered by name.
+    pub fn accounts(&self) -> rusqlite::Result<Vec<Account>> {
+        self.query_accounts("", [])
+    }
+
+    /// Runs a statement changing the account with the name.
+    fn update_user(
+        &self,
+        name: &str,
+        sql: &str,
+        parameters: impl rusqlite::Params,
+    ) -> Result<(), DatabaseError> {
+        let changed = self.conn.execute(sql, parameters)?;
+        if changed == 0 {
+            return Err(DatabaseError::UserNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    fn query_accounts(
+        &self,
+        filter: &str,
+        parameters: impl rusqlite::Params,
+    ) -> rusqlite::Result<Vec<Account>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, name, role, password_hash, failed_logins, locked_until FROM users
+            {} ORDER BY name;",
+            filter
+        ))?;
+        let accounts = stmt.query_map(parameters, |row| {
+            let role: String = row.get(2)?;
+            let locked_until: Option<i64> = row.get(5)?;
+            Ok(Account {
+                user: User {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    role: Role::parse(&role).unwrap_or(Role::User),
+                },
+                password_hash: row.get(3)?,
+                failed_logins: row.get(4)?,
+                locked_until: locked_until.and_then(|t| DateTime::from_timestamp(t, 0)),
+            })
+        })?;
+        accounts.collect()
+    }
+
+    fn friend_id(&self, owner: i64, name: &str) -> rusqlite::Result<Option<i64>> {
+        self.conn
+            .query_row(
+                "SELECT id FROM friends WHERE owner_id = ?1 AND name = ?2;",
+                params![owner, name],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Loads the friends matching `filter`, a `WHERE` clause on `friends f`, with their numbers.
+    fn query_friends(
+        &self,
+        filter: &str,
+        parameters: impl rusqlite::Params,
+    ) -> rusqlite::Result<Vec<Friend>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT f.name, n.label, n.number FROM friends f
+            LEFT JOIN numbers n ON n.friend_id = f.id
+            {} ORDER BY f.name, n.label;",
+            filter
+        ))?;
+        let mut rows = stmt.query(parameters)?;
+
+        let mut friends: Vec<Friend> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            if friends.last().map(|f| &f.name) != Some(&name) {
+                friends.push(Friend {
+                    name,
+                    numbers: Vec::new(),
+                });
+            }
+            if let (Some(label), Some(number)) = (row.get(1)?, row.get(2)?) {
+                friends
+                    .last_mut()
+                    .unwrap()
+                    .numbers
+                    .push(Number { label, number });
+            }
+        }
+        Ok(friends)
+    }
+}
+
+/// Moves the numbers of databases created before friends could have several into the `numbers`
+/// table.
+fn migrate_phone_column(conn: &Connection) -> rusqlite::Result<()> {
+    let has_phone: bool = conn
+        .prepare("SELECT 1 FROM pragma_table_info('friends') WHERE name = 'phone';")?
+        .exists([])?;
+    if has_phone {
+        conn.execute_batch(&format!(
+            "BEGIN;
+            INSERT INTO numbers (friend_id, label, number)
+                SELECT id, '{}', phone FROM friends WHERE phone != '';
+            ALTER TABLE friends DROP COLUMN phone;
+            COMMIT;",
+            DEFAULT_LABEL
+        ))?;
+    }
+    Ok(())
+}
+
+/// Gives the friends of databases created before there were accounts to the first admin. Their
+/// names were unique in the whole database, and are now unique per owner, so the table is
+/// rebuilt.
+fn migrate_owner_column(conn: &Connection) -> rusqlite::Result<()> {
+    let has_owner: bool = conn
+        .prepare("SELECT 1 FROM pragma_table_info('friends') WHERE name = 'owner_id';")?
+        .exists([])?;
+    if has_owner {
+        return Ok(());
+    }
+
+    // Dropping the old table must not delete the numbers that refer to it
+    conn.execute_batch(&format!(
+        "PRAGMA foreign_keys = OFF;
+        BEGIN;
+        CREATE TABLE friends_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            owner_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            UNIQUE (owner_id, name)
+        );
+        INSERT INTO friends_new (id, owner_id, name)
+            SELECT id, (SELECT MIN(id) FROM users WHERE role = '{}'), name FROM friends;
+        DROP TABLE friends;
+        ALTER TABLE friends_new RENAME TO friends;
+        COMMIT;
+        PRAGMA foreign_keys = ON;",
+        Role::Admin.as_str()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(label: &str, number: &str) -> Number {
+        Number {
+            label: label.to_string(),
+            number: number.to_string(),
+        }
+    }
+
+    fn first_admin(database: &Database) -> i64 {
+        database.account(FIRST_ADMIN).unwrap().unwrap().user.id
+    }
+
+    #[test]
+    fn test_numbers() {
+        let database = Database::new(":memory:").unwrap();
+        let owner: i64 = first_admin(&database);
+        assert!(database
+            .add_number(owner, "Ann Lee", "mobile", "555 0100")
+            .unwrap());
+        assert!(!database
+            .add_number(owner, "Ann Lee", "work", "555 0199")
+            .unwrap());
+        assert!(matches!(
+            database.add_number(owner, "Ann Lee", "work", "555 0000"),
+            Err(DatabaseError::DuplicateNumber(_, _))
+        ));
+        database
+            .update_number(owner, "Ann Lee", "work", "555 0142")
+            .unwrap();
+        assert!(matches!(
+            database.update_number(owner, "Bob", "work", "555 0142"),
+            Err(DatabaseError::FriendNotFound(_))
+        ));
+
+        assert_eq!(
+            database
+                .get_friend(owner, "Ann Lee")
+                .unwrap()
+                .unwrap()
+                .numbers,
+            vec![number("mobile", "555 0100"), number("work", "555 0142")]
+        );
+
+        database.delete_number(owner, "Ann Lee", "mobile").unwrap();
+        database.delete_friend(owner, "Ann Lee").unwrap();
+        assert!(matches!(
+            database.delete_friend(owner, "Ann Lee"),
+            Err(DatabaseError::FriendNotFound(_))
+        ));
+        assert_eq!(database.friend_count(owner).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_list_and_search() {
+        let database = Database::new(":memory:").unwrap();
+        let owner: i64 = first_admin(&database);
+        for (name, phone) in [("Cleo", "555 0300"), ("Ann", "555 0100"), ("Bob", "100%")] {
+            database
+                .add_number(owner, name, DEFAULT_LABEL, phone)
+                .unwrap();
+        }
+        database
+            .add_number(owner, "Bob", "work", "555 0200")
+            .unwrap();
+
+        let page: Vec<Friend> = database.list_friends(owner, 1, 2).unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!((page[0].name.as_str(), page[0].numbers.len()), ("Bob", 2));
+        assert_eq!(page[1].name, "Cleo");
+
+        let names =
+            |friends: Vec<Friend>| -> Vec<String> { friends.into_iter().map(|f| f.name).collect() };
+        assert_eq!(
+            names(database.search(owner, "o").unwrap()),
+            vec!["Bob", "Cleo"]
+        );
+        assert_eq!(names(database.search(owner, "0300").unwrap()), vec!["Cleo"]);
+        assert_eq!(names(database.search(owner, "0%").unwrap()), vec!["Bob"]);
+    }
+
+    #[test]
+    fn test_owners() {
+        let database = Database::new(":memory:").unwrap();
+        let admin: i64 = first_admin(&database);
+        database.add_user("ann", "", Role::User).unwrap();
+        let ann: i64 = database.account("ann").unwrap().unwrap().user.id;
+        assert!(matches!(
+            database.add_user("ann", "", Role::Admin),
+            Err(DatabaseError::DuplicateUser(_))
+        ));
+
+        // Users have their own friends, who may share names
+        database.add_number(admin, "Bob", "work", "1").unwrap();
+        assert!(database.add_number(ann, "Bob", "work", "2").unwrap());
+        assert!(database.get_friend(ann, "Cleo").unwrap().is_none());
+        database.add_number(admin, "Cleo", "work", "3").unwrap();
+        assert_eq!(database.friend_names(ann).unwrap(), vec!["Bob"]);
+        assert!(matches!(
+            database.delete_friend(ann, "Cleo"),
+            Err(DatabaseError::FriendNotFound(_))
+        ));
+
+        // Deleting a user deletes their friends
+        database.delete_user("ann").unwrap();
+        assert_eq!(database.friend_count(ann).unwrap(), 0);
+        assert_eq!(database.friend_count(admin).unwrap(), 2);
+        assert!(matches!(
+            database.unlock_user("ann"),
+            Err(DatabaseError::UserNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_migration() {
+        let path = std::env::temp_dir().join(format!("friends-{}.db", std::process::id()));
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE friends (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL UNIQUE,
+                phone TEXT NOT NULL
+            );
+            INSERT INTO friends (name, phone) VALUES ('Ann', '555 0100');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let database = Database::new(path.to_str().unwrap()).unwrap();
+        let owner: i64 = first_admin(&database);
+        assert_eq!(
+            database.get_friend(owner, "Ann").unwrap().unwrap().numbers,
+            vec![number(DEFAULT_LABEL, "555 0100")]
+        );
+        database
+            .add_number(owner, "Bob", DEFAULT_LABEL, "555 0199")
+            .unwrap();
+        drop(database);
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,342 @@
+//! Exporting and importing the friend database as CSV or vCard.
+//!
+//! CSV files have a `name,label,number` header and one record per number (RFC 4180). vCard files
+//! have one version 3.0 card per friend (RFC 2426) with a `TEL` line per number, the label being
+//! its `TYPE`. Records are read one line at a time, as typed or pasted into a session, so quoted
+//! CSV fields and vCard values cannot contain line breaks.
+use crate::database::{Friend, Number, DEFAULT_LABEL};
+
+pub const CSV_HEADER: &str = "name,label,number";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    VCard,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_uppercase().as_str() {
+            "CSV" => Some(Format::Csv),
+            "VCARD" | "VCF" => Some(Format::VCard),
+            _ => None,
+        }
+    }
+
+    /// Formats every friend as lines of this format.
+    pub fn export(self, friends: &[Friend]) -> Vec<String> {
+        match self {
+            Format::Csv => export_csv(friends),
+            Format::VCard => export_vcard(friends),
+        }
+    }
+
+    /// Reads friends from lines of this format.
+    ///
+    /// # Returns
+    /// The friends read, or a message naming the first line that could not be read.
+    pub fn import(self, lines: &[String]) -> Result<Vec<Friend>, String> {
+        match self {
+            Format::Csv => import_csv(lines),
+            Format::VCard => import_vcard(lines),
+        }
+    }
+}
+
+fn export_csv(friends: &[Friend]) -> Vec<String> {
+    let mut lines: Vec<String> = vec![CSV_HEADER.to_string()];
+    for friend in friends {
+        for number in &friend.numbers {
+            lines.push(
+                [&friend.name, &number.label, &number.number]
+                    .map(|field| csv_field(field))
+                    .join(","),
+            );
+        }
+    }
+    lines
+}
+
+/// Quotes a CSV field if it holds a comma or a quote.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Splits a CSV record into its fields.
+fn csv_record(line: &str) -> Option<Vec<String>> {
+    let mut fields: Vec<String> = Vec::new();
+    let mut field: String = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted: bool = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+fn import_csv(lines: &[String]) -> Result<Vec<Friend>, String> {
+    let mut friends: Vec<Friend> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() || line.trim().eq_ignore_ascii_case(CSV_HEADER) {
+            continue;
+        }
+        let fields: Vec<String> = csv_record(line)
+            .ok_or_else(|| format!("Line {} has an unterminated quote.", index + 1))?;
+        let (name, label, number) = match fields.as_slice() {
+            [name, number] => (name, DEFAULT_LABEL, number),
+            [name, label, number] => (name, label.as_str(), number),
+            _ => {
+                return Err(format!(
+                    "Line {} should have the fields {}.",
+                    index + 1,
+                    CSV_HEADER
+                ))
+            }
+        };
+        if name.trim().is_empty() || number.trim().is_empty() {
+            return Err(format!("Line {} has no name or number.", index + 1));
+        }
+        add(&mut friends, name.trim(), label.trim(), number.trim());
+    }
+    Ok(friends)
+}
+
+fn export_vcard(friends: &[Friend]) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for friend in friends {
+        let name: String = vcard_value(&friend.name);
+        lines.push(String::from("BEGIN:VCARD"));
+        lines.push(String::from("VERSION:3.0"));
+        lines.push(format!("FN:{}", name));
+        lines.push(format!("N:{};;;;", name));
+        for number in &friend.numbers {
+            lines.push(format!(
+                "TEL;TYPE={}:{}",
+                vcard_parameter(&number.label),
+                vcard_value(&number.number)
+            ));
+        }
+        lines.push(String::from("END:VCARD"));
+    }
+    lines
+}
+
+/// Escapes the characters with a meaning in vCard values.
+fn vcard_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+}
+
+/// Quotes a parameter value holding characters that separate parameters.
+fn vcard_parameter(value: &str) -> String {
+    if value.contains([',', ';', ':']) {
+        format!("\"{}\"", value.replace('"', ""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn unescape_vcard(value: &str) -> String {
+    let mut unescaped: String = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') | Some('N') => unescaped.push(' '),
+                Some(escaped) => unescaped.push(escaped),
+                None => {}
+            },
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn import_vcard(lines: &[String]) -> Result<Vec<Friend>, String> {
+    // Lines starting with a space or tab continue the line before
+    let mut unfolded: Vec<(usize, String)> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        match (line.strip_prefix([' ', '\t']), unfolded.last_mut()) {
+            (Some(rest), Some((_, previous))) => previous.push_str(rest),
+            _ => unfolded.push((index + 1, line.trim_end().to_string())),
+        }
+    }
+
+    let mut friends: Vec<Friend> = Vec::new();
+    let mut card: Option<(Option<String>, Vec<Number>)> = None;
+    for (number, line) in unfolded {
+        if line.is_empty() {
+            continue;
+        }
+        let (property, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Line {} is not a vCard property.", number))?;
+        let mut parameters = property.split(';');
+        let name: String = parameters.next().unwrap_or_default().to_uppercase();
+
+        match (name.as_str(), card.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VCARD") => {
+                card = Some((None, Vec::new()));
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VCARD") => {
+                let (full_name, numbers) = card.take().unwrap();
+                let full_name: String = full_name
+                    .ok_or_else(|| format!("The card ending on line {} has no FN.", number))?;
+                if numbers.is_empty() && !friends.iter().any(|f| f.name == full_name) {
+                    friends.push(Friend {
+                        name: full_name,
+                        numbers,
+                    });
+                } else {
+                    for n in numbers {
+                        add(&mut friends, &full_name, &n.label, &n.number);
+                    }
+                }
+            }
+            ("FN", Some((full_name, _))) => {
+                *full_name = Some(unescape_vcard(value).trim().to_string())
+            }
+            ("TEL", Some((_, numbers))) => {
+                let label: String = parameters
+                    .filter_map(|p| {
+                        let p = p.trim_matches('"');
+                        match p.split_once('=') {
+                            Some((key, types)) if key.eq_ignore_ascii_case("TYPE") => {
+                                Some(types.trim_matches('"').to_string())
+                            }
+                            // vCard 2.1 lists types without TYPE=
+                            None => Some(p.to_string()),
+                            _ => None,
+                        }
+                    })
+                    .flat_map(|types| {
+                        types
+                            .split(',')
+                            .map(str::to_string)
+                            .collect::<Vec<String>>()
+                    })
+                    .find(|t| !t.eq_ignore_ascii_case("VOICE") && !t.eq_ignore_ascii_case("PREF"))
+                    .map(|t| t.to_lowercase())
+                    .unwrap_or_else(|| DEFAULT_LABEL.to_string());
+                numbers.push(Number {
+                    label,
+                    number: unescape_vcard(value).trim().to_string(),
+                });
+            }
+            ("BEGIN", _) | ("END", _) => {
+                return Err(format!("Line {} has an unexpected {}.", number, name));
+            }
+            (_, None) => return Err(format!("Line {} is outside a card.", number)),
+            // Other properties are not kept
+            (_, Some(_)) => {}
+        }
+    }
+    if card.is_some() {
+        return Err(String::from("The last card has no END:VCARD."));
+    }
+    Ok(friends)
+}
+
+/// Adds a number to the friend with the name, adding the friend if they are not in `friends`.
+fn add(friends: &mut Vec<Friend>, name: &str, label: &str, number: &str) {
+    let number = Number {
+        label: if label.is_empty() {
+            DEFAULT_LABEL
+        } else {
+            label
+        }
+        .to_string(),
+        number: number.to_string(),
+    };
+    match friends.iter_mut().find(|f| f.name == name) {
+        Some(friend) => friend.numbers.push(number),
+        None => friends.push(Friend {
+            name: name.to_string(),
+            numbers: vec![number],
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn friends() -> Vec<Friend> {
+        vec![
+            Friend {
+                name: String::from("Lee, Ann"),
+                numbers: vec![
+                    Number {
+                        label: String::from("mobile"),
+                        number: String::from("555 0100"),
+                    },
+                    Number {
+                        label: String::from("work"),
+                        number: String::from("555 0199; ext 4"),
+                    },
+                ],
+            },
+            Friend {
+                name: String::from("Bob \"B\""),
+                numbers: vec![Number {
+                    label: String::from(DEFAULT_LABEL),
+                    number: String::from("555 0200"),
+                }],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for format in [Format::Csv, Format::VCard] {
+            let lines: Vec<String> = format.export(&friends());
+            assert_eq!(format.import(&lines), Ok(friends()));
+        }
+        assert_eq!(
+            Format::Csv.export(&friends())[1],
+            r#""Lee, Ann",mobile,555 0100"#
+        );
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let lines = |text: &str| -> Vec<String> { text.lines().map(str::to_string).collect() };
+        assert_eq!(
+            Format::Csv.import(&lines("Ann,555\n\"Bob,555")),
+            Err(String::from("Line 2 has an unterminated quote."))
+        );
+        assert_eq!(
+            Format::VCard.import(&lines("BEGIN:VCARD\nTEL:555\nEND:VCARD")),
+            Err(String::from("The card ending on line 3 has no FN."))
+        );
+
+        // Folded lines and vCard 2.1 types
+        let cards = Format::VCard
+            .import(&lines(
+                "BEGIN:VCARD\nVERSION:2.1\nFN:Ann\n  Lee\nTEL;CELL;VOICE:555 0100\nEND:VCARD",
+            ))
+            .unwrap();
+        assert_eq!(cards[0].name, "Ann Lee");
+        assert_eq!(cards[0].numbers[0].label, "cell");
+    }
+}
@@ -0,0 +1,200 @@
+//! Idle detection for Telnet sessions.
+//!
+//! `IdleTimer` keeps two clocks per session. The last activity is when the user last typed
+//! something: after `timeout` without any, the session is warned and then disconnected. The last
+//! received data is when anything at all arrived: after `probe_interval` of silence a client that
+//! speaks Telnet is sent a TIMING-MARK request (RFC 860), which it answers without the user
+//! noticing, and a client that stays silent for `probe_timeout` after that is considered gone.
+//! Clients that do not negotiate are left to TCP keepalive instead.
+use tokio::time::{Duration, Instant};
+
+/// The default idle timeouts.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+pub const DEFAULT_IDLE_WARNING: Duration = Duration::from_secs(60);
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long sessions may stay idle and how dead peers are detected.
+#[derive(Debug, Clone, Copy)]
+pub struct IdleSettings {
+    /// How long a session may go without input from the user.
+    pub timeout: Duration,
+    /// How long before the disconnection the user is warned.
+    pub warning: Duration,
+    /// How long the connection may be silent before the client is probed.
+    pub probe_interval: Duration,
+    /// How long the client has to answer a probe.
+    pub probe_timeout: Duration,
+}
+
+impl Default for IdleSettings {
+    fn default() -> Self {
+        IdleSettings {
+            timeout: DEFAULT_IDLE_TIMEOUT,
+            warning: DEFAULT_IDLE_WARNING,
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            probe_timeout: DEFAULT_PROBE_TIMEOUT,
+        }
+    }
+}
+
+/// What a session must do when its idle timer fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleEvent {
+    /// Tell the user they will be disconnected after the given time.
+    Warn(Duration),
+    /// Disconnect the idle user.
+    Disconnect,
+    /// Ask the client for a TIMING-MARK.
+    Probe,
+    /// The client did not answer the probe.
+    PeerDead,
+}
+
+#[derive(Debug)]
+pub struct IdleTimer {
+    settings: IdleSettings,
+    last_activity: Instant,
+    last_received: Instant,
+    warned: bool,
+    /// When the unanswered probe was sent.
+    probe_sent: Option<Instant>,
+}
+
+impl IdleTimer {
+    pub fn new(settings: IdleSettings, now: Instant) -> Self {
+        IdleTimer {
+            settings,
+            last_activity: now,
+            last_received: now,
+            warned: false,
+            probe_sent: None,
+        }
+    }
+
+    /// Records data from the client.
+    ///
+    /// # Arguments
+    /// - `now`: When the data arrived.
+    /// - `activity`: True if the user typed something, false for Telnet commands only.
+    pub fn received(&mut self, now: Instant, activity: bool) {
+        self.last_received = now;
+        self.probe_sent = None;
+        if activity {
+            self.last_activity = now;
+            self.warned = false;
+        }
+    }
+
+    /// Returns when `poll` next has something to report.
+    ///
+    /// # Arguments
+    /// - `probing`: True if the client can be probed with TIMING-MARK.
+    pub fn deadline(&self, probing: bool) -> Instant {
+        let mut deadline: Instant = self.disconnect_at();
+        if !self.warned {
+            deadline = deadline.min(self.warn_at());
+        }
+        if probing {
+            deadline = deadline.min(match self.probe_sent {
+                Some(sent) => sent + self.settings.probe_timeout,
+                None => self.last_received + self.settings.probe_interval,
+            });
+        }
+        deadline
+    }
+
+    /// Returns what is due at `now`, if anything.
+    pub fn poll(&mut self, now: Instant, probing: bool) -> Option<IdleEvent> {
+        if now >= self.disconnect_at() {
+            return Some(IdleEvent::Disconnect);
+        }
+        if probing {
+            match self.probe_sent {
+                Some(sent) if now >= sent + self.settings.probe_timeout => {
+                    return Some(IdleEvent::PeerDead);
+                }
+                None if now >= self.last_received + self.settings.probe_interval => {
+                    self.probe_sent = Some(now);
+                    return Some(IdleEvent::Probe);
+                }
+                _ => {}
+            }
+        }
+        if !self.warned && now >= self.warn_at() {
+            self.warned = true;
+            return Some(IdleEvent::Warn(self.disconnect_at() - now));
+        }
+        None
+    }
+
+    fn disconnect_at(&self) -> Instant {
+        self.last_activity + self.settings.timeout
+    }
+
+    fn warn_at(&self) -> Instant {
+        self.disconnect_at()
+            .checked_sub(self.settings.warning)
+            .unwrap_or(self.last_activity)
+            .max(self.last_activity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn test_warning_and_disconnect() {
+        let start: Instant = Instant::now();
+        let mut timer = IdleTimer::new(IdleSettings::default(), start);
+        assert_eq!(timer.deadline(false), start + seconds(240));
+        assert_eq!(timer.poll(start + seconds(239), false), None);
+        assert_eq!(
+            timer.poll(start + seconds(240), false),
+            Some(IdleEvent::Warn(seconds(60)))
+        );
+        assert_eq!(timer.poll(start + seconds(241), false), None);
+
+        // Typing starts the clock again and earns a new warning
+        timer.received(start + seconds(250), true);
+        assert_eq!(timer.deadline(false), start + seconds(490));
+        assert_eq!(
+            timer.poll(start + seconds(490), false),
+            Some(IdleEvent::Warn(seconds(60)))
+        );
+        assert_eq!(
+            timer.poll(start + seconds(550), false),
+            Some(IdleEvent::Disconnect)
+        );
+    }
+
+    #[test]
+    fn test_probes() {
+        let start: Instant = Instant::now();
+        let mut timer = IdleTimer::new(IdleSettings::default(), start);
+        assert_eq!(timer.deadline(true), start + seconds(30));
+        assert_eq!(
+            timer.poll(start + seconds(30), true),
+            Some(IdleEvent::Probe)
+        );
+
+        // An answer is not activity, but keeps the client alive
+        timer.received(start + seconds(31), false);
+        assert_eq!(timer.deadline(true), start + seconds(61));
+        assert_eq!(
+            timer.poll(start + seconds(61), true),
+            Some(IdleEvent::Probe)
+        );
+        assert_eq!(timer.poll(start + seconds(70), true), None);
+        assert_eq!(
+            timer.poll(start + seconds(71), true),
+            Some(IdleEvent::PeerDead)
+        );
+        assert_eq!(timer.deadline(false), start + seconds(240));
+    }
+}
@@ -0,0 +1,218 @@
+//! Decoding the keys typed on a client's terminal.
+//!
+//! Printable characters arrive as UTF-8, control keys as ASCII control characters and the arrow
+//! and editing keys as ANSI escape sequences, `ESC [` (CSI) or `ESC O` (SS3) followed by optional
+//! parameters and a final byte. Enter is CR LF or CR NUL from Telnet clients and LF from others.
+//!
+//! A lone `ESC` cannot be told apart from the start of a sequence until more input arrives.
+//! Terminals send the bytes of a sequence in one write, so an `ESC` that ends the data read is
+//! taken to be the Escape key.
+
+const BACKSPACE: u8 = 0x08;
+const TAB: u8 = 0x09;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/// The longest parameters kept from a CSI sequence.
+const MAX_PARAMETERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// A control key, given by its lowercase letter, such as `'a'` for Ctrl-A.
+    Ctrl(char),
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Normal,
+    /// After CR, where a following LF or NUL belongs to the same line ending.
+    AfterCr,
+    Escape,
+    /// Inside `ESC [`, collecting parameter bytes.
+    Csi,
+    /// After `ESC O`, which some terminals send before arrow keys.
+    Ss3,
+}
+
+#[derive(Debug)]
+pub struct KeyDecoder {
+    state: State,
+    csi: Vec<u8>,
+    /// The bytes of a UTF-8 character received so far.
+    utf8: Vec<u8>,
+}
+
+impl Default for KeyDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyDecoder {
+    pub fn new() -> Self {
+        KeyDecoder {
+            state: State::Normal,
+            csi: Vec::new(),
+            utf8: Vec::new(),
+        }
+    }
+
+    /// Decodes text received from the client, with Telnet commands already removed.
+    ///
+    /// # Returns
+    /// The keys completed by the text. Keys split across reads are kept until the rest arrives.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Key> {
+        let mut keys: Vec<Key> = Vec::new();
+        for &byte in data {
+            self.feed_byte(byte, &mut keys);
+        }
+        if self.state == State::Escape {
+            self.state = State::Normal;
+            keys.push(Key::Escape);
+        }
+        keys
+    }
+
+    fn feed_byte(&mut self, byte: u8, keys: &mut Vec<Key>) {
+        match self.state {
+            State::AfterCr => {
+                self.state = State::Normal;
+                if byte == b'\n' || byte == 0 {
+                    return;
+                }
+            }
+            State::Escape => {
+                self.csi.clear();
+                match byte {
+                    b'[' => self.state = State::Csi,
+                    b'O' => self.state = State::Ss3,
+                    // Escape, then the next key
+                    _ => {
+                        self.state = State::Normal;
+                        keys.push(Key::Escape);
+                        self.feed_byte(byte, keys);
+                    }
+                }
+                return;
+            }
+            State::Csi => {
+                if byte.is_ascii_digit() || byte == b';' {
+                    if self.csi.len() < MAX_PARAMETERS {
+                        self.csi.push(byte);
+                    }
+                    return;
+                }
+                self.state = State::Normal;
+                keys.extend(escape_sequence(byte, &std::mem::take(&mut self.csi)));
+                return;
+            }
+            State::Ss3 => {
+                self.state = State::Normal;
+                keys.extend(escape_sequence(byte, &[]));
+                return;
+            }
+            State::Normal => {}
+        }
+
+        let key: Option<Key> = match byte {
+            b'\r' => {
+                self.state = State::AfterCr;
+                Some(Key::Enter)
+            }
+            b'\n' => Some(Key::Enter),
+            BACKSPACE | DEL => Some(Key::Backspace),
+            TAB => Some(Key::Tab),
+            ESC => {
+                self.state = State::Escape;
+                None
+            }
+            1..=26 => Some(Key::Ctrl((b'a' + byte - 1) as char)),
+            0..=31 => None,
+            0x80.. => {
+                self.utf8.push(byte);
+                match std::str::from_utf8(&self.utf8) {
+                    Ok(text) => {
+                        let c: Option<char> = text.chars().next();
+                        self.utf8.clear();
+                        c.map(Key::Char)
+                    }
+                    // Wait for the rest of the character
+                    Err(e) if e.error_len().is_none() && self.utf8.len() < 4 => None,
+                    Err(_) => {
+                        self.utf8.clear();
+                        None
+                    }
+                }
+            }
+            _ => Some(Key::Char(byte as char)),
+        };
+        keys.extend(key);
+    }
+}
+
+/// Returns the key sent as an escape sequence with the final byte and parameters.
+fn escape_sequence(byte: u8, parameters: &[u8]) -> Option<Key> {
+    match (byte, parameters) {
+        (b'A', _) => Some(Key::Up),
+        (b'B', _) => Some(Key::Down),
+        (b'C', _) => Some(Key::Right),
+        (b'D', _) => Some(Key::Left),
+        (b'H', _) | (b'~', b"1") | (b'~', b"7") => Some(Key::Home),
+        (b'F', _) | (b'~', b"4") | (b'~', b"8") => Some(Key::End),
+        (b'~', b"3") => Some(Key::Delete),
+        (b'~', b"5") => Some(Key::PageUp),
+        (b'~', b"6") => Some(Key::PageDown),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let mut decoder = KeyDecoder::new();
+        assert_eq!(
+            decoder.feed(b"a\r\n\x01\x1b[A\x1bOB\x1b[1;5C\x1b[3~\x1b[6~\x7f"),
+            vec![
+                Key::Char('a'),
+                Key::Enter,
+                Key::Ctrl('a'),
+                Key::Up,
+                Key::Down,
+                Key::Right,
+                Key::Delete,
+                Key::PageDown,
+                Key::Backspace
+            ]
+        );
+
+        // Characters and sequences split across reads
+        assert_eq!(decoder.feed(&[0xc3]), vec![]);
+        assert_eq!(
+            decoder.feed(&[0xa9, b'\r']),
+            vec![Key::Char('é'), Key::Enter]
+        );
+        assert_eq!(decoder.feed(b"\0\x1b[5"), vec![]);
+        assert_eq!(decoder.feed(b"~"), vec![Key::PageUp]);
+
+        // A lone ESC at the end of a read, or before another key
+        assert_eq!(decoder.feed(b"\x1b"), vec![Key::Escape]);
+        assert_eq!(decoder.feed(b"\x1bq"), vec![Key::Escape, Key::Char('q')]);
+    }
+}
@@ -1,2 +1,12 @@
+pub mod auth;
+pub mod commands;
 pub mod connection;
 pub mod database;
+pub mod export;
+pub mod idle;
+pub mod keys;
+pub mod line_editor;
+pub mod screen;
+pub mod session;
+pub mod telnet;
+pub mod tls;
@@ -0,0 +1,457 @@
+//! The line discipline of a Telnet session.
+//!
+//! With the server echoing, clients send every keystroke as it is typed. `LineEditor` assembles
+//! them into lines and echoes the edits back: insertion at the cursor, backspace and delete,
+//! cursor movement with the arrow keys, Home/End, Ctrl-A/Ctrl-E and Ctrl-U, a per-session history
+//! on the up and down arrows and tab completion of commands and friend names. Ctrl-C discards
+//! the line and Ctrl-D on an empty line ends the session. Passwords are typed in secret mode,
+//! which echoes nothing but the line ending and keeps them out of the history.
+//!
+//! Clients that echo locally send whole lines, which pass through the same editor without echo.
+use crate::keys::{Key, KeyDecoder};
+
+/// The commands offered by tab completion.
+pub const COMMANDS: [&str; 17] = [
+    "ADD", "UPDATE", "GET", "DELETE", "LIST", "SEARCH", "EXPORT", "IMPORT", "PASSWORD", "SCREEN",
+    "HELP", "EXIT", "USERS", "ADDUSER", "DELUSER", "ROLE", "UNLOCK",
+];
+
+/// How many lines the history keeps.
+const HISTORY_LEN: usize = 100;
+
+/// The longest line accepted. Further input is ignored.
+const MAX_LINE_LENGTH: usize = 1024;
+
+const BELL: &[u8] = b"\x07";
+
+/// Input the session acts on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// A line ended with Enter, without the line ending.
+    Line(String),
+    /// Ctrl-D on an empty line.
+    End,
+}
+
+#[derive(Debug)]
+pub struct LineEditor {
+    prompt: String,
+    line: Vec<char>,
+    cursor: usize,
+    keys: KeyDecoder,
+    history: Vec<String>,
+    /// The history entry shown, `history.len()` for the line being typed.
+    history_index: usize,
+    /// The line being typed while browsing the history.
+    draft: Vec<char>,
+    /// True while a password is typed.
+    secret: bool,
+    inputs: std::collections::VecDeque<Input>,
+}
+
+impl LineEditor {
+    /// Creates an editor that shows `prompt` when it redraws the line.
+    pub fn new(prompt: &str) -> Self {
+        LineEditor {
+            prompt: prompt.to_string(),
+            line: Vec::new(),
+            cursor: 0,
+            keys: KeyDecoder::new(),
+            history: Vec::new(),
+            history_index: 0,
+            draft: Vec::new(),
+            secret: false,
+            inputs: std::collections::VecDeque::new(),
+        }
+    }
+
+    /// Processes text received from the client. Complete input is read with `next_input`.
+    ///
+    /// # Arguments
+    /// - `data`: Text with Telnet commands already removed.
+    /// - `echo`: True if the server echoes, so edits must be drawn on the client's terminal.
+    /// - `names`: The friend names offered by tab completion.
+    ///
+    /// # Returns
+    /// The output that draws the edits, empty without echo.
+    pub fn feed(&mut self, data: &[u8], echo: bool, names: &[String]) -> Vec<u8> {
+        let mut output: Vec<u8> = Vec::new();
+        for key in self.keys.feed(data) {
+            if self.secret {
+                // Only the end of the line shows
+                let lines: usize = self.inputs.len();
+                self.key(key, names, &mut Vec::new());
+                if self.inputs.len() > lines {
+                    output.extend_from_slice(b"\r\n");
+                }
+            } else {
+                self.key(key, names, &mut output);
+            }
+        }
+        if echo {
+            output
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Returns the next line or end of input.
+    pub fn next_input(&mut self) -> Option<Input> {
+        self.inputs.pop_front()
+    }
+
+    /// Changes the prompt shown when the line is redrawn.
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.prompt = prompt.to_string();
+    }
+
+    /// Hides the line being typed from now on, until secret mode is turned off again.
+    pub fn set_secret(&mut self, secret: bool) {
+        self.secret = secret;
+    }
+
+    /// Returns true if the user has started typing a line.
+    pub fn is_editing(&self) -> bool {
+        !self.line.is_empty()
+    }
+
+    /// Redraws the prompt and the line being typed, after other output interrupted it.
+    pub fn redraw(&self) -> Vec<u8> {
+        let mut output: Vec<u8> = self.prompt.clone().into_bytes();
+        if self.secret {
+            return output;
+        }
+        output.extend(self.line.iter().collect::<String>().into_bytes());
+        move_left(&mut output, self.line.len() - self.cursor);
+        output
+    }
+
+    fn key(&mut self, key: Key, names: &[String], output: &mut Vec<u8>) {
+        match key {
+            Key::Enter => self.submit(output),
+            Key::Backspace => self.backspace(output),
+            Key::Delete => self.delete(output),
+            Key::Ctrl('a') | Key::Home => self.move_to(0, output),
+            Key::Ctrl('e') | Key::End => self.move_to(self.line.len(), output),
+            Key::Ctrl('c') => {
+                output.extend_from_slice(b"^C\r\n");
+                self.reset();
+                output.extend(self.redraw());
+            }
+            Key::Ctrl('d') if self.line.is_empty() => self.inputs.push_back(Input::End),
+            Key::Ctrl('d') => self.delete(output),
+            Key::Ctrl('u') => {
+                self.move_to(0, output);
+                output.extend_from_slice(b"\x1b[K");
+                self.line.clear();
+            }
+            Key::Tab if !self.secret => self.complete(names, output),
+            Key::Up => self.history_previous(output),
+            Key::Down => self.history_next(output),
+            Key::Right if self.cursor < self.line.len() => self.move_to(self.cursor + 1, output),
+            Key::Left if self.cursor > 0 => self.move_to(self.cursor - 1, output),
+            Key::Char(c) => self.insert(&[c], output),
+            _ => {}
+        }
+    }
+
+    fn submit(&mut self, output: &mut Vec<u8>) {
+        output.extend_from_slice(b"\r\n");
+        let line: String = self.line.iter().collect();
+        if !self.secret && !line.trim().is_empty() && self.history.last() != Some(&line) {
+            if self.history.len() == HISTORY_LEN {
+                self.history.remove(0);
+            }
+            self.history.push(line.clone());
+        }
+        self.inputs.push_back(Input::Line(line));
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.history_index = self.history.len();
+        self.draft.clear();
+    }
+
+    /// Inserts characters at the cursor and redraws the rest of the line after them.
+    fn insert(&mut self, chars: &[char], output: &mut Vec<u8>) {
+        if self.line.len() + chars.len() > MAX_LINE_LENGTH {
+            output.extend_from_slice(BELL);
+            return;
+        }
+        self.line
+            .splice(self.cursor..self.cursor, chars.iter().copied());
+        let rest: String = self.line[self.cursor..].iter().collect();
+        output.extend(rest.into_bytes());
+        self.cursor += chars.len();
+        move_left(output, self.line.len() - self.cursor);
+    }
+
+    fn backspace(&mut self, output: &mut Vec<u8>) {
+        if self.cursor == 0 {
+            return;
+        }
+        self.move_to(self.cursor - 1, output);
+        self.delete(output);
+    }
+
+    /// Deletes the character under the cursor.
+    fn delete(&mut self, output: &mut Vec<u8>) {
+        if self.cursor == self.line.len() {
+            return;
+        }
+        self.line.remove(self.cursor);
+        let rest: String = self.line[self.cursor..].iter().collect();
+        output.extend(rest.into_bytes());
+        output.push(b' ');
+        move_left(output, self.line.len() - self.cursor + 1);
+    }
+
+    fn move_to(&mut self, position: usize, output: &mut Vec<u8>) {
+        if position < self.cursor {
+            move_left(output, self.cursor - position);
+        } else if position > self.cursor {
+            let passed: String = self.line[self.cursor..position].iter().collect();
+            output.extend(passed.into_bytes());
+        }
+        self.cursor = position;
+    }
+
+    /// Replaces the whole line, as when browsing the history.
+    fn replace_line(&mut self, line: Vec<char>, output: &mut Vec<u8>) {
+        self.move_to(0, output);
+        output.extend_from_slice(b"\x1b[K");
+        self.line = line;
+        self.cursor = 0;
+        self.move_to(self.line.len(), output);
+    }
+
+    fn history_previous(&mut self, output: &mut Vec<u8>) {
+        if self.history_index == 0 {
+            output.extend_from_slice(BELL);
+            return;
+        }
+        if self.history_index == self.history.len() {
+            self.draft = self.line.clone();
+        }
+        self.history_index -= 1;
+        let line: Vec<char> = self.history[self.history_index].chars().collect();
+        self.replace_line(line, output);
+    }
+
+    fn history_next(&mut self, output: &mut Vec<u8>) {
+        if self.history_index >= self.history.len() {
+            output.extend_from_slice(BELL);
+            return;
+        }
+        self.history_index += 1;
+        let line: Vec<char> = match self.history.get(self.history_index) {
+            Some(entry) => entry.chars().collect(),
+            None => std::mem::take(&mut self.draft),
+        };
+        self.replace_line(line, output);
+    }
+
+    /// Completes the word before the cursor: the command for the first word and a friend name
+    /// for the others. Names with spaces are completed in quotes.
+    fn complete(&mut self, names: &[String], output: &mut Vec<u8>) {
+        // Words are separated by spaces outside quotes
+        let mut start: usize = 0;
+        let mut quoted: bool = false;
+        for (i, c) in self.line[..self.cursor].iter().enumerate() {
+            if *c == '"' {
+                quoted = !quoted;
+            } else if c.is_whitespace() && !quoted {
+                start = i + 1;
+            }
+        }
+        let word: String = self.line[start..self.cursor].iter().collect();
+        let typed: &str = word.trim_start_matches('"');
+
+        let candidates: Vec<String> = if start == 0 {
+            COMMANDS
+                .iter()
+                .filter(|c| c.starts_with(&typed.to_uppercase()))
+                .map(|c| c.to_string())
+                .collect()
+        } else {
+            names
+                .iter()
+                .filter(|n| n.starts_with(typed))
+                .cloned()
+                .collect()
+        };
+
+        match candidates.as_slice() {
+            [] => output.extend_from_slice(BELL),
+            // Commands are completed in upper case whatever was typed
+            [only] => self.replace_word(start, &format!("{} ", quote(only)), output),
+            _ => {
+                let prefix: String = common_prefix(&candidates);
+                if prefix.chars().count() > typed.chars().count() {
+                    let replacement: String =
+                        if word.starts_with('"') || prefix.contains(char::is_whitespace) {
+                            format!("\"{}", prefix)
+                        } else {
+                            prefix
+                        };
+                    self.replace_word(start, &replacement, output);
+                } else {
+                    // Nothing more to add, so list the candidates under the line
+                    output.extend_from_slice(b"\r\n");
+                    output.extend(candidates.join("  ").into_bytes());
+                    output.extend_from_slice(b"\r\n");
+                    output.extend(self.redraw());
+                }
+            }
+        }
+    }
+
+    /// Replaces the text from `start` to the cursor.
+    fn replace_word(&mut self, start: usize, replacement: &str, output: &mut Vec<u8>) {
+        let end: usize = self.cursor;
+        self.move_to(start, output);
+        self.line.drain(start..end);
+        output.extend_from_slice(b"\x1b[K");
+        let chars: Vec<char> = replacement.chars().collect();
+        self.insert(&chars, output);
+    }
+}
+
+/// Puts a name in quotes if it has spaces or quotes, as command arguments need.
+fn quote(name: &str) -> String {
+    if name.contains(|c: char| c.is_whitespace() || c == '"') {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        name.to_string()
+    }
+}
+
+/// Returns the longest prefix shared by all of `words`.
+fn common_prefix(words: &[String]) -> String {
+    let mut prefix: Vec<char> = words[0].chars().collect();
+    for word in &words[1..] {
+        let shared: usize = prefix
+            .iter()
+            .zip(word.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(shared);
+    }
+    prefix.into_iter().collect()
+}
+
+fn move_left(output: &mut Vec<u8>, columns: usize) {
+    if columns > 0 {
+        output.extend(format!("\x1b[{}D", columns).into_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(editor: &mut LineEditor) -> Vec<Input> {
+        std::iter::from_fn(|| editor.next_input()).collect()
+    }
+
+    #[test]
+    fn test_editing_keys() {
+        let mut editor = LineEditor::new("> ");
+        // Typed one key per read, with a typo fixed by backspace and an insert after moving left
+        for key in [
+            &b"ADD An"[..],
+            b"x",
+            b"\x7f",
+            b"n 55",
+            b"\x1b[D",
+            b"5",
+            b"\r",
+            b"\0",
+        ] {
+            editor.feed(key, true, &[]);
+        }
+        assert_eq!(
+            lines(&mut editor),
+            vec![Input::Line(String::from("ADD Ann 555"))]
+        );
+
+        editor.feed(b"GET Bob\x01\x1b[3~G\x05\r\n", true, &[]);
+        editor.feed(b"junk\x03EXIT\r\n\x04", true, &[]);
+        assert_eq!(
+            lines(&mut editor),
+            vec![
+                Input::Line(String::from("GET Bob")),
+                Input::Line(String::from("EXIT")),
+                Input::End
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = LineEditor::new("> ");
+        editor.feed(b"GET Ann\rGET Bob\r", true, &[]);
+        lines(&mut editor);
+
+        // Browsing back and forth keeps the line being typed
+        editor.feed(b"DEL\x1b[A\x1b[A", true, &[]);
+        editor.feed(b"\x1b[B\x1b[B", true, &[]);
+        editor.feed(b"ETE\r", true, &[]);
+        editor.feed(b"\x1b[A\x1b[A\r", true, &[]);
+        assert_eq!(
+            lines(&mut editor),
+            vec![
+                Input::Line(String::from("DELETE")),
+                Input::Line(String::from("GET Bob"))
+            ]
+        );
+
+        // Passwords are not shown or remembered
+        editor.set_secret(true);
+        assert_eq!(editor.feed(b"hunter2\t\r", true, &[]), b"\r\n");
+        assert_eq!(editor.redraw(), b"> ");
+        editor.set_secret(false);
+        editor.feed(b"\x1b[A\r", true, &[]);
+        assert_eq!(
+            lines(&mut editor),
+            vec![
+                Input::Line(String::from("hunter2")),
+                Input::Line(String::from("GET Bob"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tab_completion() {
+        let names: Vec<String> = vec![
+            String::from("Annabel"),
+            String::from("Annika"),
+            String::from("Bob Lee"),
+        ];
+        let mut editor = LineEditor::new("> ");
+        editor.feed(b"ge\tAnna\t\r", true, &names);
+        editor.feed(b"DELETE An\tab\t\r", true, &names);
+        assert_eq!(
+            lines(&mut editor),
+            vec![
+                Input::Line(String::from("GET Annabel ")),
+                Input::Line(String::from("DELETE Annabel "))
+            ]
+        );
+
+        editor.feed(b"DELETE B\t\r", true, &names);
+        assert_eq!(
+            lines(&mut editor),
+            vec![Input::Line(String::from("DELETE \"Bob Lee\" "))]
+        );
+
+        // Both of these commands start with "E", so they are listed and the line is redrawn
+        let output: Vec<u8> = editor.feed(b"e\t\t", true, &names);
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("EXPORT  EXIT\r\n> EX"));
+    }
+}
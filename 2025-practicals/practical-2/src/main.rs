@@ -1,14 +1,33 @@
-use libc::*;
-use practical_2::connection::{create_raw_socket, handle_telnet_connection};
+use practical_2::auth::set_missing_passwords;
+use practical_2::connection::{accept_clients, create_listener};
 use practical_2::database::Database;
+use practical_2::idle::IdleSettings;
+use practical_2::session::{operator_console, SessionLimits, SessionManager};
+use practical_2::tls::{tls_acceptor, CERT_FILE, KEY_FILE};
 use std::sync::Arc;
-use tokio::sync::{Mutex, Semaphore};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+use tokio_rustls::TlsAcceptor;
 
 use std::error::Error;
 
+const USAGE: &str = "Usage: practical_2 <port> [--max-sessions <n>] [--max-per-ip <n>] \
+    [--queue <seconds>] [--idle-timeout <seconds>] [--idle-warning <seconds>] \
+    [--probe-interval <seconds>] [--probe-timeout <seconds>] [--tls-port <port>]";
+
+/// The options given after the port.
+struct Options {
+    limits: SessionLimits,
+    idle: IdleSettings,
+    /// The port of the telnets listener, if there is one.
+    tls_port: Option<u16>,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let port: u16 = match std::env::args().collect::<Vec<String>>().get(1) {
+    let args: Vec<String> = std::env::args().collect();
+    let port: u16 = match args.get(1) {
         Some(p) => match p.parse::<u16>() {
             Ok(n) => n,
             Err(_) => {
@@ -21,45 +40,105 @@ async fn main() -> Result<(), Box<dyn Error>> {
             std::process::exit(1);
         }
     };
+    let options: Options = match parse_options(&args[2..]) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
 
-    let database: Arc<Mutex<Database>> = Arc::new(Mutex::new(
-        Database::new("friends.db").expect("Failed to initialize database"),
-    ));
+    let database: Database = Database::new("friends.db").expect("Failed to initialize database");
+    // Only shown once, so the first admin can log in and choose their own password
+    match set_missing_passwords(&database) {
+        Ok(passwords) => {
+            for (name, password) in passwords {
+                println!(
+                    "The account {} had no password. Its password is now: {}",
+                    name, password
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: Failed to set passwords: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let database: Arc<Mutex<Database>> = Arc::new(Mutex::new(database));
 
-    let server_fd = create_raw_socket(port).unwrap();
+    let listener: TcpListener = match create_listener(port) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Limit the amout of async connections to 5
-    let semaphore = Arc::new(Semaphore::new(5));
+    // Each session holds one of the manager's permits until it ends, whichever listener it
+    // came from
+    let sessions: Arc<SessionManager> = Arc::new(SessionManager::new(options.limits));
+    tokio::spawn(operator_console(Arc::clone(&sessions)));
 
-    loop {
-        let client_fd;
-        let permit = semaphore.acquire().await.unwrap();
-        println!(
-            "Current concurrent connections: {}",
-            5 - semaphore.available_permits()
-        );
+    if let Some(tls_port) = options.tls_port {
+        let acceptor: TlsAcceptor = match tls_acceptor() {
+            Ok(acceptor) => acceptor,
+            Err(e) => {
+                eprintln!(
+                    "Error: The telnets listener needs {} and {}: {}",
+                    CERT_FILE, KEY_FILE, e
+                );
+                std::process::exit(1);
+            }
+        };
+        let tls_listener: TcpListener = match create_listener(tls_port) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        println!("Telnet over TLS is on port {}", tls_port);
+        tokio::spawn(accept_clients(
+            tls_listener,
+            Some(acceptor),
+            Arc::clone(&sessions),
+            Arc::clone(&database),
+            options.idle,
+        ));
+    }
 
-        unsafe {
-            let mut client_address: sockaddr_in = std::mem::zeroed::<sockaddr_in>();
-            let mut address_len: u32 = std::mem::size_of::<sockaddr_in>() as u32;
+    accept_clients(listener, None, sessions, database, options.idle).await;
+    Ok(())
+}
 
-            client_fd = accept(
-                server_fd,
-                &mut client_address as *mut sockaddr_in as *mut sockaddr,
-                &mut address_len,
-            );
+/// Reads the session limits, idle timeouts and telnets port from the options after the port.
+///
+/// # Returns
+/// `None` if an option is unknown or has no valid value.
+fn parse_options(options: &[String]) -> Option<Options> {
+    let mut limits: SessionLimits = SessionLimits::default();
+    let mut idle: IdleSettings = IdleSettings::default();
+    let mut tls_port: Option<u16> = None;
+    let mut options = options.iter();
 
-            if client_fd < 0 {
-                eprintln!("Failed to accept connection");
-                continue;
-            }
+    while let Some(option) = options.next() {
+        let value: usize = options.next()?.parse().ok()?;
+        let seconds: Duration = Duration::from_secs(value as u64);
+        match option.as_str() {
+            "--max-sessions" if value > 0 => limits.max_sessions = value,
+            "--max-per-ip" if value > 0 => limits.max_per_address = value,
+            "--queue" => limits.queue_timeout = Some(seconds),
+            "--idle-timeout" if value > 0 => idle.timeout = seconds,
+            "--idle-warning" => idle.warning = seconds,
+            "--probe-interval" if value > 0 => idle.probe_interval = seconds,
+            "--probe-timeout" if value > 0 => idle.probe_timeout = seconds,
+            "--tls-port" => tls_port = Some(u16::try_from(value).ok().filter(|&p| p > 0)?),
+            _ => return None,
         }
-
-        let db_clone: Arc<Mutex<Database>> = Arc::clone(&database);
-
-        tokio::spawn(async move {
-            let _ = handle_telnet_connection(client_fd, db_clone).await;
-        });
-        drop(permit);
     }
+    Some(Options {
+        limits,
+        idle,
+        tls_port,
+    })
 }
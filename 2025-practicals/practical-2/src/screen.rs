@@ -0,0 +1,777 @@
+//! The full-screen mode of a session.
+//!
+//! Terminals that report their window size with NAWS get a table of the user's friends instead of
+//! the command line: a title bar, a scrollable table moved through with the arrow keys, a status
+//! bar with the keys to press and dialogs with a form to add and edit friends. The whole screen is
+//! drawn again after every key, with absolute cursor positions, so nothing depends on what the
+//! terminal showed before. Dumb terminals and clients that do not negotiate keep the command line.
+//!
+//! `Screen` only draws and reads keys. Changes to the database are handed to the session as the
+//! same commands the command line runs, and the session passes back the friends and a status.
+use crate::commands::Command;
+use crate::database::{Friend, Number, DEFAULT_LABEL};
+use crate::keys::{Key, KeyDecoder};
+use std::collections::VecDeque;
+
+/// The smallest window the full-screen mode is drawn in.
+pub const MIN_WIDTH: u16 = 40;
+pub const MIN_HEIGHT: u16 = 10;
+
+/// Terminal types that cannot move the cursor.
+const DUMB_TERMINALS: [&str; 3] = ["DUMB", "UNKNOWN", "NETWORK-VIRTUAL-TERMINAL"];
+
+/// The longest value a form field takes.
+const MAX_FIELD_LENGTH: usize = 200;
+
+const ALTERNATE_SCREEN: &str = "\x1B[?1049h";
+const MAIN_SCREEN: &str = "\x1B[?1049l";
+const HIDE_CURSOR: &str = "\x1B[?25l";
+const SHOW_CURSOR: &str = "\x1B[?25h";
+const BOLD: &str = "\x1B[1m";
+const UNDERLINE: &str = "\x1B[4m";
+const REVERSE: &str = "\x1B[7m";
+const RESET: &str = "\x1B[0m";
+
+const HINTS: &str = "Arrows Move  A Add  Enter Edit  D Delete  / Search  L Line mode  Q Quit";
+const DIALOG_HINTS: &str = "Enter Save  Tab Next  Esc Cancel";
+
+/// What the session must do for the screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Run the commands and show the results in the status bar.
+    Run(Vec<Command>),
+    /// Leave the full-screen mode for the command line.
+    LineMode,
+    Quit,
+}
+
+#[derive(Debug)]
+struct Field {
+    label: String,
+    value: Vec<char>,
+    cursor: usize,
+    editable: bool,
+}
+
+impl Field {
+    fn new(label: &str, value: &str, editable: bool) -> Self {
+        let value: Vec<char> = value.chars().collect();
+        Field {
+            label: label.to_string(),
+            cursor: value.len(),
+            value,
+            editable,
+        }
+    }
+
+    fn text(&self) -> String {
+        self.value.iter().collect::<String>().trim().to_string()
+    }
+}
+
+#[derive(Debug)]
+enum DialogKind {
+    Add,
+    /// Editing the numbers of the friend with the name.
+    Edit(String, Vec<Number>),
+    Search,
+}
+
+/// A form in a box in the middle of the screen.
+#[derive(Debug)]
+struct Dialog {
+    kind: DialogKind,
+    title: String,
+    fields: Vec<Field>,
+    /// The field being typed in.
+    focus: usize,
+}
+
+#[derive(Debug)]
+pub struct Screen {
+    user: String,
+    width: usize,
+    height: usize,
+    keys: KeyDecoder,
+    friends: Vec<Friend>,
+    /// Only friends whose name or numbers contain it are shown.
+    filter: String,
+    /// The indexes in `friends` of the friends shown.
+    shown: Vec<usize>,
+    /// The row selected, an index in `shown`.
+    selected: usize,
+    /// The first row on the screen.
+    top: usize,
+    /// The friend to select when the friends are next set.
+    select_name: Option<String>,
+    status: String,
+    dialog: Option<Dialog>,
+    /// The friend whose deletion must be confirmed.
+    confirm_delete: Option<String>,
+    actions: VecDeque<Action>,
+}
+
+/// Returns the size of the full-screen mode for a client, or `None` if it must use the command
+/// line.
+///
+/// # Arguments
+/// - `terminal_type`: The terminal type the client sent, if any.
+/// - `window_size`: The window size the client sent with NAWS, if any.
+pub fn full_screen_size(
+    terminal_type: Option<&str>,
+    window_size: Option<(u16, u16)>,
+) -> Option<(u16, u16)> {
+    if terminal_type.is_some_and(|t| DUMB_TERMINALS.contains(&t)) {
+        return None;
+    }
+    window_size.filter(|&(width, height)| width >= MIN_WIDTH && height >= MIN_HEIGHT)
+}
+
+impl Screen {
+    /// Creates the screen of the user with the window size. The friends are set with
+    /// `set_friends`.
+    pub fn new(user: &str, (width, height): (u16, u16)) -> Self {
+        Screen {
+            user: user.to_string(),
+            width: width as usize,
+            height: height as usize,
+            keys: KeyDecoder::new(),
+            friends: Vec::new(),
+            filter: String::new(),
+            shown: Vec::new(),
+            selected: 0,
+            top: 0,
+            select_name: None,
+            status: String::new(),
+            dialog: None,
+            confirm_delete: None,
+            actions: VecDeque::new(),
+        }
+    }
+
+    /// Switches the terminal to the alternate screen, which keeps what was shown before for when
+    /// the full-screen mode ends, and draws the screen.
+    pub fn enter(&mut self) -> Vec<u8> {
+        let mut output: Vec<u8> = ALTERNATE_SCREEN.as_bytes().to_vec();
+        output.extend(self.render());
+        output
+    }
+
+    /// Returns the terminal to how it was before `enter`.
+    pub fn leave() -> Vec<u8> {
+        format!("{}{}{}", RESET, SHOW_CURSOR, MAIN_SCREEN).into_bytes()
+    }
+
+    /// Changes the window size, as when the client sends a new one with NAWS.
+    ///
+    /// # Returns
+    /// False if the window became too small for the full-screen mode.
+    pub fn resize(&mut self, (width, height): (u16, u16)) -> bool {
+        self.width = width as usize;
+        self.height = height as usize;
+        self.scroll();
+        width >= MIN_WIDTH && height >= MIN_HEIGHT
+    }
+
+    /// Shows the friends, keeping the selected one selected.
+    pub fn set_friends(&mut self, friends: Vec<Friend>) {
+        let selected: Option<String> = self
+            .select_name
+            .take()
+            .or_else(|| self.selected_friend().map(|f| f.name.clone()));
+        self.friends = friends;
+        self.show(selected);
+    }
+
+    /// Shows the friends matching the filter, after it changed.
+    fn refilter(&mut self) {
+        let selected: Option<String> = self.selected_friend().map(|f| f.name.clone());
+        self.show(selected);
+    }
+
+    /// Finds the friends matching the filter and selects the one with the name, if it is shown.
+    fn show(&mut self, selected: Option<String>) {
+        let filter: String = self.filter.to_lowercase();
+        self.shown = (0..self.friends.len())
+            .filter(|&i| {
+                let friend: &Friend = &self.friends[i];
+                friend.name.to_lowercase().contains(&filter)
+                    || friend.numbers.iter().any(|n| n.number.contains(&filter))
+            })
+            .collect();
+        if let Some(position) = selected.and_then(|name| {
+            self.shown
+                .iter()
+                .position(|&i| self.friends[i].name == name)
+        }) {
+            self.selected = position;
+        }
+        self.scroll();
+    }
+
+    /// Shows a message in the status bar until the next key.
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.trim().replace('\n', " ");
+    }
+
+    /// Processes text received from the client. What the session must do is read with
+    /// `next_action`.
+    pub fn feed(&mut self, data: &[u8]) {
+        for key in self.keys.feed(data) {
+            self.status.clear();
+            if self.confirm_delete.is_some() {
+                self.confirm_key(key);
+            } else if self.dialog.is_some() {
+                self.dialog_key(key);
+            } else {
+                self.table_key(key);
+            }
+        }
+    }
+
+    pub fn next_action(&mut self) -> Option<Action> {
+        self.actions.pop_front()
+    }
+
+    fn table_key(&mut self, key: Key) {
+        let rows: usize = self.table_rows();
+        match key {
+            Key::Up => self.select(self.selected.saturating_sub(1)),
+            Key::Down => self.select(self.selected + 1),
+            Key::PageUp => self.select(self.selected.saturating_sub(rows)),
+            Key::PageDown => self.select(self.selected + rows),
+            Key::Home => self.select(0),
+            Key::End => self.select(usize::MAX),
+            Key::Char('a') | Key::Char('A') => {
+                self.dialog = Some(Dialog {
+                    kind: DialogKind::Add,
+                    title: String::from("Add a friend"),
+                    fields: vec![
+                        Field::new("Name", "", true),
+                        Field::new("Label", DEFAULT_LABEL, true),
+                        Field::new("Number", "", true),
+                    ],
+                    focus: 0,
+                });
+            }
+            Key::Enter | Key::Char('e') | Key::Char('E') => {
+                if let Some(friend) = self.selected_friend().cloned() {
+                    let mut fields: Vec<Field> = vec![Field::new("Name", &friend.name, false)];
+                    for number in &friend.numbers {
+                        fields.push(Field::new(&number.label, &number.number, true));
+                    }
+                    fields.push(Field::new("New label", "", true));
+                    fields.push(Field::new("New number", "", true));
+                    self.dialog = Some(Dialog {
+                        kind: DialogKind::Edit(friend.name.clone(), friend.numbers),
+                        title: format!("Edit {}", friend.name),
+                        fields,
+                        focus: 1,
+                    });
+                }
+            }
+            Key::Char('d') | Key::Char('D') | Key::Delete => {
+                self.confirm_delete = self.selected_friend().map(|f| f.name.clone());
+            }
+            Key::Char('/') => {
+                self.dialog = Some(Dialog {
+                    kind: DialogKind::Search,
+                    title: String::from("Search"),
+                    fields: vec![Field::new("Name or number", &self.filter, true)],
+                    focus: 0,
+                });
+            }
+            Key::Escape if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.refilter();
+            }
+            Key::Char('l') | Key::Char('L') => self.actions.push_back(Action::LineMode),
+            Key::Char('q') | Key::Char('Q') | Key::Ctrl('d') => {
+                self.actions.push_back(Action::Quit)
+            }
+            _ => {}
+        }
+    }
+
+    fn confirm_key(&mut self, key: Key) {
+        let Some(name) = self.confirm_delete.take() else {
+            return;
+        };
+        if matches!(key, Key::Char('y') | Key::Char('Y')) {
+            self.actions
+                .push_back(Action::Run(vec![Command::Delete { name, label: None }]));
+        }
+    }
+
+    fn dialog_key(&mut self, key: Key) {
+        match key {
+            Key::Escape | Key::Ctrl('c') => {
+                self.dialog = None;
+                return;
+            }
+            Key::Enter => {
+                self.submit();
+                return;
+            }
+            _ => {}
+        }
+
+        let Some(dialog) = self.dialog.as_mut() else {
+            return;
+        };
+        if matches!(key, Key::Tab | Key::Down | Key::Up) {
+            dialog.move_focus(key != Key::Up);
+            return;
+        }
+        let field: &mut Field = &mut dialog.fields[dialog.focus];
+        match key {
+            Key::Left => field.cursor = field.cursor.saturating_sub(1),
+            Key::Right => field.cursor = (field.cursor + 1).min(field.value.len()),
+            Key::Home | Key::Ctrl('a') => field.cursor = 0,
+            Key::End | Key::Ctrl('e') => field.cursor = field.value.len(),
+            Key::Backspace if field.cursor > 0 => {
+                field.cursor -= 1;
+                field.value.remove(field.cursor);
+            }
+            Key::Delete if field.cursor < field.value.len() => {
+                field.value.remove(field.cursor);
+            }
+            Key::Ctrl('u') => {
+                field.value.clear();
+                field.cursor = 0;
+            }
+            Key::Char(c) if field.value.len() < MAX_FIELD_LENGTH => {
+                field.value.insert(field.cursor, c);
+                field.cursor += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Turns the dialog's form into commands for the session, or tells the user what is missing.
+    fn submit(&mut self) {
+        let Some(dialog) = self.dialog.take() else {
+            return;
+        };
+        let values: Vec<String> = dialog.fields.iter().map(Field::text).collect();
+
+        match &dialog.kind {
+            DialogKind::Add => {
+                let (name, label, number) = (&values[0], &values[1], &values[2]);
+                if name.is_empty() || number.is_empty() {
+                    self.status = String::from("A friend needs a name and a number.");
+                    self.dialog = Some(dialog);
+                    return;
+                }
+                self.select_name = Some(name.clone());
+                self.actions.push_back(Action::Run(vec![Command::Add {
+                    name: name.clone(),
+                    number: number.clone(),
+                    label: label_or_default(label),
+                }]));
+            }
+            DialogKind::Edit(name, numbers) => {
+                let mut commands: Vec<Command> = Vec::new();
+                for (number, value) in numbers.iter().zip(&values[1..]) {
+                    if value.is_empty() {
+                        commands.push(Command::Delete {
+                            name: name.clone(),
+                            label: Some(number.label.clone()),
+                        });
+                    } else if *value != number.number {
+                        commands.push(Command::Update {
+                            name: name.clone(),
+                            number: value.clone(),
+                            label: number.label.clone(),
+                        });
+                    }
+                }
+                let (new_label, new_number) =
+                    (&values[values.len() - 2], &values[values.len() - 1]);
+                if !new_number.is_empty() {
+                    commands.push(Command::Update {
+                        name: name.clone(),
+                        number: new_number.clone(),
+                        label: label_or_default(new_label),
+                    });
+                }
+                if commands.is_empty() {
+                    self.status = String::from("Nothing was changed.");
+                } else {
+                    self.actions.push_back(Action::Run(commands));
+                }
+            }
+            DialogKind::Search => {
+                self.filter = values[0].clone();
+                self.selected = 0;
+                self.refilter();
+            }
+        }
+    }
+
+    fn selected_friend(&self) -> Option<&Friend> {
+        self.shown.get(self.selected).map(|&i| &self.friends[i])
+    }
+
+    fn select(&mut self, row: usize) {
+        self.selected = row.min(self.shown.len().saturating_sub(1));
+        self.scroll();
+    }
+
+    /// Scrolls the table so that the selected row is on the screen.
+    fn scroll(&mut self) {
+        self.selected = self.selected.min(self.shown.len().saturating_sub(1));
+        let rows: usize = self.table_rows();
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + rows {
+            self.top = self.selected + 1 - rows;
+        }
+        self.top = self.top.min(self.shown.len().saturating_sub(rows));
+    }
+
+    /// Returns the number of rows between the header and the status bar.
+    fn table_rows(&self) -> usize {
+        self.height.saturating_sub(3).max(1)
+    }
+
+    /// Draws the whole screen.
+    pub fn render(&mut self) -> Vec<u8> {
+        let width: usize = self.width;
+        let mut output: String = String::from(HIDE_CURSOR);
+
+        // Title bar
+        let title: String = format!(" Friend Database - {}", self.user);
+        let count: String = if self.filter.is_empty() {
+            format!("{} friends ", self.friends.len())
+        } else {
+            format!(
+                "{} of {} friends matching \"{}\" ",
+                self.shown.len(),
+                self.friends.len(),
+                self.filter
+            )
+        };
+        let gap: usize = width.saturating_sub(title.chars().count() + count.chars().count());
+        output.push_str(&format!(
+            "{}{}{}{}",
+            goto(1, 1),
+            REVERSE,
+            fit(&format!("{}{}{}", title, " ".repeat(gap), count), width),
+            RESET
+        ));
+
+        // Table
+        let name_width: usize = self
+            .friends
+            .iter()
+            .map(|f| f.name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .clamp(8, width / 3);
+        let numbers_width: usize = width.saturating_sub(name_width + 3);
+        output.push_str(&format!(
+            "{}{} {} {}{}",
+            goto(2, 1),
+            BOLD,
+            fit("Name", name_width),
+            fit("Numbers", numbers_width + 1),
+            RESET
+        ));
+        for row in 0..self.table_rows() {
+            output.push_str(&goto(row + 3, 1));
+            let index: usize = self.top + row;
+            let line: String = match self.shown.get(index) {
+                Some(&i) => {
+                    let friend: &Friend = &self.friends[i];
+                    let numbers: Vec<String> = friend
+                        .numbers
+                        .iter()
+                        .map(|n| format!("{} {}", n.label, n.number))
+                        .collect();
+                    format!(
+                        " {} {}",
+                        fit(&friend.name, name_width),
+                        fit(&numbers.join(", "), numbers_width + 1)
+                    )
+                }
+                None if row == 0 && self.shown.is_empty() && self.filter.is_empty() => {
+                    fit(" You have no friends yet. Press A to add one.", width)
+                }
+                None if row == 0 && self.shown.is_empty() => {
+                    fit(" No friends match. Press Esc to show them all.", width)
+                }
+                None => " ".repeat(width),
+            };
+            if index == self.selected && !self.shown.is_empty() {
+                output.push_str(&format!("{}{}{}", REVERSE, fit(&line, width), RESET));
+            } else {
+                output.push_str(&fit(&line, width));
+            }
+        }
+
+        // Status bar
+        let status: String = match &self.confirm_delete {
+            Some(name) => format!(" Delete {} and all their numbers? (y/n)", name),
+            None if !self.status.is_empty() => format!(" {}", self.status),
+            None => format!(" {}", HINTS),
+        };
+        output.push_str(&format!(
+            "{}{}{}{}",
+            goto(self.height, 1),
+            REVERSE,
+            fit(&status, width),
+            RESET
+        ));
+
+        if let Some(dialog) = &self.dialog {
+            output.push_str(&self.render_dialog(dialog));
+        }
+        output.into_bytes()
+    }
+
+    /// Draws a dialog in a box in the middle of the screen, with the cursor in its field.
+    fn render_dialog(&self, dialog: &Dialog) -> String {
+        let box_width: usize = self.width.saturating_sub(4).min(60);
+        let inner: usize = box_width.saturating_sub(4);
+        let label_width: usize = dialog
+            .fields
+            .iter()
+            .map(|f| f.label.chars().count() + 1)
+            .max()
+            .unwrap_or(0)
+            .min(inner / 2);
+        let field_width: usize = inner.saturating_sub(label_width + 1).max(1);
+
+        // Fields that do not fit are scrolled to keep the focus in view
+        let visible: usize = self
+            .height
+            .saturating_sub(6)
+            .min(dialog.fields.len())
+            .max(1);
+        let first: usize = (dialog.focus + 1).saturating_sub(visible);
+        let box_height: usize = visible + 4;
+        let top: usize = (self.height.saturating_sub(box_height)) / 2 + 1;
+        let left: usize = (self.width.saturating_sub(box_width)) / 2 + 1;
+
+        let mut output: String = String::new();
+        let border: String = format!(
+            "+- {} {}+",
+            dialog.title,
+            "-".repeat(box_width.saturating_sub(dialog.title.chars().count() + 5))
+        );
+        output.push_str(&format!(
+            "{}{}{}{}",
+            goto(top, left),
+            BOLD,
+            fit(&border, box_width),
+            RESET
+        ));
+
+        let mut cursor: (usize, usize) = (top, left);
+        for (row, (index, field)) in dialog
+            .fields
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .enumerate()
+        {
+            // The value scrolls sideways to keep the cursor in the field
+            let start: usize = field.cursor.saturating_sub(field_width - 1);
+            let value: String = field.value.iter().skip(start).take(field_width).collect();
+            let value: String = if field.editable {
+                format!("{}{}{}", UNDERLINE, fit(&value, field_width), RESET)
+            } else {
+                fit(&value, field_width)
+            };
+            let y: usize = top + 1 + row;
+            output.push_str(&format!(
+                "{}| {} {}{} |",
+                goto(y, left),
+                fit(&format!("{}:", field.label), label_width),
+                value,
+                " ".repeat(inner.saturating_sub(label_width + 1 + field_width))
+            ));
+            if index == dialog.focus {
+                cursor = (y, left + 2 + label_width + 1 + field.cursor - start);
+            }
+        }
+        output.push_str(&format!(
+            "{}| {} |",
+            goto(top + 1 + visible, left),
+            " ".repeat(inner)
+        ));
+        output.push_str(&format!(
+            "{}| {} |",
+            goto(top + 2 + visible, left),
+            fit(DIALOG_HINTS, inner)
+        ));
+        output.push_str(&format!(
+            "{}+{}+",
+            goto(top + 3 + visible, left),
+            "-".repeat(box_width.saturating_sub(2))
+        ));
+        output.push_str(&format!("{}{}", goto(cursor.0, cursor.1), SHOW_CURSOR));
+        output
+    }
+}
+
+impl Dialog {
+    /// Moves the focus to the next or previous field that can be edited.
+    fn move_focus(&mut self, forward: bool) {
+        let count: usize = self.fields.len();
+        let mut focus: usize = self.focus;
+        for _ in 0..count {
+            focus = if forward {
+                (focus + 1) % count
+            } else {
+                (focus + count - 1) % count
+            };
+            if self.fields[focus].editable {
+                self.focus = focus;
+                return;
+            }
+        }
+    }
+}
+
+fn label_or_default(label: &str) -> String {
+    if label.is_empty() {
+        DEFAULT_LABEL.to_string()
+    } else {
+        label.to_lowercase()
+    }
+}
+
+/// Returns the escape sequence moving the cursor to a row and column, counted from 1.
+fn goto(row: usize, column: usize) -> String {
+    format!("\x1B[{};{}H", row, column)
+}
+
+/// Cuts or pads text to exactly `width` columns, ending cut text with `~`.
+fn fit(text: &str, width: usize) -> String {
+    let length: usize = text.chars().count();
+    if length > width {
+        let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+        if width > 0 {
+            cut.push('~');
+        }
+        cut
+    } else {
+        format!("{}{}", text, " ".repeat(width - length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn friend(name: &str, numbers: &[(&str, &str)]) -> Friend {
+        Friend {
+            name: name.to_string(),
+            numbers: numbers
+                .iter()
+                .map(|&(label, number)| Number {
+                    label: label.to_string(),
+                    number: number.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn actions(screen: &mut Screen) -> Vec<Action> {
+        std::iter::from_fn(|| screen.next_action()).collect()
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(
+            full_screen_size(Some("XTERM"), Some((80, 24))),
+            Some((80, 24))
+        );
+        assert_eq!(full_screen_size(None, Some((80, 24))), Some((80, 24)));
+        assert_eq!(full_screen_size(Some("DUMB"), Some((80, 24))), None);
+        assert_eq!(full_screen_size(Some("XTERM"), Some((30, 24))), None);
+        assert_eq!(full_screen_size(Some("XTERM"), None), None);
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut screen = Screen::new("ann", (40, 10));
+        let friends: Vec<Friend> = (0..20)
+            .map(|i| friend(&format!("Friend{:02}", i), &[("phone", "555")]))
+            .collect();
+        screen.set_friends(friends.clone());
+
+        // Seven rows fit between the header and the status bar
+        screen.feed(b"\x1b[B\x1b[6~");
+        assert_eq!((screen.selected, screen.top), (8, 2));
+        screen.feed(b"\x1b[F\x1b[A");
+        assert_eq!((screen.selected, screen.top), (18, 13));
+        assert!(String::from_utf8(screen.render())
+            .unwrap()
+            .contains(&format!("{} Friend18", REVERSE)));
+
+        // The selection follows the friend when the list changes
+        screen.set_friends(friends[10..].to_vec());
+        assert_eq!(screen.selected_friend().unwrap().name, "Friend18");
+
+        screen.feed(b"/nd05\r");
+        assert_eq!(screen.shown.len(), 0);
+        screen.feed(b"\x1b");
+        assert_eq!(screen.shown.len(), 10);
+        screen.feed(b"lq");
+        assert_eq!(actions(&mut screen), vec![Action::LineMode, Action::Quit]);
+    }
+
+    #[test]
+    fn test_dialogs() {
+        let mut screen = Screen::new("ann", (80, 24));
+        screen.set_friends(vec![friend("Bob", &[("home", "1"), ("work", "2")])]);
+
+        // A friend without a number is refused, and the dialog stays open
+        screen.feed(b"aCleo\r");
+        assert_eq!(screen.status, "A friend needs a name and a number.");
+        screen.feed(b"\t\x15Mobile\t555 0100\r");
+        assert_eq!(
+            actions(&mut screen),
+            vec![Action::Run(vec![Command::Add {
+                name: String::from("Cleo"),
+                number: String::from("555 0100"),
+                label: String::from("mobile"),
+            }])]
+        );
+
+        // Editing changes, deletes and adds numbers
+        screen.feed(b"\r\x1b[F3\t\x15\tfax\t4\r");
+        assert_eq!(
+            actions(&mut screen),
+            vec![Action::Run(vec![
+                Command::Update {
+                    name: String::from("Bob"),
+                    number: String::from("13"),
+                    label: String::from("home"),
+                },
+                Command::Delete {
+                    name: String::from("Bob"),
+                    label: Some(String::from("work")),
+                },
+                Command::Update {
+                    name: String::from("Bob"),
+                    number: String::from("4"),
+                    label: String::from("fax"),
+                },
+            ])]
+        );
+
+        screen.feed(b"dn\x1b[3~y");
+        assert_eq!(
+            actions(&mut screen),
+            vec![Action::Run(vec![Command::Delete {
+                name: String::from("Bob"),
+                label: None,
+            }])]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Admission and tracking of Telnet sessions.
+//!
+//! Every session holds a permit of the `SessionManager` for as long as it runs, so at most
+//! `max_sessions` clients are served at once and no more than `max_per_address` of them from the
+//! same IP address. Clients over the limit are turned away, or wait in a queue when a queue
+//! timeout is configured. The operator console on the server's standard input lists the sessions
+//! and disconnects them.
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Duration;
+
+pub const DEFAULT_MAX_SESSIONS: usize = 5;
+pub const DEFAULT_MAX_PER_ADDRESS: usize = 2;
+
+/// Limits on the sessions served at once.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLimits {
+    pub max_sessions: usize,
+    pub max_per_address: usize,
+    /// How long a client waits for a free session when the server is full, `None` to turn it
+    /// away at once.
+    pub queue_timeout: Option<Duration>,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        SessionLimits {
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            max_per_address: DEFAULT_MAX_PER_ADDRESS,
+            queue_timeout: None,
+        }
+    }
+}
+
+/// A running session as shown to the operator.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub address: SocketAddr,
+    pub opened: DateTime<Local>,
+    /// The name of the logged in user.
+    pub user: Option<String>,
+    kick: Arc<Notify>,
+}
+
+/// The outcome of a client's request for a session.
+#[derive(Debug)]
+pub enum Admission {
+    Admitted(SessionGuard),
+    /// Every session is in use.
+    Busy,
+    /// The client's address already has as many sessions as allowed.
+    TooManyFromAddress,
+}
+
+#[derive(Debug)]
+pub struct SessionManager {
+    limits: SessionLimits,
+    permits: Arc<Semaphore>,
+    sessions: Mutex<HashMap<u64, SessionInfo>>,
+    next_id: AtomicU64,
+    /// How many clients are waiting in the queue.
+    waiting: AtomicUsize,
+}
+
+impl SessionManager {
+    pub fn new(limits: SessionLimits) -> Self {
+        SessionManager {
+            limits,
+            permits: Arc::new(Semaphore::new(limits.max_sessions)),
+            sessions: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn limits(&self) -> SessionLimits {
+        self.limits
+    }
+
+    /// Admits a client if a session is free right away.
+    pub fn try_admit(self: &Arc<Self>, address: SocketAddr) -> Admission {
+        if self.count_from(address.ip()) >= self.limits.max_per_address {
+            return Admission::TooManyFromAddress;
+        }
+        match Arc::clone(&self.permits).try_acquire_owned() {
+            Ok(permit) => self.register(address, permit),
+            Err(_) => Admission::Busy,
+        }
+    }
+
+    /// Returns the number of clients ahead of one joining the queue now.
+    pub fn queue_length(&self) -> usize {
+        self.waiting.load(Ordering::SeqCst)
+    }
+
+    /// Waits in the queue for a free session, for at most the configured queue timeout.
+    ///
+    /// # Returns
+    /// `Busy` if no session was freed in time or queueing is disabled.
+    pub async fn admit_queued(self: &Arc<Self>, address: SocketAddr) -> Admission {
+        let Some(queue_timeout) = self.limits.queue_timeout else {
+            return Admission::Busy;
+        };
+
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        // The semaphore hands out permits in the order they were asked for
+        let permit =
+            tokio::time::timeout(queue_timeout, Arc::clone(&self.permits).acquire_owned()).await;
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+
+        match permit {
+            Ok(Ok(permit)) => self.register(address, permit),
+            _ => Admission::Busy,
+        }
+    }
+
+    /// Records a session holding `permit`, unless its address reached its limit meanwhile.
+    fn register(self: &Arc<Self>, address: SocketAddr, permit: OwnedSemaphorePermit) -> Admission {
+        let mut sessions = self.sessions.lock().unwrap();
+        let from_address: usize = sessions
+            .values()
+            .filter(|s| s.address.ip() == address.ip())
+            .count();
+        if from_address >= self.limits.max_per_address {
+            return Admission::TooManyFromAddress;
+        }
+
+        let id: u64 = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let kick: Arc<Notify> = Arc::new(Notify::new());
+        sessions.insert(
+            id,
+            SessionInfo {
+                id,
+                address,
+                opened: Local::now(),
+                user: None,
+                kick: Arc::clone(&kick),
+            },
+        );
+        println!(
+            "Session {} opened from {} ({} of {} in use)",
+            id,
+            address,
+            sessions.len(),
+            self.limits.max_sessions
+        );
+
+        Admission::Admitted(SessionGuard {
+            manager: Arc::clone(self),
+            id,
+            kick,
+            _permit: permit,
+        })
+    }
+
+    fn count_from(&self, address: IpAddr) -> usize {
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|s| s.address.ip() == address)
+            .count()
+    }
+
+    /// Returns the running sessions ordered by id.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> =
+            self.sessions.lock().unwrap().values().cloned().collect();
+        sessions.sort_by_key(|s| s.id);
+        sessions
+    }
+
+    /// Asks a session to disconnect its client.
+    ///
+    /// # Returns
+    /// False if there is no session with the id.
+    pub fn kick(&self, id: u64) -> bool {
+        match self.sessions.lock().unwrap().get(&id) {
+            Some(session) => {
+                // The notification is kept until the session next waits for it
+                session.kick.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A client's hold on a session. The session is released when the guard is dropped.
+#[derive(Debug)]
+pub struct SessionGuard {
+    manager: Arc<SessionManager>,
+    pub id: u64,
+    kick: Arc<Notify>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl SessionGuard {
+    /// Records who logged in to the session.
+    pub fn set_user(&self, name: &str) {
+        if let Some(session) = self.manager.sessions.lock().unwrap().get_mut(&self.id) {
+            session.user = Some(name.to_string());
+            println!("Session {} logged in as {}", self.id, name);
+        }
+    }
+
+    /// Completes when the operator kicks the session.
+    pub async fn kicked(&self) {
+        self.kick.notified().await;
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        if let Some(session) = self.manager.sessions.lock().unwrap().remove(&self.id) {
+            println!("Session {} from {} closed", session.id, session.address);
+        }
+    }
+}
+
+/// Reads operator commands from the server's standard input until it is closed.
+///
+/// # Arguments
+/// - `manager`: The sessions to list and kick.
+pub async fn operator_console(manager: Arc<SessionManager>) {
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let mut input = line.split_whitespace();
+        match input.next().map(|c| c.to_uppercase()).as_deref() {
+            Some("SESSIONS") => {
+                let sessions: Vec<SessionInfo> = manager.sessions();
+                println!(
+                    "{} of {} sessions in use, {} waiting",
+                    sessions.len(),
+                    manager.limits().max_sessions,
+                    manager.queue_length()
+                );
+                for session in sessions {
+                    println!(
+                        "{:>4}  {:<21}  {:<16}  since {}",
+                        session.id,
+                        session.address,
+                        session.user.as_deref().unwrap_or("-"),
+                        session.opened.format("%Y-%m-%d %H:%M:%S")
+                    );
+                }
+            }
+            Some("KICK") => match input.next().and_then(|id| id.parse::<u64>().ok()) {
+                Some(id) if manager.kick(id) => println!("Disconnecting session {}", id),
+                Some(id) => println!("No session {}", id),
+                None => println!("Usage: KICK <id>"),
+            },
+            Some(_) => println!("Operator commands: SESSIONS, KICK <id>"),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(ip: [u8; 4], port: u16) -> SocketAddr {
+        SocketAddr::from((ip, port))
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let manager = Arc::new(SessionManager::new(SessionLimits {
+            max_sessions: 3,
+            max_per_address: 2,
+            queue_timeout: Some(Duration::from_secs(5)),
+        }));
+
+        let first = manager.try_admit(address([10, 0, 0, 1], 1000));
+        let _second = manager.try_admit(address([10, 0, 0, 1], 1001));
+        assert!(matches!(
+            manager.try_admit(address([10, 0, 0, 1], 1002)),
+            Admission::TooManyFromAddress
+        ));
+        let _third = manager.try_admit(address([10, 0, 0, 2], 1000));
+        assert!(matches!(
+            manager.try_admit(address([10, 0, 0, 3], 1000)),
+            Admission::Busy
+        ));
+        assert_eq!(manager.sessions().len(), 3);
+
+        // A queued client gets the session of one that leaves
+        let queued = tokio::spawn({
+            let manager = Arc::clone(&manager);
+            async move { manager.admit_queued(address([10, 0, 0, 3], 1000)).await }
+        });
+        tokio::task::yield_now().await;
+        drop(first);
+        assert!(matches!(queued.await.unwrap(), Admission::Admitted(_)));
+    }
+
+    #[tokio::test]
+    async fn test_kick() {
+        let manager = Arc::new(SessionManager::new(SessionLimits::default()));
+        let Admission::Admitted(session) = manager.try_admit(address([127, 0, 0, 1], 1000)) else {
+            panic!("session refused");
+        };
+
+        assert!(manager.kick(session.id));
+        assert!(!manager.kick(session.id + 1));
+        tokio::time::timeout(Duration::from_secs(1), session.kicked())
+            .await
+            .unwrap();
+
+        drop(session);
+        assert!(manager.sessions().is_empty());
+    }
+}
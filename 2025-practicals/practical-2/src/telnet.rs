@@ -0,0 +1,539 @@
+//! The Telnet protocol layer (RFC 854).
+//!
+//! Bytes from the client go through `Telnet::receive`, which strips IAC command sequences,
+//! answers option negotiation and hands the remaining text to the session's `LineEditor`.
+//! The server negotiates ECHO, SUPPRESS-GO-AHEAD (RFC 858), TERMINAL-TYPE (RFC 1091) and NAWS
+//! (RFC 1073) and refuses every other option. Negotiation follows the Q method of RFC 1143 so
+//! that the two sides never loop on WILL/DO requests. TIMING-MARK (RFC 860) is not an option that
+//! stays on: the server sends DO TIMING-MARK to check that a client is still there.
+
+/// Interpret As Command, the byte that starts every Telnet command.
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+/// Subnegotiation Begin.
+pub const SB: u8 = 250;
+/// Go Ahead.
+pub const GA: u8 = 249;
+/// Erase Line.
+pub const EL: u8 = 248;
+/// Erase Character.
+pub const EC: u8 = 247;
+/// Interrupt Process.
+pub const IP: u8 = 244;
+/// No Operation.
+pub const NOP: u8 = 241;
+/// Subnegotiation End.
+pub const SE: u8 = 240;
+
+/// Telnet options.
+pub const ECHO: u8 = 1;
+pub const SUPPRESS_GO_AHEAD: u8 = 3;
+pub const TIMING_MARK: u8 = 6;
+pub const TERMINAL_TYPE: u8 = 24;
+pub const NAWS: u8 = 31;
+
+/// TERMINAL-TYPE subnegotiation commands.
+const TTYPE_IS: u8 = 0;
+const TTYPE_SEND: u8 = 1;
+
+/// The longest subnegotiation accepted. Longer ones are dropped.
+const MAX_SUBNEGOTIATION_LENGTH: usize = 256;
+
+/// A unit of input from the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Text, with IAC sequences removed and escaped IAC bytes restored.
+    Data(Vec<u8>),
+    /// A command without an option, such as NOP or GA.
+    Command(u8),
+    /// WILL, WONT, DO or DONT and the option it is about.
+    Negotiation(u8, u8),
+    /// The option and parameters of an SB ... SE sequence.
+    Subnegotiation(u8, Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    Iac,
+    Negotiation(u8),
+    SubnegotiationOption,
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Splits the byte stream from the client into text and commands. Sequences may be split across
+/// reads.
+#[derive(Debug)]
+pub struct Parser {
+    state: State,
+    option: u8,
+    parameters: Vec<u8>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser {
+            state: State::Data,
+            option: 0,
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Parses the next bytes received from the client.
+    pub fn feed(&mut self, input: &[u8]) -> Vec<Event> {
+        let mut events: Vec<Event> = Vec::new();
+        let mut data: Vec<u8> = Vec::new();
+
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    data.push(byte);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Negotiation(byte),
+                (State::Iac, SB) => State::SubnegotiationOption,
+                (State::Iac, _) => {
+                    flush(&mut data, &mut events);
+                    events.push(Event::Command(byte));
+                    State::Data
+                }
+                (State::Negotiation(verb), _) => {
+                    flush(&mut data, &mut events);
+                    events.push(Event::Negotiation(verb, byte));
+                    State::Data
+                }
+                (State::SubnegotiationOption, _) => {
+                    self.option = byte;
+                    self.parameters.clear();
+                    State::Subnegotiation
+                }
+                (State::Subnegotiation, IAC) => State::SubnegotiationIac,
+                (State::Subnegotiation, _) => {
+                    if self.parameters.len() < MAX_SUBNEGOTIATION_LENGTH {
+                        self.parameters.push(byte);
+                    }
+                    State::Subnegotiation
+                }
+                (State::SubnegotiationIac, IAC) => {
+                    if self.parameters.len() < MAX_SUBNEGOTIATION_LENGTH {
+                        self.parameters.push(IAC);
+                    }
+                    State::Subnegotiation
+                }
+                (State::SubnegotiationIac, _) => {
+                    // SE ends the subnegotiation, anything else is a broken sequence that is
+                    // ended the same way
+                    flush(&mut data, &mut events);
+                    if self.parameters.len() < MAX_SUBNEGOTIATION_LENGTH {
+                        events.push(Event::Subnegotiation(
+                            self.option,
+                            std::mem::take(&mut self.parameters),
+                        ));
+                    }
+                    self.parameters.clear();
+                    State::Data
+                }
+            };
+        }
+
+        flush(&mut data, &mut events);
+        events
+    }
+}
+
+fn flush(data: &mut Vec<u8>, events: &mut Vec<Event>) {
+    if !data.is_empty() {
+        events.push(Event::Data(std::mem::take(data)));
+    }
+}
+
+/// The RFC 1143 state of one side of an option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    No,
+    Yes,
+    /// Asked to enable, waiting for the answer.
+    WantYes,
+}
+
+/// Tracks which options are enabled on each side of the connection.
+#[derive(Debug)]
+struct Options {
+    /// Options the server performs, negotiated with WILL/WONT from the server.
+    local: [Side; 256],
+    /// Options the client performs, negotiated with WILL/WONT from the client.
+    remote: [Side; 256],
+}
+
+/// Returns true for options the server agrees to perform.
+fn supports_local(option: u8) -> bool {
+    matches!(option, ECHO | SUPPRESS_GO_AHEAD)
+}
+
+/// Returns true for options the server asks the client to perform.
+fn supports_remote(option: u8) -> bool {
+    matches!(option, SUPPRESS_GO_AHEAD | TERMINAL_TYPE | NAWS)
+}
+
+/// The Telnet state of a connection.
+#[derive(Debug)]
+pub struct Telnet {
+    parser: Parser,
+    options: Options,
+    /// The client's window size in columns and rows, sent with NAWS.
+    pub window_size: Option<(u16, u16)>,
+    /// The client's terminal type, such as `XTERM`, sent with TERMINAL-TYPE.
+    pub terminal_type: Option<String>,
+    /// True once the client has sent any negotiation, which shows it speaks Telnet.
+    negotiated: bool,
+}
+
+impl Default for Telnet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Telnet {
+    pub fn new() -> Self {
+        Telnet {
+            parser: Parser::new(),
+            options: Options {
+                local: [Side::No; 256],
+                remote: [Side::No; 256],
+            },
+            window_size: None,
+            terminal_type: None,
+            negotiated: false,
+        }
+    }
+
+    /// Returns the requests sent when the connection opens: the server echoes and suppresses
+    /// go-ahead, which puts clients in character mode, and asks for the window size and terminal
+    /// type.
+    pub fn start(&mut self) -> Vec<u8> {
+        let mut output: Vec<u8> = Vec::new();
+        self.enable_local(ECHO, &mut output);
+        self.enable_local(SUPPRESS_GO_AHEAD, &mut output);
+        self.enable_remote(SUPPRESS_GO_AHEAD, &mut output);
+        self.enable_remote(NAWS, &mut output);
+        self.enable_remote(TERMINAL_TYPE, &mut output);
+        output
+    }
+
+    /// Returns true if the client has negotiated, so it will answer a TIMING-MARK request.
+    pub fn is_telnet_client(&self) -> bool {
+        self.negotiated
+    }
+
+    /// Returns a TIMING-MARK request, which Telnet clients answer with WILL or WONT TIMING-MARK.
+    pub fn timing_mark(&self) -> Vec<u8> {
+        vec![IAC, DO, TIMING_MARK]
+    }
+
+    /// Returns true if the server performs `option`.
+    pub fn local_enabled(&self, option: u8) -> bool {
+        self.options.local[option as usize] == Side::Yes
+    }
+
+    /// Returns true if the client performs `option`.
+    pub fn remote_enabled(&self, option: u8) -> bool {
+        self.options.remote[option as usize] == Side::Yes
+    }
+
+    /// Processes bytes received from the client.
+    ///
+    /// # Returns
+    /// The text received, and the bytes to send back to the client in answer to negotiation.
+    pub fn receive(&mut self, input: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut text: Vec<u8> = Vec::new();
+        let mut output: Vec<u8> = Vec::new();
+
+        for event in self.parser.feed(input) {
+            match event {
+                Event::Data(data) => text.extend(data),
+                Event::Negotiation(verb, option) => {
+                    self.negotiated = true;
+                    self.negotiate(verb, option, &mut output);
+                }
+                Event::Subnegotiation(option, parameters) => {
+                    self.negotiated = true;
+                    self.subnegotiate(option, &parameters);
+                }
+                // The NVT editing functions act like the keys that clients in character mode send
+                Event::Command(IP) => text.push(0x03),
+                Event::Command(EC) => text.push(0x7f),
+                Event::Command(EL) => text.push(0x15),
+                // NOP, GA and the other commands carry nothing for the session
+                Event::Command(_) => {}
+            }
+        }
+        (text, output)
+    }
+
+    fn negotiate(&mut self, verb: u8, option: u8, output: &mut Vec<u8>) {
+        let index: usize = option as usize;
+        if option == TIMING_MARK {
+            // WILL and WONT answer our own requests. The server holds no output back, so a
+            // request from the client is answered at once.
+            if verb == DO {
+                send(output, WILL, TIMING_MARK);
+            }
+            return;
+        }
+        match verb {
+            WILL => {
+                let (state, reply) = on_enable(self.options.remote[index], supports_remote(option));
+                self.options.remote[index] = state;
+                if let Some(agree) = reply {
+                    send(output, if agree { DO } else { DONT }, option);
+                }
+                if state == Side::Yes && option == TERMINAL_TYPE {
+                    output.extend_from_slice(&[IAC, SB, TERMINAL_TYPE, TTYPE_SEND, IAC, SE]);
+                }
+            }
+            WONT => {
+                let (state, reply) = on_disable(self.options.remote[index]);
+                self.options.remote[index] = state;
+                if reply {
+                    send(output, DONT, option);
+                }
+            }
+            DO => {
+                let (state, reply) = on_enable(self.options.local[index], supports_local(option));
+                self.options.local[index] = state;
+                if let Some(agree) = reply {
+                    send(output, if agree { WILL } else { WONT }, option);
+                }
+            }
+            DONT => {
+                let (state, reply) = on_disable(self.options.local[index]);
+                self.options.local[index] = state;
+                if reply {
+                    send(output, WONT, option);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn subnegotiate(&mut self, option: u8, parameters: &[u8]) {
+        match (option, parameters) {
+            (NAWS, [w1, w2, h1, h2]) => {
+                self.window_size = Some((
+                    u16::from_be_bytes([*w1, *w2]),
+                    u16::from_be_bytes([*h1, *h2]),
+                ));
+            }
+            (TERMINAL_TYPE, [TTYPE_IS, name @ ..]) => {
+                self.terminal_type = Some(String::from_utf8_lossy(name).to_uppercase());
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks to perform `option` unless it is enabled or already asked for.
+    fn enable_local(&mut self, option: u8, output: &mut Vec<u8>) {
+        if self.options.local[option as usize] == Side::No {
+            self.options.local[option as usize] = Side::WantYes;
+            send(output, WILL, option);
+        }
+    }
+
+    /// Asks the client to perform `option` unless it is enabled or already asked for.
+    fn enable_remote(&mut self, option: u8, output: &mut Vec<u8>) {
+        if self.options.remote[option as usize] == Side::No {
+            self.options.remote[option as usize] = Side::WantYes;
+            send(output, DO, option);
+        }
+    }
+}
+
+/// Handles a request to enable an option (WILL for the client's side, DO for the server's).
+///
+/// # Returns
+/// The new state and, if a reply is due, whether to agree.
+fn on_enable(state: Side, supported: bool) -> (Side, Option<bool>) {
+    match state {
+        Side::No if supported => (Side::Yes, Some(true)),
+        Side::No => (Side::No, Some(false)),
+        Side::Yes => (Side::Yes, None),
+        // The answer to our own request
+        Side::WantYes => (Side::Yes, None),
+    }
+}
+
+/// Handles a request to disable an option (WONT for the client's side, DONT for the server's).
+///
+/// # Returns
+/// The new state and whether a reply is due.
+fn on_disable(state: Side) -> (Side, bool) {
+    match state {
+        Side::No => (Side::No, false),
+        Side::Yes => (Side::No, true),
+        // The peer refused our request
+        Side::WantYes => (Side::No, false),
+    }
+}
+
+fn send(output: &mut Vec<u8>, verb: u8, option: u8) {
+    output.extend_from_slice(&[IAC, verb, option]);
+}
+
+/// Converts text to the network virtual terminal's format: line feeds become CR LF, which
+/// terminals in character mode need to return to the first column, and IAC bytes are escaped.
+pub fn encode(text: &str) -> Vec<u8> {
+    let mut encoded: Vec<u8> = Vec::with_capacity(text.len());
+    let mut previous: u8 = 0;
+    for &byte in text.as_bytes() {
+        if byte == b'\n' && previous != b'\r' {
+            encoded.push(b'\r');
+        }
+        encoded.push(byte);
+        previous = byte;
+    }
+    escape(&encoded)
+}
+
+/// Doubles any IAC bytes in text sent to the client so they are not read as commands.
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped: Vec<u8> = Vec::with_capacity(data.len());
+    for &byte in data {
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+        escaped.push(byte);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parser_strips_commands_across_reads() {
+        let mut parser = Parser::new();
+        assert_eq!(
+            parser.feed(&[b'h', IAC, NOP, b'i', IAC]),
+            vec![
+                Event::Data(b"h".to_vec()),
+                Event::Command(NOP),
+                Event::Data(b"i".to_vec())
+            ]
+        );
+        assert_eq!(
+            parser.feed(&[IAC, IAC, DO, ECHO, IAC, SB, NAWS, 0, 80, 0]),
+            vec![Event::Data(vec![IAC]), Event::Negotiation(DO, ECHO)]
+        );
+        assert_eq!(
+            parser.feed(&[24, IAC, SE, b'!']),
+            vec![
+                Event::Subnegotiation(NAWS, vec![0, 80, 0, 24]),
+                Event::Data(b"!".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let mut telnet = Telnet::new();
+        let requests: Vec<u8> = telnet.start();
+        assert_eq!(
+            requests,
+            vec![
+                IAC,
+                WILL,
+                ECHO,
+                IAC,
+                WILL,
+                SUPPRESS_GO_AHEAD,
+                IAC,
+                DO,
+                SUPPRESS_GO_AHEAD,
+                IAC,
+                DO,
+                NAWS,
+                IAC,
+                DO,
+                TERMINAL_TYPE
+            ]
+        );
+
+        // Answers to our own requests are not acknowledged again
+        assert_eq!(telnet.receive(&[IAC, DO, SUPPRESS_GO_AHEAD]).1, vec![]);
+        assert_eq!(telnet.receive(&[IAC, WILL, NAWS]).1, vec![]);
+        assert_eq!(
+            telnet.receive(&[IAC, WILL, TERMINAL_TYPE]).1,
+            vec![IAC, SB, TERMINAL_TYPE, TTYPE_SEND, IAC, SE]
+        );
+        assert!(telnet.local_enabled(SUPPRESS_GO_AHEAD));
+        assert!(telnet.remote_enabled(NAWS));
+
+        // A client that echoes locally refuses, and may ask again later
+        assert_eq!(telnet.receive(&[IAC, DONT, ECHO]).1, vec![]);
+        assert!(!telnet.local_enabled(ECHO));
+        assert_eq!(telnet.receive(&[IAC, DO, ECHO]).1, vec![IAC, WILL, ECHO]);
+
+        // Unsupported options are refused, repeated requests are ignored
+        assert_eq!(telnet.receive(&[IAC, DO, 99]).1, vec![IAC, WONT, 99]);
+        assert_eq!(telnet.receive(&[IAC, WILL, NAWS]).1, vec![]);
+        assert_eq!(telnet.receive(&[IAC, DONT, ECHO]).1, vec![IAC, WONT, ECHO]);
+
+        telnet.receive(&[IAC, SB, NAWS, 0, 120, 0, 40, IAC, SE]);
+        telnet.receive(&[
+            IAC,
+            SB,
+            TERMINAL_TYPE,
+            TTYPE_IS,
+            b'x',
+            b't',
+            b'e',
+            b'r',
+            b'm',
+            IAC,
+            SE,
+        ]);
+        assert_eq!(telnet.window_size, Some((120, 40)));
+        assert_eq!(telnet.terminal_type.as_deref(), Some("XTERM"));
+    }
+
+    #[test]
+    fn test_timing_mark() {
+        let mut telnet = Telnet::new();
+        assert!(!telnet.is_telnet_client());
+        assert_eq!(telnet.receive(&[IAC, WILL, TIMING_MARK]).1, vec![]);
+        assert_eq!(telnet.receive(&[IAC, WONT, TIMING_MARK]).1, vec![]);
+        assert_eq!(
+            telnet.receive(&[IAC, DO, TIMING_MARK]).1,
+            vec![IAC, WILL, TIMING_MARK]
+        );
+        assert!(telnet.is_telnet_client());
+        assert!(!telnet.remote_enabled(TIMING_MARK));
+    }
+
+    #[test]
+    fn test_text() {
+        let mut telnet = Telnet::new();
+        assert_eq!(
+            telnet.receive(&[b'5', IAC, NOP, b'5', IAC, EC, IAC, IP]).0,
+            vec![b'5', b'5', 0x7f, 0x03]
+        );
+        assert_eq!(encode("a\nb\r\n"), b"a\r\nb\r\n".to_vec());
+    }
+}
@@ -0,0 +1,137 @@
+//! The TLS listener for Telnet over TLS (telnets).
+//!
+//! The certificate and private key are PEM files, `server.crt` and `server.key` in the working
+//! directory, like the HTTP practicals. Sessions over TLS run the same Telnet session as plain
+//! connections once the handshake is done.
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+
+pub const CERT_FILE: &str = "server.crt";
+pub const KEY_FILE: &str = "server.key";
+
+/// Loads the server's certificate chain and private key.
+///
+/// # Arguments
+/// - `cert_path`: A PEM file with the certificate, followed by any intermediate certificates.
+/// - `key_path`: A PEM file with the certificate's private key.
+///
+/// # Returns
+/// The TLS configuration of the server, or why it could not be loaded.
+pub fn load_tls_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig, Box<dyn Error>> {
+    let certs: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| format!("Cannot open the certificate {}: {}", cert_path.display(), e))?
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Cannot read the certificate {}: {}", cert_path.display(), e))?;
+    let key: PrivateKeyDer<'static> = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("Cannot read the private key {}: {}", key_path.display(), e))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config: ServerConfig = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(config)
+}
+
+/// Loads the certificate and key from `CERT_FILE` and `KEY_FILE` for accepting TLS connections.
+pub fn tls_acceptor() -> Result<TlsAcceptor, Box<dyn Error>> {
+    let config: ServerConfig = load_tls_config(Path::new(CERT_FILE), Path::new(KEY_FILE))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::hash_password;
+    use crate::connection::{accept_clients, create_listener};
+    use crate::database::{Database, Role};
+    use crate::idle::IdleSettings;
+    use crate::session::{SessionLimits, SessionManager};
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::Mutex;
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::TlsConnector;
+
+    /// Reads from the server until the text arrives, skipping Telnet negotiation.
+    async fn read_until(stream: &mut TlsStream<TcpStream>, text: &str) -> String {
+        let mut received: Vec<u8> = Vec::new();
+        let mut buffer: [u8; 1024] = [0; 1024];
+        while !String::from_utf8_lossy(&received).contains(text) {
+            let n: usize = stream.read(&mut buffer).await.unwrap();
+            assert!(n > 0, "Disconnected before {:?}", text);
+            received.extend_from_slice(&buffer[..n]);
+        }
+        String::from_utf8_lossy(&received).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_telnets_session() {
+        // A self-signed certificate, written where `load_tls_config` reads it
+        let certificate =
+            rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let directory = std::env::temp_dir().join(format!("telnets-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (cert_path, key_path) = (directory.join(CERT_FILE), directory.join(KEY_FILE));
+        std::fs::write(&cert_path, certificate.cert.pem()).unwrap();
+        std::fs::write(&key_path, certificate.key_pair.serialize_pem()).unwrap();
+        let config: ServerConfig = load_tls_config(&cert_path, &key_path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(load_tls_config(&cert_path, &key_path).is_err());
+
+        let database = Database::new(":memory:").unwrap();
+        database
+            .add_user("ann", &hash_password("ann's password"), Role::User)
+            .unwrap();
+        let listener = create_listener(0).unwrap();
+        let port: u16 = listener.local_addr().unwrap().port();
+        tokio::spawn(accept_clients(
+            listener,
+            Some(TlsAcceptor::from(Arc::new(config))),
+            Arc::new(SessionManager::new(SessionLimits::default())),
+            Arc::new(Mutex::new(database)),
+            IdleSettings::default(),
+        ));
+
+        let mut roots: RootCertStore = RootCertStore::empty();
+        roots.add(certificate.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client_config: ClientConfig = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut stream = connector
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+
+        assert!(read_until(&mut stream, "login: ")
+            .await
+            .contains("Welcome to the Telnet Friend Database!"));
+        stream.write_all(b"ann\r\n").await.unwrap();
+        read_until(&mut stream, "Password: ").await;
+        stream.write_all(b"ann's password\r\n").await.unwrap();
+        read_until(&mut stream, "Logged in as").await;
+        stream.write_all(b"ADD Bob 0123\r\n").await.unwrap();
+        read_until(&mut stream, "Added Bob").await;
+        stream.write_all(b"EXIT\r\n").await.unwrap();
+        read_until(&mut stream, "Goodbye!").await;
+
+        // A client that does not speak TLS is dropped after a failed handshake
+        let mut plain = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        plain.write_all(b"admin\r\n").await.unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        let _ = plain.read_to_end(&mut buffer).await;
+        assert!(!String::from_utf8_lossy(&buffer).contains("login:"));
+    }
+}
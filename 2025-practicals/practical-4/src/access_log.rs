@@ -0,0 +1,198 @@
+//! Structured access logging.
+//!
+//! One line is written per completed response, after the response has been flushed to the
+//! client. The line is written to the logging target of the virtual host that served the request
+//! in either Common Log Format, Combined Log Format or JSON.
+use crate::vhost::VirtualHost;
+use crate::Request;
+use chrono::{DateTime, Utc};
+use std::time::{Duration, Instant};
+
+/// The format access log lines are written in.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// `host ident authuser [date] "request" status bytes`
+    Common,
+    /// Common Log Format followed by the referer, user agent, request id and duration.
+    #[default]
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+/// The details of a request that are recorded once its response has been written.
+#[derive(Debug)]
+pub struct AccessLogEntry {
+    client_ip: String,
+    method: String,
+    uri: String,
+    protocol: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    request_id: String,
+    received: DateTime<Utc>,
+    started: Instant,
+}
+
+/// A finished access log record.
+#[derive(serde::Serialize, Debug)]
+pub struct AccessLogRecord {
+    pub client_ip: String,
+    pub time: String,
+    pub method: String,
+    pub path: String,
+    pub protocol: String,
+    pub status: u16,
+    pub bytes: usize,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: String,
+    pub duration_ms: f64,
+}
+
+impl AccessLogEntry {
+    /// Captures the request details before the request is handed to the handlers.
+    ///
+    /// # Arguments
+    /// - `request`: The parsed request.
+    /// - `started`: When the server started reading the request.
+    pub fn new(request: &Request, started: Instant) -> Self {
+        AccessLogEntry {
+            client_ip: client_ip(&request.client_ip),
+            method: request.method.to_string(),
+            uri: request.uri.clone(),
+            protocol: String::from("HTTP/1.1"),
+            referer: request.get_header("Referer").map(str::to_string),
+            user_agent: request.get_header("User-Agent").map(str::to_string),
+            request_id: request.request_id.clone(),
+            received: Utc::now(),
+            started,
+        }
+    }
+
+    /// Completes the entry once the response has been written.
+    ///
+    /// # Arguments
+    /// - `status`: The numeric status code sent to the client.
+    /// - `bytes`: The number of body bytes sent to the client.
+    pub fn finish(self, status: u16, bytes: usize) -> AccessLogRecord {
+        let duration: Duration = self.started.elapsed();
+        self.finish_after(status, bytes, duration)
+    }
+
+    fn finish_after(self, status: u16, bytes: usize, duration: Duration) -> AccessLogRecord {
+        AccessLogRecord {
+            client_ip: self.client_ip,
+            time: self.received.format("%d/%b/%Y:%H:%M:%S %z").to_string(),
+            method: self.method,
+            path: self.uri,
+            protocol: self.protocol,
+            status,
+            bytes,
+            referer: self.referer,
+            user_agent: self.user_agent,
+            request_id: self.request_id,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+impl AccessLogRecord {
+    /// Renders the record as a single log line.
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        let common = format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.client_ip,
+            self.time,
+            self.method,
+            self.path,
+            self.protocol,
+            self.status,
+            if self.bytes == 0 {
+                String::from("-")
+            } else {
+                self.bytes.to_string()
+            }
+        );
+
+        match format {
+            AccessLogFormat::Common => common,
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\" {} {:.3}ms",
+                common,
+                escape(self.referer.as_deref().unwrap_or("-")),
+                escape(self.user_agent.as_deref().unwrap_or("-")),
+                self.request_id,
+                self.duration_ms
+            ),
+            AccessLogFormat::Json => serde_json::to_string(self).unwrap_or(common),
+        }
+    }
+}
+
+/// Writes the access log line for a completed response to the host's logging target.
+pub fn log(host: &VirtualHost, record: AccessLogRecord) {
+    log::info!(target: host.log_target.as_str(), "{}", record.format(host.access_log));
+}
+
+/// Returns the IP address of a client address, dropping the port.
+fn client_ip(address: &str) -> String {
+    match address.parse::<std::net::SocketAddr>() {
+        Ok(a) => a.ip().to_string(),
+        Err(_) => address.to_string(),
+    }
+}
+
+/// Escapes quotes and backslashes in quoted log fields.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HttpMethod;
+
+    fn record() -> AccessLogRecord {
+        let request = Request {
+            request_id: String::from("7"),
+            traceparent: None,
+            client_ip: String::from("127.0.0.1:50000"),
+            headers: vec![
+                String::from("Host: localhost"),
+                String::from("User-Agent: curl/8.0"),
+            ],
+            body: String::new(),
+            multipart: None,
+            method: HttpMethod::GET,
+            uri: String::from("/friends"),
+        };
+        AccessLogEntry::new(&request, Instant::now()).finish_after(
+            200,
+            42,
+            Duration::from_millis(3),
+        )
+    }
+
+    #[test]
+    fn test_common_format() {
+        let line = record().format(AccessLogFormat::Common);
+        assert!(line.starts_with("127.0.0.1 - - ["));
+        assert!(line.ends_with("\"GET /friends HTTP/1.1\" 200 42"));
+    }
+
+    #[test]
+    fn test_combined_format() {
+        let line = record().format(AccessLogFormat::Combined);
+        assert!(line.ends_with("200 42 \"-\" \"curl/8.0\" 7 3.000ms"));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let line = record().format(AccessLogFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["request_id"], "7");
+    }
+}
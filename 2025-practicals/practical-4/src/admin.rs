@@ -0,0 +1,317 @@
+//! The admin listener.
+//!
+//! A small plain-HTTP server bound to the loopback interface on a separate port from the public
+//! TLS listener. It exposes the metrics registry on `/metrics` in the Prometheus text format,
+//! liveness on `/healthz` and readiness on `/readyz`.
+//!
+//! The admin API under `/admin/` lists the open connections, flushes the page cache, reloads the
+//! configuration and certificate and starts a graceful shutdown. Its requests must carry the token
+//! set in the `ADMIN_TOKEN` environment variable as `Authorization: Bearer <token>`, and it is
+//! disabled if the variable is not set.
+use crate::control::Control;
+use crate::response::Response;
+use crate::server::SharedState;
+use crate::{ContentType, HttpCode, Protocol};
+use log::{error, info};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+/// The default port the admin listener binds to if none is specified.
+pub const DEFAULT_ADMIN_PORT: u16 = 9878;
+
+/// The environment variable holding the token of the admin API.
+pub const ADMIN_TOKEN_VAR: &str = "ADMIN_TOKEN";
+
+/// The parts of an admin request the routes need.
+struct AdminRequest {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+}
+
+/// Serves admin requests until the task is dropped.
+///
+/// # Arguments
+/// - `listener`: The admin listener, bound to `127.0.0.1`.
+/// - `state`: A shared, thread-safe state holding the connections the readiness checks use.
+/// - `control`: The runtime control of the server.
+/// - `token`: The token admin API requests must carry, or `None` to disable the admin API.
+pub async fn run_admin_server(
+    listener: TcpListener,
+    state: Arc<Mutex<SharedState>>,
+    control: Arc<Control>,
+    token: Option<String>,
+) {
+    if let Ok(address) = listener.local_addr() {
+        info!(target: "request_logger","Admin server listening on port {}", address.port());
+    }
+    if token.is_none() {
+        info!(target: "request_logger","Admin API disabled, set {} to enable it", ADMIN_TOKEN_VAR);
+    }
+    let token: Option<Arc<str>> = token.map(Arc::from);
+
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(c) => c,
+            Err(e) => {
+                error!(target: "error_logger","Failed to accept admin connection: {}", e);
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        let control = control.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_admin_connection(stream, state, control, token).await {
+                error!(target: "error_logger","Admin connection with {} failed: {}", address, e);
+            }
+        });
+    }
+}
+
+/// Reads a single request from the admin connection and writes the response.
+async fn handle_admin_connection(
+    mut stream: TcpStream,
+    state: Arc<Mutex<SharedState>>,
+    control: Arc<Control>,
+    token: Option<Arc<str>>,
+) -> std::io::Result<()> {
+    let mut request_data: Vec<u8> = Vec::new();
+    let mut buffer: [u8; 1024] = [0; 1024];
+
+    while !request_data.windows(4).any(|w| w == b"\r\n\r\n") && request_data.len() < 8192 {
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        request_data.extend_from_slice(&buffer[..n]);
+    }
+
+    let request: AdminRequest = parse_request(&String::from_utf8_lossy(&request_data));
+    let mut response = admin_response(&request, &state, &control, token.as_deref()).await;
+    stream.write_all(&response.to_bytes()).await?;
+    stream.flush().await
+}
+
+/// Reads the request line and the `Authorization` header of an admin request.
+fn parse_request(request_text: &str) -> AdminRequest {
+    let mut lines = request_text.lines();
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let method: &str = request_line.next().unwrap_or("");
+    let path: &str = request_line.next().unwrap_or("");
+
+    let authorization: Option<String> = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(title, _)| title.trim().eq_ignore_ascii_case("Authorization"))
+        .map(|(_, value)| value.trim().to_string());
+
+    AdminRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        authorization,
+    }
+}
+
+/// Routes an admin request.
+async fn admin_response(
+    request: &AdminRequest,
+    state: &Arc<Mutex<SharedState>>,
+    control: &Arc<Control>,
+    token: Option<&str>,
+) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => {
+            let mut response =
+                Response::new(Protocol::Http, HttpCode::Ok, ContentType::Text, false)
+                    .body(crate::metrics::metrics().render().into_bytes());
+            response.headers.retain(|h| h.title != "Content-Type");
+            response.add_header(
+                String::from("Content-Type"),
+                String::from("text/plain; version=0.0.4"),
+            );
+            response
+        }
+        ("GET", "/healthz") => json_response(HttpCode::Ok, ::health::liveness()),
+        ("GET", "/readyz") => readiness(state, control).await,
+        (_, "/metrics" | "/healthz" | "/readyz") => method_not_allowed(),
+        (_, path) if path.starts_with("/admin/") => match authorize(request, token) {
+            Ok(()) => {
+                info!(target: "request_logger","Admin API: {} {}", request.method, path);
+                admin_api(&request.method, path, state, control).await
+            }
+            Err(response) => response,
+        },
+        _ => not_found(),
+    }
+}
+
+/// Answers `/readyz` with the result of every readiness check.
+async fn readiness(state: &Arc<Mutex<SharedState>>, control: &Arc<Control>) -> Response {
+    let checks: Vec<crate::health::Check> =
+        crate::health::readiness_checks(state, &control.certificate()).await;
+    for check in &checks {
+        if let Err(e) = &check.result {
+            error!(target: "error_logger","Readiness check {} failed: {}", check.name, e);
+        }
+    }
+    let (ready, body) = ::health::report(&checks, control.is_shutting_down());
+
+    json_response(
+        if ready {
+            HttpCode::Ok
+        } else {
+            HttpCode::ServiceUnavailable
+        },
+        body,
+    )
+}
+
+/// Checks the bearer token of an admin API request.
+///
+/// # Returns
+/// The response to send instead if the request may not use the admin API.
+fn authorize(request: &AdminRequest, token: Option<&str>) -> Result<(), Response> {
+    let token: &str = match token {
+        Some(t) => t,
+        None => {
+            return Err(Response::new(
+                Protocol::Http,
+                HttpCode::Forbidden,
+                ContentType::Text,
+                false,
+            )
+            .body(format!("Admin API disabled, set {}", ADMIN_TOKEN_VAR).into_bytes()))
+        }
+    };
+
+    let supplied: Option<&str> = request
+        .authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match supplied {
+        Some(s) if crate::csrf::constant_time_eq(s.trim().as_bytes(), token.as_bytes()) => Ok(()),
+        _ => {
+            error!(target: "error_logger","Rejected admin API request to {}", request.path);
+            let mut response = Response::new(
+                Protocol::Http,
+                HttpCode::Unauthorized,
+                ContentType::Text,
+                false,
+            )
+            .body(b"Unauthorized".to_vec());
+            response.add_header(String::from("WWW-Authenticate"), String::from("Bearer"));
+            Err(response)
+        }
+    }
+}
+
+/// Routes an authorized admin API request.
+async fn admin_api(
+    method: &str,
+    path: &str,
+    state: &Arc<Mutex<SharedState>>,
+    control: &Arc<Control>,
+) -> Response {
+    match (method, path) {
+        ("GET", "/admin/connections") => {
+            let connections: Vec<serde_json::Value> = control
+                .connections()
+                .iter()
+                .map(|c| {
+                    serde_json::json!({
+                        "id": c.id,
+                        "address": c.address,
+                        "protocol": c.protocol,
+                        "open_seconds": c.opened.elapsed().as_secs(),
+                    })
+                })
+                .collect();
+            json_response(
+                HttpCode::Ok,
+                serde_json::json!({ "count": connections.len(), "connections": connections }),
+            )
+        }
+        ("POST", "/admin/cache/flush") => {
+            let mut state = state.lock().await;
+            match state.cache.flush() {
+                Ok(()) => {
+                    info!(target: "request_logger","Page cache flushed");
+                    json_response(HttpCode::Ok, serde_json::json!({ "status": "flushed" }))
+                }
+                Err(e) => {
+                    error!(target: "error_logger","Failed to flush the page cache: {}", e);
+                    json_response(
+                        HttpCode::InternalServerError,
+                        serde_json::json!({ "status": "failed", "error": e.to_string() }),
+                    )
+                }
+            }
+        }
+        ("POST", "/admin/reload") => match control.reload().await {
+            Ok(()) => json_response(HttpCode::Ok, serde_json::json!({ "status": "reloaded" })),
+            Err(e) => {
+                error!(target: "error_logger","Failed to reload the configuration: {}", e);
+                json_response(
+                    HttpCode::InternalServerError,
+                    serde_json::json!({ "status": "failed", "error": e.to_string() }),
+                )
+            }
+        },
+        ("POST", "/admin/shutdown") => {
+            control.shutdown();
+            json_response(
+                HttpCode::Ok,
+                serde_json::json!({ "status": "shutting down", "open_connections": control.connections().len() }),
+            )
+        }
+        (_, "/admin/connections" | "/admin/cache/flush" | "/admin/reload" | "/admin/shutdown") => {
+            method_not_allowed()
+        }
+        _ => not_found(),
+    }
+}
+
+fn json_response(code: HttpCode, value: serde_json::Value) -> Response {
+    Response::new(Protocol::Http, code, ContentType::Json, false)
+        .body(value.to_string().into_bytes())
+}
+
+fn method_not_allowed() -> Response {
+    Response::new(
+        Protocol::Http,
+        HttpCode::MethodNotAllowed,
+        ContentType::Text,
+        false,
+    )
+    .body(b"Method Not Allowed".to_vec())
+}
+
+fn not_found() -> Response {
+    Response::new(Protocol::Http, HttpCode::NotFound, ContentType::Text, false)
+        .body(b"Not Found".to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let request = parse_request(
+            "POST /admin/shutdown HTTP/1.1\r\nHost: 127.0.0.1\r\nauthorization: Bearer secret\r\n\r\n",
+        );
+        assert_eq!(request.path, "/admin/shutdown");
+        assert!(authorize(&request, Some("secret")).is_ok());
+
+        let code = |result: Result<(), Response>| result.err().map(|r| r.code.status_code());
+        assert_eq!(code(authorize(&request, Some("other"))), Some(401));
+        assert_eq!(code(authorize(&request, None)), Some(403));
+
+        let anonymous = parse_request("POST /admin/shutdown HTTP/1.1\r\n\r\n");
+        assert_eq!(code(authorize(&anonymous, Some("secret"))), Some(401));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error_page::error_response;
 /// This module provides the core functionality for handling HTTP requests and
 /// generating appropriate responses in an asynchronous server.
 ///
@@ -7,13 +8,17 @@
 /// - Integration with shared state for caching and user management.
 use crate::response::{MyDefault, Response};
 use crate::server::SharedState;
-use crate::{ContentType, HttpCode, HttpMethod, Request};
-use log::{error, info};
+use crate::vhost::{Application, VirtualHost};
+use crate::websocket::frame::GOING_AWAY;
+use crate::websocket::{Message, WebSocket, WebSocketError};
+use crate::{ContentType, ErrorType, HttpCode, HttpMethod, Request};
+use log::error;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::fs::{self, File};
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
@@ -34,16 +39,16 @@ struct FriendName {
 /// - `path`: A string slice that holds the file path.
 ///
 /// # Returns
-/// A vector of bytes representing the file content.
-///
-/// # Panics
-/// Panics if the file cannot be opened or read.
-pub async fn read_file_to_bytes(path: &str) -> Vec<u8> {
-    let metadata = fs::metadata(path).await.unwrap();
-    let mut file = File::open(path).await.unwrap();
+/// A vector of bytes representing the file content or an `ErrorType::ReadError` if the file
+/// cannot be opened or read.
+pub async fn read_file_to_bytes(path: &str) -> Result<Vec<u8>, ErrorType> {
+    let read_error = |e: std::io::Error| ErrorType::ReadError(format!("{}: {}", path, e));
+
+    let metadata = fs::metadata(path).await.map_err(read_error)?;
+    let mut file = File::open(path).await.map_err(read_error)?;
     let mut buffer: Vec<u8> = Vec::with_capacity(metadata.len() as usize);
-    file.read_to_end(&mut buffer).await.unwrap();
-    buffer
+    file.read_to_end(&mut buffer).await.map_err(read_error)?;
+    Ok(buffer)
 }
 
 /// Fetches the byte content of a file from the cache or reads and caches it if not present.
@@ -55,7 +60,7 @@ pub async fn read_file_to_bytes(path: &str) -> Vec<u8> {
 ///
 /// # Returns
 /// A vector of bytes representing the file content.
-async fn get_bytes(
+pub(crate) async fn get_bytes(
     state: Arc<Mutex<SharedState>>,
     file_path: PathBuf,
     route_name: &str,
@@ -67,11 +72,14 @@ async fn get_bytes(
     };
 }
 
-/// Handles incoming HTTP requests and routes them to the appropriate method-specific handler.
+/// Handles incoming HTTP requests and routes them to the virtual host's static routes or to the
+/// application serving the host.
 ///
 /// # Arguments
 /// - `request`: The incoming HTTP request.
 /// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `session_id`: The session the request belongs to.
+/// - `host`: The virtual host selected for the request.
 ///
 /// # Returns
 /// A `Response` object generated based on the request.
@@ -79,13 +87,29 @@ pub async fn handle_response(
     request: Request,
     state: Arc<Mutex<SharedState>>,
     session_id: Uuid,
+    host: Arc<VirtualHost>,
 ) -> Response {
-    match request.method {
-        HttpMethod::GET => handle_get(request, state, session_id).await,
-        HttpMethod::POST => handle_post(request, state).await,
-        HttpMethod::PUT => handle_put(request).await,
-        HttpMethod::PATCH => handle_patch(request).await,
-        HttpMethod::DELETE => handle_delete(request, state).await,
+    if request.method == HttpMethod::GET {
+        if let Some(file) = host.routes.get(request.path()) {
+            return Response::default()
+                .await
+                .body(get_bytes(state, host.file(file), &host.cache_key(request.path())).await);
+        }
+    }
+
+    match host.application {
+        Application::Phonebook => match request.method {
+            HttpMethod::GET => handle_get(request, state, session_id, &host).await,
+            HttpMethod::POST => handle_post(request, state).await,
+            HttpMethod::PUT => handle_put(request, state, &host).await,
+            HttpMethod::PATCH => handle_patch(request, state, &host).await,
+            HttpMethod::DELETE => handle_delete(request, state, &host).await,
+            HttpMethod::OPTIONS => handle_options().await,
+        },
+        Application::Calculator => {
+            crate::calculator::handle_calculator(request, state, session_id, &host).await
+        }
+        Application::Clock => crate::cgi::handle_cgi(request, state, &host).await,
     }
 }
 
@@ -94,6 +118,7 @@ pub async fn handle_response(
 /// # Arguments
 /// - `request`: The incoming GET request.
 /// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `host`: The virtual host serving the request.
 ///
 /// # Returns
 /// A `Response` object with the appropriate content and status code.
@@ -101,24 +126,57 @@ async fn handle_get(
     request: Request,
     state: Arc<Mutex<SharedState>>,
     _session_id: Uuid,
+    host: &VirtualHost,
 ) -> Response {
-    let mut response = Response::default()
-        .await
-        .compression(request.is_compression_supported());
+    let mut response = Response::default().await;
 
     match request.uri.as_str() {
         "/" => {
-            info!(target: "request_logger","GET / from status 200");
-            response.add_body(get_bytes(state, PathBuf::from(r"static/index.html"), "/").await);
+            let (templates, friends) = {
+                let state = state.lock().await;
+                (state.templates.clone(), state.get_all_friends())
+            };
+
+            match templates.render("phonebook.html", &serde_json::json!({ "friends": friends })) {
+                Ok(page) => response.add_body(page.into_bytes()),
+                Err(e) => {
+                    error!(target: "error_logger", "[{}] Failed to render the phonebook: {}", request.request_id, e);
+                    return error_response(
+                        &state,
+                        host,
+                        HttpCode::InternalServerError,
+                        &request.request_id,
+                    )
+                    .await;
+                }
+            }
         }
 
-        "/friends" => {
-            info!(target: "request_logger", "GET /friends status: 200");
+        "/events/phonebook" => {
+            let events = state.lock().await.events.clone();
+            return crate::sse::event_stream(
+                events,
+                crate::sse::PHONEBOOK_TOPIC,
+                request.get_header("Last-Event-ID"),
+            );
+        }
 
+        "/friends" => {
             let friends = state.lock().await.get_all_friends();
-            let body = serde_json::to_string(&friends).unwrap();
+            let body = match serde_json::to_string(&friends) {
+                Ok(b) => b,
+                Err(e) => {
+                    error!(target: "error_logger", "[{}] Failed to serialize friends: {}", request.request_id, e);
+                    return error_response(
+                        &state,
+                        host,
+                        HttpCode::InternalServerError,
+                        &request.request_id,
+                    )
+                    .await;
+                }
+            };
 
-            info!(target: "request_logger","GET {} status: 200", request.uri);
             return response
                 .code(HttpCode::Ok)
                 .content_type(ContentType::Text)
@@ -128,13 +186,10 @@ async fn handle_get(
         uri if uri.starts_with("/friend") => {
             let params = parse_query_params(uri);
             if let Some(name) = params.get("name") {
-                info!(target: "request_logger", "GET /get?name={} status: 200", name);
-
                 let friend: Friend = match state.lock().await.get_friend(name) {
                     Ok(Some(f)) => f,
                     Ok(None) => {
-                        error!(target: "error_logger","Failed to find friend in database {}", request.uri);
-                        info!(target: "request_logger","GET {} status: 404", request.uri);
+                        error!(target: "error_logger", "[{}] Failed to find friend in database {}", request.request_id, request.uri);
                         return response
                             .code(HttpCode::BadRequest)
                             .content_type(ContentType::Text)
@@ -146,8 +201,7 @@ async fn handle_get(
                             );
                     }
                     Err(_) => {
-                        error!(target: "error_logger","Failed to find friend in database {}", request.uri);
-                        info!(target: "request_logger","GET {} status: 404", request.uri);
+                        error!(target: "error_logger", "[{}] Failed to find friend in database {}", request.request_id, request.uri);
                         return response
                             .code(HttpCode::BadRequest)
                             .content_type(ContentType::Text)
@@ -160,50 +214,95 @@ async fn handle_get(
                     }
                 };
 
-                let body = serde_json::to_string(&friend).unwrap();
+                let body = match serde_json::to_string(&friend) {
+                    Ok(b) => b,
+                    Err(e) => {
+                        error!(target: "error_logger", "[{}] Failed to serialize friend: {}", request.request_id, e);
+                        return error_response(
+                            &state,
+                            host,
+                            HttpCode::InternalServerError,
+                            &request.request_id,
+                        )
+                        .await;
+                    }
+                };
 
-                info!(target: "request_logger","GET {} status: 200", request.uri);
                 return response
                     .code(HttpCode::Ok)
                     .content_type(ContentType::Text)
                     .body(body.as_bytes().to_vec());
             }
 
-            error!(target: "error_logger","Failed when deleting friend from database {}", request.uri);
-            info!(target: "request_logger","GET {} status: 404", request.uri);
-            return response
-                .code(HttpCode::BadRequest)
-                .content_type(ContentType::Text)
-                .body(get_bytes(state, PathBuf::from(r"static/404.html"), "/404").await);
+            error!(target: "error_logger", "[{}] Failed when deleting friend from database {}", request.request_id, request.uri);
+            return error_response(&state, host, HttpCode::BadRequest, &request.request_id).await;
         }
         _ => {
-            error!(target: "error_logger","Failed to serve request GET {}", request.uri);
-            info!(target: "request_logger","GET {} status: 404", request.uri);
-            return response
-                .code(HttpCode::BadRequest)
-                .content_type(ContentType::Text)
-                .body(get_bytes(state, PathBuf::from(r"static/404.html"), "/404").await);
+            error!(target: "error_logger", "[{}] Failed to serve request GET {}", request.request_id, request.uri);
+            return error_response(&state, host, HttpCode::NotFound, &request.request_id).await;
         }
     }
 
     response
 }
 
-fn parse_query_params(uri: &str) -> HashMap<String, String> {
-    let mut params = HashMap::new();
-    if let Some(query_start) = uri.find('?') {
-        let query_string = &uri[query_start + 1..];
-        for pair in query_string.split('&') {
-            if let Some((key, value)) = pair.split_once('=') {
-                params.insert(key.to_string(), value.to_string());
+/// Streams phonebook changes to a WebSocket client until it disconnects.
+///
+/// Each change is sent as a JSON text message such as
+/// `{"action":"add","name":"Ann","number":"555"}`. A client that falls too far behind is sent
+/// `{"action":"reload"}` instead of the changes it missed.
+///
+/// # Arguments
+/// - `socket`: The client's connection.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+pub(crate) async fn phonebook_updates<S: AsyncRead + AsyncWrite + Unpin>(
+    mut socket: WebSocket<S>,
+    state: Arc<Mutex<SharedState>>,
+) -> Result<(), WebSocketError> {
+    let mut updates = state
+        .lock()
+        .await
+        .events
+        .subscribe(crate::sse::PHONEBOOK_TOPIC, None)
+        .receiver;
+    let mut ping = tokio::time::interval(crate::websocket::PING_INTERVAL);
+    ping.tick().await;
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) => socket.send(Message::Text(update.data)).await?,
+                Err(RecvError::Lagged(_)) => {
+                    let reload: String = serde_json::json!({ "action": "reload" }).to_string();
+                    socket.send(Message::Text(reload)).await?;
+                }
+                Err(RecvError::Closed) => {
+                    return socket.close(GOING_AWAY, "Server shutting down").await;
+                }
+            },
+            // Messages from the client are not used
+            message = socket.recv() => {
+                if message?.is_none() {
+                    return Ok(());
+                }
             }
+            _ = ping.tick() => socket.send(Message::Ping(Vec::new())).await?,
         }
     }
-    params
+}
+
+/// Returns the percent decoded query string parameters of `uri`.
+pub(crate) fn parse_query_params(uri: &str) -> HashMap<String, String> {
+    match uri.split_once('?') {
+        Some((_, query)) => crate::body::parse_form(query),
+        None => HashMap::new(),
+    }
 }
 
 /// Handles HTTP POST requests for specific routes like `/signup` and `/login`.
 ///
+/// Bodies may be sent as JSON, a URL encoded form or multipart form data.
+///
 /// # Arguments
 /// - `request`: The incoming POST request.
 /// - `state`: A shared, thread-safe state used for managing server data and caching.
@@ -211,74 +310,64 @@ fn parse_query_params(uri: &str) -> HashMap<String, String> {
 /// # Returns
 /// A `Response` object with the appropriate content and status code.
 async fn handle_post(request: Request, state: Arc<Mutex<SharedState>>) -> Response {
-    let response = Response::default()
-        .await
-        .compression(request.is_compression_supported())
-        .content_type(ContentType::Text);
+    let response = Response::default().await.content_type(ContentType::Text);
 
     match request.uri.as_str() {
-        "/add" => match serde_json::from_str::<Friend>(&request.body) {
+        "/add" => match crate::body::deserialize::<Friend>(&request) {
             Ok(friend) => {
                 let _ = state.lock().await.add_friend(&friend.name, &friend.number);
-                info!(target: "request_logger","POST {} status: 200", request.uri);
                 return response
                     .code(HttpCode::Ok)
                     .body("Success".to_string().as_bytes().to_vec());
             }
-            Err(_) => {
-                error!(target: "error_logger","Failed when adding friend to database {}", request.uri);
-                info!(target: "request_logger","POST {} status: 500", request.uri);
+            Err(e) => {
+                error!(target: "error_logger", "[{}] Failed when adding friend to database {}: {}", request.request_id, request.uri, e);
                 return response
-                    .code(HttpCode::InternalServerError)
+                    .code(crate::body::error_code(&e))
                     .content_type(ContentType::Text)
                     .body("Unable to add friend".to_string().as_bytes().to_vec());
             }
         },
-        "/update" => match serde_json::from_str::<Friend>(&request.body) {
+        "/update" => match crate::body::deserialize::<Friend>(&request) {
             Ok(friend) => {
                 let _ = state
                     .lock()
                     .await
                     .update_friend(&friend.name, &friend.number);
-                info!(target: "request_logger","POST {} status: 200", request.uri);
                 return response
                     .code(HttpCode::Ok)
                     .body("Success".to_string().as_bytes().to_vec());
             }
-            Err(_) => {
-                error!(target: "error_logger","Failed when adding friend to database {}", request.uri);
-                info!(target: "request_logger","POST {} status: 500", request.uri);
+            Err(e) => {
+                error!(target: "error_logger", "[{}] Failed when adding friend to database {}: {}", request.request_id, request.uri, e);
                 return response
-                    .code(HttpCode::InternalServerError)
+                    .code(crate::body::error_code(&e))
                     .content_type(ContentType::Text)
                     .body("Unable to add friend".to_string().as_bytes().to_vec());
             }
         },
-        "/del" => match serde_json::from_str::<FriendName>(request.body.as_str()) {
+        "/del" => match crate::body::deserialize::<FriendName>(&request) {
             Ok(friend) => {
                 let _ = state.lock().await.delete_friend(&friend.name);
 
                 let friends = state.lock().await.get_all_friends();
                 let body = serde_json::to_string(&friends).unwrap();
 
-                info!(target: "request_logger","POST {} status: 200", request.uri);
                 return response
                     .code(HttpCode::Ok)
                     .content_type(ContentType::Text)
                     .body(body.as_bytes().to_vec());
             }
-            Err(_) => {
-                error!(target: "error_logger","Failed when deleting friend from database {}", request.uri);
-                info!(target: "request_logger","POST {} status: 500", request.uri);
+            Err(e) => {
+                error!(target: "error_logger", "[{}] Failed when deleting friend from database {}: {}", request.request_id, request.uri, e);
                 return response
-                    .code(HttpCode::InternalServerError)
+                    .code(crate::body::error_code(&e))
                     .content_type(ContentType::Text)
                     .body("Failed to delete friend".to_string().as_bytes().to_vec());
             }
         },
         _ => {
-            error!(target: "error_logger","Invalid API request {}", request.uri);
-            info!(target: "request_logger","POST {} status: 500", request.uri);
+            error!(target: "error_logger", "[{}] Invalid API request {}", request.request_id, request.uri);
             return response
                 .code(HttpCode::InternalServerError)
                 .content_type(ContentType::Text)
@@ -291,51 +380,88 @@ async fn handle_post(request: Request, state: Arc<Mutex<SharedState>>) -> Respon
 /// Allowed.
 ///
 /// # Arguments
-/// - `request`: The incoming POST request.
+/// - `request`: The incoming PUT request.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `host`: The virtual host serving the request.
 ///
 /// # Returns
 /// A `Response` object with the appropriate content and status code.
-async fn handle_put(request: Request) -> Response {
-    info!(target: "request_logger","PUT {} status 405", request.uri);
-
-    Response::default()
-        .await
-        .compression(request.is_compression_supported())
-        .body(read_file_to_bytes("static/index.html").await)
-        .code(HttpCode::MethodNotAllowed)
+async fn handle_put(
+    request: Request,
+    state: Arc<Mutex<SharedState>>,
+    host: &VirtualHost,
+) -> Response {
+    error_response(
+        &state,
+        host,
+        HttpCode::MethodNotAllowed,
+        &request.request_id,
+    )
+    .await
 }
 
 /// Handles HTTP PATCH requests which are currently unsupported and return a `405` Method Not
 /// Allowed.
 ///
 /// # Arguments
-/// - `request`: The incoming POST request.
+/// - `request`: The incoming PATCH request.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `host`: The virtual host serving the request.
 ///
 /// # Returns
 /// A `Response` object with the appropriate content and status code.
-async fn handle_patch(request: Request) -> Response {
-    info!(target: "request_logger","PATCH {} status 405", request.uri);
-    Response::default()
-        .await
-        .compression(request.is_compression_supported())
-        .body(read_file_to_bytes("static/index.html").await)
-        .code(HttpCode::MethodNotAllowed)
+async fn handle_patch(
+    request: Request,
+    state: Arc<Mutex<SharedState>>,
+    host: &VirtualHost,
+) -> Response {
+    error_response(
+        &state,
+        host,
+        HttpCode::MethodNotAllowed,
+        &request.request_id,
+    )
+    .await
 }
 
 /// Handles HTTP DELETE requests which are currently unsupported and return a `405` Method Not
 /// Allowed.
 ///
 /// # Arguments
-/// - `request`: The incoming POST request.
+/// - `request`: The incoming DELETE request.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `host`: The virtual host serving the request.
 ///
 /// # Returns
 /// A `Response` object with the appropriate content and status code.
+async fn handle_delete(
+    request: Request,
+    state: Arc<Mutex<SharedState>>,
+    host: &VirtualHost,
+) -> Response {
+    error_response(
+        &state,
+        host,
+        HttpCode::MethodNotAllowed,
+        &request.request_id,
+    )
+    .await
+}
 
-async fn handle_delete(request: Request, _state: Arc<Mutex<SharedState>>) -> Response {
-    info!(target: "request_logger","Delete {} status 405", request.uri);
-    Response::default()
-        .await
-        .compression(request.is_compression_supported())
-        .body(read_file_to_bytes("static/index.html").await)
-        .code(HttpCode::MethodNotAllowed)
+/// Handles HTTP OPTIONS requests that are not CORS preflights by listing the supported methods.
+///
+/// # Returns
+/// A `204` No Content `Response` with an `Allow` header.
+async fn handle_options() -> Response {
+    let mut response = Response::new(
+        crate::Protocol::Http,
+        HttpCode::NoContent,
+        ContentType::Text,
+        false,
+    );
+    response.add_header(
+        String::from("Allow"),
+        String::from("GET, POST, PUT, PATCH, DELETE, OPTIONS"),
+    );
+    response
 }
@@ -0,0 +1,571 @@
+//! Request body decoding.
+//!
+//! The body of a request is decoded according to its `Content-Type` header:
+//! - `application/json` is parsed with `serde_json`.
+//! - `application/x-www-form-urlencoded` is split into fields and percent decoded.
+//! - `multipart/form-data` is parsed while it is read from the connection, with file parts
+//!   written to the upload directory chunk by chunk rather than buffered in memory.
+//!
+//! Any other content type is rejected with `415 Unsupported Media Type`.
+use crate::{ErrorType, HttpCode, Request};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+/// The directory uploaded files are written to.
+pub const UPLOAD_DIR: &str = "uploads";
+
+/// The largest request body accepted, in bytes.
+pub const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+/// The largest header block accepted for a single multipart part.
+const MAX_PART_HEADER_SIZE: usize = 8192;
+
+/// The largest value accepted for a multipart field that is not a file.
+const MAX_FIELD_SIZE: usize = 64 * 1024;
+
+/// How long to wait for the next chunk of a body before giving up.
+const BODY_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// A decoded request body.
+#[derive(Debug)]
+pub enum Body<'a> {
+    Empty,
+    Json(serde_json::Value),
+    Form(HashMap<String, String>),
+    Multipart(&'a Multipart),
+}
+
+/// A file received in a `multipart/form-data` body.
+///
+/// The file is removed from the upload directory when it is dropped unless it has been moved
+/// somewhere else with `persist`.
+#[derive(Debug)]
+pub struct UploadedFile {
+    /// The name of the form field the file was sent in.
+    pub field: String,
+    /// The file name sent by the client with any directory components removed.
+    pub filename: String,
+    pub content_type: Option<String>,
+    /// Where the file has been written in the upload directory.
+    pub path: PathBuf,
+    pub size: usize,
+}
+
+impl UploadedFile {
+    /// Moves the uploaded file to `destination`.
+    pub async fn persist(&self, destination: &Path) -> std::io::Result<()> {
+        tokio::fs::rename(&self.path, destination).await
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The fields and files of a `multipart/form-data` body.
+#[derive(Debug, Default)]
+pub struct Multipart {
+    pub fields: HashMap<String, String>,
+    pub files: Vec<UploadedFile>,
+}
+
+/// Reads the body of `request` from the connection.
+///
+/// `multipart/form-data` bodies are parsed as they arrive and stored in `request.multipart`,
+/// every other body is stored in `request.body`.
+///
+/// # Arguments
+/// - `stream`: The connection the request is being read from.
+/// - `request`: The request whose headers have already been parsed.
+/// - `received`: Any body bytes that were read together with the headers.
+///
+/// # Returns
+/// `ErrorType::PayloadTooLarge` if the body is larger than `MAX_BODY_SIZE`, otherwise an
+/// `ErrorType::BadRequest` if the body is incomplete or malformed.
+pub async fn read_body<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    request: &mut Request,
+    received: Vec<u8>,
+) -> Result<(), ErrorType> {
+    let content_length: usize = match request.get_header("Content-Length") {
+        Some(length) => match length.trim().parse() {
+            Ok(l) => l,
+            Err(_) => {
+                return Err(ErrorType::BadRequest(String::from(
+                    "Invalid Content-Length",
+                )))
+            }
+        },
+        None => received.len(),
+    };
+
+    if content_length > MAX_BODY_SIZE {
+        return Err(ErrorType::PayloadTooLarge(format!(
+            "Body of {} bytes exceeds the limit of {} bytes",
+            content_length, MAX_BODY_SIZE
+        )));
+    }
+
+    let mut remaining: usize = content_length.saturating_sub(received.len());
+    let mut received: Vec<u8> = received;
+    received.truncate(content_length);
+
+    if let Some(boundary) = multipart_boundary(request) {
+        let mut parser = MultipartParser::new(&boundary, Path::new(UPLOAD_DIR));
+        parser.feed(&received).await?;
+
+        let mut buffer: [u8; 8192] = [0; 8192];
+        while remaining > 0 {
+            let n: usize = read_chunk(stream, &mut buffer[..remaining.min(8192)]).await?;
+            parser.feed(&buffer[..n]).await?;
+            remaining -= n;
+        }
+
+        request.multipart = Some(parser.finish()?);
+        return Ok(());
+    }
+
+    let mut body: Vec<u8> = received;
+    let mut buffer: [u8; 8192] = [0; 8192];
+    while remaining > 0 {
+        let n: usize = read_chunk(stream, &mut buffer[..remaining.min(8192)]).await?;
+        body.extend_from_slice(&buffer[..n]);
+        remaining -= n;
+    }
+
+    request.body = String::from_utf8_lossy(&body).into_owned();
+    Ok(())
+}
+
+async fn read_chunk<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    buffer: &mut [u8],
+) -> Result<usize, ErrorType> {
+    match tokio::time::timeout(BODY_READ_TIMEOUT, stream.read(buffer)).await {
+        Ok(Ok(n)) if n > 0 => Ok(n),
+        Ok(Ok(_)) => Err(ErrorType::BadRequest(String::from(
+            "Connection closed before the body was received",
+        ))),
+        Ok(Err(e)) => Err(ErrorType::ReadError(e.to_string())),
+        Err(_) => Err(ErrorType::BadRequest(String::from(
+            "Timed out reading the request body",
+        ))),
+    }
+}
+
+/// Decodes the body of `request` according to its `Content-Type` header.
+///
+/// A request without a `Content-Type` is treated as JSON, which is what the phonebook page
+/// sends.
+pub fn decode(request: &Request) -> Result<Body<'_>, ErrorType> {
+    if let Some(multipart) = &request.multipart {
+        return Ok(Body::Multipart(multipart));
+    }
+
+    if request.body.trim().is_empty() {
+        return Ok(Body::Empty);
+    }
+
+    let media_type: String = match request.get_header("Content-Type") {
+        Some(content_type) => media_type(content_type),
+        None => String::from("application/json"),
+    };
+
+    match media_type.as_str() {
+        "application/json" => match serde_json::from_str(&request.body) {
+            Ok(value) => Ok(Body::Json(value)),
+            Err(e) => Err(ErrorType::BadRequest(format!("Invalid JSON body: {}", e))),
+        },
+        "application/x-www-form-urlencoded" => Ok(Body::Form(parse_form(&request.body))),
+        other => Err(ErrorType::UnsupportedMediaType(format!(
+            "Unsupported Content-Type {}",
+            other
+        ))),
+    }
+}
+
+/// Decodes the body of `request` into `T` regardless of whether it was sent as JSON, a form or
+/// multipart fields.
+pub fn deserialize<T: DeserializeOwned>(request: &Request) -> Result<T, ErrorType> {
+    let fields_to_value = |fields: &HashMap<String, String>| {
+        serde_json::Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+                .collect(),
+        )
+    };
+
+    let value: serde_json::Value = match decode(request)? {
+        Body::Empty => return Err(ErrorType::BadRequest(String::from("Missing body"))),
+        Body::Json(value) => value,
+        Body::Form(fields) => fields_to_value(&fields),
+        Body::Multipart(multipart) => fields_to_value(&multipart.fields),
+    };
+
+    serde_json::from_value(value).map_err(|e| ErrorType::BadRequest(e.to_string()))
+}
+
+/// Returns the status code a body decoding error should be reported with.
+pub fn error_code(error: &ErrorType) -> HttpCode {
+    match error {
+        ErrorType::UnsupportedMediaType(_) => HttpCode::UnsupportedMediaType,
+        ErrorType::PayloadTooLarge(_) => HttpCode::PayloadTooLarge,
+        ErrorType::BadRequest(_) => HttpCode::BadRequest,
+        _ => HttpCode::InternalServerError,
+    }
+}
+
+/// Splits an `application/x-www-form-urlencoded` string (a request body or query string) into
+/// decoded fields. Later fields replace earlier fields with the same name.
+pub fn parse_form(form: &str) -> HashMap<String, String> {
+    form.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (percent_decode(key), percent_decode(value)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes and `+` as a space. Malformed escapes are kept as they are and invalid
+/// UTF-8 is replaced.
+pub fn percent_decode(value: &str) -> String {
+    let bytes: &[u8] = value.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i: usize = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        i += 3;
+                        continue;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Returns the lower case media type of a `Content-Type` value without its parameters.
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Returns the value of the parameter `name` in a header value such as
+/// `form-data; name="file"; filename="a.txt"`.
+fn header_parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|parameter| {
+        let (key, value) = parameter.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn multipart_boundary(request: &Request) -> Option<String> {
+    let content_type: &str = request.get_header("Content-Type")?;
+    if media_type(content_type) != "multipart/form-data" {
+        return None;
+    }
+    header_parameter(content_type, "boundary").filter(|b| !b.is_empty() && b.len() <= 70)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+enum ParserState {
+    /// Skipping anything before the first boundary.
+    Preamble,
+    /// Just after a boundary, waiting for `\r\n` or the closing `--`.
+    Boundary,
+    /// Reading the headers of a part.
+    Headers,
+    /// Reading the content of a part.
+    Content,
+    /// The closing boundary has been read.
+    Done,
+}
+
+enum Part {
+    Field { name: String, value: Vec<u8> },
+    File { file: File, upload: UploadedFile },
+}
+
+/// An incremental `multipart/form-data` parser fed with chunks of the body as they are read.
+struct MultipartParser {
+    /// `\r\n--boundary`, the delimiter that ends the content of each part.
+    delimiter: Vec<u8>,
+    upload_dir: PathBuf,
+    state: ParserState,
+    buffer: Vec<u8>,
+    part: Option<Part>,
+    multipart: Multipart,
+}
+
+impl MultipartParser {
+    fn new(boundary: &str, upload_dir: &Path) -> Self {
+        MultipartParser {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            upload_dir: upload_dir.to_path_buf(),
+            state: ParserState::Preamble,
+            buffer: Vec::new(),
+            part: None,
+            multipart: Multipart::default(),
+        }
+    }
+
+    async fn feed(&mut self, chunk: &[u8]) -> Result<(), ErrorType> {
+        self.buffer.extend_from_slice(chunk);
+
+        loop {
+            match self.state {
+                ParserState::Preamble => {
+                    // the first boundary is not preceded by a line break
+                    let boundary: &[u8] = &self.delimiter[2..];
+                    match find(&self.buffer, boundary) {
+                        Some(i) => {
+                            self.buffer.drain(..i + boundary.len());
+                            self.state = ParserState::Boundary;
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                ParserState::Boundary => {
+                    if self.buffer.len() < 2 {
+                        return Ok(());
+                    }
+                    if self.buffer.starts_with(b"--") {
+                        self.buffer.clear();
+                        self.state = ParserState::Done;
+                    } else if self.buffer.starts_with(b"\r\n") {
+                        self.buffer.drain(..2);
+                        self.state = ParserState::Headers;
+                    } else {
+                        return Err(malformed("Invalid multipart boundary"));
+                    }
+                }
+                ParserState::Headers => match find(&self.buffer, b"\r\n\r\n") {
+                    Some(i) => {
+                        let headers: String = String::from_utf8_lossy(&self.buffer[..i]).into();
+                        self.buffer.drain(..i + 4);
+                        self.part = Some(self.start_part(&headers).await?);
+                        self.state = ParserState::Content;
+                    }
+                    None if self.buffer.len() > MAX_PART_HEADER_SIZE => {
+                        return Err(malformed("Multipart headers are too large"));
+                    }
+                    None => return Ok(()),
+                },
+                ParserState::Content => match find(&self.buffer, &self.delimiter) {
+                    Some(i) => {
+                        let content: Vec<u8> = self.buffer.drain(..i).collect();
+                        self.write_content(&content).await?;
+                        self.buffer.drain(..self.delimiter.len());
+                        self.end_part().await?;
+                        self.state = ParserState::Boundary;
+                    }
+                    None => {
+                        // keep enough bytes to match a delimiter split across two chunks
+                        let keep: usize = self.delimiter.len() - 1;
+                        if self.buffer.len() > keep {
+                            let content: Vec<u8> =
+                                self.buffer.drain(..self.buffer.len() - keep).collect();
+                            self.write_content(&content).await?;
+                        }
+                        return Ok(());
+                    }
+                },
+                ParserState::Done => {
+                    self.buffer.clear();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn start_part(&mut self, headers: &str) -> Result<Part, ErrorType> {
+        let mut disposition: Option<&str> = None;
+        let mut content_type: Option<String> = None;
+        for line in headers.lines() {
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                    disposition = Some(value.trim());
+                } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+                    content_type = Some(value.trim().to_string());
+                }
+            }
+        }
+
+        let disposition: &str = match disposition {
+            Some(d) if media_type(d) == "form-data" => d,
+            _ => return Err(malformed("Multipart part is missing Content-Disposition")),
+        };
+        let name: String = match header_parameter(disposition, "name") {
+            Some(n) => n,
+            None => return Err(malformed("Multipart part is missing a name")),
+        };
+
+        let filename: String = match header_parameter(disposition, "filename") {
+            Some(f) => f,
+            None => {
+                return Ok(Part::Field {
+                    name,
+                    value: Vec::new(),
+                })
+            }
+        };
+
+        // never use the client's file name on disk
+        if let Err(e) = tokio::fs::create_dir_all(&self.upload_dir).await {
+            return Err(ErrorType::WriteError(e.to_string()));
+        }
+        let path: PathBuf = self.upload_dir.join(uuid::Uuid::new_v4().to_string());
+        let file: File = match File::create(&path).await {
+            Ok(f) => f,
+            Err(e) => return Err(ErrorType::WriteError(e.to_string())),
+        };
+
+        Ok(Part::File {
+            file,
+            upload: UploadedFile {
+                field: name,
+                filename: filename
+                    .rsplit(['/', '\\'])
+                    .next()
+                    .unwrap_or("")
+                    .to_string(),
+                content_type,
+                path,
+                size: 0,
+            },
+        })
+    }
+
+    async fn write_content(&mut self, content: &[u8]) -> Result<(), ErrorType> {
+        match &mut self.part {
+            Some(Part::Field { value, .. }) => {
+                if value.len() + content.len() > MAX_FIELD_SIZE {
+                    return Err(ErrorType::PayloadTooLarge(String::from(
+                        "Multipart field is too large",
+                    )));
+                }
+                value.extend_from_slice(content);
+            }
+            Some(Part::File { file, upload }) => {
+                if let Err(e) = file.write_all(content).await {
+                    return Err(ErrorType::WriteError(e.to_string()));
+                }
+                upload.size += content.len();
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    async fn end_part(&mut self) -> Result<(), ErrorType> {
+        match self.part.take() {
+            Some(Part::Field { name, value }) => {
+                self.multipart
+                    .fields
+                    .insert(name, String::from_utf8_lossy(&value).into_owned());
+            }
+            Some(Part::File { mut file, upload }) => {
+                if let Err(e) = file.flush().await {
+                    return Err(ErrorType::WriteError(e.to_string()));
+                }
+                self.multipart.files.push(upload);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Multipart, ErrorType> {
+        match self.state {
+            ParserState::Done => Ok(self.multipart),
+            _ => Err(malformed(
+                "Multipart body ended before the closing boundary",
+            )),
+        }
+    }
+}
+
+fn malformed(message: &str) -> ErrorType {
+    ErrorType::BadRequest(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%2Bb+c"), "a+b c");
+        assert_eq!(percent_decode("%E2%9C%93"), "\u{2713}");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+
+        let form = parse_form("name=Jo+Bloggs&number=%2B27+12");
+        assert_eq!(form["name"], "Jo Bloggs");
+        assert_eq!(form["number"], "+27 12");
+    }
+
+    #[tokio::test]
+    async fn test_multipart_split_across_chunks() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("upload-{}", uuid::Uuid::new_v4()));
+        let body: &[u8] = b"--XyZ\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\nJo\r\n\
+--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"../a.txt\"\r\n\
+Content-Type: text/plain\r\n\r\nline one\r\n--Xy not yet\r\n--XyZ--\r\n";
+
+        let mut parser = MultipartParser::new("XyZ", &dir);
+        for chunk in body.chunks(5) {
+            parser.feed(chunk).await.unwrap();
+        }
+        let multipart: Multipart = parser.finish().unwrap();
+
+        assert_eq!(multipart.fields["name"], "Jo");
+        let file: &UploadedFile = &multipart.files[0];
+        assert_eq!(file.filename, "a.txt");
+        assert_eq!(
+            std::fs::read(&file.path).unwrap(),
+            b"line one\r\n--Xy not yet"
+        );
+
+        let path: PathBuf = file.path.clone();
+        drop(multipart);
+        assert!(!path.exists());
+        let _ = std::fs::remove_dir(dir);
+    }
+}
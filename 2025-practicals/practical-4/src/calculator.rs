@@ -0,0 +1,99 @@
+/// This module provides the calculator application from practical 3 so that it can be served as
+/// a virtual host next to the phonebook.
+///
+/// Every session keeps a running value and a queue of buffered operators in its `UserState`.
+use crate::api::{get_bytes, parse_query_params};
+use crate::response::{MyDefault, Response};
+use crate::server::{SharedState, UserState};
+use crate::vhost::VirtualHost;
+use crate::{ContentType, HttpCode, HttpMethod, Request};
+use log::error;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// Handles requests for a calculator host.
+///
+/// # Arguments
+/// - `request`: The incoming HTTP request.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `session_id`: The session whose calculator state is updated.
+/// - `host`: The virtual host serving the calculator.
+///
+/// # Returns
+/// A `Response` object with the appropriate content and status code.
+pub async fn handle_calculator(
+    request: Request,
+    state: Arc<Mutex<SharedState>>,
+    session_id: Uuid,
+    host: &VirtualHost,
+) -> Response {
+    let mut response = Response::default().await;
+
+    if request.method != HttpMethod::GET {
+        return crate::error_page::error_response(
+            &state,
+            host,
+            HttpCode::MethodNotAllowed,
+            &request.request_id,
+        )
+        .await;
+    }
+
+    match request.path() {
+        "/" | "/cal" => {
+            response.add_body(
+                get_bytes(state, host.file("calculator.html"), &host.cache_key("/cal")).await,
+            );
+        }
+        "/calculate" => {
+            let params = parse_query_params(&request.uri);
+            let input: &String = match params.get("input") {
+                Some(i) => i,
+                None => {
+                    return response
+                        .code(HttpCode::BadRequest)
+                        .content_type(ContentType::Text)
+                        .body("Missing input".to_string().as_bytes().to_vec());
+                }
+            };
+
+            let mut state = state.lock().await;
+            let user_state: &mut UserState = state.user_states.entry(session_id).or_default();
+
+            match input.as_str() {
+                "*" | "/" | "-" | "+" => {
+                    user_state.buffer(String::from(input));
+                }
+                _ => {
+                    let operator: Option<String> = user_state.pop();
+                    let input: f64 = input.parse().unwrap_or(0.0);
+                    user_state.value = match operator.as_deref() {
+                        Some("+") => user_state.value + input,
+                        Some("-") => user_state.value - input,
+                        Some("*") => user_state.value * input,
+                        Some("/") => user_state.value / input,
+                        _ => input,
+                    };
+                }
+            }
+
+            return response
+                .code(HttpCode::Ok)
+                .content_type(ContentType::Text)
+                .body(user_state.value.to_string().as_bytes().to_vec());
+        }
+        _ => {
+            error!(target: "error_logger", "[{}] Failed to serve request GET {}", request.request_id, request.uri);
+            return crate::error_page::error_response(
+                &state,
+                host,
+                HttpCode::NotFound,
+                &request.request_id,
+            )
+            .await;
+        }
+    }
+
+    response
+}
@@ -0,0 +1,163 @@
+/// This module runs CGI programs for the world-clock application from practical 1.
+///
+/// Programs are looked up in the CGI directory of the virtual host. A request for
+/// `/show_time.cgi` runs `<cgi_dir>/show_time.cgi` and `/time` is kept as an alias for it.
+/// Programs run with the CGI directory as their working directory, so they find the files they
+/// read with relative paths there.
+use crate::error_page::error_response;
+use crate::response::{MyDefault, Response};
+use crate::server::SharedState;
+use crate::vhost::VirtualHost;
+use crate::{HttpCode, Request};
+use log::error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// Handles requests for a host serving CGI programs.
+///
+/// # Arguments
+/// - `request`: The incoming HTTP request.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `host`: The virtual host whose CGI directory is used.
+///
+/// # Returns
+/// A `Response` object containing the output of the CGI program.
+pub async fn handle_cgi(
+    request: Request,
+    state: Arc<Mutex<SharedState>>,
+    host: &VirtualHost,
+) -> Response {
+    let response = Response::default().await;
+
+    let program: PathBuf = match program_path(host, request.path()) {
+        Some(p) => p,
+        None => {
+            return error_response(&state, host, HttpCode::NotFound, &request.request_id).await;
+        }
+    };
+
+    let query: &str = request.uri.split_once('?').map(|(_, q)| q).unwrap_or("");
+
+    // The program is absolute, so it does not depend on the working directory
+    let directory: &Path = program.parent().unwrap_or(Path::new("."));
+    let output = match Command::new(&program)
+        .current_dir(directory)
+        .env("GATEWAY_INTERFACE", "CGI/1.1")
+        .env("REQUEST_METHOD", request.method.to_string())
+        .env("QUERY_STRING", query)
+        .env("SCRIPT_NAME", request.path())
+        .env("SERVER_NAME", &host.name)
+        .env("REMOTE_ADDR", &request.client_ip)
+        .output()
+        .await
+    {
+        Ok(o) => o,
+        Err(e) => {
+            error!(target: "error_logger", "[{}] Failed to run cgi program {:?}: {:?}", request.request_id, program, e);
+            return error_response(
+                &state,
+                host,
+                HttpCode::InternalServerError,
+                &request.request_id,
+            )
+            .await;
+        }
+    };
+
+    if !output.status.success() {
+        error!(target: "error_logger", "[{}] CGI program {:?} failed", request.request_id, program);
+        return error_response(
+            &state,
+            host,
+            HttpCode::InternalServerError,
+            &request.request_id,
+        )
+        .await;
+    }
+
+    response
+        .code(HttpCode::Ok)
+        .body(strip_cgi_headers(output.stdout))
+}
+
+/// Maps a request path to a program inside the host's CGI directory.
+///
+/// Only plain file names ending in `.cgi` are accepted so that requests cannot escape the
+/// directory.
+fn program_path(host: &VirtualHost, path: &str) -> Option<PathBuf> {
+    let cgi_dir = host.cgi_dir.as_ref()?;
+
+    let name: &str = match path {
+        "/" | "/time" => "show_time.cgi",
+        p => p.trim_start_matches('/'),
+    };
+
+    if !name.ends_with(".cgi") || name.contains('/') || name.contains("..") {
+        return None;
+    }
+
+    let program = cgi_dir.join(name);
+    if program.is_file() {
+        std::fs::canonicalize(program).ok()
+    } else {
+        None
+    }
+}
+
+/// Removes the CGI header block (everything up to the first blank line) from the program output.
+fn strip_cgi_headers(output: Vec<u8>) -> Vec<u8> {
+    if !output.starts_with(b"Content-") {
+        return output;
+    }
+
+    let lf = output.windows(2).position(|w| w == b"\n\n").map(|p| p + 2);
+    let crlf = output
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|p| p + 4);
+
+    match (lf, crlf) {
+        (Some(a), Some(b)) => output[a.min(b)..].to_vec(),
+        (Some(a), None) | (None, Some(a)) => output[a..].to_vec(),
+        (None, None) => output,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vhost::Application;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_program_runs_in_cgi_dir() {
+        let dir = std::env::temp_dir().join(format!("cgi-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("show_time.cgi");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nprintf 'Content-Type: text/plain\\n\\n'\nprintf '%s %s' \"$(pwd)\" \"$QUERY_STRING\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut host = VirtualHost::new("clock.local", Path::new("static"));
+        host.application = Application::Clock;
+        host.cgi_dir = Some(dir.clone());
+        let state = Arc::new(Mutex::new(SharedState::in_memory().unwrap()));
+        let request = Request::new(
+            b"GET /time?zone=uk HTTP/1.1\r\nHost: clock.local\r\n\r\n",
+            String::from("127.0.0.1:5000"),
+        )
+        .unwrap();
+
+        let response = handle_cgi(request, state, &host).await;
+        let expected = format!("{} zone=uk", std::fs::canonicalize(&dir).unwrap().display());
+        assert_eq!(response.code, HttpCode::Ok);
+        assert_eq!(String::from_utf8_lossy(&response.body), expected);
+        assert!(program_path(&host, "/../show_time.cgi").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+//! Runtime control of the server.
+//!
+//! `Control` is shared by the TLS listener and the admin listener. It holds the configuration
+//! that can be reloaded while the server runs (the virtual hosts and the TLS certificate), keeps
+//! a table of the open connections and carries the shutdown signal.
+use crate::socket::connection::TlsIdentity;
+use crate::vhost::HostTable;
+use rustls::pki_types::CertificateDer;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_rustls::TlsAcceptor;
+
+/// How long a graceful shutdown waits for open connections to finish.
+pub const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// An open client connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub address: String,
+    /// The protocol negotiated with ALPN, `h2` or `http/1.1`.
+    pub protocol: String,
+    pub opened: Instant,
+}
+
+/// Removes its connection from the table when the connection ends.
+pub struct ConnectionGuard {
+    control: Arc<Control>,
+    id: u64,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.control
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+/// The files a server's configuration was loaded from, which `Control::reload` reads again.
+#[derive(Debug, Clone)]
+pub struct ConfigFiles {
+    pub hosts: String,
+    pub certificate: String,
+    pub key: String,
+}
+
+impl Default for ConfigFiles {
+    /// `hosts.json`, `server.crt` and `server.key` in the working directory.
+    fn default() -> Self {
+        ConfigFiles {
+            hosts: String::from(crate::vhost::HOSTS_FILE),
+            certificate: String::from(crate::socket::connection::CERT_PATH),
+            key: String::from(crate::socket::connection::KEY_PATH),
+        }
+    }
+}
+
+pub struct Control {
+    hosts: RwLock<Arc<HostTable>>,
+    acceptor: RwLock<TlsAcceptor>,
+    certificate: RwLock<CertificateDer<'static>>,
+    /// `None` for a configuration built in code, which cannot be reloaded.
+    files: Option<ConfigFiles>,
+    connections: Mutex<HashMap<u64, ConnectionInfo>>,
+    next_connection_id: AtomicU64,
+    shutting_down: AtomicBool,
+    shutdown: Notify,
+}
+
+impl Control {
+    pub fn new(hosts: HostTable, tls: TlsIdentity, files: Option<ConfigFiles>) -> Self {
+        Control {
+            hosts: RwLock::new(Arc::new(hosts)),
+            acceptor: RwLock::new(TlsAcceptor::from(Arc::new(tls.config))),
+            certificate: RwLock::new(tls.certificate),
+            files,
+            connections: Mutex::new(HashMap::new()),
+            next_connection_id: AtomicU64::new(1),
+            shutting_down: AtomicBool::new(false),
+            shutdown: Notify::new(),
+        }
+    }
+
+    /// Returns the current virtual hosts. Connections keep the table they started with.
+    pub fn hosts(&self) -> Arc<HostTable> {
+        Arc::clone(&self.hosts.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Returns the acceptor for new TLS connections.
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Returns the certificate new TLS connections are offered.
+    pub fn certificate(&self) -> CertificateDer<'static> {
+        self.certificate
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Reloads the host table and the TLS certificate and key from the files the server was
+    /// started with. Nothing is replaced if either fails to load, and open connections are not
+    /// affected.
+    pub async fn reload(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(files) = &self.files else {
+            return Err("The configuration was not loaded from files".into());
+        };
+        let hosts: HostTable = HostTable::load(&files.hosts)?;
+        let tls =
+            crate::socket::connection::load_tls_config(&files.certificate, &files.key).await?;
+
+        *self.hosts.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(hosts);
+        *self.acceptor.write().unwrap_or_else(|e| e.into_inner()) =
+            TlsAcceptor::from(Arc::new(tls.config));
+        *self.certificate.write().unwrap_or_else(|e| e.into_inner()) = tls.certificate;
+        log::info!(target: "request_logger", "Reloaded {} and the TLS certificate {}", files.hosts, files.certificate);
+        Ok(())
+    }
+
+    /// Adds a connection to the table of open connections.
+    ///
+    /// # Arguments
+    /// - `address`: The address of the client.
+    /// - `protocol`: The protocol negotiated for the connection.
+    ///
+    /// # Returns
+    /// A guard that removes the connection again when it is dropped.
+    pub fn open_connection(self: &Arc<Self>, address: &str, protocol: &str) -> ConnectionGuard {
+        let id: u64 = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        self.connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                id,
+                ConnectionInfo {
+                    id,
+                    address: address.to_string(),
+                    protocol: protocol.to_string(),
+                    opened: Instant::now(),
+                },
+            );
+        ConnectionGuard {
+            control: Arc::clone(self),
+            id,
+        }
+    }
+
+    /// Returns the open connections, oldest first.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<ConnectionInfo> = self
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        connections.sort_by_key(|c| c.id);
+        connections
+    }
+
+    /// Starts a graceful shutdown: the listener stops accepting connections and the server
+    /// exits once the open ones finish or the grace period ends.
+    pub fn shutdown(&self) {
+        if !self.shutting_down.swap(true, Ordering::SeqCst) {
+            log::info!(target: "request_logger", "Graceful shutdown started");
+            self.shutdown.notify_one();
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Waits until a shutdown is started.
+    pub async fn shutdown_requested(&self) {
+        if !self.is_shutting_down() {
+            self.shutdown.notified().await;
+        }
+    }
+}
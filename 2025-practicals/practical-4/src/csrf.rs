@@ -0,0 +1,77 @@
+//! Cross-site request forgery protection.
+//!
+//! Every session is given a synchronizer token that is kept in its `UserState`. The token is
+//! added to the HTML pages served to the session, as a `<meta name="csrf-token">` tag and as a
+//! hidden `csrf_token` field in every form, and state-changing requests must send it back in the
+//! `X-CSRF-Token` header or the `csrf_token` field of their body. The tokens, and the pages they
+//! are added to, come from the `csrf` crate the 2024 quiz practicals share.
+use crate::body::Body;
+use crate::{HttpMethod, Request};
+
+pub use ::csrf::{constant_time_eq, generate_token, inject, CSRF_FIELD, CSRF_HEADER};
+
+/// Returns true if requests using `method` change state and must carry a token.
+pub fn requires_token(method: &HttpMethod) -> bool {
+    matches!(
+        method,
+        HttpMethod::POST | HttpMethod::PUT | HttpMethod::PATCH | HttpMethod::DELETE
+    )
+}
+
+/// Returns true if `request` carries `expected` in the CSRF header or body field.
+pub fn verify(request: &Request, expected: &str) -> bool {
+    ::csrf::token_matches(supplied_token(request).as_deref(), expected)
+}
+
+/// Returns the token sent with a request, preferring the header over the body.
+fn supplied_token(request: &Request) -> Option<String> {
+    if let Some(token) = request.get_header(CSRF_HEADER) {
+        return Some(token.trim().to_string());
+    }
+
+    match crate::body::decode(request).ok()? {
+        Body::Form(fields) => fields.get(CSRF_FIELD).cloned(),
+        Body::Multipart(multipart) => multipart.fields.get(CSRF_FIELD).cloned(),
+        Body::Json(value) => value.get(CSRF_FIELD)?.as_str().map(str::to_string),
+        Body::Empty => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(headers: Vec<String>, body: &str) -> Request {
+        Request {
+            request_id: String::from("1"),
+            traceparent: None,
+            client_ip: String::from("127.0.0.1:50000"),
+            headers,
+            body: body.to_string(),
+            multipart: None,
+            method: HttpMethod::POST,
+            uri: String::from("/add"),
+        }
+    }
+
+    #[test]
+    fn test_verify_header_and_form_field() {
+        let token: String = generate_token();
+
+        let header = post(vec![format!("X-CSRF-Token: {}", token)], "{}");
+        assert!(verify(&header, &token));
+
+        let form = post(
+            vec![String::from(
+                "Content-Type: application/x-www-form-urlencoded",
+            )],
+            &format!("name=Jo&csrf_token={}", token),
+        );
+        assert!(verify(&form, &token));
+
+        let missing = post(Vec::new(), r#"{"name":"Jo"}"#);
+        assert!(!verify(&missing, &token));
+        let wrong = post(vec![String::from("X-CSRF-Token: abc")], "{}");
+        assert!(!verify(&wrong, &token));
+    }
+}
@@ -13,6 +13,8 @@ pub mod my_errors {
         InternalServerError(String),
         ProtocolError(String),
         ConnectionError(String),
+        UnsupportedMediaType(String),
+        PayloadTooLarge(String),
     }
 
     impl std::error::Error for ErrorType {}
@@ -32,6 +34,8 @@ pub mod my_errors {
                 ErrorType::InternalServerError(msg) => msg,
                 ErrorType::ProtocolError(msg) => msg,
                 ErrorType::ConnectionError(msg) => msg,
+                ErrorType::UnsupportedMediaType(msg) => msg,
+                ErrorType::PayloadTooLarge(msg) => msg,
             }
         }
     }
@@ -47,6 +51,10 @@ pub mod my_errors {
                 ErrorType::InternalServerError(msg) => write!(f, "Internal Server Error: {}", msg),
                 ErrorType::ProtocolError(msg) => write!(f, "Protocol Error: {}", msg),
                 ErrorType::ConnectionError(msg) => write!(f, "Connection Error: {}", msg),
+                ErrorType::UnsupportedMediaType(msg) => {
+                    write!(f, "Unsupported Media Type: {}", msg)
+                }
+                ErrorType::PayloadTooLarge(msg) => write!(f, "Payload Too Large: {}", msg),
             }
         }
     }
@@ -125,6 +133,20 @@ pub mod my_errors {
                     line!(),
                     msg
                 ),
+                ErrorType::UnsupportedMediaType(msg) => write!(
+                    f,
+                    "Unsupported Media Type Error: {{ file: {}, line: {} message: {} }}",
+                    file!(),
+                    line!(),
+                    msg
+                ),
+                ErrorType::PayloadTooLarge(msg) => write!(
+                    f,
+                    "Payload Too Large Error: {{ file: {}, line: {} message: {} }}",
+                    file!(),
+                    line!(),
+                    msg
+                ),
             }
         }
     }
@@ -0,0 +1,124 @@
+//! Error pages and panic reporting.
+//!
+//! Error responses are rendered from templates so each status gets its own page. A virtual host
+//! can point a status at its own template with `error_pages` in the hosts file, otherwise
+//! `errors/<status>.html` is used, falling back to `errors/error.html` for statuses without a
+//! page of their own. If no template can be rendered the status line is sent as plain text.
+use crate::response::Response;
+use crate::server::SharedState;
+use crate::vhost::VirtualHost;
+use crate::{ContentType, HttpCode, Protocol};
+use log::error;
+use std::sync::Arc;
+use templates::Templates;
+use tokio::sync::Mutex;
+
+/// The template used for statuses without a page of their own.
+const FALLBACK_TEMPLATE: &str = "errors/error.html";
+
+/// Builds the error page response for `code`.
+///
+/// # Arguments
+/// - `templates`: The templates the page is rendered from.
+/// - `host`: The virtual host serving the request.
+/// - `code`: The status of the response.
+/// - `request_id`: The ID of the failed request, shown on the page so it can be reported.
+///
+/// # Returns
+/// A `Response` with the status and an HTML error page.
+pub fn render(
+    templates: &Templates,
+    host: &VirtualHost,
+    code: HttpCode,
+    request_id: &str,
+) -> Response {
+    let status: u16 = code.status_code();
+    let status_line: String = code.to_string();
+    let reason: &str = status_line
+        .split_once(' ')
+        .map(|(_, r)| r)
+        .unwrap_or(&status_line);
+
+    let context = serde_json::json!({
+        "status": status,
+        "reason": reason,
+        "request_id": request_id,
+        "host": host.name,
+    });
+
+    let name: String = host
+        .error_pages
+        .get(&status)
+        .cloned()
+        .unwrap_or_else(|| format!("errors/{}.html", status));
+    let name: &str = if templates.exists(&name) {
+        &name
+    } else {
+        FALLBACK_TEMPLATE
+    };
+
+    match templates.render(name, &context) {
+        Ok(page) => {
+            Response::new(Protocol::Http, code, ContentType::Html, false).body(page.into_bytes())
+        }
+        Err(e) => {
+            error!(target: "error_logger", "[{}] Failed to render error page {}: {}", request_id, name, e);
+            Response::new(Protocol::Http, code, ContentType::Text, false)
+                .body(status_line.into_bytes())
+        }
+    }
+}
+
+/// Builds the error page response for `code` using the server's templates.
+///
+/// # Arguments
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `host`: The virtual host serving the request.
+/// - `code`: The status of the response.
+/// - `request_id`: The ID of the failed request.
+pub async fn error_response(
+    state: &Arc<Mutex<SharedState>>,
+    host: &VirtualHost,
+    code: HttpCode,
+    request_id: &str,
+) -> Response {
+    let templates: Arc<Templates> = state.lock().await.templates.clone();
+    render(&templates, host, code, request_id)
+}
+
+/// Logs panics with a backtrace to the error log instead of only printing them to stderr.
+pub fn log_panics() {
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        error!(target: "error_logger", "{}\n{}", info, backtrace);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_status_and_fallback_pages() {
+        let dir = std::env::temp_dir().join(format!("error-pages-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("errors")).unwrap();
+        std::fs::write(dir.join("errors/error.html"), "{{ status }} {{ reason }}").unwrap();
+        std::fs::write(dir.join("errors/404.html"), "missing {{ request_id }}").unwrap();
+        std::fs::write(dir.join("teapot.html"), "short and stout").unwrap();
+
+        let templates = Templates::new(&dir);
+        let mut host = VirtualHost::new("localhost", Path::new("static"));
+        host.error_pages.insert(418, String::from("teapot.html"));
+
+        let not_found = render(&templates, &host, HttpCode::NotFound, "42");
+        assert_eq!(not_found.code.status_code(), 404);
+        assert_eq!(not_found.body, b"missing 42");
+
+        let unavailable = render(&templates, &host, HttpCode::ServiceUnavailable, "42");
+        assert_eq!(unavailable.body, b"503 Service Unavailable");
+
+        let teapot = render(&templates, &host, HttpCode::Teapot, "42");
+        assert_eq!(teapot.body, b"short and stout");
+    }
+}
@@ -0,0 +1,55 @@
+//! Readiness checks of the services the server depends on.
+//!
+//! `/readyz` on the admin listener runs every check and reports the server ready only if they
+//! all pass: Redis answers a `PING`, SQLite answers a query and the TLS certificate the server
+//! presents has not expired. The certificate check and the report come from the `health` crate
+//! in `shared/health`.
+use crate::server::SharedState;
+pub use ::health::Check;
+use rustls::pki_types::CertificateDer;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// How long a check waits for the shared state before failing.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs every readiness check.
+///
+/// # Arguments
+/// - `state`: A shared, thread-safe state holding the Redis and SQLite connections.
+/// - `certificate`: The certificate the TLS listener presents.
+///
+/// # Returns
+/// The result of each check, in a fixed order.
+pub async fn readiness_checks(
+    state: &Arc<Mutex<SharedState>>,
+    certificate: &CertificateDer<'_>,
+) -> Vec<Check> {
+    let mut checks: Vec<Check> = match tokio::time::timeout(LOCK_TIMEOUT, state.lock()).await {
+        Ok(mut state) => vec![
+            Check {
+                name: "redis",
+                result: state.cache.ping().map_err(|e| e.to_string()),
+            },
+            Check {
+                name: "sqlite",
+                result: state
+                    .conn
+                    .query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+                    .map(|_| String::from("OK"))
+                    .map_err(|e| e.to_string()),
+            },
+        ],
+        Err(_) => ["redis", "sqlite"]
+            .into_iter()
+            .map(|name| Check {
+                name,
+                result: Err(String::from("Timed out waiting for the shared state")),
+            })
+            .collect(),
+    };
+
+    checks.push(Check::certificate(certificate));
+    checks
+}
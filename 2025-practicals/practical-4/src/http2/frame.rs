@@ -0,0 +1,348 @@
+//! HTTP/2 frames (RFC 9113, Section 4 and 6).
+use std::fmt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The length of the fixed frame header.
+pub const HEADER_LEN: usize = 9;
+
+/// The largest frame payload allowed until the peer raises it with SETTINGS.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+
+/// The flow control window every stream and the connection start with.
+pub const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+
+/// The largest flow control window allowed.
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+pub const END_STREAM: u8 = 0x1;
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+pub const PADDED: u8 = 0x8;
+pub const PRIORITY: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    Data,
+    Headers,
+    Priority,
+    RstStream,
+    Settings,
+    PushPromise,
+    Ping,
+    GoAway,
+    WindowUpdate,
+    Continuation,
+    /// Frames of unknown types must be ignored.
+    Unknown(u8),
+}
+
+impl FrameType {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0x0 => FrameType::Data,
+            0x1 => FrameType::Headers,
+            0x2 => FrameType::Priority,
+            0x3 => FrameType::RstStream,
+            0x4 => FrameType::Settings,
+            0x5 => FrameType::PushPromise,
+            0x6 => FrameType::Ping,
+            0x7 => FrameType::GoAway,
+            0x8 => FrameType::WindowUpdate,
+            0x9 => FrameType::Continuation,
+            other => FrameType::Unknown(other),
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            FrameType::Data => 0x0,
+            FrameType::Headers => 0x1,
+            FrameType::Priority => 0x2,
+            FrameType::RstStream => 0x3,
+            FrameType::Settings => 0x4,
+            FrameType::PushPromise => 0x5,
+            FrameType::Ping => 0x6,
+            FrameType::GoAway => 0x7,
+            FrameType::WindowUpdate => 0x8,
+            FrameType::Continuation => 0x9,
+            FrameType::Unknown(other) => *other,
+        }
+    }
+}
+
+/// The error codes sent in RST_STREAM and GOAWAY frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    Cancel,
+    CompressionError,
+}
+
+impl ErrorCode {
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            ErrorCode::NoError => 0x0,
+            ErrorCode::ProtocolError => 0x1,
+            ErrorCode::InternalError => 0x2,
+            ErrorCode::FlowControlError => 0x3,
+            ErrorCode::StreamClosed => 0x5,
+            ErrorCode::FrameSizeError => 0x6,
+            ErrorCode::RefusedStream => 0x7,
+            ErrorCode::Cancel => 0x8,
+            ErrorCode::CompressionError => 0x9,
+        }
+    }
+}
+
+/// A connection error. The connection is closed with a GOAWAY frame carrying `code`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Http2Error {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl Http2Error {
+    pub fn new(code: ErrorCode, message: &str) -> Self {
+        Http2Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Http2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for Http2Error {}
+
+/// A single frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameType,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(kind: FrameType, flags: u8, stream_id: u32, payload: Vec<u8>) -> Self {
+        Frame {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Serializes the frame with its header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let length: usize = self.payload.len();
+        let mut bytes: Vec<u8> = Vec::with_capacity(HEADER_LEN + length);
+        bytes.extend_from_slice(&(length as u32).to_be_bytes()[1..]);
+        bytes.push(self.kind.as_u8());
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&(self.stream_id & MAX_WINDOW_SIZE).to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    /// Returns the payload of a DATA or HEADERS frame without its padding and, for HEADERS, the
+    /// priority fields.
+    pub fn content(&self) -> Result<&[u8], Http2Error> {
+        let mut start: usize = 0;
+        let mut end: usize = self.payload.len();
+
+        if self.has_flag(PADDED) {
+            let padding: usize = *self.payload.first().ok_or_else(frame_size_error)? as usize;
+            start = 1;
+            end = end
+                .checked_sub(padding)
+                .ok_or_else(|| Http2Error::new(ErrorCode::ProtocolError, "padding too long"))?;
+        }
+        if self.kind == FrameType::Headers && self.has_flag(PRIORITY) {
+            start += 5;
+        }
+
+        if start > end {
+            return Err(frame_size_error());
+        }
+        Ok(&self.payload[start..end])
+    }
+
+    pub fn settings(settings: &[(Setting, u32)]) -> Self {
+        let mut payload: Vec<u8> = Vec::with_capacity(settings.len() * 6);
+        for (setting, value) in settings {
+            payload.extend_from_slice(&setting.id().to_be_bytes());
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        Frame::new(FrameType::Settings, 0, 0, payload)
+    }
+
+    pub fn window_update(stream_id: u32, increment: u32) -> Self {
+        Frame::new(
+            FrameType::WindowUpdate,
+            0,
+            stream_id,
+            increment.to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn rst_stream(stream_id: u32, code: ErrorCode) -> Self {
+        Frame::new(
+            FrameType::RstStream,
+            0,
+            stream_id,
+            code.as_u32().to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn go_away(last_stream_id: u32, code: ErrorCode) -> Self {
+        let mut payload: Vec<u8> = last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.as_u32().to_be_bytes());
+        Frame::new(FrameType::GoAway, 0, 0, payload)
+    }
+
+    /// Reads the 31 bit value at the start of a WINDOW_UPDATE or GOAWAY payload.
+    pub fn read_u31(&self) -> Result<u32, Http2Error> {
+        let bytes: [u8; 4] = self
+            .payload
+            .get(..4)
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(frame_size_error)?;
+        Ok(u32::from_be_bytes(bytes) & MAX_WINDOW_SIZE)
+    }
+}
+
+/// The SETTINGS parameters this server understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    HeaderTableSize,
+    EnablePush,
+    MaxConcurrentStreams,
+    InitialWindowSize,
+    MaxFrameSize,
+    MaxHeaderListSize,
+}
+
+impl Setting {
+    fn id(&self) -> u16 {
+        match self {
+            Setting::HeaderTableSize => 0x1,
+            Setting::EnablePush => 0x2,
+            Setting::MaxConcurrentStreams => 0x3,
+            Setting::InitialWindowSize => 0x4,
+            Setting::MaxFrameSize => 0x5,
+            Setting::MaxHeaderListSize => 0x6,
+        }
+    }
+
+    fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0x1 => Some(Setting::HeaderTableSize),
+            0x2 => Some(Setting::EnablePush),
+            0x3 => Some(Setting::MaxConcurrentStreams),
+            0x4 => Some(Setting::InitialWindowSize),
+            0x5 => Some(Setting::MaxFrameSize),
+            0x6 => Some(Setting::MaxHeaderListSize),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the parameters of a SETTINGS frame, skipping unknown ones.
+pub fn parse_settings(payload: &[u8]) -> Result<Vec<(Setting, u32)>, Http2Error> {
+    if !payload.len().is_multiple_of(6) {
+        return Err(frame_size_error());
+    }
+
+    Ok(payload
+        .chunks(6)
+        .filter_map(|chunk| {
+            let setting: Setting = Setting::from_id(u16::from_be_bytes([chunk[0], chunk[1]]))?;
+            Some((
+                setting,
+                u32::from_be_bytes([chunk[2], chunk[3], chunk[4], chunk[5]]),
+            ))
+        })
+        .collect())
+}
+
+/// Reads the next frame from `stream`.
+///
+/// # Returns
+/// The frame, `Ok(None)` if the connection was closed between frames or a `FRAME_SIZE_ERROR` if
+/// the frame is larger than `max_frame_size`.
+pub async fn read_frame<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    max_frame_size: usize,
+) -> Result<Option<Frame>, Http2Error> {
+    let mut header: [u8; HEADER_LEN] = [0; HEADER_LEN];
+    match stream.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(Http2Error::new(ErrorCode::InternalError, &e.to_string())),
+    }
+
+    let length: usize = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > max_frame_size {
+        return Err(frame_size_error());
+    }
+
+    let mut payload: Vec<u8> = vec![0; length];
+    stream
+        .read_exact(&mut payload)
+        .await
+        .map_err(|e| Http2Error::new(ErrorCode::InternalError, &e.to_string()))?;
+
+    Ok(Some(Frame {
+        kind: FrameType::from_u8(header[3]),
+        flags: header[4],
+        stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+            & MAX_WINDOW_SIZE,
+        payload,
+    }))
+}
+
+fn frame_size_error() -> Http2Error {
+    Http2Error::new(ErrorCode::FrameSizeError, "invalid frame size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame_round_trip() {
+        let frame = Frame::new(FrameType::Headers, END_HEADERS | PADDED, 3, {
+            let mut payload = vec![2];
+            payload.extend_from_slice(b"block");
+            payload.extend_from_slice(&[0, 0]);
+            payload
+        });
+        let bytes = frame.to_bytes();
+        assert_eq!(&bytes[..HEADER_LEN], &[0, 0, 8, 1, 0x0c, 0, 0, 0, 3]);
+
+        let read = read_frame(&mut &bytes[..], DEFAULT_MAX_FRAME_SIZE)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(read, frame);
+        assert_eq!(read.content().unwrap(), b"block");
+
+        let too_large = read_frame(&mut &bytes[..], 4).await.unwrap_err();
+        assert_eq!(too_large.code, ErrorCode::FrameSizeError);
+    }
+}
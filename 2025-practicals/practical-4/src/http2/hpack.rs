@@ -0,0 +1,363 @@
+//! HPACK header compression (RFC 7541).
+//!
+//! The decoder supports the whole format, including the dynamic table and Huffman coded
+//! strings. The encoder never adds entries to the dynamic table, so header blocks for different
+//! streams can be encoded independently of each other and in any order.
+use super::frame::{ErrorCode, Http2Error};
+use super::huffman;
+use std::collections::VecDeque;
+
+/// The default and maximum size of the decoder's dynamic table.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// The static table (RFC 7541, Appendix A). Index 1 is the first entry.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// A header field as a lowercase name and a value.
+pub type HeaderField = (String, String);
+
+/// Decodes header blocks. One decoder is kept per connection as the dynamic table is shared by
+/// all of its streams.
+#[derive(Debug)]
+pub struct Decoder {
+    /// Newest entry first.
+    entries: VecDeque<HeaderField>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+}
+
+impl Decoder {
+    /// Decodes a complete header block.
+    ///
+    /// # Returns
+    /// The header fields in the order they were sent or a `COMPRESSION_ERROR`.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<HeaderField>, Http2Error> {
+        let mut fields: Vec<HeaderField> = Vec::new();
+        let mut position: usize = 0;
+
+        while position < block.len() {
+            let first: u8 = block[position];
+
+            if first & 0x80 != 0 {
+                // Indexed header field
+                let index: usize = decode_integer(block, &mut position, 7)?;
+                fields.push(self.entry(index)?);
+            } else if first & 0x40 != 0 {
+                // Literal with incremental indexing
+                let field: HeaderField = self.literal(block, &mut position, 6)?;
+                self.insert(field.clone());
+                fields.push(field);
+            } else if first & 0x20 != 0 {
+                // Dynamic table size update
+                let size: usize = decode_integer(block, &mut position, 5)?;
+                if size > DEFAULT_TABLE_SIZE {
+                    return Err(compression_error("table size update exceeds the limit"));
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // Literal without indexing or never indexed
+                fields.push(self.literal(block, &mut position, 4)?);
+            }
+        }
+
+        Ok(fields)
+    }
+
+    fn literal(
+        &self,
+        block: &[u8],
+        position: &mut usize,
+        prefix: u8,
+    ) -> Result<HeaderField, Http2Error> {
+        let index: usize = decode_integer(block, position, prefix)?;
+        let name: String = if index == 0 {
+            decode_string(block, position)?
+        } else {
+            self.entry(index)?.0
+        };
+        let value: String = decode_string(block, position)?;
+        Ok((name, value))
+    }
+
+    fn entry(&self, index: usize) -> Result<HeaderField, Http2Error> {
+        if index == 0 {
+            return Err(compression_error("index 0 is not used"));
+        }
+        if let Some((name, value)) = STATIC_TABLE.get(index - 1) {
+            return Ok((name.to_string(), value.to_string()));
+        }
+        self.entries
+            .get(index - STATIC_TABLE.len() - 1)
+            .cloned()
+            .ok_or_else(|| compression_error("index out of range"))
+    }
+
+    fn insert(&mut self, field: HeaderField) {
+        self.size += entry_size(&field);
+        self.entries.push_front(field);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.entries.pop_back() {
+                Some(field) => self.size -= entry_size(&field),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Encodes a header block for a response.
+pub fn encode(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut block: Vec<u8> = Vec::new();
+
+    for &(name, value) in fields {
+        if let Some(index) = STATIC_TABLE.iter().position(|&f| f == (name, value)) {
+            encode_integer(&mut block, index + 1, 7, 0x80);
+            continue;
+        }
+
+        // Literal without indexing, with an indexed name when the static table has it
+        match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+            Some(index) => encode_integer(&mut block, index + 1, 4, 0x00),
+            None => {
+                block.push(0x00);
+                encode_string(&mut block, name);
+            }
+        }
+        encode_string(&mut block, value);
+    }
+
+    block
+}
+
+fn entry_size(field: &HeaderField) -> usize {
+    field.0.len() + field.1.len() + 32
+}
+
+fn compression_error(message: &str) -> Http2Error {
+    Http2Error::new(ErrorCode::CompressionError, message)
+}
+
+/// Decodes an integer with an `prefix` bit prefix (RFC 7541, Section 5.1).
+fn decode_integer(block: &[u8], position: &mut usize, prefix: u8) -> Result<usize, Http2Error> {
+    let mask: u8 = ((1u16 << prefix) - 1) as u8;
+    let mut value: usize = usize::from(block[*position] & mask);
+    *position += 1;
+
+    if value < usize::from(mask) {
+        return Ok(value);
+    }
+
+    let mut shift: u32 = 0;
+    loop {
+        let byte: u8 = *block
+            .get(*position)
+            .ok_or_else(|| compression_error("truncated integer"))?;
+        *position += 1;
+
+        if shift > 28 {
+            return Err(compression_error("integer overflow"));
+        }
+        value += usize::from(byte & 0x7f) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_integer(block: &mut Vec<u8>, value: usize, prefix: u8, flags: u8) {
+    let mask: usize = (1 << prefix) - 1;
+    if value < mask {
+        block.push(flags | value as u8);
+        return;
+    }
+
+    block.push(flags | mask as u8);
+    let mut value: usize = value - mask;
+    while value >= 0x80 {
+        block.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+fn decode_string(block: &[u8], position: &mut usize) -> Result<String, Http2Error> {
+    let huffman_coded: bool = *block
+        .get(*position)
+        .ok_or_else(|| compression_error("truncated string"))?
+        & 0x80
+        != 0;
+    let length: usize = decode_integer(block, position, 7)?;
+
+    let bytes: &[u8] = block
+        .get(*position..*position + length)
+        .ok_or_else(|| compression_error("truncated string"))?;
+    *position += length;
+
+    let bytes: Vec<u8> = if huffman_coded {
+        huffman::decode(bytes).ok_or_else(|| compression_error("invalid Huffman code"))?
+    } else {
+        bytes.to_vec()
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn encode_string(block: &mut Vec<u8>, value: &str) {
+    let bytes: &[u8] = value.as_bytes();
+    if huffman::encoded_len(bytes) < bytes.len() {
+        let encoded: Vec<u8> = huffman::encode(bytes);
+        encode_integer(block, encoded.len(), 7, 0x80);
+        block.extend_from_slice(&encoded);
+    } else {
+        encode_integer(block, bytes.len(), 7, 0x00);
+        block.extend_from_slice(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<HeaderField> {
+        pairs
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rfc_request_examples_with_huffman() {
+        // RFC 7541, Appendix C.4
+        let mut decoder = Decoder::default();
+        let first = decoder
+            .decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"))
+            .unwrap();
+        assert_eq!(
+            first,
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+
+        let second = decoder
+            .decode(&hex("8286 84be 5886 a8eb 1064 9cbf"))
+            .unwrap();
+        assert_eq!(
+            second,
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+        assert_eq!(decoder.size, 110);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let response = [
+            (":status", "200"),
+            (":status", "413"),
+            ("content-type", "text/html"),
+            ("x-request-id", "0af7651916cd43dd8448eb211c80319c"),
+            ("set-cookie", "session_id=8f0e; Path=/"),
+        ];
+        let block = encode(&response);
+        assert_eq!(block[0], 0x88);
+
+        let decoded = Decoder::default().decode(&block).unwrap();
+        let expected: Vec<HeaderField> = fields(&response);
+        assert_eq!(decoded, expected);
+        assert!(Decoder::default().decode(&[0x80]).is_err());
+    }
+}
@@ -0,0 +1,856 @@
+//! HTTP/2 (RFC 9113) for TLS connections that negotiate `h2` with ALPN.
+//!
+//! A connection is served by two tasks. The reading task decodes frames, keeps track of the open
+//! streams and hands each complete request to the handler on a task of its own, so the streams of
+//! a connection are served concurrently. Finished responses go to the writing task, which owns
+//! the send side flow control windows and interleaves the DATA frames of different streams as
+//! the client's windows allow.
+//!
+//! Requests are converted into the same `Request` the HTTP/1.1 server parses, so they go through
+//! the same middleware and handlers. Server push is not supported.
+pub mod frame;
+pub mod hpack;
+mod huffman;
+
+use crate::body::MAX_BODY_SIZE;
+use crate::response::{BodyStream, Response};
+use crate::Request;
+use frame::{ErrorCode, Frame, FrameType, Http2Error, Setting};
+use hpack::HeaderField;
+use log::{error, info};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+
+/// The ALPN protocol ID of HTTP/2 over TLS.
+pub const ALPN: &[u8] = b"h2";
+
+/// The connection preface every client starts with.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// How many streams a client may have open at once.
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// The largest header block accepted for a request.
+const MAX_HEADER_LIST_SIZE: u32 = 64 * 1024;
+
+/// How long a connection without open streams may stay idle before it is closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers that only apply to a single HTTP/1.1 connection and must not be sent over HTTP/2.
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Serves an HTTP/2 connection until the client closes it or a connection error occurs.
+///
+/// # Arguments
+/// - `stream`: The connection, after the TLS handshake negotiated `h2`.
+/// - `client_ip`: The address of the client.
+/// - `handler`: Called with every request and its body. Each call runs on its own task.
+///
+/// # Returns
+/// `Ok(())` once the connection is closed or the `Http2Error` it was closed with.
+pub async fn serve<S, H, F>(stream: S, client_ip: String, handler: H) -> Result<(), Http2Error>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+    H: Fn(Request, Vec<u8>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response> + Send + 'static,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let mut preface: [u8; PREFACE.len()] = [0; PREFACE.len()];
+    if reader.read_exact(&mut preface).await.is_err() || preface != PREFACE {
+        return Err(Http2Error::new(
+            ErrorCode::ProtocolError,
+            "invalid connection preface",
+        ));
+    }
+
+    let (commands, receiver) = mpsc::unbounded_channel::<Command>();
+    let writer = tokio::spawn(Writer::new(writer).run(receiver));
+
+    let _ = commands.send(Command::Frame(Frame::settings(&[
+        (Setting::MaxConcurrentStreams, MAX_CONCURRENT_STREAMS),
+        (Setting::MaxHeaderListSize, MAX_HEADER_LIST_SIZE),
+    ])));
+
+    let mut connection = Connection {
+        client_ip,
+        handler,
+        commands,
+        decoder: hpack::Decoder::default(),
+        streams: HashMap::new(),
+        header_block: None,
+        last_stream_id: 0,
+        going_away: false,
+        permits: Arc::new(Semaphore::new(MAX_CONCURRENT_STREAMS as usize)),
+        cancels: HashMap::new(),
+    };
+
+    let result: Result<(), Http2Error> = connection.read_frames(&mut reader).await;
+    if let Err(e) = &result {
+        error!(target: "error_logger", "HTTP/2 connection error with {}: {}", connection.client_ip, e);
+        let _ = connection.commands.send(Command::Frame(Frame::go_away(
+            connection.last_stream_id,
+            e.code,
+        )));
+    }
+
+    // The writer finishes once every stream still being handled has sent its response
+    drop(connection);
+    let _ = writer.await;
+    result
+}
+
+/// A stream whose request is still being received.
+struct Stream {
+    fields: Vec<HeaderField>,
+    body: Vec<u8>,
+    permit: OwnedSemaphorePermit,
+}
+
+/// A header block that continues in CONTINUATION frames.
+struct HeaderBlock {
+    stream_id: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+}
+
+struct Connection<H> {
+    client_ip: String,
+    handler: H,
+    commands: mpsc::UnboundedSender<Command>,
+    decoder: hpack::Decoder,
+    streams: HashMap<u32, Stream>,
+    header_block: Option<HeaderBlock>,
+    last_stream_id: u32,
+    going_away: bool,
+    permits: Arc<Semaphore>,
+    /// Stops the streamed response of a stream when the client resets it.
+    cancels: HashMap<u32, oneshot::Sender<()>>,
+}
+
+impl<H, F> Connection<H>
+where
+    H: Fn(Request, Vec<u8>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response> + Send + 'static,
+{
+    async fn read_frames<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<(), Http2Error> {
+        loop {
+            let idle: bool = self.streams.is_empty()
+                && self.permits.available_permits() == MAX_CONCURRENT_STREAMS as usize;
+            let read = frame::read_frame(reader, frame::DEFAULT_MAX_FRAME_SIZE);
+
+            // Only an idle connection is timed out, a frame cut short would corrupt the stream
+            let frame: Option<Frame> = if idle {
+                match tokio::time::timeout(IDLE_TIMEOUT, read).await {
+                    Ok(frame) => frame?,
+                    Err(_) => {
+                        info!(target: "request_logger", "Closing idle HTTP/2 connection with {}", self.client_ip);
+                        self.send(Command::Frame(Frame::go_away(
+                            self.last_stream_id,
+                            ErrorCode::NoError,
+                        )));
+                        return Ok(());
+                    }
+                }
+            } else {
+                read.await?
+            };
+
+            let frame: Frame = match frame {
+                Some(f) => f,
+                None => return Ok(()),
+            };
+            self.handle_frame(frame)?;
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if let Some(pending) = &self.header_block {
+            if frame.kind != FrameType::Continuation || frame.stream_id != pending.stream_id {
+                return Err(protocol_error("expected CONTINUATION"));
+            }
+        }
+
+        match frame.kind {
+            FrameType::Settings => {
+                if frame.stream_id != 0 {
+                    return Err(protocol_error("SETTINGS on a stream"));
+                }
+                if !frame.has_flag(frame::ACK) {
+                    let settings = frame::parse_settings(&frame.payload)?;
+                    self.send(Command::Settings(settings));
+                }
+            }
+            FrameType::Ping => {
+                if frame.stream_id != 0 || frame.payload.len() != 8 {
+                    return Err(protocol_error("invalid PING"));
+                }
+                if !frame.has_flag(frame::ACK) {
+                    self.send(Command::Frame(Frame::new(
+                        FrameType::Ping,
+                        frame::ACK,
+                        0,
+                        frame.payload,
+                    )));
+                }
+            }
+            FrameType::WindowUpdate => {
+                let increment: u32 = frame.read_u31()?;
+                if increment == 0 {
+                    if frame.stream_id == 0 {
+                        return Err(protocol_error("WINDOW_UPDATE of 0"));
+                    }
+                    self.send(Command::Frame(Frame::rst_stream(
+                        frame.stream_id,
+                        ErrorCode::ProtocolError,
+                    )));
+                } else {
+                    self.send(Command::WindowUpdate(frame.stream_id, increment));
+                }
+            }
+            FrameType::Headers => {
+                if frame.stream_id == 0 {
+                    return Err(protocol_error("HEADERS on stream 0"));
+                }
+                let block: Vec<u8> = frame.content()?.to_vec();
+                let pending = HeaderBlock {
+                    stream_id: frame.stream_id,
+                    block,
+                    end_stream: frame.has_flag(frame::END_STREAM),
+                };
+
+                if frame.has_flag(frame::END_HEADERS) {
+                    self.headers(pending)?;
+                } else {
+                    self.header_block = Some(pending);
+                }
+            }
+            FrameType::Continuation => {
+                let mut pending: HeaderBlock = self
+                    .header_block
+                    .take()
+                    .ok_or_else(|| protocol_error("unexpected CONTINUATION"))?;
+                pending.block.extend_from_slice(&frame.payload);
+                if pending.block.len() > MAX_HEADER_LIST_SIZE as usize {
+                    return Err(Http2Error::new(
+                        ErrorCode::ProtocolError,
+                        "header block too large",
+                    ));
+                }
+
+                if frame.has_flag(frame::END_HEADERS) {
+                    self.headers(pending)?;
+                } else {
+                    self.header_block = Some(pending);
+                }
+            }
+            FrameType::Data => self.data(frame)?,
+            FrameType::RstStream => {
+                self.streams.remove(&frame.stream_id);
+                if let Some(cancel) = self.cancels.remove(&frame.stream_id) {
+                    let _ = cancel.send(());
+                }
+                self.send(Command::Reset(frame.stream_id));
+            }
+            FrameType::GoAway => self.going_away = true,
+            FrameType::PushPromise => return Err(protocol_error("clients cannot push")),
+            FrameType::Priority | FrameType::Unknown(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Handles a complete header block, which either opens a stream or carries its trailers.
+    fn headers(&mut self, pending: HeaderBlock) -> Result<(), Http2Error> {
+        // Every block is decoded, even for refused streams, to keep the HPACK state in sync
+        let fields: Vec<HeaderField> = self.decoder.decode(&pending.block)?;
+        let stream_id: u32 = pending.stream_id;
+
+        if self.streams.contains_key(&stream_id) {
+            // Trailers
+            if !pending.end_stream {
+                return Err(protocol_error("trailers without END_STREAM"));
+            }
+            return self.dispatch(stream_id);
+        }
+
+        if stream_id.is_multiple_of(2) || stream_id <= self.last_stream_id {
+            return Err(protocol_error("invalid stream ID"));
+        }
+        self.last_stream_id = stream_id;
+
+        if self.going_away {
+            return Ok(());
+        }
+        let permit: OwnedSemaphorePermit = match self.permits.clone().try_acquire_owned() {
+            Ok(p) => p,
+            Err(_) => {
+                self.send(Command::Frame(Frame::rst_stream(
+                    stream_id,
+                    ErrorCode::RefusedStream,
+                )));
+                return Ok(());
+            }
+        };
+
+        self.send(Command::Open(stream_id));
+        self.streams.insert(
+            stream_id,
+            Stream {
+                fields,
+                body: Vec::new(),
+                permit,
+            },
+        );
+
+        if pending.end_stream {
+            self.dispatch(stream_id)?;
+        }
+        Ok(())
+    }
+
+    fn data(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if frame.stream_id == 0 {
+            return Err(protocol_error("DATA on stream 0"));
+        }
+
+        // Received data is handed on straight away, so the windows are restored immediately
+        if !frame.payload.is_empty() {
+            self.send(Command::Frame(Frame::window_update(
+                0,
+                frame.payload.len() as u32,
+            )));
+        }
+
+        let stream: &mut Stream = match self.streams.get_mut(&frame.stream_id) {
+            Some(s) => s,
+            None => {
+                self.send(Command::Frame(Frame::rst_stream(
+                    frame.stream_id,
+                    ErrorCode::StreamClosed,
+                )));
+                return Ok(());
+            }
+        };
+
+        // Stop buffering past the limit, the body is then rejected with 413 by `read_body`
+        let content: &[u8] = frame.content()?;
+        let room: usize = (MAX_BODY_SIZE + 1).saturating_sub(stream.body.len());
+        stream
+            .body
+            .extend_from_slice(&content[..content.len().min(room)]);
+
+        if frame.has_flag(frame::END_STREAM) {
+            return self.dispatch(frame.stream_id);
+        }
+        if !frame.payload.is_empty() {
+            self.send(Command::Frame(Frame::window_update(
+                frame.stream_id,
+                frame.payload.len() as u32,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Hands a fully received request to the handler.
+    fn dispatch(&mut self, stream_id: u32) -> Result<(), Http2Error> {
+        let stream: Stream = match self.streams.remove(&stream_id) {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        let request: Request = match to_request(&stream.fields, &self.client_ip) {
+            Some(r) => r,
+            None => {
+                error!(target: "error_logger", "Malformed HTTP/2 request on stream {} from {}", stream_id, self.client_ip);
+                self.send(Command::Frame(Frame::rst_stream(
+                    stream_id,
+                    ErrorCode::ProtocolError,
+                )));
+                return Ok(());
+            }
+        };
+
+        let handler: H = self.handler.clone();
+        let commands: mpsc::UnboundedSender<Command> = self.commands.clone();
+        let permit: OwnedSemaphorePermit = stream.permit;
+        let body: Vec<u8> = stream.body;
+
+        let (cancel, cancelled) = oneshot::channel::<()>();
+        self.cancels.retain(|_, c| !c.is_closed());
+        self.cancels.insert(stream_id, cancel);
+
+        tokio::spawn(async move {
+            let mut response: Response = handler(request, body).await;
+            let body: Vec<u8> = response.encode_body();
+            let header_block: Vec<u8> = response_header_block(&response);
+            let stream: Option<BodyStream> = response.stream.take();
+
+            let _ = commands.send(Command::Response {
+                stream_id,
+                header_block,
+                body,
+                end_stream: stream.is_none(),
+            });
+            if let Some(stream) = stream {
+                send_stream(stream_id, stream, &commands, cancelled).await;
+            }
+            drop(permit);
+        });
+        Ok(())
+    }
+
+    fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+}
+
+/// Sends a streamed body as it is produced, until it ends or the client resets the stream.
+async fn send_stream(
+    stream_id: u32,
+    mut body: BodyStream,
+    commands: &mpsc::UnboundedSender<Command>,
+    mut cancelled: oneshot::Receiver<()>,
+) {
+    loop {
+        tokio::select! {
+            chunk = body.recv() => {
+                let end_stream: bool = chunk.is_none();
+                let data: Vec<u8> = chunk.unwrap_or_default();
+                if commands.send(Command::Data { stream_id, data, end_stream }).is_err() || end_stream {
+                    return;
+                }
+            }
+            // Reset by the client or the connection is gone
+            _ = &mut cancelled => return,
+        }
+    }
+}
+
+/// Builds a `Request` from the header fields of a stream.
+///
+/// The request is rebuilt as an HTTP/1.1 head and parsed by `Request::new`, with the
+/// `:authority` pseudo-header as the `Host` header and the cookie fields joined back together.
+///
+/// # Returns
+/// The request or `None` if it is malformed.
+fn to_request(fields: &[HeaderField], client_ip: &str) -> Option<Request> {
+    let pseudo = |name: &str| {
+        fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let method: &str = pseudo(":method")?;
+    let path: &str = pseudo(":path")?;
+    let host: Option<&str> = pseudo(":authority").or_else(|| pseudo("host"));
+
+    let mut head: String = format!("{} {} HTTP/2\r\n", method, path);
+    if let Some(host) = host {
+        head.push_str(&format!("Host: {}\r\n", host));
+    }
+
+    let mut cookies: Vec<&str> = Vec::new();
+    for (name, value) in fields {
+        if name.contains(['\r', '\n', ':']) && !name.starts_with(':')
+            || value.contains(['\r', '\n'])
+        {
+            return None;
+        }
+        match name.as_str() {
+            n if n.starts_with(':') || n == "host" => {}
+            "cookie" => cookies.push(value),
+            n => head.push_str(&format!("{}: {}\r\n", title_case(n), value)),
+        }
+    }
+    if !cookies.is_empty() {
+        head.push_str(&format!("Cookie: {}\r\n", cookies.join("; ")));
+    }
+
+    Request::new(head.as_bytes(), client_ip.to_string()).ok()
+}
+
+/// Converts a lowercase HTTP/2 header name to the `Title-Case` the handlers expect.
+fn title_case(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+/// Encodes the status and headers of a response, leaving out connection-specific headers.
+fn response_header_block(response: &Response) -> Vec<u8> {
+    let status: String = response.code.status_code().to_string();
+    let names: Vec<String> = response
+        .headers
+        .iter()
+        .map(|h| h.title.to_ascii_lowercase())
+        .collect();
+
+    let mut fields: Vec<(&str, &str)> = vec![(":status", status.as_str())];
+    for (name, header) in names.iter().zip(&response.headers) {
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            fields.push((name, &header.value));
+        }
+    }
+    hpack::encode(&fields)
+}
+
+fn protocol_error(message: &str) -> Http2Error {
+    Http2Error::new(ErrorCode::ProtocolError, message)
+}
+
+/// Work for the writing task.
+enum Command {
+    /// Writes a frame as is.
+    Frame(Frame),
+    /// Applies the client's settings and acknowledges them.
+    Settings(Vec<(Setting, u32)>),
+    /// A stream was opened and gets the initial send window.
+    Open(u32),
+    /// Increases the send window of a stream, or of the connection for stream 0.
+    WindowUpdate(u32, u32),
+    /// Sends the response for a stream. A streamed body follows in `Data` commands.
+    Response {
+        stream_id: u32,
+        header_block: Vec<u8>,
+        body: Vec<u8>,
+        end_stream: bool,
+    },
+    /// Sends more of a streamed body.
+    Data {
+        stream_id: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+    },
+    /// The client cancelled a stream.
+    Reset(u32),
+}
+
+/// The unsent part of a response body, waiting for flow control window or, for a streamed
+/// body, for more data.
+struct PendingBody {
+    stream_id: u32,
+    body: Vec<u8>,
+    /// True once the whole body is known.
+    end_stream: bool,
+}
+
+struct Writer<W> {
+    writer: W,
+    /// Send windows may go negative when the client shrinks the initial window.
+    connection_window: i64,
+    stream_windows: HashMap<u32, i64>,
+    initial_window: i64,
+    max_frame_size: usize,
+    pending: VecDeque<PendingBody>,
+}
+
+impl<W: AsyncWrite + Unpin> Writer<W> {
+    fn new(writer: W) -> Self {
+        Writer {
+            writer,
+            connection_window: i64::from(frame::DEFAULT_WINDOW_SIZE),
+            stream_windows: HashMap::new(),
+            initial_window: i64::from(frame::DEFAULT_WINDOW_SIZE),
+            max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+            pending: VecDeque::new(),
+        }
+    }
+
+    async fn run(mut self, mut commands: mpsc::UnboundedReceiver<Command>) {
+        while let Some(command) = commands.recv().await {
+            let result: std::io::Result<()> = match self.apply(command).await {
+                Ok(()) => self.send_data().await,
+                Err(e) => Err(e),
+            };
+            if result.and(self.writer.flush().await).is_err() {
+                return;
+            }
+        }
+        let _ = self.writer.shutdown().await;
+    }
+
+    async fn apply(&mut self, command: Command) -> std::io::Result<()> {
+        match command {
+            Command::Frame(frame) => self.write(&frame).await?,
+            Command::Settings(settings) => {
+                for (setting, value) in settings {
+                    match setting {
+                        Setting::InitialWindowSize => {
+                            let delta: i64 = i64::from(value) - self.initial_window;
+                            self.initial_window = i64::from(value);
+                            self.stream_windows.values_mut().for_each(|w| *w += delta);
+                        }
+                        Setting::MaxFrameSize => {
+                            self.max_frame_size = (value as usize)
+                                .clamp(frame::DEFAULT_MAX_FRAME_SIZE, (1 << 24) - 1);
+                        }
+                        _ => {}
+                    }
+                }
+                self.write(&Frame::new(FrameType::Settings, frame::ACK, 0, Vec::new()))
+                    .await?;
+            }
+            Command::Open(stream_id) => {
+                self.stream_windows.insert(stream_id, self.initial_window);
+            }
+            Command::WindowUpdate(0, increment) => self.connection_window += i64::from(increment),
+            Command::WindowUpdate(stream_id, increment) => {
+                if let Some(window) = self.stream_windows.get_mut(&stream_id) {
+                    *window += i64::from(increment);
+                }
+            }
+            Command::Response {
+                stream_id,
+                header_block,
+                body,
+                end_stream,
+            } => {
+                if !self.stream_windows.contains_key(&stream_id) {
+                    // reset by the client
+                    return Ok(());
+                }
+                let headers_only: bool = end_stream && body.is_empty();
+                self.write_headers(stream_id, header_block, headers_only)
+                    .await?;
+                if headers_only {
+                    self.stream_windows.remove(&stream_id);
+                } else {
+                    self.pending.push_back(PendingBody {
+                        stream_id,
+                        body,
+                        end_stream,
+                    });
+                }
+            }
+            Command::Data {
+                stream_id,
+                data,
+                end_stream,
+            } => {
+                if !self.stream_windows.contains_key(&stream_id) {
+                    return Ok(());
+                }
+                match self.pending.iter_mut().find(|p| p.stream_id == stream_id) {
+                    Some(pending) => {
+                        pending.body.extend_from_slice(&data);
+                        pending.end_stream = end_stream;
+                    }
+                    None => self.pending.push_back(PendingBody {
+                        stream_id,
+                        body: data,
+                        end_stream,
+                    }),
+                }
+            }
+            Command::Reset(stream_id) => {
+                self.stream_windows.remove(&stream_id);
+                self.pending.retain(|p| p.stream_id != stream_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a header block, split into CONTINUATION frames if it is larger than a frame.
+    async fn write_headers(
+        &mut self,
+        stream_id: u32,
+        header_block: Vec<u8>,
+        end_stream: bool,
+    ) -> std::io::Result<()> {
+        let mut chunks = header_block.chunks(self.max_frame_size).peekable();
+        let mut kind: FrameType = FrameType::Headers;
+        let mut flags: u8 = if end_stream { frame::END_STREAM } else { 0 };
+
+        loop {
+            let chunk: &[u8] = chunks.next().unwrap_or(&[]);
+            if chunks.peek().is_none() {
+                flags |= frame::END_HEADERS;
+            }
+            let frame = Frame::new(kind, flags, stream_id, chunk.to_vec());
+            self.writer.write_all(&frame.to_bytes()).await?;
+
+            if flags & frame::END_HEADERS != 0 {
+                return Ok(());
+            }
+            kind = FrameType::Continuation;
+            flags = 0;
+        }
+    }
+
+    /// Sends as much pending body data as the windows allow, one frame per stream in turn.
+    async fn send_data(&mut self) -> std::io::Result<()> {
+        let mut progress: bool = true;
+
+        while progress {
+            progress = false;
+
+            for _ in 0..self.pending.len() {
+                let mut pending: PendingBody = match self.pending.pop_front() {
+                    Some(p) => p,
+                    None => break,
+                };
+                let window: i64 = self
+                    .stream_windows
+                    .get(&pending.stream_id)
+                    .copied()
+                    .unwrap_or(0);
+
+                let size: usize = pending
+                    .body
+                    .len()
+                    .min(self.max_frame_size)
+                    .min(window.max(0) as usize)
+                    .min(self.connection_window.max(0) as usize);
+
+                // An empty frame can still end the stream, otherwise wait for window or data
+                let done: bool = size == pending.body.len() && pending.end_stream;
+                if size == 0 && !done {
+                    self.pending.push_back(pending);
+                    continue;
+                }
+
+                let frame = Frame::new(
+                    FrameType::Data,
+                    if done { frame::END_STREAM } else { 0 },
+                    pending.stream_id,
+                    pending.body.drain(..size).collect(),
+                );
+                self.writer.write_all(&frame.to_bytes()).await?;
+
+                self.connection_window -= size as i64;
+                if let Some(window) = self.stream_windows.get_mut(&pending.stream_id) {
+                    *window -= size as i64;
+                }
+                progress = true;
+
+                if done {
+                    self.stream_windows.remove(&pending.stream_id);
+                } else {
+                    self.pending.push_back(pending);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, frame: &Frame) -> std::io::Result<()> {
+        self.writer.write_all(&frame.to_bytes()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContentType, HttpCode, Protocol};
+
+    fn field(name: &str, value: &str) -> HeaderField {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_to_request() {
+        let fields = vec![
+            field(":method", "POST"),
+            field(":scheme", "https"),
+            field(":path", "/add?x=1"),
+            field(":authority", "phonebook.local"),
+            field("content-type", "application/json"),
+            field("cookie", "session_id=1"),
+            field("cookie", "theme=dark"),
+        ];
+        let request = to_request(&fields, "127.0.0.1:50000").unwrap();
+        assert_eq!(request.uri, "/add?x=1");
+        assert_eq!(request.get_header("Host"), Some("phonebook.local"));
+        assert!(request
+            .headers
+            .contains(&String::from("Content-Type: application/json")));
+        assert!(request
+            .headers
+            .contains(&String::from("Cookie: session_id=1; theme=dark")));
+
+        let injected = vec![
+            field(":method", "GET"),
+            field(":path", "/"),
+            field("x-test", "a\r\nHost: evil"),
+        ];
+        assert!(to_request(&injected, "127.0.0.1:50000").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_request_over_h2() {
+        let (client, server) = tokio::io::duplex(1 << 16);
+        tokio::spawn(serve(
+            server,
+            String::from("127.0.0.1:50000"),
+            |request, _| async move {
+                Response::new(Protocol::Http, HttpCode::Ok, ContentType::Text, false)
+                    .body(request.uri.into_bytes())
+            },
+        ));
+
+        let (mut read, mut write) = tokio::io::split(client);
+        let mut bytes: Vec<u8> = PREFACE.to_vec();
+        bytes.extend(Frame::settings(&[]).to_bytes());
+        let block = hpack::encode(&[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/friends"),
+            (":authority", "localhost"),
+        ]);
+        bytes.extend(
+            Frame::new(
+                FrameType::Headers,
+                frame::END_HEADERS | frame::END_STREAM,
+                1,
+                block,
+            )
+            .to_bytes(),
+        );
+        write.write_all(&bytes).await.unwrap();
+
+        let mut decoder = hpack::Decoder::default();
+        let mut status: Option<String> = None;
+        loop {
+            let frame = frame::read_frame(&mut read, frame::DEFAULT_MAX_FRAME_SIZE)
+                .await
+                .unwrap()
+                .unwrap();
+            match frame.kind {
+                FrameType::Headers => {
+                    let fields = decoder.decode(frame.content().unwrap()).unwrap();
+                    assert_eq!(frame.stream_id, 1);
+                    status = Some(fields[0].1.clone());
+                }
+                FrameType::Data => {
+                    assert_eq!(frame.payload, b"/friends");
+                    assert!(frame.has_flag(frame::END_STREAM));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(status.as_deref(), Some("200"));
+    }
+}
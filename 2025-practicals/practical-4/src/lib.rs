@@ -1,14 +1,33 @@
 pub mod error;
 pub use crate::error::my_errors::{ErrorType, Logger};
 
+pub mod access_log;
+pub mod admin;
 pub mod api;
 pub use api::*;
 
+pub mod body;
+
+pub mod calculator;
+pub mod cgi;
+pub mod control;
+pub mod csrf;
+pub mod error_page;
+pub mod health;
+pub mod http2;
+pub mod metrics;
+pub mod middleware;
 pub mod redis_connection;
+pub mod security;
 pub mod server;
+pub mod sse;
 
 pub mod response;
 pub mod socket;
+pub mod trace;
 
 pub mod request;
 pub use request::*;
+
+pub mod vhost;
+pub mod websocket;
@@ -0,0 +1,324 @@
+//! In-process metrics registry.
+//!
+//! Counters, gauges and histograms are updated from the connection, request, cache and database
+//! paths and rendered in the Prometheus text exposition format on the admin port.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Upper bounds (in seconds) of the response latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The maximum number of label sets a labelled counter tracks before folding new ones into
+/// `other`, so that clients requesting random paths cannot grow the registry without bound.
+const MAX_LABEL_SETS: usize = 256;
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Returns the process wide metrics registry.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// A monotonically increasing counter.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that can go up and down.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A counter partitioned by a fixed set of labels.
+#[derive(Debug)]
+pub struct CounterVec {
+    labels: &'static [&'static str],
+    values: std::sync::Mutex<HashMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    pub fn new(labels: &'static [&'static str]) -> Self {
+        CounterVec {
+            labels,
+            values: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Increments the counter for `values`, which must match the label names in order.
+    pub fn inc(&self, values: &[&str]) {
+        let mut map = match self.values.lock() {
+            Ok(m) => m,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let mut key: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        if !map.contains_key(&key) && map.len() >= MAX_LABEL_SETS {
+            key = vec![String::from("other"); self.labels.len()];
+        }
+        *map.entry(key).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, values: &[&str]) -> u64 {
+        let key: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        match self.values.lock() {
+            Ok(m) => m.get(&key).copied().unwrap_or(0),
+            Err(poisoned) => poisoned.into_inner().get(&key).copied().unwrap_or(0),
+        }
+    }
+}
+
+/// A histogram with fixed buckets.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(i) = self.bounds.iter().position(|b| seconds <= *b) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// All metrics exported by the server.
+#[derive(Debug)]
+pub struct Metrics {
+    pub connections_accepted: Counter,
+    pub active_connections: Gauge,
+    pub tls_handshake_failures: Counter,
+    pub requests: CounterVec,
+    pub response_latency: Histogram,
+    pub cache_hits: Counter,
+    pub cache_misses: Counter,
+    pub cache_errors: Counter,
+    pub database_errors: Counter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            connections_accepted: Counter::default(),
+            active_connections: Gauge::default(),
+            tls_handshake_failures: Counter::default(),
+            requests: CounterVec::new(&["host", "route", "status"]),
+            response_latency: Histogram::new(&LATENCY_BUCKETS),
+            cache_hits: Counter::default(),
+            cache_misses: Counter::default(),
+            cache_errors: Counter::default(),
+            database_errors: Counter::default(),
+        }
+    }
+
+    /// Records a completed request.
+    ///
+    /// # Arguments
+    /// - `host`: The name of the virtual host that served the request.
+    /// - `route`: The request path without the query string.
+    /// - `status`: The numeric status code of the response.
+    /// - `duration`: The time taken to read the request and write the response.
+    pub fn observe_request(&self, host: &str, route: &str, status: u16, duration: Duration) {
+        self.requests.inc(&[host, route, &status.to_string()]);
+        self.response_latency.observe(duration);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        write_single(
+            &mut out,
+            "http_connections_accepted_total",
+            "Connections accepted by the listener.",
+            "counter",
+            self.connections_accepted.get().to_string(),
+        );
+        write_single(
+            &mut out,
+            "http_active_connections",
+            "Connections currently being served.",
+            "gauge",
+            self.active_connections.get().to_string(),
+        );
+        write_single(
+            &mut out,
+            "tls_handshake_failures_total",
+            "TLS handshakes that failed.",
+            "counter",
+            self.tls_handshake_failures.get().to_string(),
+        );
+
+        let _ = writeln!(out, "# HELP http_requests_total Completed HTTP requests.");
+        let _ = writeln!(out, "# TYPE http_requests_total counter");
+        let requests = match self.requests.values.lock() {
+            Ok(m) => m.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        let mut requests: Vec<(Vec<String>, u64)> = requests.into_iter().collect();
+        requests.sort();
+        for (values, count) in requests {
+            let labels: Vec<String> = self
+                .requests
+                .labels
+                .iter()
+                .zip(values.iter())
+                .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+                .collect();
+            let _ = writeln!(out, "http_requests_total{{{}}} {}", labels.join(","), count);
+        }
+
+        let name = "http_response_latency_seconds";
+        let _ = writeln!(out, "# HELP {} Time taken to serve a request.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative: u64 = 0;
+        for (bound, bucket) in self
+            .response_latency
+            .bounds
+            .iter()
+            .zip(&self.response_latency.buckets)
+        {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.response_latency.count();
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.response_latency.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        write_single(
+            &mut out,
+            "cache_hits_total",
+            "Pages served from the Redis cache.",
+            "counter",
+            self.cache_hits.get().to_string(),
+        );
+        write_single(
+            &mut out,
+            "cache_misses_total",
+            "Pages that were not found in the Redis cache.",
+            "counter",
+            self.cache_misses.get().to_string(),
+        );
+        write_single(
+            &mut out,
+            "cache_errors_total",
+            "Page cache lookups that failed because Redis could not be read.",
+            "counter",
+            self.cache_errors.get().to_string(),
+        );
+        write_single(
+            &mut out,
+            "database_errors_total",
+            "Failed database queries.",
+            "counter",
+            self.database_errors.get().to_string(),
+        );
+
+        out
+    }
+}
+
+fn write_single(out: &mut String, name: &str, help: &str, kind: &str, value: String) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_requests_and_histogram() {
+        let metrics = Metrics::new();
+        metrics.observe_request("localhost", "/friends", 200, Duration::from_millis(3));
+        metrics.observe_request("localhost", "/friends", 200, Duration::from_millis(300));
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "http_requests_total{host=\"localhost\",route=\"/friends\",status=\"200\"} 2"
+        ));
+        assert!(text.contains("http_response_latency_seconds_bucket{le=\"0.005\"} 1"));
+        assert!(text.contains("http_response_latency_seconds_bucket{le=\"+Inf\"} 2"));
+        assert!(text.contains("http_response_latency_seconds_count 2"));
+
+        metrics.cache_misses.inc();
+        metrics.cache_errors.inc();
+        metrics.cache_errors.inc();
+        let text = metrics.render();
+        assert!(text.contains("cache_misses_total 1"));
+        assert!(text.contains("cache_errors_total 2"));
+    }
+
+    #[test]
+    fn test_label_sets_are_bounded() {
+        let metrics = Metrics::new();
+        for i in 0..MAX_LABEL_SETS + 10 {
+            metrics
+                .requests
+                .inc(&["localhost", &format!("/{}", i), "404"]);
+        }
+        assert_eq!(metrics.requests.get(&["other", "other", "other"]), 10);
+    }
+}
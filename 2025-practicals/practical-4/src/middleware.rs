@@ -0,0 +1,402 @@
+//! The middleware pipeline wrapped around the request handlers.
+//!
+//! Cross-cutting concerns are written as `Middleware` with a `before` hook that runs on the
+//! request before it reaches the handlers and an `after` hook that runs on the response. A
+//! `before` hook may rewrite the request or answer it directly, in which case the handlers and
+//! the remaining middleware are skipped and only the `after` hooks of the middleware that have
+//! already run are applied, innermost first.
+//!
+//! Any middleware can be switched off for a route of a virtual host with `disabled_middleware`
+//! in the hosts file, without touching the handlers.
+use crate::response::Response;
+use crate::server::SharedState;
+use crate::vhost::VirtualHost;
+use crate::{ContentType, HttpCode, Protocol, Request};
+use log::error;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// Per-request values shared by the middleware in a pipeline.
+#[derive(Debug)]
+pub struct Context {
+    /// The virtual host serving the request.
+    pub host: Arc<VirtualHost>,
+    pub session_id: Uuid,
+    /// True if the session was created for this request and its cookie must be set.
+    pub new_session: bool,
+    pub request_id: String,
+    /// The request path without the query string.
+    pub path: String,
+    /// The value of the `Origin` header, if any.
+    pub origin: Option<String>,
+    /// The CSRF token of the session.
+    pub csrf_token: String,
+    /// Whether the response may be gzip compressed.
+    pub compression: bool,
+}
+
+impl Context {
+    /// Creates the context for a request, loading the session's CSRF token.
+    ///
+    /// # Arguments
+    /// - `request`: The parsed request.
+    /// - `state`: A shared, thread-safe state used for managing server data and caching.
+    /// - `host`: The virtual host serving the request.
+    /// - `session_id`: The session the request belongs to.
+    /// - `new_session`: True if the session was created for this request.
+    pub async fn new(
+        request: &Request,
+        state: &Arc<Mutex<SharedState>>,
+        host: Arc<VirtualHost>,
+        session_id: Uuid,
+        new_session: bool,
+    ) -> Self {
+        let csrf_token: String = state
+            .lock()
+            .await
+            .user_states
+            .entry(session_id)
+            .or_default()
+            .csrf_token
+            .clone();
+
+        Context {
+            host,
+            session_id,
+            new_session,
+            request_id: request.request_id.clone(),
+            path: request.path().to_string(),
+            origin: request.get_header("Origin").map(str::to_string),
+            csrf_token,
+            compression: false,
+        }
+    }
+}
+
+/// A step in the pipeline.
+pub trait Middleware: Send + Sync {
+    /// The name used to switch the middleware off in the hosts file.
+    fn name(&self) -> &'static str;
+
+    /// Runs before the handlers. Returning a response stops the request from going further.
+    fn before(&self, _request: &mut Request, _context: &mut Context) -> Option<Response> {
+        None
+    }
+
+    /// Runs on the response on its way back to the client.
+    fn after(&self, _context: &Context, _response: &mut Response) {}
+}
+
+/// An ordered list of middleware around `crate::handle_response`.
+#[derive(Default)]
+pub struct Pipeline {
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline {
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Appends `middleware`. Middleware added first sees the request first and the response
+    /// last.
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Returns the pipeline used by the server.
+    pub fn standard() -> Self {
+        Pipeline::new()
+            .with(RequestId)
+            .with(SecurityHeaders)
+            .with(SessionCookie)
+            .with(Compression)
+            .with(Csrf)
+    }
+
+    /// Passes a request through the middleware and handlers.
+    ///
+    /// # Arguments
+    /// - `request`: The incoming HTTP request.
+    /// - `state`: A shared, thread-safe state used for managing server data and caching.
+    /// - `context`: The values shared by the middleware for this request.
+    ///
+    /// # Returns
+    /// The `Response` to write to the client.
+    pub async fn run(
+        &self,
+        mut request: Request,
+        state: Arc<Mutex<SharedState>>,
+        context: &mut Context,
+    ) -> Response {
+        let enabled: Vec<&dyn Middleware> = self.enabled(context);
+        let (ran, short_circuit) = run_before(&enabled, &mut request, context);
+
+        let mut response: Response = match short_circuit {
+            Some(r) => r,
+            None => handle(request, state, context).await,
+        };
+
+        run_after(&enabled[..ran], context, &mut response);
+        response
+    }
+
+    /// Returns the middleware that are switched on for the request's route.
+    fn enabled(&self, context: &Context) -> Vec<&dyn Middleware> {
+        self.middleware
+            .iter()
+            .map(|m| m.as_ref())
+            .filter(|m| context.host.middleware_enabled(m.name(), &context.path))
+            .collect()
+    }
+}
+
+/// Runs the handlers on a task of their own so that a panic produces a `500` error page instead
+/// of taking down the connection.
+async fn handle(request: Request, state: Arc<Mutex<SharedState>>, context: &Context) -> Response {
+    let handler = tokio::spawn(crate::handle_response(
+        request,
+        state.clone(),
+        context.session_id,
+        context.host.clone(),
+    ));
+
+    match handler.await {
+        Ok(response) => response,
+        Err(e) => {
+            error!(target: "error_logger", "[{}] Request handler failed: {}", context.request_id, e);
+            crate::error_page::error_response(
+                &state,
+                &context.host,
+                HttpCode::InternalServerError,
+                &context.request_id,
+            )
+            .await
+        }
+    }
+}
+
+/// Runs the `before` hooks in order until one of them answers the request.
+///
+/// # Returns
+/// The number of middleware whose `before` hook ran and the response if the request was answered.
+fn run_before(
+    middleware: &[&dyn Middleware],
+    request: &mut Request,
+    context: &mut Context,
+) -> (usize, Option<Response>) {
+    for (i, m) in middleware.iter().enumerate() {
+        if let Some(response) = m.before(request, context) {
+            return (i + 1, Some(response));
+        }
+    }
+    (middleware.len(), None)
+}
+
+/// Runs the `after` hooks in reverse order.
+fn run_after(middleware: &[&dyn Middleware], context: &Context, response: &mut Response) {
+    for m in middleware.iter().rev() {
+        m.after(context, response);
+    }
+}
+
+/// Echoes the request ID to the client in the `X-Request-ID` header.
+pub struct RequestId;
+
+impl Middleware for RequestId {
+    fn name(&self) -> &'static str {
+        "request_id"
+    }
+
+    fn after(&self, context: &Context, response: &mut Response) {
+        response.add_header(String::from("X-Request-ID"), context.request_id.clone());
+    }
+}
+
+/// Answers CORS preflight requests and adds the host's security and CORS headers.
+pub struct SecurityHeaders;
+
+impl Middleware for SecurityHeaders {
+    fn name(&self) -> &'static str {
+        "security_headers"
+    }
+
+    fn before(&self, request: &mut Request, context: &mut Context) -> Option<Response> {
+        let response: Response = crate::security::preflight(&context.host, request)?;
+        // the preflight response is not a response to the origin's actual request
+        context.origin = None;
+        Some(response)
+    }
+
+    fn after(&self, context: &Context, response: &mut Response) {
+        crate::security::apply(
+            &context.host,
+            &context.path,
+            context.origin.as_deref(),
+            response,
+        );
+    }
+}
+
+/// Sets the session cookie on the first response of a new session.
+pub struct SessionCookie;
+
+impl Middleware for SessionCookie {
+    fn name(&self) -> &'static str {
+        "session"
+    }
+
+    fn after(&self, context: &Context, response: &mut Response) {
+        if context.new_session {
+            response.add_header(
+                String::from("Set-Cookie"),
+                format!("session_id={}", context.session_id),
+            );
+        }
+    }
+}
+
+/// Gzip compresses responses for clients that accept it.
+pub struct Compression;
+
+impl Middleware for Compression {
+    fn name(&self) -> &'static str {
+        "compression"
+    }
+
+    fn before(&self, request: &mut Request, context: &mut Context) -> Option<Response> {
+        context.compression = request.is_compression_supported();
+        None
+    }
+
+    fn after(&self, context: &Context, response: &mut Response) {
+        response.add_compression(context.compression && !response.body.is_empty());
+    }
+}
+
+/// Rejects state-changing requests without the session's CSRF token and adds the token to the
+/// HTML pages served to the session.
+pub struct Csrf;
+
+impl Middleware for Csrf {
+    fn name(&self) -> &'static str {
+        "csrf"
+    }
+
+    fn before(&self, request: &mut Request, context: &mut Context) -> Option<Response> {
+        if !crate::csrf::requires_token(&request.method)
+            || crate::csrf::verify(request, &context.csrf_token)
+        {
+            return None;
+        }
+
+        error!(target: "error_logger", "[{}] Missing or invalid CSRF token for {} {}", request.request_id, request.method, request.uri);
+        Some(
+            Response::new(
+                Protocol::Http,
+                HttpCode::Forbidden,
+                ContentType::Text,
+                false,
+            )
+            .body(b"Invalid CSRF token".to_vec()),
+        )
+    }
+
+    fn after(&self, context: &Context, response: &mut Response) {
+        if matches!(response.content_type, ContentType::Html) {
+            response.body = crate::csrf::inject(&response.body, &context.csrf_token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HttpMethod;
+    use std::path::Path;
+
+    struct Deny;
+
+    impl Middleware for Deny {
+        fn name(&self) -> &'static str {
+            "deny"
+        }
+
+        fn before(&self, _request: &mut Request, _context: &mut Context) -> Option<Response> {
+            Some(Response::new(
+                Protocol::Http,
+                HttpCode::Teapot,
+                ContentType::Text,
+                false,
+            ))
+        }
+    }
+
+    fn context(host: VirtualHost) -> Context {
+        Context {
+            host: Arc::new(host),
+            session_id: Uuid::new_v4(),
+            new_session: true,
+            request_id: String::from("42"),
+            path: String::from("/"),
+            origin: None,
+            csrf_token: String::from("token"),
+            compression: false,
+        }
+    }
+
+    fn request() -> Request {
+        Request {
+            request_id: String::from("42"),
+            traceparent: None,
+            client_ip: String::from("127.0.0.1:50000"),
+            headers: Vec::new(),
+            body: String::new(),
+            multipart: None,
+            method: HttpMethod::GET,
+            uri: String::from("/"),
+        }
+    }
+
+    fn has_header(response: &Response, title: &str) -> bool {
+        response.headers.iter().any(|h| h.title == title)
+    }
+
+    #[test]
+    fn test_short_circuit_runs_outer_after_hooks() {
+        let pipeline = Pipeline::new()
+            .with(RequestId)
+            .with(Deny)
+            .with(SessionCookie);
+        let mut context = context(VirtualHost::new("localhost", Path::new("static")));
+
+        let enabled = pipeline.enabled(&context);
+        let (ran, response) = run_before(&enabled, &mut request(), &mut context);
+        let mut response = response.expect("the request should be answered by a middleware");
+        run_after(&enabled[..ran], &context, &mut response);
+
+        assert_eq!(response.code.status_code(), 418);
+        assert!(has_header(&response, "X-Request-ID"));
+        assert!(!has_header(&response, "Set-Cookie"));
+    }
+
+    #[test]
+    fn test_middleware_disabled_for_route() {
+        let pipeline = Pipeline::new().with(Deny).with(RequestId);
+        let mut host = VirtualHost::new("localhost", Path::new("static"));
+        host.disabled_middleware
+            .insert(String::from("/"), vec![String::from("deny")]);
+
+        let context = context(host);
+        let names: Vec<&str> = pipeline
+            .enabled(&context)
+            .iter()
+            .map(|m| m.name())
+            .collect();
+        assert_eq!(names, vec!["request_id"]);
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::time::{Duration, Instant};
 
 use log::{error, info};
 use redis::Commands;
@@ -38,30 +40,100 @@ pub fn set_up_redis() -> Result<redis::Connection, Box<dyn std::error::Error>> {
     Ok(client.get_connection()?)
 }
 
+/// Where pages are cached.
+pub enum PageCache {
+    Redis(redis::Connection),
+    /// Keeps pages in the process with their expiry time, for tests and running without Redis.
+    Memory(HashMap<String, (Instant, Vec<u8>)>),
+}
+
+impl PageCache {
+    pub fn memory() -> Self {
+        PageCache::Memory(HashMap::new())
+    }
+
+    /// Returns the page cached under `key`, `None` if there is none, or why the cache could not
+    /// be read.
+    fn get(&mut self, key: &str) -> redis::RedisResult<Option<Vec<u8>>> {
+        match self {
+            PageCache::Redis(connection) => Ok(connection
+                .get::<_, Option<String>>(key)?
+                .map(|content| content.into_bytes())),
+            PageCache::Memory(pages) => Ok(match pages.get(key) {
+                Some((expires, content)) if *expires > Instant::now() => Some(content.clone()),
+                _ => None,
+            }),
+        }
+    }
+
+    fn set_ex(&mut self, key: &str, content: &str, seconds: u64) -> redis::RedisResult<()> {
+        match self {
+            PageCache::Redis(connection) => connection.set_ex(key, content, seconds),
+            PageCache::Memory(pages) => {
+                let expires: Instant = Instant::now() + Duration::from_secs(seconds);
+                pages.insert(key.to_string(), (expires, content.as_bytes().to_vec()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks the cache is reachable.
+    pub fn ping(&mut self) -> redis::RedisResult<String> {
+        match self {
+            PageCache::Redis(connection) => redis::cmd("PING").query(connection),
+            PageCache::Memory(_) => Ok(String::from("PONG")),
+        }
+    }
+
+    /// Removes every cached page.
+    pub fn flush(&mut self) -> redis::RedisResult<()> {
+        match self {
+            // Redis only holds cached pages, so the whole database is the page cache
+            PageCache::Redis(connection) => redis::cmd("FLUSHDB").query(connection),
+            PageCache::Memory(pages) => {
+                pages.clear();
+                Ok(())
+            }
+        }
+    }
+}
+
 // caches a files content and returns the string
-pub async fn read_and_cache_page(
-    redis_connection: &mut redis::Connection,
-    path: &Path,
-    route_name: &str,
-) -> Vec<u8> {
+pub async fn read_and_cache_page(cache: &mut PageCache, path: &Path, route_name: &str) -> Vec<u8> {
     let content: String = match fs::read_to_string(path.to_path_buf()).await {
         Ok(content) => content,
-        Err(_) => fs::read_to_string("static/404.html")
-            .await
-            .expect("404 Not Found"),
+        Err(_) => match fs::read_to_string("static/404.html").await {
+            Ok(content) => content,
+            Err(e) => {
+                error!(target:"error_logger","Failed to read {:?}: {}", path, e);
+                String::new()
+            }
+        },
     };
 
     // set for 10 minuets
-    let _: () = redis_connection.set_ex(route_name, &content, 6).unwrap();
+    if let Err(e) = cache.set_ex(route_name, &content, 6) {
+        error!(target:"error_logger","Failed to cache {}: {}", route_name, e);
+    }
     content.as_bytes().to_vec()
 }
 
-pub async fn get_cached_content(
-    redis_connection: &mut redis::Connection,
-    route_name: &str,
-) -> Option<Vec<u8>> {
-    match redis_connection.get::<_, String>(route_name) {
-        Ok(content) => Some(content.as_bytes().to_vec()),
-        Err(_) => None,
+/// Returns the page cached for `route_name`. A cache that cannot be read is treated like a miss,
+/// but counted separately so an unreachable Redis does not look like a cold cache.
+pub async fn get_cached_content(cache: &mut PageCache, route_name: &str) -> Option<Vec<u8>> {
+    match cache.get(route_name) {
+        Ok(Some(content)) => {
+            crate::metrics::metrics().cache_hits.inc();
+            Some(content)
+        }
+        Ok(None) => {
+            crate::metrics::metrics().cache_misses.inc();
+            None
+        }
+        Err(e) => {
+            error!(target:"error_logger","Failed to read {} from the page cache: {}", route_name, e);
+            crate::metrics::metrics().cache_errors.inc();
+            None
+        }
     }
 }
@@ -1,3 +1,4 @@
+use crate::trace::TraceParent;
 use crate::ErrorType;
 use colored::Colorize;
 use core::str;
@@ -49,7 +50,7 @@ pub struct Header {
 
 impl Display for Header {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} : {}", self.title, self.value)
+        write!(f, "{}: {}", self.title, self.value)
     }
 }
 
@@ -58,6 +59,7 @@ pub enum ContentType {
     Text,
     Html,
     Json,
+    EventStream,
 }
 
 impl Display for ContentType {
@@ -66,15 +68,19 @@ impl Display for ContentType {
             ContentType::Text => write!(f, "text/plain"),
             ContentType::Html => write!(f, "text/html"),
             ContentType::Json => write!(f, "application/json"),
+            ContentType::EventStream => write!(f, "text/event-stream"),
         }
     }
 }
 
 pub struct Request {
-    pub request_id: i64,
+    pub request_id: String,
+    pub traceparent: Option<TraceParent>,
     pub client_ip: String,
     pub headers: Vec<String>,
     pub body: String,
+    /// The decoded fields and uploaded files of a `multipart/form-data` body.
+    pub multipart: Option<crate::body::Multipart>,
     pub method: HttpMethod,
     pub uri: String,
 }
@@ -95,14 +101,20 @@ impl Request {
         println!("{}{}", self.method.to_string().magenta(), self.uri.cyan());
     }
 
-    pub fn new(buffer: &[u8], client_ip: String, request_id: i64) -> Result<Request, ErrorType> {
+    pub fn new(buffer: &[u8], client_ip: String) -> Result<Request, ErrorType> {
         // unwrap is safe as request has been parsed for any issues before this is called
         let request = String::from_utf8(buffer.to_vec()).unwrap();
 
-        // split the request by line
-        let request: Vec<&str> = request.lines().collect();
+        // the body is everything after the blank line that ends the headers
+        let (head, body): (&str, String) = match request.split_once("\r\n\r\n") {
+            Some((head, body)) => (head, body.to_string()),
+            None => (request.as_str(), String::new()),
+        };
 
-        if request.len() < 3 {
+        // split the request head by line
+        let request: Vec<&str> = head.lines().collect();
+
+        if request.len() < 2 {
             error!(target: "error_logger","Recieved invalid request");
             return Err(ErrorType::ConnectionError(String::from("Invalid request")));
         }
@@ -117,32 +129,44 @@ impl Request {
             uri = "/".to_string();
         }
 
-        // headers are the rest of the
-        let mut headers: Vec<String> = Vec::with_capacity(request.len() - 1);
-        let mut body: String = String::new();
-        let mut flag = false;
-        for line in &request[1..] {
-            if line.is_empty() {
-                flag = true;
-                continue;
-            }
-            if flag {
-                body.push_str(line);
-            } else {
-                headers.push(line.to_string());
-            }
-        }
+        // headers are the rest of the head
+        let headers: Vec<String> = request[1..]
+            .iter()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_string())
+            .collect();
+
+        // Accept the request ID and trace context sent by the client or a load balancer
+        let traceparent: Option<TraceParent> =
+            find_header(&headers, "traceparent").and_then(TraceParent::parse);
+        let request_id: String =
+            crate::trace::request_id(find_header(&headers, "X-Request-ID"), traceparent.as_ref());
 
         Ok(Request {
             request_id,
+            traceparent,
             client_ip,
             headers,
             body,
+            multipart: None,
             method,
             uri,
         })
     }
 
+    /// Returns the value of the first header called `name`, ignoring case.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Returns the request path without the query string.
+    pub fn path(&self) -> &str {
+        match self.uri.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.uri,
+        }
+    }
+
     pub fn is_compression_supported(&self) -> bool {
         for header in &self.headers {
             let header = header.to_lowercase();
@@ -177,31 +201,77 @@ impl Request {
     }
 }
 
+/// Returns the value of the first header in `headers` called `name`, ignoring case.
+fn find_header<'a>(headers: &'a [String], name: &str) -> Option<&'a str> {
+    headers.iter().find_map(|h| match h.split_once(':') {
+        Some((title, value)) if title.trim().eq_ignore_ascii_case(name) => Some(value.trim()),
+        _ => None,
+    })
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum HttpCode {
+    SwitchingProtocols,
     Ok,
     Created,
+    NoContent,
     BadRequest,
     Unauthorized,
+    Forbidden,
     NotFound,
     MethodNotAllowed,
     RequestTimeout,
+    PayloadTooLarge,
+    UnsupportedMediaType,
     Teapot,
+    MisdirectedRequest,
     InternalServerError,
+    ServiceUnavailable,
+}
+
+impl HttpCode {
+    /// Returns the numeric status code.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            HttpCode::SwitchingProtocols => 101,
+            HttpCode::Ok => 200,
+            HttpCode::Created => 201,
+            HttpCode::NoContent => 204,
+            HttpCode::BadRequest => 400,
+            HttpCode::Unauthorized => 401,
+            HttpCode::Forbidden => 403,
+            HttpCode::NotFound => 404,
+            HttpCode::MethodNotAllowed => 405,
+            HttpCode::RequestTimeout => 408,
+            HttpCode::PayloadTooLarge => 413,
+            HttpCode::UnsupportedMediaType => 415,
+            HttpCode::Teapot => 418,
+            HttpCode::MisdirectedRequest => 421,
+            HttpCode::InternalServerError => 500,
+            HttpCode::ServiceUnavailable => 503,
+        }
+    }
 }
 
 impl Display for HttpCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            HttpCode::SwitchingProtocols => write!(f, "101 Switching Protocols"),
             HttpCode::Ok => write!(f, "200 OK"),
             HttpCode::Created => write!(f, "201 Created"),
+            HttpCode::NoContent => write!(f, "204 No Content"),
             HttpCode::BadRequest => write!(f, "400 Bad Request"),
             HttpCode::Unauthorized => write!(f, "401 Unauthorized"),
+            HttpCode::Forbidden => write!(f, "403 Forbidden"),
             HttpCode::NotFound => write!(f, "404 Not Found"),
             HttpCode::MethodNotAllowed => write!(f, "405 Method Not Allowed"),
             HttpCode::RequestTimeout => write!(f, "408 Request Timeout"),
+            HttpCode::PayloadTooLarge => write!(f, "413 Payload Too Large"),
+            HttpCode::UnsupportedMediaType => write!(f, "415 Unsupported Media Type"),
             HttpCode::Teapot => write!(f, "418 I'm a teapot"),
+            HttpCode::MisdirectedRequest => write!(f, "421 Misdirected Request"),
             HttpCode::InternalServerError => write!(f, "500 Internal Server Error"),
+            HttpCode::ServiceUnavailable => write!(f, "503 Service Unavailable"),
         }
     }
 }
@@ -213,11 +283,14 @@ pub enum HttpMethod {
     PUT,
     PATCH,
     DELETE,
+    OPTIONS,
 }
 
 impl HttpMethod {
     pub fn new(method: &str) -> HttpMethod {
-        if method.to_uppercase().contains("GET") {
+        if method.to_uppercase().contains("OPTIONS") {
+            HttpMethod::OPTIONS
+        } else if method.to_uppercase().contains("GET") {
             HttpMethod::GET
         } else if method.to_uppercase().contains("POST") {
             HttpMethod::POST
@@ -239,6 +312,7 @@ impl Display for HttpMethod {
             HttpMethod::PUT => write!(f, "PUT"),
             HttpMethod::PATCH => write!(f, "PATCH"),
             HttpMethod::DELETE => write!(f, "DELETE"),
+            HttpMethod::OPTIONS => write!(f, "OPTIONS"),
         }
     }
 }
@@ -14,8 +14,14 @@ pub struct Response {
     pub body: Vec<u8>,
     pub compression: bool,
     pub headers: Vec<Header>,
+    /// A body sent in chunks as they are produced, used instead of `body` for responses such as
+    /// event streams that stay open.
+    pub stream: Option<BodyStream>,
 }
 
+/// The chunks of a streamed body. The body ends when every sender has been dropped.
+pub type BodyStream = tokio::sync::mpsc::Receiver<Vec<u8>>;
+
 #[allow(async_fn_in_trait)]
 pub trait MyDefault {
     async fn default() -> Self;
@@ -25,7 +31,10 @@ impl MyDefault for Response {
     async fn default() -> Self {
         let mut response = Response::new(Protocol::Http, HttpCode::Ok, ContentType::Html, true);
 
-        response.add_body(read_file_to_bytes("static/index.html").await);
+        match read_file_to_bytes("static/index.html").await {
+            Ok(body) => response.add_body(body),
+            Err(e) => log::error!(target: "error_logger", "{}", e),
+        }
 
         response
     }
@@ -37,21 +46,20 @@ impl Response {
     }
 
     pub fn to_bytes(&mut self) -> Vec<u8> {
+        let body: Vec<u8> = self.encode_body();
+        let mut response: Vec<u8> = self.head_bytes();
+        response.extend_from_slice(&body);
+        response
+    }
+
+    /// Serializes the status line and headers, ending with the blank line before the body.
+    pub fn head_bytes(&mut self) -> Vec<u8> {
         // Response line: HTTP/1.1 <status code>
         let response_line: String = format!("{} {}\r\n", self.protocol, self.code);
 
-        let body: Vec<u8> = if !self.compression {
-            self.body.clone()
-        } else {
-            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-            encoder
-                .write_all(&self.body)
-                .expect("Failed to write body to gzip encoder");
-            encoder.finish().expect("Failed to finish gzip compression")
-        };
-
-        self.add_header(String::from("Content-Length"), body.len().to_string());
-        self.add_header(String::from("Connection"), String::from("keep-alive"));
+        if !self.headers.iter().any(|h| h.title == "Connection") {
+            self.add_header(String::from("Connection"), String::from("keep-alive"));
+        }
 
         let mut headers: Vec<String> = Vec::new();
 
@@ -59,17 +67,50 @@ impl Response {
             headers.push(header.to_string());
         }
 
-        println!("{:?}", headers);
-
         let mut response = Vec::new();
         response.extend_from_slice(response_line.as_bytes());
         response.extend_from_slice(headers.join("\r\n").as_bytes());
         response.extend_from_slice(b"\r\n\r\n");
-        response.extend_from_slice(&body);
 
         response
     }
 
+    /// Compresses the body if compression is on and sets the `Content-Length` header to match.
+    ///
+    /// The body is replaced by its encoded form, so calling this again returns the same bytes.
+    ///
+    /// # Returns
+    /// The body as it is sent to the client.
+    pub fn encode_body(&mut self) -> Vec<u8> {
+        if self.compression {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(&self.body)
+                .expect("Failed to write body to gzip encoder");
+            self.body = encoder.finish().expect("Failed to finish gzip compression");
+            // The Content-Encoding header stays, only the body is already encoded
+            self.compression = false;
+        }
+
+        self.headers.retain(|h| h.title != "Content-Length");
+        // Informational responses such as `101 Switching Protocols` have no body and the length
+        // of a streamed body is not known up front
+        if self.code.status_code() >= 200 && self.stream.is_none() {
+            self.add_header(String::from("Content-Length"), self.body.len().to_string());
+        }
+        self.body.clone()
+    }
+
+    /// Returns the number of body bytes sent, as set by `encode_body`.
+    pub fn content_length(&self) -> usize {
+        self.headers
+            .iter()
+            .rev()
+            .find(|h| h.title == "Content-Length")
+            .and_then(|h| h.value.parse().ok())
+            .unwrap_or(0)
+    }
+
     pub fn add_body(&mut self, body: Vec<u8>) {
         self.body = body;
     }
@@ -119,6 +160,7 @@ impl Response {
             body,
             compression,
             headers,
+            stream: None,
         }
     }
 
@@ -141,28 +183,29 @@ impl Response {
         self
     }
 
+    /// Sends the body from `stream` as it is produced instead of from `body`.
+    pub fn stream(mut self, stream: BodyStream) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
     pub fn compression(mut self, compression: bool) -> Self {
+        self.add_compression(compression);
+        self
+    }
+
+    pub fn add_compression(&mut self, compression: bool) {
         self.compression = compression;
         // add header
         if compression {
             for header in &self.headers {
                 if header.title == "Content-Encoding" {
-                    return self;
+                    return;
                 }
             }
             self.add_header(String::from("Content-Encoding"), String::from("gzip"));
         } else {
-            let mut index: isize = -1;
-            for (i, _) in self.headers.iter().enumerate() {
-                if &self.headers[i].title == "Content-Encoding" {
-                    index = i as isize;
-                }
-            }
-
-            if index > 0 {
-                self.headers.remove(index as usize);
-            }
+            self.headers.retain(|h| h.title != "Content-Encoding");
         }
-        self
     }
 }
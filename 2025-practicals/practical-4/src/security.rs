@@ -0,0 +1,359 @@
+//! Security headers and CORS.
+//!
+//! Every virtual host has a `SecurityPolicy` describing the security headers added to its
+//! responses, with optional overrides for routes that need something different (for example a
+//! page that is allowed to be framed). A host may also have a `CorsPolicy`, which answers
+//! preflight `OPTIONS` requests and marks responses to allowed origins with the
+//! `Access-Control-*` headers.
+use crate::response::Response;
+use crate::vhost::VirtualHost;
+use crate::{ContentType, HttpCode, HttpMethod, Protocol, Request};
+use std::collections::HashMap;
+
+/// The security headers added to a response. A header set to `null` in the hosts file is not
+/// sent.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    /// `Strict-Transport-Security`
+    #[serde(default = "default_hsts")]
+    pub hsts: Option<String>,
+    /// `Content-Security-Policy`
+    #[serde(default = "default_content_security_policy")]
+    pub content_security_policy: Option<String>,
+    /// Sends `X-Content-Type-Options: nosniff` when true.
+    #[serde(default = "default_true")]
+    pub nosniff: bool,
+    /// `Referrer-Policy`
+    #[serde(default = "default_referrer_policy")]
+    pub referrer_policy: Option<String>,
+    /// `X-Frame-Options`
+    #[serde(default = "default_frame_options")]
+    pub frame_options: Option<String>,
+}
+
+fn default_hsts() -> Option<String> {
+    Some(String::from("max-age=31536000; includeSubDomains"))
+}
+
+// The bundled pages use inline scripts and styles
+fn default_content_security_policy() -> Option<String> {
+    Some(String::from(
+        "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; frame-ancestors 'none'",
+    ))
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_referrer_policy() -> Option<String> {
+    Some(String::from("strict-origin-when-cross-origin"))
+}
+
+fn default_frame_options() -> Option<String> {
+    Some(String::from("DENY"))
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders {
+            hsts: default_hsts(),
+            content_security_policy: default_content_security_policy(),
+            nosniff: true,
+            referrer_policy: default_referrer_policy(),
+            frame_options: default_frame_options(),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    fn apply(&self, response: &mut Response) {
+        let headers: [(&str, Option<&str>); 5] = [
+            ("Strict-Transport-Security", self.hsts.as_deref()),
+            (
+                "Content-Security-Policy",
+                self.content_security_policy.as_deref(),
+            ),
+            (
+                "X-Content-Type-Options",
+                Some("nosniff").filter(|_| self.nosniff),
+            ),
+            ("Referrer-Policy", self.referrer_policy.as_deref()),
+            ("X-Frame-Options", self.frame_options.as_deref()),
+        ];
+
+        for (title, value) in headers {
+            if let Some(value) = value {
+                set_header(response, title, value);
+            }
+        }
+    }
+}
+
+/// The security headers of a virtual host.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct SecurityPolicy {
+    /// The headers sent with every response.
+    #[serde(default)]
+    pub headers: SecurityHeaders,
+    /// Replacement headers for routes, keyed by path prefix. The longest matching prefix wins.
+    #[serde(default)]
+    pub routes: HashMap<String, SecurityHeaders>,
+}
+
+impl SecurityPolicy {
+    /// Returns the headers used for `path`.
+    pub fn headers_for(&self, path: &str) -> &SecurityHeaders {
+        self.routes
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, headers)| headers)
+            .unwrap_or(&self.headers)
+    }
+}
+
+/// The cross-origin requests a virtual host accepts.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CorsPolicy {
+    /// Origins allowed to call the host, e.g. `https://example.com`. `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default = "default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts on the calling origin may read.
+    #[serde(default = "default_expose_headers")]
+    pub expose_headers: Vec<String>,
+    /// Allows cookies to be sent with cross-origin requests.
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// How long in seconds a browser may cache a preflight response.
+    #[serde(default = "default_max_age")]
+    pub max_age: u64,
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    ["GET", "POST"].iter().map(|m| m.to_string()).collect()
+}
+
+fn default_allowed_headers() -> Vec<String> {
+    ["Content-Type", "X-Request-ID", crate::csrf::CSRF_HEADER]
+        .iter()
+        .map(|h| h.to_string())
+        .collect()
+}
+
+fn default_expose_headers() -> Vec<String> {
+    vec![String::from("X-Request-ID")]
+}
+
+fn default_max_age() -> u64 {
+    600
+}
+
+impl CorsPolicy {
+    /// Returns true if requests from `origin` are allowed.
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|o| o == "*" || o.eq_ignore_ascii_case(origin))
+    }
+
+    /// The value of `Access-Control-Allow-Origin` for an allowed origin. A wildcard cannot be
+    /// combined with credentials so the origin is echoed instead.
+    fn allow_origin_value(&self, origin: &str) -> String {
+        if self.allowed_origins.iter().any(|o| o == "*") && !self.allow_credentials {
+            String::from("*")
+        } else {
+            origin.to_string()
+        }
+    }
+
+    /// Answers a preflight request.
+    ///
+    /// # Arguments
+    /// - `origin`: The value of the `Origin` header.
+    /// - `method`: The value of the `Access-Control-Request-Method` header.
+    /// - `headers`: The value of the `Access-Control-Request-Headers` header, if any.
+    ///
+    /// # Returns
+    /// `204 No Content` with the `Access-Control-*` headers if the request is allowed, otherwise
+    /// `403 Forbidden`.
+    pub fn preflight(&self, origin: &str, method: &str, headers: Option<&str>) -> Response {
+        let method_allowed: bool = self
+            .allowed_methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method.trim()));
+        let headers_allowed: bool = headers
+            .unwrap_or("")
+            .split(',')
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .all(|h| {
+                self.allowed_headers
+                    .iter()
+                    .any(|a| a.eq_ignore_ascii_case(h))
+            });
+
+        if !self.allows_origin(origin) || !method_allowed || !headers_allowed {
+            return Response::new(
+                Protocol::Http,
+                HttpCode::Forbidden,
+                ContentType::Text,
+                false,
+            )
+            .body(b"CORS request not allowed".to_vec());
+        }
+
+        let mut response = Response::new(
+            Protocol::Http,
+            HttpCode::NoContent,
+            ContentType::Text,
+            false,
+        );
+        response.add_header(
+            String::from("Access-Control-Allow-Origin"),
+            self.allow_origin_value(origin),
+        );
+        response.add_header(
+            String::from("Access-Control-Allow-Methods"),
+            self.allowed_methods.join(", "),
+        );
+        if !self.allowed_headers.is_empty() {
+            response.add_header(
+                String::from("Access-Control-Allow-Headers"),
+                self.allowed_headers.join(", "),
+            );
+        }
+        if self.allow_credentials {
+            response.add_header(
+                String::from("Access-Control-Allow-Credentials"),
+                String::from("true"),
+            );
+        }
+        response.add_header(
+            String::from("Access-Control-Max-Age"),
+            self.max_age.to_string(),
+        );
+        response.add_header(String::from("Vary"), String::from("Origin"));
+        response
+    }
+
+    /// Adds the CORS headers to the response of an actual cross-origin request.
+    pub fn apply(&self, origin: &str, response: &mut Response) {
+        if !self.allows_origin(origin) {
+            return;
+        }
+
+        set_header(
+            response,
+            "Access-Control-Allow-Origin",
+            &self.allow_origin_value(origin),
+        );
+        if !self.expose_headers.is_empty() {
+            set_header(
+                response,
+                "Access-Control-Expose-Headers",
+                &self.expose_headers.join(", "),
+            );
+        }
+        if self.allow_credentials {
+            set_header(response, "Access-Control-Allow-Credentials", "true");
+        }
+        set_header(response, "Vary", "Origin");
+    }
+}
+
+/// Returns the preflight response for `request` if it is a CORS preflight to a host with a CORS
+/// policy.
+pub fn preflight(host: &VirtualHost, request: &Request) -> Option<Response> {
+    if request.method != HttpMethod::OPTIONS {
+        return None;
+    }
+
+    let cors: &CorsPolicy = host.cors.as_ref()?;
+    let origin: &str = request.get_header("Origin")?;
+    let method: &str = request.get_header("Access-Control-Request-Method")?;
+
+    Some(cors.preflight(
+        origin,
+        method,
+        request.get_header("Access-Control-Request-Headers"),
+    ))
+}
+
+/// Adds the security headers for `path` and any CORS headers to a response.
+///
+/// # Arguments
+/// - `host`: The virtual host that served the request.
+/// - `path`: The request path without the query string.
+/// - `origin`: The value of the request's `Origin` header, if any.
+/// - `response`: The response about to be written.
+pub fn apply(host: &VirtualHost, path: &str, origin: Option<&str>, response: &mut Response) {
+    host.security.headers_for(path).apply(response);
+
+    if let (Some(cors), Some(origin)) = (&host.cors, origin) {
+        cors.apply(origin, response);
+    }
+}
+
+/// Replaces any existing header called `title`.
+fn set_header(response: &mut Response, title: &str, value: &str) {
+    response
+        .headers
+        .retain(|h| !h.title.eq_ignore_ascii_case(title));
+    response.add_header(title.to_string(), value.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors() -> CorsPolicy {
+        serde_json::from_str(r#"{ "allowed_origins": ["https://app.example"] }"#).unwrap()
+    }
+
+    fn header<'a>(response: &'a Response, title: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|h| h.title == title)
+            .map(|h| h.value.as_str())
+    }
+
+    #[test]
+    fn test_preflight() {
+        let allowed = cors().preflight(
+            "https://app.example",
+            "POST",
+            Some("content-type, x-csrf-token"),
+        );
+        assert_eq!(allowed.code.status_code(), 204);
+        assert_eq!(
+            header(&allowed, "Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+
+        let origin = cors().preflight("https://evil.example", "POST", None);
+        assert_eq!(origin.code.status_code(), 403);
+        let method = cors().preflight("https://app.example", "DELETE", None);
+        assert_eq!(method.code.status_code(), 403);
+    }
+
+    #[test]
+    fn test_route_headers_override_defaults() {
+        let policy: SecurityPolicy = serde_json::from_str(
+            r#"{ "routes": { "/embed": { "frame_options": "SAMEORIGIN", "hsts": null } } }"#,
+        )
+        .unwrap();
+
+        let mut response = Response::new(Protocol::Http, HttpCode::Ok, ContentType::Html, false);
+        policy.headers_for("/embed/clock").apply(&mut response);
+        assert_eq!(header(&response, "X-Frame-Options"), Some("SAMEORIGIN"));
+        assert_eq!(header(&response, "Strict-Transport-Security"), None);
+        assert_eq!(header(&response, "X-Content-Type-Options"), Some("nosniff"));
+
+        assert_eq!(policy.headers_for("/"), &SecurityHeaders::default());
+    }
+}
@@ -1,4 +1,4 @@
-//! A Secure, multi-threaded HTTP/1.1 server implementation using TLS for secure communication.
+//! A Secure, multi-threaded HTTP/1.1 and HTTP/2 server implementation using TLS for secure communication.
 //! This server demonstrates concurrency management, secure password storage and itergration with
 //! external services like PostgreSQL and Redis.
 use colored::Colorize;
@@ -6,34 +6,56 @@ use std::path::Path;
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{Mutex, Notify, Semaphore};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
 
 /// The default port used by the server if none is specified.
 const DEFAULT_PORT: u16 = 7878;
 
-/// Shared state structure holding connecions to Redis, PostgreSQL and a logical clock.
+/// The most connections served at once.
+pub const MAX_CONNECTIONS: u32 = 15;
+/// The most WebSocket and event stream connections open at once. They have their own limit so
+/// that long-lived streams do not take the permits of ordinary requests.
+pub const MAX_STREAMS: u32 = 100;
+
+/// Shared state structure holding the page cache, the SQLite connection and a logical clock.
 pub struct SharedState {
-    pub(crate) redis_connection: redis::Connection,
+    pub(crate) cache: crate::redis_connection::PageCache,
     pub(crate) conn: rusqlite::Connection,
     pub(crate) clock: crate::Clock,
     pub(crate) user_states: std::collections::HashMap<uuid::Uuid, UserState>,
+    pub(crate) templates: Arc<templates::Templates>,
+    /// The topics streamed to clients, such as the phonebook changes.
+    pub(crate) events: Arc<crate::sse::Hub>,
 }
 
 impl SharedState {
     /// Creates a new `SharedState` instance with the appropriate connections and initializations.
     pub fn new(
-        redis_connection: redis::Connection,
+        cache: crate::redis_connection::PageCache,
         clock: crate::Clock,
         conn: rusqlite::Connection,
     ) -> Self {
         SharedState {
-            redis_connection,
+            cache,
             conn,
             clock,
             user_states: std::collections::HashMap::new(),
+            templates: Arc::new(templates::Templates::new(templates::TEMPLATE_DIR)),
+            events: Arc::new(crate::sse::Hub::new()),
         }
     }
 
+    /// Creates a state with an in-memory SQLite database and page cache, as used by tests.
+    pub fn in_memory() -> rusqlite::Result<Self> {
+        let conn = rusqlite::Connection::open_in_memory()?;
+        create_tables(&conn)?;
+        Ok(SharedState::new(
+            crate::redis_connection::PageCache::memory(),
+            crate::Clock::new(),
+            conn,
+        ))
+    }
+
     // Insert a new user session for session management
     pub fn insert_user(&mut self, session_id: uuid::Uuid) {
         self.user_states.insert(session_id, UserState::new());
@@ -46,32 +68,47 @@ impl SharedState {
 
     /// Retrieves the cached content for a given route name from Redis.
     pub async fn get_cached_content(&mut self, route_name: &str) -> Option<Vec<u8>> {
-        crate::redis_connection::get_cached_content(&mut self.redis_connection, route_name).await
+        crate::redis_connection::get_cached_content(&mut self.cache, route_name).await
     }
 
     /// Reads and caches a page in Redis based on the path and route name.
     pub async fn read_and_cache_page(&mut self, path: &Path, route_name: &str) -> Vec<u8> {
-        crate::redis_connection::read_and_cache_page(&mut self.redis_connection, path, route_name)
-            .await
+        crate::redis_connection::read_and_cache_page(&mut self.cache, path, route_name).await
     }
 
     pub fn add_friend(&mut self, name: &str, number: &str) -> rusqlite::Result<()> {
-        self.conn.execute(
-            "INSERT INTO friends (name, number) VALUES (?1, ?2);",
-            rusqlite::params![name, number],
-        )?;
+        self.conn
+            .execute(
+                "INSERT INTO friends (name, number) VALUES (?1, ?2);",
+                rusqlite::params![name, number],
+            )
+            .inspect_err(|_| crate::metrics::metrics().database_errors.inc())?;
+        self.publish_update("add", name, Some(number));
         Ok(())
     }
 
+    /// Changes a friend's number. Clients following the phonebook are only told if the friend
+    /// exists.
     pub fn update_friend(&mut self, name: &str, number: &str) -> rusqlite::Result<()> {
-        self.conn.execute(
-            "UPDATE friends SET number = ?1 WHERE name = ?2;",
-            rusqlite::params![number, name],
-        )?;
+        let changed: usize = self
+            .conn
+            .execute(
+                "UPDATE friends SET number = ?1 WHERE name = ?2;",
+                rusqlite::params![number, name],
+            )
+            .inspect_err(|_| crate::metrics::metrics().database_errors.inc())?;
+        if changed > 0 {
+            self.publish_update("update", name, Some(number));
+        }
         Ok(())
     }
 
     pub fn get_friend(&self, name: &str) -> rusqlite::Result<Option<crate::api::Friend>> {
+        self.query_friend(name)
+            .inspect_err(|_| crate::metrics::metrics().database_errors.inc())
+    }
+
+    fn query_friend(&self, name: &str) -> rusqlite::Result<Option<crate::api::Friend>> {
         let mut stmt = self
             .conn
             .prepare("SELECT number FROM friends WHERE name = ?1;")?;
@@ -88,14 +125,28 @@ impl SharedState {
         }
     }
 
+    /// Removes a friend. Clients following the phonebook are only told if the friend existed.
     pub fn delete_friend(&self, name: &str) -> rusqlite::Result<()> {
-        self.conn.execute(
-            "DELETE FROM friends WHERE name = ?1;",
-            rusqlite::params![name],
-        )?;
+        let deleted: usize = self
+            .conn
+            .execute(
+                "DELETE FROM friends WHERE name = ?1;",
+                rusqlite::params![name],
+            )
+            .inspect_err(|_| crate::metrics::metrics().database_errors.inc())?;
+        if deleted > 0 {
+            self.publish_update("delete", name, None);
+        }
         Ok(())
     }
 
+    /// Publishes a phonebook change to the clients following the phonebook.
+    fn publish_update(&self, action: &str, name: &str, number: Option<&str>) {
+        let update = serde_json::json!({ "action": action, "name": name, "number": number });
+        self.events
+            .publish(crate::sse::PHONEBOOK_TOPIC, None, &update.to_string());
+    }
+
     pub(crate) fn get_all_friends(&self) -> Vec<crate::api::Friend> {
         let mut stmt = self
             .conn
@@ -122,6 +173,19 @@ impl SharedState {
     }
 }
 
+/// Creates the tables of the database if they do not exist already.
+pub fn create_tables(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS friends (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            number TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
 /// Sets up the server by initializing the connections and configuring logging.
 ///
 /// # Returns
@@ -147,18 +211,11 @@ pub async fn set_up_server() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Create the `friends` table if it doesn't exist already
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS friends (
-            id TEXT PRIMARY KEY AUTOINCREMENT,
-            name TEXT NOT NULL,
-            number TEXT NOT NULL
-        )",
-        [],
-    )?;
+    create_tables(&conn)?;
 
-    let state: Arc<Mutex<SharedState>> = Arc::new(Mutex::new(SharedState::new(
+    let state: SharedState = SharedState::new(
         match crate::redis_connection::set_up_redis() {
-            Ok(c) => c,
+            Ok(c) => crate::redis_connection::PageCache::Redis(c),
             _ => {
                 log::error!(target:"error_logger","Failed to set up REDIS connection");
                 std::process::exit(1);
@@ -166,7 +223,7 @@ pub async fn set_up_server() -> Result<(), Box<dyn std::error::Error>> {
         },
         crate::Clock::new(),
         conn,
-    )));
+    );
 
     println!("{}{}", ">> ".red().bold(), "Redis working: ".cyan(),);
 
@@ -177,89 +234,259 @@ pub async fn set_up_server() -> Result<(), Box<dyn std::error::Error>> {
 
     // Setup logging
     log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
+    crate::error_page::log_panics();
+
+    // Load the virtual hosts served by this process
+    let files = crate::control::ConfigFiles::default();
+    let hosts: crate::vhost::HostTable = crate::vhost::HostTable::load(&files.hosts)?;
+
+    // Seup up TLS for connection
+    let tls_config =
+        match crate::socket::connection::load_tls_config(&files.certificate, &files.key).await {
+            Ok(c) => c,
+            Err(_) => std::process::exit(1),
+        };
+
+    // The admin listener serving metrics, health checks and the admin API runs on its own port
+    let admin_port: u16 = match std::env::args().nth(2).map(|p| p.parse()) {
+        Some(Ok(p)) => p,
+        _ => crate::admin::DEFAULT_ADMIN_PORT,
+    };
+    let admin_token: Option<String> = std::env::var(crate::admin::ADMIN_TOKEN_VAR)
+        .ok()
+        .filter(|t| !t.is_empty());
+
+    let config = ServerConfig::new(hosts, tls_config)
+        .config_files(Some(files))
+        .port(port)
+        .admin_port(Some(admin_port))
+        .admin_token(admin_token);
+    let server: Server = Server::bind(config, state).await?;
+
+    // Ctrl+C starts the same graceful shutdown as the admin API
+    let control: Arc<crate::control::Control> = server.control();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            eprintln!("Failed to listen for shutdown signal");
+            log::error!(target: "error_logger", "Failed to listen for shutdonw signal");
+        } else {
+            println!("Recieved shutdown request");
+            control.shutdown();
+        }
+    });
 
     print_server_info(port);
 
     log::info!(target: "request_logger","Server Started");
-    let _ = start_server(port, state).await;
+    server.run().await;
     Ok(())
 }
 
-/// Starts the server, accepting incoming connections and managing their lifecycles.
-///
-/// # Arguments
-/// - `port`: The port the server is running on.
-/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// The configuration of a `Server`.
+pub struct ServerConfig {
+    /// The port of the TLS listener. Port 0 picks a free port.
+    pub port: u16,
+    /// The port of the admin listener on `127.0.0.1`, or `None` to run without one.
+    pub admin_port: Option<u16>,
+    /// The token of the admin API, which is disabled without one.
+    pub admin_token: Option<String>,
+    pub hosts: crate::vhost::HostTable,
+    pub tls: crate::socket::connection::TlsIdentity,
+    /// The files `hosts` and `tls` were loaded from, or `None` if they cannot be reloaded.
+    pub config_files: Option<crate::control::ConfigFiles>,
+    /// The middleware wrapped around the request handlers.
+    pub pipeline: crate::middleware::Pipeline,
+}
+
+impl ServerConfig {
+    /// Creates a configuration using the default ports and the standard middleware pipeline,
+    /// without an admin token or files to reload the configuration from.
+    pub fn new(
+        hosts: crate::vhost::HostTable,
+        tls: crate::socket::connection::TlsIdentity,
+    ) -> Self {
+        ServerConfig {
+            port: DEFAULT_PORT,
+            admin_port: Some(crate::admin::DEFAULT_ADMIN_PORT),
+            admin_token: None,
+            hosts,
+            tls,
+            config_files: None,
+            pipeline: crate::middleware::Pipeline::standard(),
+        }
+    }
+
+    pub fn config_files(mut self, config_files: Option<crate::control::ConfigFiles>) -> Self {
+        self.config_files = config_files;
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn admin_port(mut self, admin_port: Option<u16>) -> Self {
+        self.admin_port = admin_port;
+        self
+    }
+
+    pub fn admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token;
+        self
+    }
+
+    pub fn pipeline(mut self, pipeline: crate::middleware::Pipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+}
+
+/// A server with its listeners bound, ready to accept connections.
 ///
-/// # Returns
-/// A `Result` object with either and Ok(()) or an Err(Box<dyn std::error::Error>)
-async fn start_server(
-    port: u16,
+/// `set_up_server` builds one from the command line, `hosts.json`, `friends.db` and Redis. Tests
+/// can build one with their own configuration, port 0 and `SharedState::in_memory`.
+pub struct Server {
+    listener: TcpListener,
+    admin_listener: Option<TcpListener>,
+    admin_token: Option<String>,
     state: Arc<Mutex<SharedState>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Get the TCP listener from connection module
-    let listener: TcpListener = crate::socket::connection::get_listener(port)?;
+    control: Arc<crate::control::Control>,
+    pipeline: Arc<crate::middleware::Pipeline>,
+}
 
-    // Seup up TLS for connection
-    let tls_config = crate::socket::connection::load_tls_config().await?;
-    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls_config));
+impl Server {
+    /// Binds the TLS listener and, if configured, the admin listener.
+    ///
+    /// # Arguments
+    /// - `config`: The ports, virtual hosts, TLS configuration and middleware of the server.
+    /// - `state`: The state holding the page cache and database the handlers use.
+    ///
+    /// # Returns
+    /// A `Result` object with either the `Server` or an Err(Box<dyn std::error::Error>)
+    pub async fn bind(
+        config: ServerConfig,
+        state: SharedState,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Get the TCP listener from connection module
+        let listener: TcpListener = crate::socket::connection::get_listener(config.port)?;
+        let admin_listener: Option<TcpListener> = match config.admin_port {
+            Some(port) => Some(TcpListener::bind(("127.0.0.1", port)).await?),
+            None => None,
+        };
 
-    // Set up secure shutdown green thread
-    let shutdown: Arc<Notify> = Arc::new(Notify::new());
-    let is_shutting_down = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        Ok(Server {
+            listener,
+            admin_listener,
+            admin_token: config.admin_token,
+            state: Arc::new(Mutex::new(state)),
+            // The configuration that can be reloaded, the open connections and the shutdown signal
+            control: Arc::new(crate::control::Control::new(
+                config.hosts,
+                config.tls,
+                config.config_files,
+            )),
+            pipeline: Arc::new(config.pipeline),
+        })
+    }
 
-    // Semaphore limits concurrent connections to at most 15 (green threads)
-    let connections: Arc<Semaphore> = Arc::new(Semaphore::new(15));
+    /// Returns the address of the TLS listener.
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
 
-    let shutdown_signal = shutdown.clone();
-    let shutdown_flag = is_shutting_down.clone();
+    /// Returns the address of the admin listener, if there is one.
+    pub fn admin_addr(&self) -> Option<std::net::SocketAddr> {
+        self.admin_listener
+            .as_ref()
+            .and_then(|l| l.local_addr().ok())
+    }
 
-    tokio::spawn(async move {
-        if tokio::signal::ctrl_c().await.is_err() {
-            eprintln!("Failed to listen for shutdown signal");
-            log::error!(target: "error_logger", "Failed to listen for shutdonw signal");
-            std::process::exit(1);
-        } else {
-            println!("Recieved shutdown request");
-            println!("Waiting for tasks to finish");
-            shutdown_flag.store(true, std::sync::atomic::Ordering::SeqCst);
-            shutdown_signal.notify_one();
-            println!("Tasks complete, server shutdown started");
-            std::process::exit(0);
+    pub fn state(&self) -> Arc<Mutex<SharedState>> {
+        self.state.clone()
+    }
+
+    pub fn control(&self) -> Arc<crate::control::Control> {
+        self.control.clone()
+    }
+
+    /// Accepts connections until a shutdown is started with `Control::shutdown`.
+    ///
+    /// The server then stops accepting connections and waits for the open ones to finish, for at
+    /// most `control::SHUTDOWN_GRACE_PERIOD`.
+    pub async fn run(self) {
+        let Server {
+            listener,
+            admin_listener,
+            admin_token,
+            state,
+            control,
+            pipeline,
+        } = self;
+
+        let admin: Option<tokio::task::JoinHandle<()>> = admin_listener.map(|admin_listener| {
+            tokio::spawn(crate::admin::run_admin_server(
+                admin_listener,
+                state.clone(),
+                control.clone(),
+                admin_token,
+            ))
+        });
+
+        // Semaphore limits concurrent connections to at most 15 (green threads)
+        let connections: Arc<Semaphore> = Arc::new(Semaphore::new(MAX_CONNECTIONS as usize));
+        let streams: Arc<Semaphore> = Arc::new(Semaphore::new(MAX_STREAMS as usize));
+
+        tokio::select! {
+            _ = run_server(listener,connections.clone(),streams.clone(),control.clone(),state.clone(),pipeline)=> {
+            }
+            _ = control.shutdown_requested() => {
+                    log::info!(target: "request_logger","Server shutdown signal recieved.");
+                    println!("Server shutdown signal recieved.");
+            }
         }
-    });
 
-    tokio::select! {
-        _ = run_server(listener,acceptor,connections.clone(),is_shutting_down.clone(),state.clone())=> {
+        println!("Waiting for tasks to finish");
+        let drained = async {
+            let _ = connections.acquire_many(MAX_CONNECTIONS).await;
+            let _ = streams.acquire_many(MAX_STREAMS).await;
+        };
+        match tokio::time::timeout(crate::control::SHUTDOWN_GRACE_PERIOD, drained).await {
+            Ok(_) => println!("Tasks complete, server shutdown"),
+            Err(_) => {
+                let open: usize = control.connections().len();
+                println!("Closing {} connections left open, server shutdown", open);
+                log::error!(target: "error_logger", "Shut down with {} connections still open", open);
+            }
         }
-        _ = shutdown.notified() => {
-                log::info!(target: "request_logger","Server shutdown signal recieved.");
-                println!("Server shutdown signal recieved.");
+        if let Some(admin) = admin {
+            admin.abort();
         }
+        log::info!(target: "request_logger","Server shutdown complete");
     }
-    while connections.clone().available_permits() != 15 {}
-    Ok(())
 }
 
 /// Accepts connections from incoming clients and completes the TLS hanshake.
 ///
 /// # Arguments
 /// - `listener`: A TcpListener
-/// - `acceptor`: A TlsAcceptor to handle the Tls hanshake.
 /// - `connections`: A Semaphore for limiting the amout of concurrent connections.
-/// - `is_shutdown`: A thread-safe `AtomicBool` to indicate if the server is in shutdown mode.
+/// - `streams`: A Semaphore for limiting the WebSocket and event stream connections.
+/// - `control`: The virtual hosts and TLS acceptor of the server, and whether it is shutting down.
 /// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `pipeline`: The middleware wrapped around the request handlers.
 async fn run_server(
     listener: TcpListener,
-    acceptor: tokio_rustls::TlsAcceptor,
     connections: Arc<Semaphore>,
-    is_shutdown: Arc<std::sync::atomic::AtomicBool>,
+    streams: Arc<Semaphore>,
+    control: Arc<crate::control::Control>,
     state: Arc<Mutex<SharedState>>,
+    pipeline: Arc<crate::middleware::Pipeline>,
 ) {
     loop {
         let connections = connections.clone();
 
-        if Arc::clone(&is_shutdown).load(std::sync::atomic::Ordering::SeqCst) {
+        if control.is_shutting_down() {
             return;
         }
 
@@ -281,22 +508,88 @@ async fn run_server(
             }
         };
 
-        let acceptor = acceptor.clone();
+        // Connections use the hosts and certificate loaded when they were accepted
+        let acceptor = control.acceptor();
+        let hosts = control.hosts();
+        let control = control.clone();
         let state = state.clone();
+        let pipeline = pipeline.clone();
+        let mut permit = ConnectionPermit {
+            permit,
+            streams: streams.clone(),
+        };
+        crate::metrics::metrics().connections_accepted.inc();
 
         // TLS handshake
         let handle = tokio::spawn(async move {
             if let Ok(tls_stream) = acceptor.accept(stream).await {
                 log::info!(target: "request_logger","TLS handshake successful with {}", address);
-                let _ = handle_connection(tls_stream, address.to_string(), state.clone()).await;
+                crate::metrics::metrics().active_connections.inc();
+                let is_http2: bool =
+                    tls_stream.get_ref().1.alpn_protocol() == Some(crate::http2::ALPN);
+                let _connection = control.open_connection(
+                    &address.to_string(),
+                    if is_http2 { "h2" } else { "http/1.1" },
+                );
+                if is_http2 {
+                    let _ = handle_http2_connection(
+                        tls_stream,
+                        address.to_string(),
+                        state.clone(),
+                        hosts,
+                        pipeline,
+                    )
+                    .await;
+                } else {
+                    let _ = handle_connection(
+                        tls_stream,
+                        address.to_string(),
+                        state.clone(),
+                        hosts,
+                        pipeline,
+                        &mut permit,
+                    )
+                    .await;
+                }
+                crate::metrics::metrics().active_connections.dec();
             } else {
+                crate::metrics::metrics().tls_handshake_failures.inc();
                 log::error!(target: "error_logger","TLS handshake failed with {}", address);
             }
 
             drop(permit);
         });
 
-        handle.await.unwrap();
+        // Connections are served concurrently, up to the permits of the semaphore, as WebSocket
+        // and HTTP/2 connections stay open
+        tokio::spawn(async move {
+            if let Err(e) = handle.await {
+                log::error!(target: "error_logger", "Connection task for {} failed: {}", address, e);
+            }
+        });
+    }
+}
+
+/// The semaphore permit a connection holds while it is served.
+struct ConnectionPermit {
+    permit: OwnedSemaphorePermit,
+    streams: Arc<Semaphore>,
+}
+
+impl ConnectionPermit {
+    /// Swaps the connection permit for one of the stream permits, freeing it for ordinary
+    /// requests while the connection stays open as a WebSocket or event stream.
+    ///
+    /// # Returns
+    /// False if the most streams are already open.
+    fn swap_for_stream(&mut self) -> bool {
+        match self.streams.clone().try_acquire_owned() {
+            Ok(permit) => {
+                self.permit = permit;
+                true
+            }
+            Err(_) => false,
+        }
     }
 }
 
@@ -306,6 +599,10 @@ async fn run_server(
 /// - `stream`: A mutable TlsStream.
 /// - `address`: The address of the client connected to the server.
 /// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `hosts`: The virtual hosts served by the server.
+/// - `pipeline`: The middleware wrapped around the request handlers.
+/// - `permit`: The connection's permit, swapped for a stream permit by WebSockets and event
+///   streams.
 ///
 /// # Returns
 /// A `Result` object with either and Ok(()) or an Err(Box<dyn std::error::Error>)
@@ -313,7 +610,11 @@ async fn handle_connection(
     mut stream: tokio_rustls::server::TlsStream<TcpStream>,
     address: String,
     state: Arc<Mutex<SharedState>>,
+    hosts: Arc<crate::vhost::HostTable>,
+    pipeline: Arc<crate::middleware::Pipeline>,
+    permit: &mut ConnectionPermit,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let started: std::time::Instant = std::time::Instant::now();
     let mut request_data: Vec<u8> = Vec::new();
     let mut buffer: [u8; 8192] = [0; 8192];
 
@@ -343,91 +644,403 @@ async fn handle_connection(
         return Ok(());
     }
 
-    // Convert request bytes to a UTF-8 string for header parsing
-    let request_text: &str = match std::str::from_utf8(&request_data) {
-        Ok(text) => text,
+    // Separate the head from any body bytes that arrived with it
+    let head_end: usize = match request_data
+        .windows(4)
+        .position(|w: &[u8]| w == b"\r\n\r\n")
+    {
+        Some(i) => i + 4,
+        None => request_data.len(),
+    };
+    let received_body: Vec<u8> = request_data.split_off(head_end);
+
+    // Check the request head is valid UTF-8 before header parsing
+    if std::str::from_utf8(&request_data).is_err() {
+        log::error!(target: "error_logger", "Received invalid UTF-8 request");
+        return Ok(());
+    }
+
+    let request: crate::Request = match crate::Request::new(&request_data[..], address.clone()) {
+        Ok(r) => r,
         Err(_) => {
-            log::error!(target: "error_logger", "Received invalid UTF-8 request");
+            log::error!(target:"error_logger","Failed to parse incomming request from {}", address);
+            let mut response: crate::response::Response = crate::error_page::error_response(
+                &state,
+                &hosts.default_host(),
+                crate::HttpCode::BadRequest,
+                "",
+            )
+            .await;
+            stream.write_all(&response.to_bytes()).await?;
+            stream.flush().await?;
             return Ok(());
         }
     };
 
-    println!("{}", request_text);
+    let access_log = crate::access_log::AccessLogEntry::new(&request, started);
 
-    let request: crate::Request = match crate::Request::new(
-        &request_data[..],
-        address.clone(),
-        state.lock().await.increment_clock().await,
-    ) {
-        Ok(r) => r,
-        Err(_) => {
-            log::error!(target:"error_logger","Failed to parse incomming request");
-            std::process::exit(1);
+    let host: Arc<crate::vhost::VirtualHost> =
+        match resolve_host(&hosts, &request, stream.get_ref().1.server_name()) {
+            Ok(h) => h,
+            Err(response) => {
+                return write_response(&mut stream, &hosts.default_host(), access_log, response)
+                    .await;
+            }
+        };
+
+    // WebSocket upgrades take over the connection once the handshake is done
+    if crate::websocket::is_upgrade(&request) {
+        if !permit.swap_for_stream() {
+            let response = too_many_streams(&state, &host, &request.request_id).await;
+            return write_response(&mut stream, &host, access_log, response).await;
+        }
+        return handle_websocket(stream, request, host, state, access_log).await;
+    }
+
+    let request_id: String = request.request_id.clone();
+    let mut response: crate::response::Response = serve_request(
+        &mut stream,
+        request,
+        received_body,
+        &host,
+        &state,
+        &pipeline,
+    )
+    .await;
+
+    // Event streams stay open until the client leaves
+    if response.stream.is_some() && !permit.swap_for_stream() {
+        response = too_many_streams(&state, &host, &request_id).await;
+    }
+
+    write_response(&mut stream, &host, access_log, response).await
+}
+
+/// The response to a WebSocket or event stream refused because the most streams are open.
+async fn too_many_streams(
+    state: &Arc<Mutex<SharedState>>,
+    host: &crate::vhost::VirtualHost,
+    request_id: &str,
+) -> crate::response::Response {
+    log::error!(target: "error_logger", "[{}] Refused a stream, {} are open", request_id, MAX_STREAMS);
+    let mut response: crate::response::Response = crate::error_page::error_response(
+        state,
+        host,
+        crate::HttpCode::ServiceUnavailable,
+        request_id,
+    )
+    .await;
+    response.add_header(String::from("X-Request-ID"), request_id.to_string());
+    response
+}
+
+/// Completes a WebSocket handshake and hands the connection to the endpoint's handler.
+///
+/// # Arguments
+/// - `stream`: A mutable TlsStream.
+/// - `request`: The upgrade request.
+/// - `host`: The virtual host serving the request.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `access_log`: The access log entry of the request, completed once the handshake is sent.
+///
+/// # Returns
+/// A `Result` object with either and Ok(()) or an Err(Box<dyn std::error::Error>)
+async fn handle_websocket(
+    mut stream: tokio_rustls::server::TlsStream<TcpStream>,
+    request: crate::Request,
+    host: Arc<crate::vhost::VirtualHost>,
+    state: Arc<Mutex<SharedState>>,
+    access_log: crate::access_log::AccessLogEntry,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let handshake = match crate::websocket::Endpoint::find(&host, request.path()) {
+        Some(endpoint) => crate::websocket::handshake(&request, &host).map(|r| (endpoint, r)),
+        None => Err(crate::HttpCode::NotFound),
+    };
+
+    let (endpoint, mut response) = match handshake {
+        Ok(h) => h,
+        Err(code) => {
+            log::error!(target: "error_logger", "[{}] Rejected WebSocket upgrade to {}: {}", request.request_id, request.uri, code);
+            let mut response: crate::response::Response =
+                crate::error_page::error_response(&state, &host, code, &request.request_id).await;
+            response.add_header(String::from("X-Request-ID"), request.request_id.clone());
+            return write_response(&mut stream, &host, access_log, response).await;
         }
     };
 
-    println!("{}", request);
+    response.add_header(String::from("X-Request-ID"), request.request_id.clone());
+    write_response(&mut stream, &host, access_log, response).await?;
+    log::info!(target: "request_logger", "[{}] WebSocket opened on {}", request.request_id, request.uri);
+
+    let socket = crate::websocket::WebSocket::new(stream);
+    match endpoint.serve(socket, state).await {
+        Ok(()) => {
+            log::info!(target: "request_logger", "[{}] WebSocket closed on {}", request.request_id, request.uri)
+        }
+        Err(e) => {
+            log::error!(target: "error_logger", "[{}] WebSocket on {} failed: {}", request.request_id, request.uri, e)
+        }
+    }
+    Ok(())
+}
+
+/// Serves an HTTP/2 connection, handling each of its streams like an HTTP/1.1 request.
+///
+/// # Arguments
+/// - `stream`: A TlsStream that negotiated `h2`.
+/// - `address`: The address of the client connected to the server.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `hosts`: The virtual hosts served by the server.
+/// - `pipeline`: The middleware wrapped around the request handlers.
+///
+/// # Returns
+/// A `Result` object with either and Ok(()) or an Err(Box<dyn std::error::Error>)
+async fn handle_http2_connection(
+    stream: tokio_rustls::server::TlsStream<TcpStream>,
+    address: String,
+    state: Arc<Mutex<SharedState>>,
+    hosts: Arc<crate::vhost::HostTable>,
+    pipeline: Arc<crate::middleware::Pipeline>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server_name: Option<String> = stream.get_ref().1.server_name().map(str::to_string);
+
+    crate::http2::serve(stream, address, move |request, body| {
+        let state = state.clone();
+        let hosts = hosts.clone();
+        let pipeline = pipeline.clone();
+        let server_name = server_name.clone();
+
+        async move {
+            let access_log =
+                crate::access_log::AccessLogEntry::new(&request, std::time::Instant::now());
+
+            let (host, mut response) = match resolve_host(&hosts, &request, server_name.as_deref())
+            {
+                Ok(host) => {
+                    let response = serve_request(
+                        &mut tokio::io::empty(),
+                        request,
+                        body,
+                        &host,
+                        &state,
+                        &pipeline,
+                    )
+                    .await;
+                    (host, response)
+                }
+                Err(response) => (hosts.default_host(), response),
+            };
+
+            response.encode_body();
+            complete_request(
+                &host,
+                access_log.finish(response.code.status_code(), response.content_length()),
+            );
+            response
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+/// Selects the virtual host of a request using the Host header and the TLS SNI name.
+///
+/// # Returns
+/// The virtual host or the `421 Misdirected Request` response to send instead.
+fn resolve_host(
+    hosts: &crate::vhost::HostTable,
+    request: &crate::Request,
+    server_name: Option<&str>,
+) -> Result<Arc<crate::vhost::VirtualHost>, crate::response::Response> {
+    match hosts.resolve(request.get_header("Host"), server_name) {
+        crate::vhost::HostMatch::Found(h) => Ok(h),
+        crate::vhost::HostMatch::Misdirected => {
+            let mut response = crate::response::Response::new(
+                crate::Protocol::Http,
+                crate::HttpCode::MisdirectedRequest,
+                crate::ContentType::Text,
+                false,
+            )
+            .body(b"Misdirected Request".to_vec());
+            response.add_header(String::from("X-Request-ID"), request.request_id.clone());
+            Err(response)
+        }
+    }
+}
+
+/// Reads the body of a request and runs it through the middleware pipeline.
+///
+/// # Arguments
+/// - `body_stream`: The stream the rest of the body is read from.
+/// - `request`: The parsed request.
+/// - `received_body`: The body bytes already received with the request head.
+/// - `host`: The virtual host serving the request.
+/// - `state`: A shared, thread-safe state used for managing server data and caching.
+/// - `pipeline`: The middleware wrapped around the request handlers.
+///
+/// # Returns
+/// The `Response` to send to the client.
+async fn serve_request<S: tokio::io::AsyncRead + Unpin>(
+    body_stream: &mut S,
+    mut request: crate::Request,
+    received_body: Vec<u8>,
+    host: &Arc<crate::vhost::VirtualHost>,
+    state: &Arc<Mutex<SharedState>>,
+    pipeline: &crate::middleware::Pipeline,
+) -> crate::response::Response {
+    // Read the rest of the body now the host is known
+    if let Err(e) = crate::body::read_body(body_stream, &mut request, received_body).await {
+        log::error!(target: "error_logger", "[{}] Failed to read request body: {}", request.request_id, e);
+        let mut response: crate::response::Response = crate::error_page::error_response(
+            state,
+            host,
+            crate::body::error_code(&e),
+            &request.request_id,
+        )
+        .await;
+        crate::security::apply(
+            host,
+            request.path(),
+            request.get_header("Origin"),
+            &mut response,
+        );
+        response.add_header(String::from("X-Request-ID"), request.request_id.clone());
+        return response;
+    }
 
     // Check if the request contains a "Cookie" header (session management)
-    if let Some(index) = request.headers.iter().position(|r| r.starts_with("Cookie")) {
-        let cookie_value = request
-            .headers
-            .get(index)
-            .unwrap()
-            .split("=")
-            .last()
-            .unwrap();
-
-        let uuid_str = match uuid::Uuid::parse_str(cookie_value) {
-            Ok(uuid) => uuid,
-            Err(_) => {
-                let response = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nBye, World!";
-                stream.write_all(response).await?;
-                stream.flush().await?;
-                return Ok(());
+    let session: Option<uuid::Uuid> =
+        match request.headers.iter().position(|r| r.starts_with("Cookie")) {
+            Some(index) => {
+                let cookie_value = request
+                    .headers
+                    .get(index)
+                    .unwrap()
+                    .split("=")
+                    .last()
+                    .unwrap();
+
+                match uuid::Uuid::parse_str(cookie_value) {
+                    Ok(uuid) => Some(uuid),
+                    Err(_) => return goodbye(&request.request_id),
+                }
             }
+            None => None,
         };
 
-        // Handle "Connection: close" header
-        if request
-            .headers
-            .iter()
-            .any(|h: &String| h == "Connection: close")
-        {
-            let response: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nBye, World!";
-            stream.write_all(response).await?;
-            stream.flush().await?;
-            return Ok(());
+    let (session_id, new_session): (uuid::Uuid, bool) = match session {
+        Some(session_id) => {
+            // Handle "Connection: close" header
+            if request
+                .headers
+                .iter()
+                .any(|h: &String| h == "Connection: close")
+            {
+                return goodbye(&request.request_id);
+            }
+            (session_id, false)
+        }
+        None => {
+            let session_id: uuid::Uuid = uuid::Uuid::new_v4();
+            state.lock().await.insert_user(session_id);
+            (session_id, true)
         }
+    };
 
-        let mut response: crate::response::Response =
-            crate::handle_response(request, state.clone(), uuid_str).await;
+    let mut context: crate::middleware::Context =
+        crate::middleware::Context::new(&request, state, host.clone(), session_id, new_session)
+            .await;
+    pipeline.run(request, state.clone(), &mut context).await
+}
 
-        stream.write_all(&response.to_bytes()).await?;
-        stream.flush().await?;
-    } else {
-        let session_id: uuid::Uuid = uuid::Uuid::new_v4();
-        let user_state: UserState = UserState::new();
-
-        state
-            .lock()
-            .await
-            .user_states
-            .insert(session_id, user_state);
-
-        let mut response: crate::response::Response =
-            crate::handle_response(request, state.clone(), session_id).await;
-
-        response.add_header(
-            String::from("Set-Cookie"),
-            format!("session_id={session_id}"),
-        );
+/// Writes a response to the client and records it once it has been flushed.
+///
+/// # Arguments
+/// - `stream`: The connection to the client.
+/// - `host`: The virtual host that served the request.
+/// - `access_log`: The access log entry of the request.
+/// - `response`: The response to write.
+async fn write_response(
+    stream: &mut tokio_rustls::server::TlsStream<TcpStream>,
+    host: &crate::vhost::VirtualHost,
+    access_log: crate::access_log::AccessLogEntry,
+    mut response: crate::response::Response,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sent: usize = match response.stream.take() {
+        Some(body) => {
+            response.add_header(String::from("Transfer-Encoding"), String::from("chunked"));
+            stream.write_all(&response.head_bytes()).await?;
+            stream.flush().await?;
+            write_chunks(stream, body).await?
+        }
+        None => {
+            stream.write_all(&response.to_bytes()).await?;
+            stream.flush().await?;
+            response.content_length()
+        }
+    };
 
-        stream.write_all(&response.to_bytes()).await?;
+    complete_request(host, access_log.finish(response.code.status_code(), sent));
+    Ok(())
+}
+
+/// Writes a streamed body with chunked transfer encoding until it ends or the client goes away.
+///
+/// # Returns
+/// The number of body bytes written.
+async fn write_chunks(
+    stream: &mut tokio_rustls::server::TlsStream<TcpStream>,
+    mut body: crate::response::BodyStream,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut sent: usize = 0;
+
+    while let Some(chunk) = body.recv().await {
+        if chunk.is_empty() {
+            continue;
+        }
+        stream
+            .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+            .await?;
+        stream.write_all(&chunk).await?;
+        stream.write_all(b"\r\n").await?;
+        // Each chunk is sent straight away, event streams must not wait for a full buffer
         stream.flush().await?;
+        sent += chunk.len();
     }
 
-    Ok(())
+    stream.write_all(b"0\r\n\r\n").await?;
+    stream.flush().await?;
+    Ok(sent)
+}
+
+/// The response for a client that asked to close the connection or whose session cookie is
+/// invalid.
+fn goodbye(request_id: &str) -> crate::response::Response {
+    let mut response = crate::response::Response::new(
+        crate::Protocol::Http,
+        crate::HttpCode::Ok,
+        crate::ContentType::Text,
+        false,
+    )
+    .body(b"Bye, World!".to_vec());
+    response.add_header(String::from("X-Request-ID"), request_id.to_string());
+    response
+}
+
+/// Records metrics for a completed response and writes its access log line.
+///
+/// # Arguments
+/// - `host`: The virtual host that served the request.
+/// - `record`: The access log record of the completed response.
+fn complete_request(host: &crate::vhost::VirtualHost, record: crate::access_log::AccessLogRecord) {
+    let route: &str = record.path.split('?').next().unwrap_or("");
+    crate::metrics::metrics().observe_request(
+        &host.name,
+        route,
+        record.status,
+        std::time::Duration::from_secs_f64(record.duration_ms / 1000.0),
+    );
+    crate::access_log::log(host, record);
 }
 
 /// Prints server information on startup.
@@ -458,6 +1071,13 @@ fn print_server_info(port: u16) {
         "true".red().bold()
     );
 
+    println!(
+        "{}{}{}",
+        ">> ".red().bold(),
+        "HTTP/2: ".cyan(),
+        "true".red().bold()
+    );
+
     println!(
         "{}{}{}",
         ">> ".red().bold(),
@@ -475,6 +1095,14 @@ fn print_server_info(port: u16) {
 pub struct UserState {
     pub(crate) value: f64,
     pub(crate) operator_buffer: std::collections::VecDeque<String>,
+    /// The synchronizer token state-changing requests from this session must carry.
+    pub(crate) csrf_token: String,
+}
+
+impl Default for UserState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UserState {
@@ -482,6 +1110,7 @@ impl UserState {
         UserState {
             value: 0.0,
             operator_buffer: std::collections::VecDeque::new(),
+            csrf_token: crate::csrf::generate_token(),
         }
     }
 
@@ -493,3 +1122,35 @@ impl UserState {
         self.operator_buffer.pop_front()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_changes_are_published() {
+        let mut state = SharedState::in_memory().unwrap();
+
+        // Friends that do not exist change nothing, so nothing is published
+        state.update_friend("Ann", "555 0100").unwrap();
+        state.delete_friend("Ann").unwrap();
+        assert!(state
+            .events
+            .since(crate::sse::PHONEBOOK_TOPIC, 0)
+            .is_empty());
+
+        state.add_friend("Ann", "555 0100").unwrap();
+        state.update_friend("Ann", "555 0101").unwrap();
+        state.delete_friend("Ann").unwrap();
+        let actions: Vec<String> = state
+            .events
+            .since(crate::sse::PHONEBOOK_TOPIC, 0)
+            .iter()
+            .map(|e| {
+                let update: serde_json::Value = serde_json::from_str(&e.data).unwrap();
+                update["action"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(actions, ["add", "update", "delete"]);
+    }
+}
@@ -0,0 +1,262 @@
+//! Server-Sent Events and the broadcast hub behind them.
+//!
+//! Handlers publish events to a topic of the `Hub`, which numbers them and keeps the most recent
+//! ones. `event_stream` answers a request with a `text/event-stream` response that follows a
+//! topic. A client that reconnects sends the ID of the last event it received in `Last-Event-ID`
+//! and is first sent the events it missed, as long as they are still kept. Idle streams get a
+//! heartbeat comment so proxies keep them open and closed connections are noticed.
+use crate::response::Response;
+use crate::{ContentType, HttpCode, Protocol};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+
+/// The topic phonebook changes are published to.
+pub const PHONEBOOK_TOPIC: &str = "phonebook";
+
+/// How many events of a topic are kept for clients that resume.
+const HISTORY_LEN: usize = 100;
+
+/// How often an idle stream is sent a heartbeat comment.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long clients wait before reconnecting, in milliseconds.
+const RETRY_MS: u64 = 3000;
+
+/// An event published to a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Increases by one for every event of a topic, starting at 1.
+    pub id: u64,
+    /// The event type, or `None` for the default `message` type.
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl Event {
+    /// Formats the event for a `text/event-stream` body.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut text: String = format!("id: {}\n", self.id);
+        if let Some(event) = &self.event {
+            text.push_str(&format!("event: {}\n", event));
+        }
+        for line in self.data.split('\n') {
+            text.push_str(&format!("data: {}\n", line));
+        }
+        text.push('\n');
+        text.into_bytes()
+    }
+}
+
+struct Topic {
+    last_id: u64,
+    history: VecDeque<Event>,
+    sender: broadcast::Sender<Event>,
+}
+
+impl Topic {
+    fn new() -> Self {
+        Topic {
+            last_id: 0,
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            sender: broadcast::channel(HISTORY_LEN).0,
+        }
+    }
+
+    fn since(&self, id: u64) -> Vec<Event> {
+        self.history.iter().filter(|e| e.id > id).cloned().collect()
+    }
+}
+
+/// A subscription to a topic.
+pub struct Subscription {
+    /// Kept events published after the `Last-Event-ID` the subscriber sent.
+    pub missed: Vec<Event>,
+    /// The ID of the newest event the subscriber has or is being sent in `missed`.
+    pub last_id: u64,
+    pub receiver: broadcast::Receiver<Event>,
+}
+
+/// Broadcasts events to the subscribers of named topics.
+#[derive(Default)]
+pub struct Hub {
+    topics: std::sync::Mutex<HashMap<String, Topic>>,
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Hub::default()
+    }
+
+    /// Publishes an event to every subscriber of `topic`.
+    ///
+    /// # Arguments
+    /// - `topic`: The topic to publish to, created if it does not exist yet.
+    /// - `event`: The event type, or `None` for the default `message` type.
+    /// - `data`: The event data.
+    ///
+    /// # Returns
+    /// The ID given to the event.
+    pub fn publish(&self, topic: &str, event: Option<&str>, data: &str) -> u64 {
+        let mut topics = self.topics.lock().unwrap_or_else(|e| e.into_inner());
+        let topic: &mut Topic = topics.entry(topic.to_string()).or_insert_with(Topic::new);
+
+        topic.last_id += 1;
+        let event = Event {
+            id: topic.last_id,
+            event: event.map(str::to_string),
+            data: data.to_string(),
+        };
+
+        if topic.history.len() == HISTORY_LEN {
+            topic.history.pop_front();
+        }
+        topic.history.push_back(event.clone());
+        // Sending only fails when nobody is subscribed
+        let _ = topic.sender.send(event);
+        topic.last_id
+    }
+
+    /// Subscribes to `topic`.
+    ///
+    /// # Arguments
+    /// - `topic`: The topic to follow.
+    /// - `last_event_id`: The ID of the last event the subscriber received before, if it is
+    ///   resuming. Unknown IDs, e.g. from before a restart, are ignored.
+    pub fn subscribe(&self, topic: &str, last_event_id: Option<u64>) -> Subscription {
+        let mut topics = self.topics.lock().unwrap_or_else(|e| e.into_inner());
+        let topic: &mut Topic = topics.entry(topic.to_string()).or_insert_with(Topic::new);
+
+        let missed: Vec<Event> = match last_event_id {
+            Some(id) if id < topic.last_id => topic.since(id),
+            _ => Vec::new(),
+        };
+
+        Subscription {
+            missed,
+            last_id: topic.last_id,
+            receiver: topic.sender.subscribe(),
+        }
+    }
+
+    /// Returns the kept events of `topic` published after `id`.
+    pub fn since(&self, topic: &str, id: u64) -> Vec<Event> {
+        let topics = self.topics.lock().unwrap_or_else(|e| e.into_inner());
+        topics.get(topic).map(|t| t.since(id)).unwrap_or_default()
+    }
+}
+
+/// Builds a `text/event-stream` response that follows `topic` until the client disconnects.
+///
+/// # Arguments
+/// - `hub`: The hub the topic is published to.
+/// - `topic`: The topic to stream.
+/// - `last_event_id`: The client's `Last-Event-ID` header, if it is resuming.
+///
+/// # Returns
+/// A `Response` with a streamed body.
+pub fn event_stream(hub: Arc<Hub>, topic: &str, last_event_id: Option<&str>) -> Response {
+    let last_event_id: Option<u64> = last_event_id.and_then(|id| id.trim().parse().ok());
+    let subscription: Subscription = hub.subscribe(topic, last_event_id);
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>(16);
+
+    tokio::spawn(forward(hub, topic.to_string(), subscription, sender));
+
+    Response::new(
+        Protocol::Http,
+        HttpCode::Ok,
+        ContentType::EventStream,
+        false,
+    )
+    .stream(receiver)
+}
+
+/// Writes a subscription's events to a streamed body until the body is dropped.
+async fn forward(
+    hub: Arc<Hub>,
+    topic: String,
+    mut subscription: Subscription,
+    sender: mpsc::Sender<Vec<u8>>,
+) {
+    let mut first: Vec<u8> = format!("retry: {}\n\n", RETRY_MS).into_bytes();
+    for event in &subscription.missed {
+        first.extend(event.to_bytes());
+    }
+    if sender.send(first).await.is_err() {
+        return;
+    }
+
+    let mut last_id: u64 = subscription.last_id;
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await;
+
+    loop {
+        let events: Vec<Event> = tokio::select! {
+            event = subscription.receiver.recv() => match event {
+                Ok(event) => vec![event],
+                // Catch up from the history when the subscriber fell behind
+                Err(RecvError::Lagged(_)) => hub.since(&topic, last_id),
+                Err(RecvError::Closed) => return,
+            },
+            _ = heartbeat.tick() => {
+                if sender.send(b": heartbeat\n\n".to_vec()).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let mut chunk: Vec<u8> = Vec::new();
+        for event in events {
+            if event.id > last_id {
+                chunk.extend(event.to_bytes());
+                last_id = event.id;
+            }
+        }
+        if !chunk.is_empty() && sender.send(chunk).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_format() {
+        let event = Event {
+            id: 7,
+            event: Some(String::from("update")),
+            data: String::from("first\nsecond"),
+        };
+        assert_eq!(
+            event.to_bytes(),
+            b"id: 7\nevent: update\ndata: first\ndata: second\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_last_event_id() {
+        let hub = Arc::new(Hub::new());
+        for name in ["a", "b", "c"] {
+            hub.publish(PHONEBOOK_TOPIC, None, name);
+        }
+
+        let mut response = event_stream(hub.clone(), PHONEBOOK_TOPIC, Some("1"));
+        let mut stream = response.stream.take().unwrap();
+        assert_eq!(
+            stream.recv().await.unwrap(),
+            b"retry: 3000\n\nid: 2\ndata: b\n\nid: 3\ndata: c\n\n"
+        );
+
+        hub.publish(PHONEBOOK_TOPIC, None, "d");
+        assert_eq!(stream.recv().await.unwrap(), b"id: 4\ndata: d\n\n");
+
+        let fresh = hub.subscribe(PHONEBOOK_TOPIC, None);
+        assert!(fresh.missed.is_empty());
+        assert_eq!(fresh.last_id, 4);
+    }
+}
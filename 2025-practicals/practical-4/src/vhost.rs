@@ -0,0 +1,283 @@
+//! Name-based virtual hosting.
+//!
+//! A single server process can host several sites side by side. Each site is described by a
+//! `VirtualHost` which carries its own document root, static routes, CGI directory and logging
+//! target. The `Host` header (and the TLS SNI name when present) selects the site a request is
+//! dispatched to.
+use crate::access_log::AccessLogFormat;
+use crate::security::{CorsPolicy, SecurityPolicy};
+use crate::ErrorType;
+use log::{error, info};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The file the host table is loaded from on startup.
+pub const HOSTS_FILE: &str = "hosts.json";
+
+/// The application that handles the dynamic routes of a virtual host.
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Application {
+    Phonebook,
+    Calculator,
+    Clock,
+}
+
+/// A single site definition.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct VirtualHost {
+    /// The primary host name of the site, e.g. `phonebook.local`.
+    pub name: String,
+    /// Additional host names that should resolve to this site.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Directory static files are served from.
+    pub document_root: PathBuf,
+    /// The application responsible for the dynamic routes.
+    pub application: Application,
+    /// Static routes mapping a request path to a file relative to the document root.
+    #[serde(default)]
+    pub routes: HashMap<String, String>,
+    /// Directory CGI programs are executed from.
+    #[serde(default)]
+    pub cgi_dir: Option<PathBuf>,
+    /// The `log4rs` target request logs for this site are written to.
+    #[serde(default = "default_log_target")]
+    pub log_target: String,
+    /// The format access log lines for this site are written in.
+    #[serde(default)]
+    pub access_log: AccessLogFormat,
+    /// The security headers added to responses from this site.
+    #[serde(default)]
+    pub security: SecurityPolicy,
+    /// The cross-origin requests this site accepts. Without a policy no CORS headers are sent.
+    #[serde(default)]
+    pub cors: Option<CorsPolicy>,
+    /// Middleware switched off for routes, keyed by path prefix.
+    #[serde(default)]
+    pub disabled_middleware: HashMap<String, Vec<String>>,
+    /// Templates used for error responses, keyed by status code.
+    #[serde(default)]
+    pub error_pages: HashMap<u16, String>,
+}
+
+fn default_log_target() -> String {
+    String::from("request_logger")
+}
+
+impl VirtualHost {
+    /// Creates a phonebook host serving files from `document_root`.
+    pub fn new(name: &str, document_root: &Path) -> Self {
+        VirtualHost {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            document_root: document_root.to_path_buf(),
+            application: Application::Phonebook,
+            routes: HashMap::new(),
+            cgi_dir: None,
+            log_target: default_log_target(),
+            access_log: AccessLogFormat::default(),
+            security: SecurityPolicy::default(),
+            cors: None,
+            disabled_middleware: HashMap::new(),
+            error_pages: HashMap::new(),
+        }
+    }
+
+    /// Returns true if `host` names this site.
+    pub fn matches(&self, host: &str) -> bool {
+        self.name.eq_ignore_ascii_case(host)
+            || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(host))
+    }
+
+    /// Returns the path of `file` inside the document root.
+    pub fn file(&self, file: &str) -> PathBuf {
+        self.document_root.join(file.trim_start_matches('/'))
+    }
+
+    /// Returns true unless the middleware called `name` is switched off for `path`.
+    pub fn middleware_enabled(&self, name: &str, path: &str) -> bool {
+        !self.disabled_middleware.iter().any(|(prefix, names)| {
+            path.starts_with(prefix.as_str()) && names.iter().any(|n| n == name)
+        })
+    }
+
+    /// Returns the key used to cache `route` for this site, so hosts never share cache entries.
+    pub fn cache_key(&self, route: &str) -> String {
+        format!("{}{}", self.name, route)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct HostsFile {
+    default: String,
+    hosts: Vec<VirtualHost>,
+}
+
+/// The outcome of matching a request against the host table.
+#[derive(Debug)]
+pub enum HostMatch {
+    Found(Arc<VirtualHost>),
+    /// The request was sent on a connection established for a different configured host.
+    Misdirected,
+}
+
+/// All virtual hosts served by this process together with the default host.
+#[derive(Debug)]
+pub struct HostTable {
+    hosts: Vec<Arc<VirtualHost>>,
+    default_host: usize,
+}
+
+impl HostTable {
+    /// Creates a table containing a single default phonebook host rooted at `static/`.
+    pub fn single() -> Self {
+        HostTable {
+            hosts: vec![Arc::new(VirtualHost::new("localhost", Path::new("static")))],
+            default_host: 0,
+        }
+    }
+
+    /// Builds a table from a list of hosts. `default` must name one of them.
+    pub fn new(hosts: Vec<VirtualHost>, default: &str) -> Result<Self, ErrorType> {
+        let default_host = match hosts.iter().position(|h| h.matches(default)) {
+            Some(i) => i,
+            None => {
+                return Err(ErrorType::BadRequest(format!(
+                    "Default host {} is not defined",
+                    default
+                )))
+            }
+        };
+
+        Ok(HostTable {
+            hosts: hosts.into_iter().map(Arc::new).collect(),
+            default_host,
+        })
+    }
+
+    /// Loads the host table from `path`, falling back to a single host if the file is missing.
+    pub fn load(path: &str) -> Result<Self, ErrorType> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => {
+                info!(target: "request_logger","No {} found, serving a single host", path);
+                return Ok(HostTable::single());
+            }
+        };
+
+        let file: HostsFile = match serde_json::from_str(&content) {
+            Ok(f) => f,
+            Err(e) => {
+                error!(target: "error_logger","Failed to parse {}: {}", path, e);
+                return Err(ErrorType::ReadError(format!("Invalid hosts file: {}", e)));
+            }
+        };
+
+        HostTable::new(file.hosts, &file.default)
+    }
+
+    pub fn hosts(&self) -> &[Arc<VirtualHost>] {
+        &self.hosts
+    }
+
+    pub fn default_host(&self) -> Arc<VirtualHost> {
+        Arc::clone(&self.hosts[self.default_host])
+    }
+
+    fn find(&self, host: &str) -> Option<Arc<VirtualHost>> {
+        self.hosts.iter().find(|h| h.matches(host)).cloned()
+    }
+
+    /// Selects the virtual host for a request.
+    ///
+    /// # Arguments
+    /// - `host`: The value of the `Host` header, if any.
+    /// - `sni`: The server name sent during the TLS handshake, if any.
+    ///
+    /// # Returns
+    /// `HostMatch::Misdirected` when the SNI name and `Host` header name two different configured
+    /// hosts, otherwise the matching host or the default host.
+    pub fn resolve(&self, host: Option<&str>, sni: Option<&str>) -> HostMatch {
+        let host: Option<String> = host.map(strip_port);
+
+        if let (Some(host), Some(sni)) = (&host, sni) {
+            if let Some(tls_host) = self.find(sni) {
+                if !tls_host.matches(host) && self.find(host).is_some() {
+                    return HostMatch::Misdirected;
+                }
+            }
+        }
+
+        let selected = host
+            .as_deref()
+            .and_then(|h| self.find(h))
+            .or_else(|| sni.and_then(|s| self.find(s)))
+            .unwrap_or_else(|| self.default_host());
+
+        HostMatch::Found(selected)
+    }
+}
+
+/// Removes the port from a `Host` header value, handling bracketed IPv6 literals.
+fn strip_port(host: &str) -> String {
+    let host = host.trim();
+    if let Some(end) = host.strip_prefix('[').and_then(|h| h.find(']')) {
+        return host[1..=end].to_string();
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+        _ => host.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> HostTable {
+        let mut calculator = VirtualHost::new("calculator.local", Path::new("static"));
+        calculator.application = Application::Calculator;
+        calculator.aliases.push(String::from("calc.local"));
+
+        HostTable::new(
+            vec![
+                VirtualHost::new("phonebook.local", Path::new("static")),
+                calculator,
+            ],
+            "phonebook.local",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_by_host_header() {
+        match table().resolve(Some("calc.local:7878"), None) {
+            HostMatch::Found(h) => assert_eq!(h.application, Application::Calculator),
+            HostMatch::Misdirected => panic!("expected a host"),
+        }
+    }
+
+    #[test]
+    fn test_unknown_host_uses_default() {
+        match table().resolve(Some("example.com"), None) {
+            HostMatch::Found(h) => assert_eq!(h.name, "phonebook.local"),
+            HostMatch::Misdirected => panic!("expected the default host"),
+        }
+    }
+
+    #[test]
+    fn test_sni_mismatch_is_misdirected() {
+        assert!(matches!(
+            table().resolve(Some("calculator.local"), Some("phonebook.local")),
+            HostMatch::Misdirected
+        ));
+    }
+
+    #[test]
+    fn test_strip_port() {
+        assert_eq!(strip_port("localhost:7878"), "localhost");
+        assert_eq!(strip_port("[::1]:7878"), "::1");
+    }
+}
@@ -0,0 +1,221 @@
+//! WebSocket frames (RFC 6455, Section 5).
+
+/// Close status codes (RFC 6455, Section 7.4.1).
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const INVALID_PAYLOAD: u16 = 1007;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// The largest payload of a control frame.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    /// Returns true for Close, Ping and Pong, which may be sent between the fragments of a
+    /// message.
+    pub fn is_control(&self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Frame {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+
+    /// Builds a Close frame with a status code and reason.
+    pub fn close(code: u16, reason: &str) -> Self {
+        let mut payload: Vec<u8> = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        Frame::new(Opcode::Close, payload)
+    }
+
+    /// Serializes the frame as the server sends it, without a mask.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let length: usize = self.payload.len();
+        let mut bytes: Vec<u8> = Vec::with_capacity(length + 10);
+        bytes.push(if self.fin { 0x80 } else { 0 } | self.opcode.as_u8());
+
+        if length < 126 {
+            bytes.push(length as u8);
+        } else if length <= u16::MAX as usize {
+            bytes.push(126);
+            bytes.extend_from_slice(&(length as u16).to_be_bytes());
+        } else {
+            bytes.push(127);
+            bytes.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+/// Parses a frame sent by a client from the start of `buffer`, removing its bytes.
+///
+/// # Arguments
+/// - `buffer`: The bytes received so far.
+/// - `max_payload`: The largest payload accepted.
+///
+/// # Returns
+/// The unmasked frame, `Ok(None)` if `buffer` does not hold a whole frame yet or the close code
+/// to fail the connection with.
+pub fn parse(buffer: &mut Vec<u8>, max_payload: usize) -> Result<Option<Frame>, u16> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+
+    let fin: bool = buffer[0] & 0x80 != 0;
+    if buffer[0] & 0x70 != 0 {
+        // No extensions are negotiated, so the reserved bits must be clear
+        return Err(PROTOCOL_ERROR);
+    }
+    let opcode: Opcode = Opcode::from_u8(buffer[0] & 0x0f).ok_or(PROTOCOL_ERROR)?;
+
+    // Clients must mask every frame
+    if buffer[1] & 0x80 == 0 {
+        return Err(PROTOCOL_ERROR);
+    }
+
+    let (length, mut offset): (u64, usize) = match buffer[1] & 0x7f {
+        126 => match buffer.get(2..4) {
+            Some(b) => (u64::from(u16::from_be_bytes([b[0], b[1]])), 4),
+            None => return Ok(None),
+        },
+        127 => match buffer.get(2..10) {
+            Some(b) => (u64::from_be_bytes(b.try_into().unwrap_or_default()), 10),
+            None => return Ok(None),
+        },
+        length => (u64::from(length), 2),
+    };
+
+    if opcode.is_control() && (!fin || length > MAX_CONTROL_PAYLOAD as u64) {
+        return Err(PROTOCOL_ERROR);
+    }
+    if length > max_payload as u64 {
+        return Err(MESSAGE_TOO_BIG);
+    }
+    let length: usize = length as usize;
+
+    let mask: [u8; 4] = match buffer.get(offset..offset + 4) {
+        Some(m) => [m[0], m[1], m[2], m[3]],
+        None => return Ok(None),
+    };
+    offset += 4;
+
+    if buffer.len() < offset + length {
+        return Ok(None);
+    }
+
+    let payload: Vec<u8> = buffer[offset..offset + length]
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ mask[i % 4])
+        .collect();
+    buffer.drain(..offset + length);
+
+    Ok(Some(Frame {
+        fin,
+        opcode,
+        payload,
+    }))
+}
+
+/// Returns true if `code` may be sent by a peer in a Close frame.
+pub fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Masks a frame the way a client sends it.
+    fn client_frame(first: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![first, 0x80 | payload.len() as u8];
+        bytes.extend_from_slice(&mask);
+        bytes.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        bytes
+    }
+
+    #[test]
+    fn test_parse_masked_frames() {
+        // RFC 6455, Section 5.7: a masked "Hello" followed by the start of another frame
+        let mut buffer: Vec<u8> = vec![
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58, 0x01,
+        ];
+        let frame = parse(&mut buffer, 1024).unwrap().unwrap();
+        assert_eq!(frame, Frame::new(Opcode::Text, b"Hello".to_vec()));
+        assert_eq!(buffer, vec![0x01]);
+        assert_eq!(parse(&mut buffer, 1024), Ok(None));
+
+        let mut unmasked: Vec<u8> = vec![0x81, 0x02, b'h', b'i'];
+        assert_eq!(parse(&mut unmasked, 1024), Err(PROTOCOL_ERROR));
+
+        let mut fragmented_ping = client_frame(0x09, b"", [1, 2, 3, 4]);
+        assert_eq!(parse(&mut fragmented_ping, 1024), Err(PROTOCOL_ERROR));
+
+        let mut too_big = client_frame(0x82, &[0; 100], [1, 2, 3, 4]);
+        assert_eq!(parse(&mut too_big, 10), Err(MESSAGE_TOO_BIG));
+    }
+
+    #[test]
+    fn test_server_frame_lengths() {
+        assert_eq!(
+            Frame::new(Opcode::Text, b"Hello".to_vec()).to_bytes(),
+            vec![0x81, 0x05, b'H', b'e', b'l', b'l', b'o']
+        );
+        assert_eq!(
+            &Frame::new(Opcode::Binary, vec![0; 256]).to_bytes()[..4],
+            &[0x82, 126, 0x01, 0x00]
+        );
+        assert_eq!(
+            &Frame::new(Opcode::Binary, vec![0; 65536]).to_bytes()[..10],
+            &[0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,426 @@
+//! WebSocket (RFC 6455) support for HTTP/1.1 connections.
+//!
+//! A request with `Upgrade: websocket` to one of the `Endpoint`s is answered with
+//! `101 Switching Protocols`, after which the connection belongs to the endpoint's handler. The
+//! handler talks to the client through a `WebSocket`, which answers pings, reassembles
+//! fragmented messages and takes part in the close handshake, so handlers only see whole text
+//! and binary messages.
+//!
+//! `recv` and `send` keep partially read and written frames in the `WebSocket`, so either can be
+//! used in `tokio::select!` alongside other events without losing data.
+pub mod frame;
+mod sha1;
+
+use crate::response::Response;
+use crate::server::SharedState;
+use crate::vhost::{Application, VirtualHost};
+use crate::{ContentType, HttpCode, HttpMethod, Protocol, Request};
+use frame::{Frame, Opcode};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+/// The GUID appended to the client's key to compute `Sec-WebSocket-Accept`.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest message accepted from a client, after reassembling fragments.
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// How often handlers ping idle clients to keep the connection open.
+pub const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long `close` waits for the client to answer the close handshake.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The WebSocket endpoints served by the applications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Live changes to the phonebook, used by the phonebook page.
+    PhonebookUpdates,
+}
+
+impl Endpoint {
+    /// Returns the endpoint at `path` on `host`, if there is one.
+    pub fn find(host: &VirtualHost, path: &str) -> Option<Self> {
+        match (&host.application, path) {
+            (Application::Phonebook, "/ws/phonebook") => Some(Endpoint::PhonebookUpdates),
+            _ => None,
+        }
+    }
+
+    /// Runs the endpoint's handler until the connection is closed.
+    ///
+    /// # Arguments
+    /// - `socket`: The connection, after the handshake.
+    /// - `state`: A shared, thread-safe state used for managing server data and caching.
+    pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        self,
+        socket: WebSocket<S>,
+        state: Arc<Mutex<SharedState>>,
+    ) -> Result<(), WebSocketError> {
+        match self {
+            Endpoint::PhonebookUpdates => crate::api::phonebook_updates(socket, state).await,
+        }
+    }
+}
+
+/// Returns true if the client asks to upgrade the connection to a WebSocket.
+pub fn is_upgrade(request: &Request) -> bool {
+    request
+        .get_header("Upgrade")
+        .is_some_and(|u| u.eq_ignore_ascii_case("websocket"))
+}
+
+/// Checks an upgrade request and builds the `101 Switching Protocols` response.
+///
+/// # Arguments
+/// - `request`: The upgrade request.
+/// - `host`: The virtual host serving the request, whose CORS policy lists the other origins
+///   allowed to connect.
+///
+/// # Returns
+/// The handshake response or the status to reject the request with.
+pub fn handshake(request: &Request, host: &VirtualHost) -> Result<Response, HttpCode> {
+    if request.method != HttpMethod::GET {
+        return Err(HttpCode::MethodNotAllowed);
+    }
+
+    let connection_upgrade: bool = request.get_header("Connection").is_some_and(|c| {
+        c.split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+    });
+    if !connection_upgrade || request.get_header("Sec-WebSocket-Version") != Some("13") {
+        return Err(HttpCode::BadRequest);
+    }
+
+    // The key is 16 random bytes in base64
+    let key: &str = match request.get_header("Sec-WebSocket-Key") {
+        Some(k) if k.len() == 24 && k.ends_with("==") => k,
+        _ => return Err(HttpCode::BadRequest),
+    };
+
+    // Browsers send the page's origin, which stops other sites from connecting with the
+    // user's cookies
+    if let Some(origin) = request.get_header("Origin") {
+        if !is_allowed_origin(origin, request.get_header("Host"), host) {
+            return Err(HttpCode::Forbidden);
+        }
+    }
+
+    let mut response = Response::new(
+        Protocol::Http,
+        HttpCode::SwitchingProtocols,
+        ContentType::Text,
+        false,
+    );
+    response.headers.retain(|h| h.title != "Content-Type");
+    response.add_header(String::from("Upgrade"), String::from("websocket"));
+    response.add_header(String::from("Connection"), String::from("Upgrade"));
+    response.add_header(String::from("Sec-WebSocket-Accept"), accept_key(key));
+    Ok(response)
+}
+
+/// Computes the `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    sha1::base64(&sha1::digest(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// Returns true if a page from `origin` may open a WebSocket to this host.
+fn is_allowed_origin(origin: &str, host_header: Option<&str>, host: &VirtualHost) -> bool {
+    let same_origin: bool = match (origin.split_once("://"), host_header) {
+        (Some((_, authority)), Some(requested)) => authority.eq_ignore_ascii_case(requested),
+        _ => false,
+    };
+
+    same_origin
+        || host
+            .cors
+            .as_ref()
+            .is_some_and(|cors| cors.allows_origin(origin))
+}
+
+/// A message sent over a WebSocket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+}
+
+impl Message {
+    fn into_frame(self) -> Frame {
+        match self {
+            Message::Text(text) => Frame::new(Opcode::Text, text.into_bytes()),
+            Message::Binary(bytes) => Frame::new(Opcode::Binary, bytes),
+            Message::Ping(bytes) => Frame::new(Opcode::Ping, bytes),
+            Message::Pong(bytes) => Frame::new(Opcode::Pong, bytes),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(std::io::Error),
+    /// The client broke the protocol and the connection was closed with this status code.
+    Protocol(u16),
+    /// A message was sent after the connection was closed.
+    Closed,
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Io(e) => write!(f, "WebSocket I/O error: {}", e),
+            WebSocketError::Protocol(code) => write!(f, "WebSocket closed with status {}", code),
+            WebSocketError::Closed => write!(f, "WebSocket is closed"),
+        }
+    }
+}
+
+impl std::error::Error for WebSocketError {}
+
+impl From<std::io::Error> for WebSocketError {
+    fn from(e: std::io::Error) -> Self {
+        WebSocketError::Io(e)
+    }
+}
+
+/// The server side of a WebSocket connection.
+pub struct WebSocket<S> {
+    stream: S,
+    read_buffer: Vec<u8>,
+    write_buffer: Vec<u8>,
+    /// The opcode and payload so far of a fragmented message.
+    fragments: Option<(Opcode, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocket<S> {
+    /// Wraps a connection whose handshake has been completed.
+    pub fn new(stream: S) -> Self {
+        WebSocket {
+            stream,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Waits for the next text or binary message.
+    ///
+    /// Pings are answered and pongs are skipped. When the client closes the connection its
+    /// Close frame is answered before `Ok(None)` is returned.
+    ///
+    /// # Returns
+    /// The message, `Ok(None)` once the connection is closed or a `WebSocketError`. If the client
+    /// broke the protocol the connection has already been closed with the matching status.
+    pub async fn recv(&mut self) -> Result<Option<Message>, WebSocketError> {
+        loop {
+            self.flush().await?;
+            if self.close_received {
+                return Ok(None);
+            }
+
+            let message: Result<Option<Message>, u16> =
+                match frame::parse(&mut self.read_buffer, MAX_MESSAGE_SIZE) {
+                    Ok(Some(frame)) => self.on_frame(frame),
+                    Ok(None) => {
+                        let mut chunk: [u8; 4096] = [0; 4096];
+                        let n: usize = self.stream.read(&mut chunk).await?;
+                        if n == 0 {
+                            // The connection was dropped without a close handshake
+                            self.close_received = true;
+                        }
+                        self.read_buffer.extend_from_slice(&chunk[..n]);
+                        continue;
+                    }
+                    Err(code) => Err(code),
+                };
+
+            match message {
+                Ok(Some(message)) => return Ok(Some(message)),
+                Ok(None) => continue,
+                Err(code) => {
+                    if !self.close_sent {
+                        self.queue(Frame::close(code, ""));
+                    }
+                    let _ = self.flush().await;
+                    self.close_received = true;
+                    return Err(WebSocketError::Protocol(code));
+                }
+            }
+        }
+    }
+
+    /// Sends a message to the client.
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        self.queue(message.into_frame());
+        self.flush().await?;
+        Ok(())
+    }
+
+    /// Starts the close handshake and waits a short time for the client to answer it.
+    ///
+    /// # Arguments
+    /// - `code`: The close status, e.g. `frame::GOING_AWAY`.
+    /// - `reason`: A short description for the client.
+    pub async fn close(mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if !self.close_sent {
+            self.queue(Frame::close(code, reason));
+        }
+
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+            while self.recv().await?.is_some() {}
+            Ok::<(), WebSocketError>(())
+        })
+        .await;
+        let _ = self.stream.shutdown().await;
+        Ok(())
+    }
+
+    /// Handles a frame from the client.
+    ///
+    /// # Returns
+    /// A complete message, `Ok(None)` if the frame did not complete one or the close code to
+    /// fail the connection with.
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Message>, u16> {
+        match frame.opcode {
+            Opcode::Ping => {
+                if !self.close_sent {
+                    self.queue(Frame::new(Opcode::Pong, frame.payload));
+                }
+                Ok(None)
+            }
+            Opcode::Pong => Ok(None),
+            Opcode::Close => {
+                let code: u16 = match frame.payload.as_slice() {
+                    [] => frame::NORMAL_CLOSURE,
+                    [_] => return Err(frame::PROTOCOL_ERROR),
+                    [high, low, reason @ ..] => {
+                        let code: u16 = u16::from_be_bytes([*high, *low]);
+                        if !frame::is_valid_close_code(code) {
+                            return Err(frame::PROTOCOL_ERROR);
+                        }
+                        if std::str::from_utf8(reason).is_err() {
+                            return Err(frame::INVALID_PAYLOAD);
+                        }
+                        code
+                    }
+                };
+
+                if !self.close_sent {
+                    self.queue(Frame::close(code, ""));
+                }
+                self.close_received = true;
+                Ok(None)
+            }
+            Opcode::Text | Opcode::Binary => {
+                if self.fragments.is_some() {
+                    return Err(frame::PROTOCOL_ERROR);
+                }
+                if frame.fin {
+                    return to_message(frame.opcode, frame.payload).map(Some);
+                }
+                self.fragments = Some((frame.opcode, frame.payload));
+                Ok(None)
+            }
+            Opcode::Continuation => {
+                let (opcode, mut payload) = self.fragments.take().ok_or(frame::PROTOCOL_ERROR)?;
+                if payload.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                    return Err(frame::MESSAGE_TOO_BIG);
+                }
+                payload.extend_from_slice(&frame.payload);
+
+                if frame.fin {
+                    return to_message(opcode, payload).map(Some);
+                }
+                self.fragments = Some((opcode, payload));
+                Ok(None)
+            }
+        }
+    }
+
+    fn queue(&mut self, frame: Frame) {
+        if frame.opcode == Opcode::Close {
+            self.close_sent = true;
+        }
+        self.write_buffer.extend_from_slice(&frame.to_bytes());
+    }
+
+    /// Writes the queued frames, keeping whatever could not be written yet.
+    async fn flush(&mut self) -> std::io::Result<()> {
+        while !self.write_buffer.is_empty() {
+            let n: usize = self.stream.write(&self.write_buffer).await?;
+            if n == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            self.write_buffer.drain(..n);
+        }
+        self.stream.flush().await
+    }
+}
+
+fn to_message(opcode: Opcode, payload: Vec<u8>) -> Result<Message, u16> {
+    match opcode {
+        Opcode::Text => String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| frame::INVALID_PAYLOAD),
+        _ => Ok(Message::Binary(payload)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+        let mut bytes: Vec<u8> = vec![first, 0x80 | payload.len() as u8];
+        bytes.extend_from_slice(&mask);
+        bytes.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        bytes
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // RFC 6455, Section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fragments_ping_and_close() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let mut socket = WebSocket::new(server);
+
+        let mut bytes: Vec<u8> = client_frame(0x01, b"Hel");
+        bytes.extend(client_frame(0x89, b"are you there"));
+        bytes.extend(client_frame(0x80, b"lo"));
+        bytes.extend(client_frame(0x88, &[0x03, 0xe8]));
+        client.write_all(&bytes).await.unwrap();
+
+        assert_eq!(
+            socket.recv().await.unwrap(),
+            Some(Message::Text(String::from("Hello")))
+        );
+        assert_eq!(socket.recv().await.unwrap(), None);
+        drop(socket);
+
+        let mut replies: Vec<u8> = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        let mut expected: Vec<u8> = Frame::new(Opcode::Pong, b"are you there".to_vec()).to_bytes();
+        expected.extend(Frame::close(1000, "").to_bytes());
+        assert_eq!(replies, expected);
+    }
+}
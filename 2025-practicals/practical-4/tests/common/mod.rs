@@ -0,0 +1,393 @@
+//! Boots a server in-process and talks to it over real TLS connections.
+//!
+//! `TestServer::start` runs the server on a free port with hosts built in code, a freshly
+//! generated self-signed certificate and in-memory stores, so tests need neither Redis,
+//! `friends.db` nor the files the server is deployed with. `TestClient`
+//! keeps the session cookie like a browser and opens a new connection for every request.
+#![allow(dead_code)]
+
+use practical_4::control::Control;
+use practical_4::server::{Server, ServerConfig, SharedState};
+use practical_4::socket::connection::TlsIdentity;
+use practical_4::vhost::{Application, HostTable, VirtualHost};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+/// The admin token of test servers.
+pub const ADMIN_TOKEN: &str = "test-token";
+
+/// The phonebook as the default host, also named `localhost`, and the calculator with its
+/// `/home` route, both serving `static/`.
+pub fn test_hosts() -> HostTable {
+    let mut phonebook = VirtualHost::new("phonebook.local", Path::new("static"));
+    phonebook.aliases = vec![String::from("localhost"), String::from("127.0.0.1")];
+
+    let mut calculator = VirtualHost::new("calculator.local", Path::new("static"));
+    calculator.application = Application::Calculator;
+    calculator
+        .routes
+        .insert(String::from("/home"), String::from("home.html"));
+
+    HostTable::new(vec![phonebook, calculator], "phonebook.local").unwrap()
+}
+
+/// A self-signed certificate for the test hosts.
+fn test_identity() -> TlsIdentity {
+    let certificate = rcgen::generate_simple_self_signed(vec![
+        String::from("localhost"),
+        String::from("phonebook.local"),
+        String::from("calculator.local"),
+    ])
+    .unwrap();
+    let key: PrivateKeyDer<'static> =
+        PrivatePkcs8KeyDer::from(certificate.key_pair.serialize_der()).into();
+    TlsIdentity::new(certificate.cert.der().clone(), key).unwrap()
+}
+
+/// A server running on a task of the test's runtime.
+pub struct TestServer {
+    pub address: SocketAddr,
+    pub admin_address: SocketAddr,
+    pub control: Arc<Control>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Starts a server with the hosts from `test_hosts`.
+    pub async fn start() -> TestServer {
+        TestServer::start_with(test_hosts()).await
+    }
+
+    /// Starts a server with the given hosts.
+    pub async fn start_with(hosts: HostTable) -> TestServer {
+        // The server's TLS configuration uses the process-wide provider
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        let config = ServerConfig::new(hosts, test_identity())
+            .port(0)
+            .admin_port(Some(0))
+            .admin_token(Some(ADMIN_TOKEN.to_string()));
+        let server = Server::bind(config, SharedState::in_memory().unwrap())
+            .await
+            .unwrap();
+
+        let address = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        let admin_address = server.admin_addr().unwrap();
+        let control = server.control();
+        let task = Some(tokio::spawn(server.run()));
+
+        TestServer {
+            address,
+            admin_address,
+            control,
+            task,
+        }
+    }
+
+    /// Returns a client for the default host.
+    pub fn client(&self) -> TestClient {
+        TestClient::new(self.address, "localhost")
+    }
+
+    /// Sends a plain HTTP request to the admin listener.
+    pub async fn admin(&self, method: &str, path: &str, token: Option<&str>) -> TestResponse {
+        let mut stream = TcpStream::connect(self.admin_address).await.unwrap();
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: 127.0.0.1\r\n", method, path);
+        if let Some(token) = token {
+            head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).await.unwrap();
+
+        let mut data: Vec<u8> = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+        TestResponse::parse(&data)
+    }
+
+    /// Waits for the server to finish after a shutdown was started.
+    pub async fn finished(mut self) {
+        let task = self.task.take().unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(15), task)
+            .await
+            .expect("server did not shut down")
+            .unwrap();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.control.shutdown();
+    }
+}
+
+/// A client that connects to a test server over TLS, keeping its session cookie.
+pub struct TestClient {
+    address: SocketAddr,
+    host: String,
+    /// The `session_id` cookie set by the server, sent with every later request.
+    pub cookie: Option<String>,
+    connector: tokio_rustls::TlsConnector,
+}
+
+impl TestClient {
+    /// Creates a client sending `host` as the SNI name and `Host` header.
+    pub fn new(address: SocketAddr, host: &str) -> TestClient {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        TestClient {
+            address,
+            host: host.to_string(),
+            cookie: None,
+            connector: tokio_rustls::TlsConnector::from(Arc::new(config)),
+        }
+    }
+
+    pub async fn get(&mut self, path: &str) -> TestResponse {
+        self.request("GET", path, &[], b"").await
+    }
+
+    /// Sends a JSON body with the session's CSRF token.
+    pub async fn post_json(&mut self, path: &str, csrf_token: &str, body: &str) -> TestResponse {
+        self.request(
+            "POST",
+            path,
+            &[
+                ("Content-Type", "application/json"),
+                ("X-CSRF-Token", csrf_token),
+            ],
+            body.as_bytes(),
+        )
+        .await
+    }
+
+    /// Sends a request on a new connection and reads the response until the server closes it.
+    pub async fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> TestResponse {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, self.host);
+        if let Some(cookie) = &self.cookie {
+            head.push_str(&format!("Cookie: {}\r\n", cookie));
+        }
+        for (title, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", title, value));
+        }
+        if !body.is_empty() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut request: Vec<u8> = head.into_bytes();
+        request.extend_from_slice(body);
+        let response = TestResponse::parse(&self.send_raw(&request).await);
+
+        if let Some(cookie) = response.header("Set-Cookie") {
+            self.cookie = cookie.split(';').next().map(str::to_string);
+        }
+        response
+    }
+
+    /// Writes `request` as it is and returns everything the server sends back.
+    pub async fn send_raw(&self, request: &[u8]) -> Vec<u8> {
+        let stream = TcpStream::connect(self.address).await.unwrap();
+        let server_name = ServerName::try_from(self.host.clone()).unwrap();
+        let mut stream = self.connector.connect(server_name, stream).await.unwrap();
+
+        stream.write_all(request).await.unwrap();
+        stream.flush().await.unwrap();
+
+        let mut data: Vec<u8> = Vec::new();
+        let mut buffer: [u8; 8192] = [0; 8192];
+        loop {
+            match stream.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(n) => data.extend_from_slice(&buffer[..n]),
+                // The server closes connections without a TLS close_notify
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => panic!("failed to read response: {}", e),
+            }
+        }
+        data
+    }
+
+    /// Opens a streamed response, e.g. an event stream, and returns the status code and the
+    /// connection once the head is read, leaving the stream open.
+    pub async fn open_stream(
+        &self,
+        path: &str,
+    ) -> (u16, tokio_rustls::client::TlsStream<TcpStream>) {
+        let stream = TcpStream::connect(self.address).await.unwrap();
+        let server_name = ServerName::try_from(self.host.clone()).unwrap();
+        let mut stream = self.connector.connect(server_name, stream).await.unwrap();
+
+        let request: String = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: text/event-stream\r\n\r\n",
+            path, self.host
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream.flush().await.unwrap();
+
+        let mut head: Vec<u8> = Vec::new();
+        let mut byte: [u8; 1] = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            match stream.read(&mut byte).await {
+                Ok(1) => head.push(byte[0]),
+                _ => break,
+            }
+        }
+        (TestResponse::parse(&head).status, stream)
+    }
+}
+
+/// A response read by a test client.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// The body with any chunked transfer encoding removed, still compressed if it was sent so.
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn parse(data: &[u8]) -> TestResponse {
+        let head_end = data
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("response has no head");
+        let head = String::from_utf8_lossy(&data[..head_end]);
+        let mut lines = head.split("\r\n");
+
+        let status: u16 = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .expect("invalid status line");
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(title, value)| (title.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        let mut response = TestResponse {
+            status,
+            headers,
+            body: data[head_end + 4..].to_vec(),
+        };
+        if response.header("Transfer-Encoding") == Some("chunked") {
+            response.body = dechunk(&response.body);
+        }
+        response
+    }
+
+    /// Returns the value of the first header called `title`, ignoring case.
+    pub fn header(&self, title: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(t, _)| t.eq_ignore_ascii_case(title))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the body as text, decompressing it if it was gzip encoded.
+    pub fn text(&self) -> String {
+        if self.header("Content-Encoding") == Some("gzip") {
+            let mut text = String::new();
+            flate2::read::GzDecoder::new(&self.body[..])
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        } else {
+            String::from_utf8_lossy(&self.body).to_string()
+        }
+    }
+
+    /// Returns the CSRF token of an HTML page.
+    pub fn csrf_token(&self) -> String {
+        let text = self.text();
+        let start = text
+            .find("name=\"csrf-token\" content=\"")
+            .expect("page has no token")
+            + 27;
+        text[start..start + 64].to_string()
+    }
+}
+
+/// Removes chunked transfer encoding from a complete body.
+fn dechunk(mut data: &[u8]) -> Vec<u8> {
+    let mut body: Vec<u8> = Vec::new();
+    while let Some(line_end) = data.windows(2).position(|w| w == b"\r\n") {
+        let size = usize::from_str_radix(&String::from_utf8_lossy(&data[..line_end]), 16).unwrap();
+        if size == 0 {
+            break;
+        }
+        body.extend_from_slice(&data[line_end + 2..line_end + 2 + size]);
+        data = &data[line_end + 4 + size..];
+    }
+    body
+}
+
+/// Accepts the server's self-signed test certificate, whatever its name or expiry.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
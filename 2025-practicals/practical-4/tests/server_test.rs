@@ -0,0 +1,226 @@
+//! End-to-end tests that send real TLS requests to a server running in-process.
+mod common;
+
+use common::{TestClient, TestResponse, TestServer, ADMIN_TOKEN};
+
+#[tokio::test]
+async fn test_routing() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    let page = client.get("/").await;
+    assert_eq!(page.status, 200);
+    assert!(page
+        .header("Content-Type")
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(page.header("X-Request-ID").is_some());
+
+    let friends = client.get("/friends").await;
+    assert_eq!((friends.status, friends.text().as_str()), (200, "[]"));
+
+    let missing = client.get("/missing").await;
+    assert_eq!(missing.status, 404);
+    assert!(missing.text().contains("404"));
+
+    let token = page.csrf_token();
+    let put = client
+        .request("PUT", "/", &[("X-CSRF-Token", &token)], b"")
+        .await;
+    assert_eq!(put.status, 405);
+
+    // Routes of another virtual host, selected by SNI and the Host header
+    let mut calculator = TestClient::new(server.address, "calculator.local");
+    let home = calculator.get("/home").await;
+    assert_eq!(home.status, 200);
+    assert!(home.text().contains("<h1>FERRIS!</h1>"));
+}
+
+#[tokio::test]
+async fn test_sessions_and_csrf() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+
+    let page = client.get("/").await;
+    let cookie = client.cookie.clone().expect("no session cookie");
+    assert!(cookie.starts_with("session_id="));
+    let token = page.csrf_token();
+
+    // The session is kept and its token is required to change state
+    let forged = client
+        .request(
+            "POST",
+            "/add",
+            &[("Content-Type", "application/json")],
+            br#"{"name":"Ann","number":"555 0100"}"#,
+        )
+        .await;
+    assert_eq!(forged.status, 403);
+    assert!(forged.header("Set-Cookie").is_none());
+
+    let added = client
+        .post_json("/add", &token, r#"{"name":"Ann","number":"555 0100"}"#)
+        .await;
+    assert_eq!((added.status, added.text().as_str()), (200, "Success"));
+
+    let friend = client.get("/friend?name=Ann").await;
+    assert_eq!(friend.text(), r#"{"name":"Ann","number":"555 0100"}"#);
+
+    // Another session has its own token
+    let mut other = server.client();
+    other.get("/").await;
+    assert_ne!(other.cookie, client.cookie);
+    let wrong_token = other.post_json("/del", &token, r#"{"name":"Ann"}"#).await;
+    assert_eq!(wrong_token.status, 403);
+    assert_eq!(
+        client.get("/friends").await.text().matches("Ann").count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_compression() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+    let token = client.get("/").await.csrf_token();
+    client
+        .post_json("/add", &token, r#"{"name":"Bob","number":"555 0199"}"#)
+        .await;
+
+    let plain = client.get("/friends").await;
+    assert!(plain.header("Content-Encoding").is_none());
+
+    let compressed = client
+        .request(
+            "GET",
+            "/friends",
+            &[("Accept-Encoding", "gzip, deflate")],
+            b"",
+        )
+        .await;
+    assert_eq!(compressed.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(
+        compressed.header("Content-Length"),
+        Some(compressed.body.len().to_string().as_str())
+    );
+    assert_ne!(compressed.body, plain.body);
+    assert_eq!(compressed.text(), plain.text());
+}
+
+#[tokio::test]
+async fn test_error_paths() {
+    let server = TestServer::start().await;
+    let mut client = server.client();
+    let token = client.get("/").await.csrf_token();
+
+    let unsupported = client
+        .request(
+            "POST",
+            "/add",
+            &[
+                ("Content-Type", "application/xml"),
+                ("X-CSRF-Token", &token),
+            ],
+            b"<friend/>",
+        )
+        .await;
+    assert_eq!(unsupported.status, 415);
+
+    let too_large = client
+        .request(
+            "POST",
+            "/add",
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Length", "20000000"),
+            ],
+            b"",
+        )
+        .await;
+    assert_eq!(too_large.status, 413);
+
+    let malformed = TestResponse::parse(&client.send_raw(b"GARBAGE\r\n\r\n").await);
+    assert_eq!(malformed.status, 400);
+
+    // An SNI name and Host header naming different hosts
+    let misdirected = TestClient::new(server.address, "calculator.local")
+        .send_raw(b"GET / HTTP/1.1\r\nHost: phonebook.local\r\n\r\n")
+        .await;
+    assert_eq!(TestResponse::parse(&misdirected).status, 421);
+
+    client.cookie = Some(String::from("session_id=not-a-uuid"));
+    assert_eq!(client.get("/").await.text(), "Bye, World!");
+}
+
+#[tokio::test]
+async fn test_health_and_admin_api() {
+    let server = TestServer::start().await;
+
+    let health = server.admin("GET", "/healthz", None).await;
+    assert_eq!(health.status, 200);
+
+    let ready: serde_json::Value =
+        serde_json::from_str(&server.admin("GET", "/readyz", None).await.text()).unwrap();
+    assert_eq!(ready["checks"]["redis"]["status"], "ok");
+    assert_eq!(ready["checks"]["sqlite"]["status"], "ok");
+    assert_eq!(ready["checks"]["certificate"]["status"], "ok");
+
+    assert_eq!(
+        server.admin("GET", "/admin/connections", None).await.status,
+        401
+    );
+    assert_eq!(
+        server
+            .admin("GET", "/admin/connections", Some("wrong"))
+            .await
+            .status,
+        401
+    );
+    let connections = server
+        .admin("GET", "/admin/connections", Some(ADMIN_TOKEN))
+        .await;
+    assert_eq!(connections.status, 200);
+    assert_eq!(
+        server
+            .admin("POST", "/admin/cache/flush", Some(ADMIN_TOKEN))
+            .await
+            .status,
+        200
+    );
+    // A configuration built in code has no files to reload
+    assert_eq!(
+        server
+            .admin("POST", "/admin/reload", Some(ADMIN_TOKEN))
+            .await
+            .status,
+        500
+    );
+
+    let shutdown = server
+        .admin("POST", "/admin/shutdown", Some(ADMIN_TOKEN))
+        .await;
+    assert_eq!(shutdown.status, 200);
+    assert!(server.control.is_shutting_down());
+    server.finished().await;
+}
+
+#[tokio::test]
+async fn test_event_streams_leave_connections_free() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    // More streams than connections may be open at once, and requests are still served
+    let served = async {
+        let mut streams = Vec::new();
+        for _ in 0..practical_4::server::MAX_CONNECTIONS + 1 {
+            let (status, stream) = client.open_stream("/events/phonebook").await;
+            assert_eq!(status, 200);
+            streams.push(stream);
+        }
+        server.client().get("/").await
+    };
+    let page = tokio::time::timeout(std::time::Duration::from_secs(5), served)
+        .await
+        .expect("the open streams held every connection permit");
+    assert_eq!(page.status, 200);
+}
@@ -0,0 +1,287 @@
+//! Cross-site request forgery protection using synchronizer tokens.
+//!
+//! Every session is given a random token that is kept on the server with the session. The pages
+//! served to the session carry the token, as a `<meta name="csrf-token">` tag and as a hidden
+//! `csrf_token` field in their forms, and state-changing requests must send it back in the
+//! `X-CSRF-Token` header or the `csrf_token` field of their body. Another site can make the
+//! browser send the session cookie but cannot read the token from the pages.
+//!
+//! Servers that already keep state per session store the token with it. `Sessions` keeps the
+//! tokens for servers that have no sessions of their own.
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The header a client sends the token in.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// The form or JSON body field a client sends the token in.
+pub const CSRF_FIELD: &str = "csrf_token";
+
+/// The cookie holding the session ID.
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// How long a session lasts after its last request.
+const SESSION_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// The most sessions kept at once. The least recently used one is dropped to make room.
+const MAX_SESSIONS: usize = 10_000;
+
+/// Generates a new random token.
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns true if `supplied`, the token a request sent, is `expected`.
+pub fn token_matches(supplied: Option<&str>, expected: &str) -> bool {
+    match supplied {
+        Some(token) => constant_time_eq(token.trim().as_bytes(), expected.as_bytes()),
+        None => false,
+    }
+}
+
+/// Compares two byte strings in time that only depends on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Returns the value of the cookie called `name` in the value of a `Cookie` header.
+pub fn cookie_value<'a>(cookies: &'a str, name: &str) -> Option<&'a str> {
+    cookies.split(';').find_map(|cookie| {
+        let (key, value) = cookie.split_once('=')?;
+        if key.trim() == name {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the `Set-Cookie` value that stores the session ID `id` in the browser.
+pub fn session_cookie(id: &str) -> String {
+    format!(
+        "{}={}; Path=/; Secure; HttpOnly; SameSite=Strict",
+        SESSION_COOKIE, id
+    )
+}
+
+/// Adds `token` to an HTML page as a meta tag in the head and a hidden field in every form.
+pub fn inject(html: &[u8], token: &str) -> Vec<u8> {
+    let page: String = match String::from_utf8(html.to_vec()) {
+        Ok(p) => p,
+        Err(_) => return html.to_vec(),
+    };
+
+    let field: String = format!(
+        "<input type=\"hidden\" name=\"{}\" value=\"{}\" />",
+        CSRF_FIELD, token
+    );
+    let mut output: String = String::with_capacity(page.len());
+    let mut rest: &str = &page;
+
+    while let Some(start) = find_ignore_case(rest, "<form") {
+        let end: usize = match rest[start..].find('>') {
+            Some(e) => start + e + 1,
+            None => break,
+        };
+        output.push_str(&rest[..end]);
+        output.push_str(&field);
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+
+    let meta: String = format!("<meta name=\"csrf-token\" content=\"{}\">\n", token);
+    if let Some(head) = find_ignore_case(&output, "</head>") {
+        output.insert_str(head, &meta);
+    }
+
+    output.into_bytes()
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// A session and its token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub id: String,
+    pub token: String,
+    /// True if the session was started by this request, so its cookie must be set.
+    pub new: bool,
+}
+
+struct Entry {
+    token: String,
+    last_used: Instant,
+}
+
+/// The tokens of the open sessions, by session ID.
+pub struct Sessions {
+    entries: HashMap<String, Entry>,
+    lifetime: Duration,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Sessions {
+            entries: HashMap::new(),
+            lifetime: SESSION_LIFETIME,
+        }
+    }
+
+    /// Sets how long a session lasts after its last request.
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Returns the session with the ID from the client's cookie, or starts a new one if the
+    /// client has none or it is unknown or expired.
+    pub fn start(&mut self, id: Option<&str>) -> Session {
+        let now: Instant = Instant::now();
+        if let Some(id) = id {
+            if let Some(entry) = self.open(id, now) {
+                return Session {
+                    id: id.to_string(),
+                    token: entry.token.clone(),
+                    new: false,
+                };
+            }
+        }
+
+        self.make_room(now);
+        let session = Session {
+            id: generate_token(),
+            token: generate_token(),
+            new: true,
+        };
+        self.entries.insert(
+            session.id.clone(),
+            Entry {
+                token: session.token.clone(),
+                last_used: now,
+            },
+        );
+        session
+    }
+
+    /// Returns true if `supplied` is the token of the open session `id`.
+    pub fn verify(&mut self, id: Option<&str>, supplied: Option<&str>) -> bool {
+        match id.and_then(|id| self.open(id, Instant::now())) {
+            Some(entry) => token_matches(supplied, &entry.token),
+            None => false,
+        }
+    }
+
+    /// Returns the number of sessions kept.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the session `id` and marks it used, or `None` if it is unknown or has expired.
+    fn open(&mut self, id: &str, now: Instant) -> Option<&Entry> {
+        let lifetime: Duration = self.lifetime;
+        let expired: bool = now.duration_since(self.entries.get(id)?.last_used) >= lifetime;
+        if expired {
+            self.entries.remove(id);
+            return None;
+        }
+
+        let entry: &mut Entry = self.entries.get_mut(id)?;
+        entry.last_used = now;
+        Some(entry)
+    }
+
+    /// Drops expired sessions and, if the store is still full, the least recently used one.
+    fn make_room(&mut self, now: Instant) {
+        if self.entries.len() < MAX_SESSIONS {
+            return;
+        }
+        let lifetime: Duration = self.lifetime;
+        self.entries
+            .retain(|_, e| now.duration_since(e.last_used) < lifetime);
+
+        if self.entries.len() >= MAX_SESSIONS {
+            let oldest: Option<String> = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(id, _)| id.clone());
+            if let Some(id) = oldest {
+                self.entries.remove(&id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_bound_to_sessions() {
+        let mut sessions = Sessions::new();
+        let first: Session = sessions.start(None);
+        let second: Session = sessions.start(Some("unknown"));
+        assert!(first.new && second.new);
+        assert_ne!(first.token, second.token);
+
+        let again: Session = sessions.start(Some(&first.id));
+        assert_eq!(
+            (again.token.as_str(), again.new),
+            (first.token.as_str(), false)
+        );
+
+        assert!(sessions.verify(Some(&first.id), Some(&first.token)));
+        assert!(!sessions.verify(Some(&first.id), Some(&second.token)));
+        assert!(!sessions.verify(Some(&first.id), None));
+        assert!(!sessions.verify(None, Some(&first.token)));
+        assert!(!sessions.verify(Some("unknown"), Some(&first.token)));
+    }
+
+    #[test]
+    fn test_expired_sessions_are_dropped() {
+        let mut sessions = Sessions::new().lifetime(Duration::ZERO);
+        let session: Session = sessions.start(None);
+
+        assert!(!sessions.verify(Some(&session.id), Some(&session.token)));
+        assert!(sessions.is_empty());
+        assert!(sessions.start(Some(&session.id)).new);
+    }
+
+    #[test]
+    fn test_cookie_value() {
+        let cookies = "theme=dark; session_id=abc ;empty=";
+        assert_eq!(cookie_value(cookies, SESSION_COOKIE), Some("abc"));
+        assert_eq!(cookie_value(cookies, "empty"), Some(""));
+        assert_eq!(cookie_value(cookies, "session"), None);
+    }
+
+    #[test]
+    fn test_inject() {
+        let page = inject(
+            b"<html><head><title>t</title></head><body><form id=\"a\"></form></body></html>",
+            "abc",
+        );
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.contains("<meta name=\"csrf-token\" content=\"abc\">\n</head>"));
+        assert!(page.contains(
+            "<form id=\"a\"><input type=\"hidden\" name=\"csrf_token\" value=\"abc\" /></form>"
+        ));
+    }
+}
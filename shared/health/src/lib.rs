@@ -0,0 +1,216 @@
+//! Health checks shared by the HTTP practicals.
+//!
+//! `/healthz` only tells that the process is up and answering. `/readyz` runs a check of every
+//! service the server depends on, such as its database, its cache and the TLS certificate it
+//! presents, and reports the server ready only if they all pass. Each server runs its own checks
+//! and builds the `/readyz` body from them with `report`.
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    /// A short description of the healthy service, or why the check failed.
+    pub result: Result<String, String>,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// Checks that the DER encoded certificate has not expired.
+    pub fn certificate(der: &[u8]) -> Self {
+        Check {
+            name: "certificate",
+            result: check_certificate(der, Utc::now()),
+        }
+    }
+}
+
+/// The body of a `/healthz` response.
+pub fn liveness() -> serde_json::Value {
+    serde_json::json!({ "status": "ok" })
+}
+
+/// Builds the body of a `/readyz` response.
+///
+/// # Arguments
+/// - `checks`: The result of each readiness check.
+/// - `shutting_down`: Whether the server is shutting down, which makes it not ready.
+///
+/// # Returns
+/// Whether the server is ready, and the body listing the result of each check.
+pub fn report(checks: &[Check], shutting_down: bool) -> (bool, serde_json::Value) {
+    let ready: bool = !shutting_down && checks.iter().all(|c| c.passed());
+
+    let mut results = serde_json::Map::new();
+    for check in checks {
+        let result = match &check.result {
+            Ok(detail) => serde_json::json!({ "status": "ok", "detail": detail }),
+            Err(e) => serde_json::json!({ "status": "failed", "detail": e }),
+        };
+        results.insert(check.name.to_string(), result);
+    }
+
+    let status: &str = match (ready, shutting_down) {
+        (true, _) => "ready",
+        (false, true) => "shutting down",
+        (false, false) => "not ready",
+    };
+    (
+        ready,
+        serde_json::json!({ "status": status, "checks": results }),
+    )
+}
+
+/// Checks that the certificate is valid at `now`.
+fn check_certificate(der: &[u8], now: DateTime<Utc>) -> Result<String, String> {
+    let not_after: DateTime<Utc> =
+        not_after(der).ok_or_else(|| String::from("Cannot parse the certificate"))?;
+
+    if not_after <= now {
+        Err(format!("Expired on {}", not_after.to_rfc3339()))
+    } else {
+        Ok(format!("Valid until {}", not_after.to_rfc3339()))
+    }
+}
+
+/// Reads the end of the validity period of a DER encoded X.509 certificate (RFC 5280, Section
+/// 4.1).
+pub fn not_after(der: &[u8]) -> Option<DateTime<Utc>> {
+    let (_, certificate, _) = der_element(der)?;
+    let (_, mut fields, _) = der_element(certificate)?;
+
+    // The version is optional and tagged [0]. It is followed by the serial number, the
+    // signature algorithm, the issuer and the validity.
+    if fields.first() == Some(&0xa0) {
+        fields = der_element(fields)?.2;
+    }
+    for _ in 0..3 {
+        fields = der_element(fields)?.2;
+    }
+    let (_, validity, _) = der_element(fields)?;
+    let (_, _, validity) = der_element(validity)?;
+    let (tag, time, _) = der_element(validity)?;
+
+    let format: &str = match tag {
+        0x17 => "%y%m%d%H%M%SZ",
+        0x18 => "%Y%m%d%H%M%SZ",
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(std::str::from_utf8(time).ok()?, format)
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+/// Splits the first DER element off `data`.
+///
+/// # Returns
+/// The tag, the contents and the bytes after the element.
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag: u8 = *data.first()?;
+    let first: usize = *data.get(1)? as usize;
+
+    let (length, start): (usize, usize) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count: usize = first & 0x7f;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes: &[u8] = data.get(2..2 + count)?;
+        (
+            bytes.iter().fold(0, |length, b| length << 8 | *b as usize),
+            2 + count,
+        )
+    };
+
+    let end: usize = start.checked_add(length)?;
+    Some((tag, data.get(start..end)?, &data[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wraps `contents` in a DER element.
+    fn element(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![tag];
+        if contents.len() < 0x80 {
+            bytes.push(contents.len() as u8);
+        } else {
+            bytes.push(0x81);
+            bytes.push(contents.len() as u8);
+        }
+        bytes.extend_from_slice(contents);
+        bytes
+    }
+
+    /// A certificate whose validity ends at `not_after`, a GeneralizedTime.
+    fn certificate(not_after: &[u8]) -> Vec<u8> {
+        let validity: Vec<u8> =
+            [element(0x17, b"250101000000Z"), element(0x18, not_after)].concat();
+        let tbs: Vec<u8> = [
+            element(0xa0, &element(0x02, &[2])),
+            element(0x02, &[0x12, 0x34]),
+            element(0x30, &element(0x06, &[0x2a, 0x86, 0x48])),
+            element(0x30, &[0; 100]),
+            element(0x30, &validity),
+            element(0x30, b"subject"),
+        ]
+        .concat();
+        element(0x30, &[element(0x30, &tbs), element(0x03, &[0])].concat())
+    }
+
+    #[test]
+    fn test_not_after() {
+        let certificate: Vec<u8> = certificate(b"20350630120000Z");
+
+        assert_eq!(
+            not_after(&certificate).map(|t| t.to_rfc3339()),
+            Some(String::from("2035-06-30T12:00:00+00:00"))
+        );
+        assert_eq!(not_after(&certificate[..20]), None);
+    }
+
+    #[test]
+    fn test_check_certificate() {
+        let now: DateTime<Utc> = "2030-01-01T00:00:00Z".parse().unwrap();
+
+        assert!(check_certificate(&certificate(b"20350630120000Z"), now).is_ok());
+        assert_eq!(
+            check_certificate(&certificate(b"20250630120000Z"), now),
+            Err(String::from("Expired on 2025-06-30T12:00:00+00:00"))
+        );
+        assert!(check_certificate(b"not a certificate", now).is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        let checks: Vec<Check> = vec![
+            Check {
+                name: "redis",
+                result: Ok(String::from("PONG")),
+            },
+            Check {
+                name: "postgres",
+                result: Err(String::from("Connection refused")),
+            },
+        ];
+
+        let (ready, body) = report(&checks[..1], false);
+        assert!(ready);
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["checks"]["redis"]["detail"], "PONG");
+
+        let (ready, body) = report(&checks, false);
+        assert!(!ready);
+        assert_eq!(body["status"], "not ready");
+        assert_eq!(body["checks"]["postgres"]["status"], "failed");
+
+        let (ready, body) = report(&checks[..1], true);
+        assert!(!ready);
+        assert_eq!(body["status"], "shutting down");
+    }
+}
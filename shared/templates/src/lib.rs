@@ -0,0 +1,720 @@
+//! A small template engine for server-rendered pages.
+//!
+//! Templates are files in a templates directory written in a Jinja-like syntax:
+//!
+//! - `{{ name }}` inserts a value, HTML escaped. Dotted paths such as `{{ friend.name }}` look
+//!   up object fields and array indices, and `{{ name | raw }}` inserts a value unescaped.
+//! - `{% if name %} ... {% else %} ... {% endif %}` renders a branch depending on whether a value
+//!   is truthy (not null, false, zero or empty). `{% if not name %}` negates the test.
+//! - `{% for item in items %} ... {% endfor %}` renders its body for every element of an array,
+//!   with `loop.index` (counting from 1), `loop.first` and `loop.last` available inside.
+//! - `{% include "file.html" %}` renders another template with the current values.
+//! - `{% extends "layout.html" %}` at the top of a template renders the layout instead, with
+//!   each of its `{% block name %} ... {% endblock %}` sections replaced by the block of the same
+//!   name in the template.
+//! - `{# ... #}` is a comment.
+//!
+//! Parsed templates are cached. With hot reload on, the default in debug builds, a template is
+//! parsed again whenever its file changes so pages can be edited without restarting the server.
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The directory templates are loaded from.
+pub const TEMPLATE_DIR: &str = "templates";
+
+/// How deeply includes and layouts may nest before rendering gives up.
+const MAX_DEPTH: usize = 16;
+
+/// An error loading, parsing or rendering a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError(pub String);
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Template error: {}", self.0)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var {
+        path: String,
+        raw: bool,
+    },
+    If {
+        path: String,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        name: String,
+        path: String,
+        body: Vec<Node>,
+    },
+    Include(String),
+    Block {
+        name: String,
+        body: Arc<Vec<Node>>,
+    },
+}
+
+#[derive(Debug)]
+struct Template {
+    /// The layout named by `{% extends %}`, if any.
+    extends: Option<String>,
+    nodes: Vec<Node>,
+}
+
+struct Cached {
+    modified: Option<SystemTime>,
+    template: Arc<Template>,
+}
+
+/// The templates in a directory.
+pub struct Templates {
+    dir: PathBuf,
+    hot_reload: bool,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+impl fmt::Debug for Templates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Templates")
+            .field("dir", &self.dir)
+            .field("hot_reload", &self.hot_reload)
+            .finish()
+    }
+}
+
+impl Templates {
+    /// Creates a loader for the templates in `dir`. Hot reload is on in debug builds.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Templates {
+            dir: dir.into(),
+            hot_reload: cfg!(debug_assertions),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Turns reloading of changed template files on or off.
+    pub fn hot_reload(mut self, hot_reload: bool) -> Self {
+        self.hot_reload = hot_reload;
+        self
+    }
+
+    /// Renders the template called `name` with the fields of `context`.
+    ///
+    /// # Arguments
+    /// - `name`: The file name of the template, relative to the templates directory.
+    /// - `context`: The values available to the template, usually a struct or `json!` object.
+    ///
+    /// # Returns
+    /// The rendered page or a `TemplateError` if a template is missing or invalid.
+    pub fn render<T: Serialize>(&self, name: &str, context: &T) -> Result<String, TemplateError> {
+        let template: Arc<Template> = self.load(name)?;
+        self.render_template(template, context)
+    }
+
+    /// Returns true if a template called `name` exists in the templates directory.
+    pub fn exists(&self, name: &str) -> bool {
+        self.path(name).map(|p| p.is_file()).unwrap_or(false)
+    }
+
+    /// Renders a template given as a string. Includes and layouts are loaded from the templates
+    /// directory.
+    pub fn render_str<T: Serialize>(
+        &self,
+        source: &str,
+        context: &T,
+    ) -> Result<String, TemplateError> {
+        let template: Arc<Template> = Arc::new(parse(source)?);
+        self.render_template(template, context)
+    }
+
+    fn render_template<T: Serialize>(
+        &self,
+        template: Arc<Template>,
+        context: &T,
+    ) -> Result<String, TemplateError> {
+        let root: Value = serde_json::to_value(context)
+            .map_err(|e| TemplateError(format!("invalid context: {}", e)))?;
+
+        let mut renderer = Renderer {
+            templates: self,
+            scopes: vec![root],
+            blocks: HashMap::new(),
+            output: String::new(),
+        };
+        renderer.template(template, 0)?;
+        Ok(renderer.output)
+    }
+
+    /// Returns the parsed template called `name`, reading it from disk if it is not cached or
+    /// has changed.
+    fn load(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        let path: PathBuf = self.path(name)?;
+        let modified: Option<SystemTime> = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+
+        if let Some(cached) = self.cache().get(name) {
+            if !self.hot_reload || cached.modified == modified {
+                return Ok(cached.template.clone());
+            }
+        }
+
+        let source: String = std::fs::read_to_string(&path)
+            .map_err(|e| TemplateError(format!("failed to read {}: {}", path.display(), e)))?;
+        let template: Arc<Template> =
+            Arc::new(parse(&source).map_err(|e| TemplateError(format!("{} in {}", e.0, name)))?);
+
+        self.cache().insert(
+            name.to_string(),
+            Cached {
+                modified,
+                template: template.clone(),
+            },
+        );
+        Ok(template)
+    }
+
+    /// Resolves a template name inside the templates directory, rejecting names that would
+    /// escape it.
+    fn path(&self, name: &str) -> Result<PathBuf, TemplateError> {
+        let relative: &Path = Path::new(name);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(TemplateError(format!("invalid template name {:?}", name)));
+        }
+        Ok(self.dir.join(relative))
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, HashMap<String, Cached>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Escapes the characters that are special in HTML text and attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped: String = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    Tag(&'a str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, TemplateError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut rest: &str = source;
+
+    while let Some(start) = rest.find('{') {
+        let close: &str = match rest[start..].get(..2) {
+            Some("{{") => "}}",
+            Some("{%") => "%}",
+            Some("{#") => "#}",
+            _ => {
+                tokens.push(Token::Text(&rest[..start + 1]));
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+        let inner: &str = &rest[start + 2..];
+        let end: usize = inner
+            .find(close)
+            .ok_or_else(|| TemplateError(format!("unclosed {}", &rest[start..start + 2])))?;
+
+        match close {
+            "}}" => tokens.push(Token::Var(inner[..end].trim())),
+            "%}" => tokens.push(Token::Tag(inner[..end].trim())),
+            _ => {}
+        }
+        rest = &inner[end + 2..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+    Ok(tokens)
+}
+
+fn parse(source: &str) -> Result<Template, TemplateError> {
+    let tokens: Vec<Token> = tokenize(source)?;
+    let mut position: usize = 0;
+
+    // A layout must be named before anything but whitespace
+    let mut extends: Option<String> = None;
+    while let Some(token) = tokens.get(position) {
+        match token {
+            Token::Text(text) if text.trim().is_empty() => position += 1,
+            Token::Tag(tag) if tag.starts_with("extends ") => {
+                extends = Some(quoted(&tag["extends ".len()..])?);
+                position += 1;
+                break;
+            }
+            _ => break,
+        }
+    }
+
+    let (nodes, end) = parse_nodes(&tokens, &mut position, &[])?;
+    if let Some(end) = end {
+        return Err(TemplateError(format!("unexpected {{% {} %}}", end)));
+    }
+    Ok(Template { extends, nodes })
+}
+
+/// Parses nodes until one of the tags in `ends` or the end of the template.
+///
+/// # Returns
+/// The nodes and the tag that ended them, or `None` at the end of the template.
+fn parse_nodes(
+    tokens: &[Token],
+    position: &mut usize,
+    ends: &[&str],
+) -> Result<(Vec<Node>, Option<String>), TemplateError> {
+    let mut nodes: Vec<Node> = Vec::new();
+
+    while let Some(token) = tokens.get(*position) {
+        *position += 1;
+        let tag: &str = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text.to_string()));
+                continue;
+            }
+            Token::Var(expression) => {
+                nodes.push(parse_var(expression)?);
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+
+        let words: Vec<&str> = tag.split_whitespace().collect();
+        let keyword: &str = words.first().copied().unwrap_or("");
+        if ends.contains(&keyword) {
+            return Ok((nodes, Some(keyword.to_string())));
+        }
+
+        match words.as_slice() {
+            ["if", path] | ["if", "not", path] => {
+                let (then, end) = parse_nodes(tokens, position, &["else", "endif"])?;
+                let otherwise: Vec<Node> = match end.as_deref() {
+                    Some("else") => match parse_nodes(tokens, position, &["endif"])? {
+                        (nodes, Some(_)) => nodes,
+                        (_, None) => return Err(missing("endif")),
+                    },
+                    Some(_) => Vec::new(),
+                    None => return Err(missing("endif")),
+                };
+                nodes.push(Node::If {
+                    path: path.to_string(),
+                    negate: words.len() == 3,
+                    then,
+                    otherwise,
+                });
+            }
+            ["for", name, "in", path] => {
+                let (body, end) = parse_nodes(tokens, position, &["endfor"])?;
+                if end.is_none() {
+                    return Err(missing("endfor"));
+                }
+                nodes.push(Node::For {
+                    name: name.to_string(),
+                    path: path.to_string(),
+                    body,
+                });
+            }
+            ["block", name] => {
+                let (body, end) = parse_nodes(tokens, position, &["endblock"])?;
+                if end.is_none() {
+                    return Err(missing("endblock"));
+                }
+                nodes.push(Node::Block {
+                    name: name.to_string(),
+                    body: Arc::new(body),
+                });
+            }
+            ["include", ..] => nodes.push(Node::Include(quoted(&tag["include".len()..])?)),
+            _ => return Err(TemplateError(format!("unknown tag {{% {} %}}", tag))),
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn parse_var(expression: &str) -> Result<Node, TemplateError> {
+    let (path, filter) = match expression.split_once('|') {
+        Some((path, filter)) => (path.trim(), Some(filter.trim())),
+        None => (expression, None),
+    };
+
+    if path.is_empty() || path.contains(char::is_whitespace) {
+        return Err(TemplateError(format!(
+            "invalid expression {{{{ {} }}}}",
+            expression
+        )));
+    }
+    match filter {
+        None | Some("raw") => Ok(Node::Var {
+            path: path.to_string(),
+            raw: filter.is_some(),
+        }),
+        Some(f) => Err(TemplateError(format!("unknown filter {}", f))),
+    }
+}
+
+fn quoted(argument: &str) -> Result<String, TemplateError> {
+    let argument: &str = argument.trim();
+    argument
+        .strip_prefix('"')
+        .and_then(|a| a.strip_suffix('"'))
+        .map(str::to_string)
+        .ok_or_else(|| TemplateError(format!("expected a quoted name, found {}", argument)))
+}
+
+fn missing(tag: &str) -> TemplateError {
+    TemplateError(format!("missing {{% {} %}}", tag))
+}
+
+struct Renderer<'a> {
+    templates: &'a Templates,
+    /// The context followed by the values bound by enclosing loops, innermost last.
+    scopes: Vec<Value>,
+    /// Block bodies from the templates extending the current layout.
+    blocks: HashMap<String, Arc<Vec<Node>>>,
+    output: String,
+}
+
+impl Renderer<'_> {
+    fn template(&mut self, template: Arc<Template>, depth: usize) -> Result<(), TemplateError> {
+        if depth > MAX_DEPTH {
+            return Err(TemplateError(String::from(
+                "templates are nested too deeply",
+            )));
+        }
+
+        match &template.extends {
+            Some(layout) => {
+                // The most derived template's block wins
+                for node in &template.nodes {
+                    if let Node::Block { name, body } = node {
+                        self.blocks
+                            .entry(name.clone())
+                            .or_insert_with(|| body.clone());
+                    }
+                }
+                let layout: Arc<Template> = self.templates.load(layout)?;
+                self.template(layout, depth + 1)
+            }
+            None => self.nodes(&template.nodes, depth),
+        }
+    }
+
+    fn nodes(&mut self, nodes: &[Node], depth: usize) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.output.push_str(text),
+                Node::Var { path, raw } => {
+                    let text: String = display(&self.lookup(path));
+                    if *raw {
+                        self.output.push_str(&text);
+                    } else {
+                        self.output.push_str(&escape_html(&text));
+                    }
+                }
+                Node::If {
+                    path,
+                    negate,
+                    then,
+                    otherwise,
+                } => {
+                    if truthy(&self.lookup(path)) != *negate {
+                        self.nodes(then, depth)?;
+                    } else {
+                        self.nodes(otherwise, depth)?;
+                    }
+                }
+                Node::For { name, path, body } => {
+                    let items: Vec<Value> = match self.lookup(path) {
+                        Value::Array(items) => items,
+                        Value::Null => Vec::new(),
+                        _ => return Err(TemplateError(format!("{} is not a list", path))),
+                    };
+
+                    let count: usize = items.len();
+                    for (i, item) in items.into_iter().enumerate() {
+                        let mut scope = serde_json::Map::new();
+                        scope.insert(name.clone(), item);
+                        scope.insert(
+                            String::from("loop"),
+                            json!({ "index": i + 1, "first": i == 0, "last": i + 1 == count }),
+                        );
+
+                        self.scopes.push(Value::Object(scope));
+                        let result = self.nodes(body, depth);
+                        self.scopes.pop();
+                        result?;
+                    }
+                }
+                Node::Include(name) => {
+                    let template: Arc<Template> = self.templates.load(name)?;
+                    self.template(template, depth + 1)?;
+                }
+                Node::Block { name, body } => {
+                    let body: Arc<Vec<Node>> = self
+                        .blocks
+                        .get(name)
+                        .cloned()
+                        .unwrap_or_else(|| body.clone());
+                    self.nodes(&body, depth)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up a dotted path, innermost scope first. Missing values are null.
+    fn lookup(&self, path: &str) -> Value {
+        let mut segments = path.split('.');
+        let first: &str = segments.next().unwrap_or("");
+
+        let mut value: &Value = match self.scopes.iter().rev().find_map(|s| s.get(first)) {
+            Some(v) => v,
+            None => return Value::Null,
+        };
+        for segment in segments {
+            let next: Option<&Value> = match segment.parse::<usize>() {
+                Ok(index) => value.get(index),
+                Err(_) => value.get(segment),
+            };
+            value = match next {
+                Some(v) => v,
+                None => return Value::Null,
+            };
+        }
+        value.clone()
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("templates-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_escaping_loops_and_conditionals() {
+        let templates = Templates::new(temp_dir("inline"));
+        let source = "{% for f in friends %}<li>{{ loop.index }}. {{ f.name }}{% if not loop.last %},{% endif %}</li>{% endfor %}\
+            {% if empty %}none{% else %}{{ html | raw }}{% endif %}";
+        let context = json!({
+            "friends": [{ "name": "<b>Jo</b>" }, { "name": "Sam & Al" }],
+            "empty": [],
+            "html": "<hr>",
+        });
+
+        assert_eq!(
+            templates.render_str(source, &context).unwrap(),
+            "<li>1. &lt;b&gt;Jo&lt;/b&gt;,</li><li>2. Sam &amp; Al</li><hr>"
+        );
+        assert!(templates.render_str("{% if x %}", &context).is_err());
+        assert!(templates.render_str("{{ x | upper }}", &context).is_err());
+    }
+
+    #[test]
+    fn test_layouts_and_includes() {
+        let dir = temp_dir("layout");
+        std::fs::write(
+            dir.join("layout.html"),
+            "<title>{% block title %}Default{% endblock %}</title>{% include \"nav.html\" %}<main>{% block content %}{% endblock %}</main>",
+        )
+        .unwrap();
+        std::fs::write(dir.join("nav.html"), "<nav>{{ user }}</nav>").unwrap();
+        std::fs::write(
+            dir.join("page.html"),
+            "{% extends \"layout.html\" %}\n{% block content %}Hi {{ user }}{% endblock %}",
+        )
+        .unwrap();
+
+        let templates = Templates::new(&dir);
+        assert_eq!(
+            templates
+                .render("page.html", &json!({ "user": "<Jo>" }))
+                .unwrap(),
+            "<title>Default</title><nav>&lt;Jo&gt;</nav><main>Hi &lt;Jo&gt;</main>"
+        );
+        assert!(templates.render("../page.html", &json!({})).is_err());
+    }
+
+    /// Rewrites a template file with a later modification time, so the change is seen even on
+    /// file systems with coarse timestamps.
+    fn rewrite(path: &Path, source: &str, seconds_later: u64) {
+        std::fs::write(path, source).unwrap();
+        let modified = SystemTime::now() + std::time::Duration::from_secs(seconds_later);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_hot_reload_picks_up_changes() {
+        let dir = temp_dir("reload");
+        let path = dir.join("page.html");
+        std::fs::write(&path, "Hello {{ name }}").unwrap();
+
+        let templates = Templates::new(&dir).hot_reload(true);
+        let context = json!({ "name": "Jo" });
+        assert_eq!(templates.render("page.html", &context).unwrap(), "Hello Jo");
+
+        rewrite(&path, "Goodbye {{ name }}", 10);
+        assert_eq!(
+            templates.render("page.html", &context).unwrap(),
+            "Goodbye Jo"
+        );
+
+        // A change that no longer parses is reported instead of serving the old template
+        rewrite(&path, "{% if name %}Hi", 20);
+        assert_eq!(
+            templates.render("page.html", &context),
+            Err(TemplateError(String::from(
+                "missing {% endif %} in page.html"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_cached_without_hot_reload() {
+        let dir = temp_dir("cached");
+        let path = dir.join("page.html");
+        std::fs::write(&path, "Hello {{ name }}").unwrap();
+
+        let templates = Templates::new(&dir).hot_reload(false);
+        let context = json!({ "name": "Jo" });
+        assert_eq!(templates.render("page.html", &context).unwrap(), "Hello Jo");
+
+        rewrite(&path, "Goodbye {{ name }}", 10);
+        assert_eq!(templates.render("page.html", &context).unwrap(), "Hello Jo");
+    }
+
+    #[test]
+    fn test_missing_templates_and_values() {
+        let dir = temp_dir("missing");
+        std::fs::write(dir.join("page.html"), "{% include \"nav.html\" %}").unwrap();
+        let templates = Templates::new(&dir);
+
+        assert!(!templates.exists("absent.html"));
+        let error = templates.render("absent.html", &json!({})).unwrap_err();
+        assert!(error.0.starts_with("failed to read"), "{}", error);
+        let error = templates.render("page.html", &json!({})).unwrap_err();
+        assert!(error.0.contains("nav.html"), "{}", error);
+
+        // Missing values render as nothing and are falsy, but only lists can be looped over
+        let context = json!({ "friend": { "name": "Jo" }, "count": 3 });
+        assert_eq!(
+            templates
+                .render_str(
+                    "[{{ absent }}][{{ friend.number }}][{{ friend.name.0 }}]{% if absent %}yes{% endif %}{% for f in absent %}{{ f }}{% endfor %}",
+                    &context
+                )
+                .unwrap(),
+            "[][][]"
+        );
+        assert_eq!(
+            templates.render_str("{% for c in count %}{% endfor %}", &context),
+            Err(TemplateError(String::from("count is not a list")))
+        );
+    }
+
+    #[test]
+    fn test_unterminated_tags() {
+        let templates = Templates::new(temp_dir("unterminated"));
+        let context = json!({ "items": [1] });
+
+        for (source, message) in [
+            ("{% if items %}yes", "missing {% endif %}"),
+            ("{% if items %}yes{% else %}no", "missing {% endif %}"),
+            ("{% for i in items %}{{ i }}", "missing {% endfor %}"),
+            (
+                "{% for i in items %}{% if i %}{{ i }}{% endfor %}",
+                "unknown tag {% endfor %}",
+            ),
+            ("{% endif %}", "unknown tag {% endif %}"),
+            ("{{ items", "unclosed {{"),
+            ("{% if items", "unclosed {%"),
+        ] {
+            assert_eq!(
+                templates.render_str(source, &context),
+                Err(TemplateError(String::from(message))),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_attribute_escaping() {
+        let templates = Templates::new(temp_dir("attributes"));
+        let context = json!({ "title": "Jo's \"friend\" <&>" });
+
+        assert_eq!(
+            templates
+                .render_str(
+                    "<a title=\"{{ title }}\" data-name='{{ title }}'>",
+                    &context
+                )
+                .unwrap(),
+            "<a title=\"Jo&#39;s &quot;friend&quot; &lt;&amp;&gt;\" data-name='Jo&#39;s &quot;friend&quot; &lt;&amp;&gt;'>"
+        );
+        assert_eq!(escape_html("\"'><script>"), "&quot;&#39;&gt;&lt;script&gt;");
+    }
+}